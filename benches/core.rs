@@ -0,0 +1,333 @@
+//! Benchmarks of the CPU, PPU and APU hot loops, each running a component on its own, and of taking save states.
+//!
+//! Usage: cargo bench
+//!
+//! To compare a change against the current numbers, save them as a baseline before making it, then compare:
+//!
+//! cargo bench -- --save-baseline before
+//! cargo bench -- --baseline before
+
+use std::time::{Duration, Instant};
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+
+// The benchmarks run the core's crate-private components on their own, so include the core directly.
+#[allow(dead_code)]
+#[path = "../src/gameboy/mod.rs"]
+mod gameboy;
+
+use gameboy::{
+    apu::Apu,
+    bus::Bus,
+    cpu::Cpu,
+    io_registers::IoRegisters,
+    ppu::Ppu,
+    GameBoy,
+    Mem,
+    Model,
+    Rewind,
+};
+
+#[macro_use]
+extern crate bitflags;
+
+const INSTRUCTIONS: u64 = 1_000_000;
+const FRAMES: u64 = 1_000;
+const DOTS_PER_FRAME: u64 = 70224;
+const AUDIO_SECONDS: u64 = 10;
+// The APU ticks once per m-cycle.
+const APU_TICKS_PER_SECOND: u64 = 1024 * 1024;
+const REWIND_SNAPSHOTS: usize = 64;
+
+const PROGRAM_START: usize = 0x150;
+const PROGRAM_END: usize = 0x7ffd;
+
+/// A ROM-only cartridge running a fixed stream of register-only loads, arithmetic, bit operations and rotates, then
+/// jumping back to the start. Memory operands are left out, so the stream doesn't depend on what they point at.
+fn instruction_stream_rom() -> Vec<u8> {
+    const SIMPLE_OPCODES: [u8; 8] = [0x04, 0x0d, 0x14, 0x1d, 0x24, 0x03, 0x13, 0x23];
+
+    let mut rom = vec![0; 0x8000];
+
+    // JP 0x0150, from the entry point.
+    rom[0x100..0x103].copy_from_slice(&[0xc3, 0x50, 0x01]);
+
+    // xorshift, so the stream is the same on every run.
+    let mut seed = 0x2545_f491u32;
+    let mut next = || {
+        seed ^= seed << 13;
+        seed ^= seed >> 17;
+        seed ^= seed << 5;
+        seed
+    };
+
+    let mut addr = PROGRAM_START;
+
+    while addr < PROGRAM_END - 2 {
+        let value = next();
+        let r = (value >> 8) as u8 & 0x7;
+
+        // Register 6 is (HL), swap it for A.
+        let r = if r == 6 { 7 } else { r };
+        let dst = if (value >> 11) as u8 & 0x7 == 6 { 7 } else { (value >> 11) as u8 & 0x7 };
+
+        let instruction: &[u8] = match value % 6 {
+            0 => &[0x40 | dst << 3 | r],
+            1 => &[0x80 | ((value >> 14) as u8 & 0x7) << 3 | r],
+            2 => &[0xcb, ((value >> 14) as u8 & 0x1f) << 3 | r],
+            3 => &[0x06 | dst << 3, (value >> 16) as u8],
+            4 => &[0x09 | ((value >> 14) as u8 & 0x3) << 4],
+            _ => &[SIMPLE_OPCODES[(value >> 14) as usize % SIMPLE_OPCODES.len()]],
+        };
+
+        rom[addr..addr + instruction.len()].copy_from_slice(instruction);
+        addr += instruction.len();
+    }
+
+    // JP 0x0150
+    rom[addr..addr + 3].copy_from_slice(&[0xc3, 0x50, 0x01]);
+
+    rom
+}
+
+/// A ROM-only cartridge pointing HL at ROM, WRAM, echo RAM or HRAM and loading, storing or adding through it, then
+/// jumping back to the start, so that most of the time goes to memory accesses.
+fn memory_stream_rom() -> Vec<u8> {
+    // LD A,(HL), LD (HL),A, ADD A,(HL), INC (HL), LD B,(HL), XOR (HL)
+    const MEMORY_OPCODES: [u8; 6] = [0x7e, 0x77, 0x86, 0x34, 0x46, 0xae];
+
+    let mut rom = vec![0; 0x8000];
+
+    // JP 0x0150, from the entry point.
+    rom[0x100..0x103].copy_from_slice(&[0xc3, 0x50, 0x01]);
+
+    let mut seed = 0x2545_f491u32;
+    let mut next = || {
+        seed ^= seed << 13;
+        seed ^= seed >> 17;
+        seed ^= seed << 5;
+        seed
+    };
+
+    let mut addr = PROGRAM_START;
+
+    while addr < PROGRAM_END - 4 {
+        let value = next();
+        let offset = (value >> 8) as u16;
+
+        let (target, opcodes) = match value % 4 {
+            // Only reads of ROM, since writes to it switch banks.
+            0 => (offset & 0x7fff, &MEMORY_OPCODES[2..3]),
+            1 => (0xc000 | offset & 0x1fff, &MEMORY_OPCODES[..]),
+            2 => (0xe000 + offset % 0x1e00, &MEMORY_OPCODES[..]),
+            _ => (0xff80 | offset & 0x7e, &MEMORY_OPCODES[..]),
+        };
+
+        let opcode = opcodes[(value >> 24) as usize % opcodes.len()];
+
+        // LD HL,target and the access.
+        rom[addr..addr + 4].copy_from_slice(&[0x21, target as u8, (target >> 8) as u8, opcode]);
+        addr += 4;
+    }
+
+    // JP 0x0150
+    rom[addr..addr + 3].copy_from_slice(&[0xc3, 0x50, 0x01]);
+
+    rom
+}
+
+fn cpu(c: &mut Criterion) {
+    let mut group = c.benchmark_group("cpu");
+    group.sample_size(20);
+    group.throughput(Throughput::Elements(INSTRUCTIONS));
+
+    for (name, rom) in [("1M instructions", instruction_stream_rom()), ("1M instructions, memory operands", memory_stream_rom())] {
+        let mut bus = Bus::new();
+        bus.load(rom, Model::Dmg).unwrap();
+
+        let mut cpu = Cpu::new(Model::Dmg);
+
+        group.bench_function(name, |b| b.iter(|| {
+            for _ in 0..INSTRUCTIONS {
+                black_box(cpu.tick(&mut bus));
+            }
+        }));
+    }
+
+    group.finish();
+}
+
+/// Background and window tiles with varied rows, and 40 objects spread over the screen, overlapping in places.
+fn busy_scene() -> (Ppu, IoRegisters) {
+    let mut ppu = Ppu::new();
+    let mut registers = IoRegisters::new();
+
+    for addr in 0x8000..0x9800u16 {
+        ppu.vram.mem_write(addr, (addr as u8).wrapping_mul(37) ^ (addr >> 4) as u8);
+    }
+
+    for addr in 0x9800..0xa000u16 {
+        ppu.vram.mem_write(addr, (addr as u8).wrapping_mul(7));
+    }
+
+    for sprite in 0..40u16 {
+        let oam_addr = 0xfe00 + sprite * 4;
+
+        ppu.vram.mem_write(oam_addr, 16 + (sprite * 29 % 144) as u8);
+        ppu.vram.mem_write(oam_addr + 1, 8 + (sprite * 41 % 160) as u8);
+        ppu.vram.mem_write(oam_addr + 2, sprite as u8);
+        ppu.vram.mem_write(oam_addr + 3, (sprite as u8 & 0x3) << 5);
+    }
+
+    // LCD, window with the 0x9c00 tile map, objects and background on.
+    registers.mem_write(0xff40, 0xe3);
+    registers.mem_write(0xff42, 3);
+    registers.mem_write(0xff43, 5);
+    registers.mem_write(0xff47, 0xe4);
+    registers.mem_write(0xff48, 0xd2);
+    registers.mem_write(0xff49, 0x1b);
+    registers.mem_write(0xff4a, 96);
+    registers.mem_write(0xff4b, 87);
+
+    (ppu, registers)
+}
+
+fn ppu(c: &mut Criterion) {
+    let mut group = c.benchmark_group("ppu");
+    group.sample_size(10);
+    group.throughput(Throughput::Elements(FRAMES));
+
+    for (name, dot_accurate) in [("1000 frames", false), ("1000 frames, dot accurate", true)] {
+        let (mut ppu, mut registers) = busy_scene();
+
+        group.bench_function(name, |b| b.iter(|| {
+            for _ in 0..FRAMES * DOTS_PER_FRAME {
+                black_box(ppu.tick(&mut registers, dot_accurate));
+            }
+        }));
+    }
+
+    group.finish();
+}
+
+fn apu(c: &mut Criterion) {
+    let mut apu = Apu::new();
+    let registers = IoRegisters::new();
+
+    // A ramp for the wave channel to loop.
+    for i in 0..16u16 {
+        apu.mem_write(0xff30 + i, (i as u8) << 4 | i as u8);
+    }
+
+    for (addr, value) in [
+        // Sound on, full volume, every channel on both sides.
+        (0xff26, 0x80),
+        (0xff24, 0x77),
+        (0xff25, 0xff),
+        // A square on channel 1 with a sweep, and one on channel 2 with an envelope.
+        (0xff10, 0x16),
+        (0xff11, 0x80),
+        (0xff12, 0xf0),
+        (0xff13, 0x00),
+        (0xff14, 0x86),
+        (0xff16, 0x40),
+        (0xff17, 0xf3),
+        (0xff18, 0x80),
+        (0xff19, 0x87),
+        // The wave channel.
+        (0xff1a, 0x80),
+        (0xff1c, 0x20),
+        (0xff1d, 0x40),
+        (0xff1e, 0x85),
+        // Noise.
+        (0xff21, 0xf0),
+        (0xff22, 0x55),
+        (0xff23, 0x80),
+    ] {
+        apu.mem_write(addr, value);
+    }
+
+    let mut samples = vec![0.0; 4096];
+
+    let mut group = c.benchmark_group("apu");
+    group.sample_size(10);
+    group.throughput(Throughput::Elements(AUDIO_SECONDS));
+
+    // Without audio, only what games can see of the APU runs, which is all the time saved by turning it off.
+    for (name, audio_enabled) in [("10 seconds", true), ("10 seconds, audio disabled", false)] {
+        apu.set_audio_enabled(audio_enabled);
+
+        group.bench_function(name, |b| b.iter(|| {
+            for _ in 0..AUDIO_SECONDS * APU_TICKS_PER_SECOND {
+                apu.tick(&registers);
+
+                // Taken like the audio device does, a buffer at a time.
+                if apu.buffer.len() >= samples.len() {
+                    apu.fill_audio(&mut samples);
+                }
+            }
+
+            black_box(&samples);
+        }));
+    }
+
+    group.finish();
+}
+
+/// A running game on an MBC3 cartridge with 128 KiB of RAM, the largest a state gets, with the mapped bank and WRAM
+/// filled with noise so they don't compress away.
+fn busy_game() -> GameBoy {
+    let mut rom = instruction_stream_rom();
+    rom[0x147] = 0x13;
+    rom[0x149] = 0x04;
+
+    let mut gameboy = GameBoy::new();
+    gameboy.load(rom).unwrap();
+
+    for addr in (0xa000..0xc000u16).chain(0xc000..0xe000) {
+        gameboy.poke(addr, (addr as u8).wrapping_mul(37) ^ (addr >> 5) as u8);
+    }
+
+    for _ in 0..10 {
+        gameboy.run_frame();
+    }
+
+    gameboy
+}
+
+fn state(c: &mut Criterion) {
+    let mut gameboy = busy_game();
+
+    let mut group = c.benchmark_group("state");
+    group.sample_size(20);
+
+    group.bench_function("save", |b| b.iter(|| black_box(gameboy.save_state())));
+
+    let state = gameboy.save_state().unwrap();
+
+    group.bench_function("load", |b| b.iter(|| gameboy.load_state(black_box(&state)).unwrap()));
+
+    // Snapshots are taken every 10 frames while playing, so each one differs from the last by that much running.
+    let mut rewind = Rewind::new(REWIND_SNAPSHOTS);
+
+    group.bench_function("rewind snapshot", |b| b.iter_custom(|iterations| {
+        let mut elapsed = Duration::ZERO;
+
+        for _ in 0..iterations {
+            for _ in 0..10 {
+                gameboy.run_frame();
+            }
+
+            let start = Instant::now();
+            rewind.push(black_box(&gameboy));
+            elapsed += start.elapsed();
+        }
+
+        elapsed
+    }));
+
+    group.finish();
+}
+
+criterion_group!(benches, cpu, ppu, apu, state);
+criterion_main!(benches);
@@ -0,0 +1,116 @@
+/*
+ * Runs a ROM headless for a number of frames through the C bindings, then writes the screen as a PPM image.
+ *
+ * Usage: example <ROM> <FRAMES> <OUTPUT.ppm>
+ *
+ * Build, from examples/ffi:
+ *
+ * cargo build --release
+ * cc example.c -Iinclude target/release/libyagbe.a -lm -lpthread -ldl -o example
+ */
+
+#include <stdio.h>
+#include <stdlib.h>
+
+#include "yagbe.h"
+
+/* The grey palette of the desktop frontend, from shade 0 to 3. */
+static const uint8_t PALETTE[4] = {0xff, 0xc0, 0x40, 0x00};
+
+static uint8_t *read_file(const char *path, size_t *len) {
+    FILE *file = fopen(path, "rb");
+
+    if (!file) {
+        return NULL;
+    }
+
+    fseek(file, 0, SEEK_END);
+    long size = ftell(file);
+    fseek(file, 0, SEEK_SET);
+
+    uint8_t *data = size > 0 ? malloc((size_t)size) : NULL;
+
+    if (data && fread(data, 1, (size_t)size, file) != (size_t)size) {
+        free(data);
+        data = NULL;
+    }
+
+    fclose(file);
+
+    *len = (size_t)size;
+
+    return data;
+}
+
+int main(int argc, char **argv) {
+    if (argc != 4) {
+        fprintf(stderr, "Usage: %s <ROM> <FRAMES> <OUTPUT.ppm>\n", argv[0]);
+        return 1;
+    }
+
+    size_t rom_len;
+    uint8_t *rom = read_file(argv[1], &rom_len);
+
+    if (!rom) {
+        fprintf(stderr, "Could not read %s\n", argv[1]);
+        return 1;
+    }
+
+    YagbeGameBoy *gameboy = yagbe_new();
+
+    if (!gameboy) {
+        fprintf(stderr, "Could not create the emulator\n");
+        free(rom);
+        return 1;
+    }
+
+    /* The ROM is copied, so it can be freed right away. */
+    int32_t result = yagbe_load_rom(gameboy, rom, rom_len);
+    free(rom);
+
+    if (result != YAGBE_OK) {
+        fprintf(stderr, "Could not load %s: error %d\n", argv[1], result);
+        yagbe_free(gameboy);
+        return 1;
+    }
+
+    long frames = strtol(argv[2], NULL, 10);
+    float audio[4096];
+
+    for (long i = 0; i < frames && result == YAGBE_OK; i++) {
+        result = yagbe_run_frame(gameboy);
+
+        /* Nothing plays it, but draining it keeps it from growing. */
+        yagbe_fill_audio(gameboy, audio, sizeof(audio) / sizeof(audio[0]));
+    }
+
+    if (result != YAGBE_OK) {
+        fprintf(stderr, "Emulation failed: error %d\n", result);
+        yagbe_free(gameboy);
+        return 1;
+    }
+
+    FILE *out = fopen(argv[3], "wb");
+
+    if (!out) {
+        fprintf(stderr, "Could not write %s\n", argv[3]);
+        yagbe_free(gameboy);
+        return 1;
+    }
+
+    const uint8_t *screen = yagbe_screen_ptr(gameboy);
+
+    fprintf(out, "P6\n%d %d\n255\n", YAGBE_SCREEN_WIDTH, YAGBE_SCREEN_HEIGHT);
+
+    for (size_t i = 0; i < YAGBE_SCREEN_WIDTH * YAGBE_SCREEN_HEIGHT; i++) {
+        uint8_t shade = PALETTE[screen[i] & 3];
+        uint8_t rgb[3] = {shade, shade, shade};
+
+        fwrite(rgb, 1, sizeof(rgb), out);
+    }
+
+    fclose(out);
+    yagbe_free(gameboy);
+
+    return 0;
+}
@@ -0,0 +1,195 @@
+#ifndef YAGBE_H
+#define YAGBE_H
+
+/* Generated with cbindgen from src/lib.rs, don't edit by hand. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/**
+ * The call succeeded.
+ */
+#define YAGBE_OK 0
+
+/**
+ * A required pointer was null.
+ */
+#define YAGBE_ERROR_NULL -1
+
+/**
+ * The emulator panicked, during this call or an earlier one on the same instance.
+ */
+#define YAGBE_ERROR_PANIC -2
+
+/**
+ * The ROM could not be loaded. The running game, if any, is left as it is.
+ */
+#define YAGBE_ERROR_ROM -3
+
+/**
+ * A buffer given by the caller is too small.
+ */
+#define YAGBE_ERROR_BUFFER_TOO_SMALL -4
+
+/**
+ * The operation isn't supported by this build.
+ */
+#define YAGBE_ERROR_UNSUPPORTED -5
+
+/**
+ * A save state couldn't be made because no ROM is loaded, or couldn't be restored because it is invalid or was
+ * made with another ROM.
+ */
+#define YAGBE_ERROR_STATE -6
+
+/**
+ * Width of the screen in pixels.
+ */
+#define YAGBE_SCREEN_WIDTH 160
+
+/**
+ * Height of the screen in pixels.
+ */
+#define YAGBE_SCREEN_HEIGHT 144
+
+/**
+ * An emulator instance, see `yagbe_new`.
+ */
+typedef struct YagbeGameBoy YagbeGameBoy;
+
+/**
+ * Called with the `user_data` given to `yagbe_set_scanline_callback`, the line the PPU finished drawing, and its
+ * `YAGBE_SCREEN_WIDTH` shades. The pixels are only valid during the call.
+ */
+typedef void (*YagbeScanlineCallback)(void *user_data, uint8_t line, const uint8_t *pixels);
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Creates an emulator instance without a ROM. Returns null if it can't be created.
+ *
+ * The instance must be freed with `yagbe_free`.
+ */
+YagbeGameBoy *yagbe_new(void);
+
+/**
+ * Frees an instance created by `yagbe_new`. Does nothing if `instance` is null.
+ *
+ * # Safety
+ *
+ * `instance` must be null or an instance from `yagbe_new` that wasn't freed. It must not be used after this call.
+ */
+void yagbe_free(YagbeGameBoy *instance);
+
+/**
+ * Loads a ROM of `len` bytes from `data`, which is copied, and starts it from the beginning.
+ *
+ * # Safety
+ *
+ * `instance` must be null or an instance from `yagbe_new` that wasn't freed, and `data` must point to `len` readable
+ * bytes.
+ */
+int32_t yagbe_load_rom(YagbeGameBoy *instance, const uint8_t *data, size_t len);
+
+/**
+ * Runs until a frame is completed, or for as long as a frame takes while the LCD is off. Does nothing before a ROM
+ * is loaded.
+ *
+ * # Safety
+ *
+ * `instance` must be null or an instance from `yagbe_new` that wasn't freed.
+ */
+int32_t yagbe_run_frame(YagbeGameBoy *instance);
+
+/**
+ * The screen, as `YAGBE_SCREEN_WIDTH` * `YAGBE_SCREEN_HEIGHT` shades from 0 (lightest) to 3 (darkest), row by row.
+ * Returns null if `instance` is null or panicked.
+ *
+ * The screen is owned by the instance. The pointer stays valid until the instance is freed, and its contents change
+ * while frames run.
+ *
+ * # Safety
+ *
+ * `instance` must be null or an instance from `yagbe_new` that wasn't freed.
+ */
+const uint8_t *yagbe_screen_ptr(const YagbeGameBoy *instance);
+
+/**
+ * Calls `callback` with `user_data` as soon as each line is drawn while frames run, for frontends that stream lines
+ * rather than copy whole screens, or stops calling it if `callback` is null. The screen is still drawn.
+ *
+ * # Safety
+ *
+ * `instance` must be null or an instance from `yagbe_new` that wasn't freed. `callback` must be safe to call with
+ * `user_data` from whichever thread runs frames, until it is replaced or the instance is freed, and must not call back
+ * into the instance.
+ */
+int32_t yagbe_set_scanline_callback(YagbeGameBoy *instance,
+                                    YagbeScanlineCallback callback,
+                                    void *user_data);
+
+/**
+ * Sets the pressed buttons, one bit each: Right, Left, Up, Down, A, B, Select and Start from bit 0.
+ *
+ * # Safety
+ *
+ * `instance` must be null or an instance from `yagbe_new` that wasn't freed.
+ */
+int32_t yagbe_set_buttons(YagbeGameBoy *instance, uint8_t buttons);
+
+/**
+ * Moves buffered audio into the `len` floats at `out` as interleaved stereo samples at 48 kHz, zero-filling it if
+ * there aren't enough. Returns the number of samples moved, or a negative error code.
+ *
+ * # Safety
+ *
+ * `instance` must be null or an instance from `yagbe_new` that wasn't freed, and `out` must point to `len` writable
+ * floats.
+ */
+ptrdiff_t yagbe_fill_audio(YagbeGameBoy *instance, float *out, size_t len);
+
+/**
+ * Saves the state of the emulator into the `len` bytes at `buffer`, writing its size to `written`. If `buffer` is
+ * null or too small, only the size is written, and `YAGBE_ERROR_BUFFER_TOO_SMALL` is returned.
+ *
+ * States are compressed, so their size changes as the game runs. A buffer of `yagbe_state_size_hint` bytes always
+ * fits.
+ *
+ * # Safety
+ *
+ * `instance` must be null or an instance from `yagbe_new` that wasn't freed, `buffer` must be null or point to `len`
+ * writable bytes, and `written` must be null or writable.
+ */
+int32_t yagbe_save_state(YagbeGameBoy *instance, uint8_t *buffer, size_t len, size_t *written);
+
+/**
+ * Restores a state saved by `yagbe_save_state` from the `len` bytes at `buffer`, which are copied. If it can't be
+ * restored, `YAGBE_ERROR_STATE` is returned and the instance is left as it was.
+ *
+ * # Safety
+ *
+ * `instance` must be null or an instance from `yagbe_new` that wasn't freed, and `buffer` must point to `len`
+ * readable bytes.
+ */
+int32_t yagbe_load_state(YagbeGameBoy *instance, const uint8_t *buffer, size_t len);
+
+/**
+ * Writes an upper bound of the size of a save state of the loaded ROM to `size`.
+ *
+ * # Safety
+ *
+ * `instance` must be null or an instance from `yagbe_new` that wasn't freed, and `size` must point to a writable
+ * `size_t`.
+ */
+int32_t yagbe_state_size_hint(YagbeGameBoy *instance, size_t *size);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* YAGBE_H */
@@ -0,0 +1,320 @@
+//! C bindings to the core, for embedding it in other frontends. The header is include/yagbe.h, generated with:
+//!
+//! cbindgen --config cbindgen.toml --output include/yagbe.h
+//!
+//! Ownership: instances are created by `yagbe_new` and owned by the caller until passed to `yagbe_free`. Every buffer
+//! is owned by the caller and only borrowed for the duration of a call; ROMs and states are copied in. The one
+//! exception is the screen returned by `yagbe_screen_ptr`, which is owned by the instance.
+//!
+//! Every function catches panics and reports them as `YAGBE_ERROR_PANIC`. An instance that panicked is left in an
+//! unknown state, so every later call on it fails the same way, and it can only be freed.
+
+use std::{
+    ffi::c_void,
+    panic::{self, AssertUnwindSafe},
+    ptr,
+    slice,
+};
+
+// Depending on the yagbe package would also build and link the desktop frontend's SDL2 and Tao, so include the core's
+// source rather than the package's library.
+#[allow(dead_code)]
+#[path = "../../../src/gameboy/mod.rs"]
+mod gameboy;
+
+#[macro_use]
+extern crate bitflags;
+
+use gameboy::{Buttons, GameBoy, ScanlineCallback};
+
+/// The call succeeded.
+pub const YAGBE_OK: i32 = 0;
+/// A required pointer was null.
+pub const YAGBE_ERROR_NULL: i32 = -1;
+/// The emulator panicked, during this call or an earlier one on the same instance.
+pub const YAGBE_ERROR_PANIC: i32 = -2;
+/// The ROM could not be loaded. The running game, if any, is left as it is.
+pub const YAGBE_ERROR_ROM: i32 = -3;
+/// A buffer given by the caller is too small.
+pub const YAGBE_ERROR_BUFFER_TOO_SMALL: i32 = -4;
+/// The operation isn't supported by this build.
+pub const YAGBE_ERROR_UNSUPPORTED: i32 = -5;
+/// A save state couldn't be made because no ROM is loaded, or couldn't be restored because it is invalid or was
+/// made with another ROM.
+pub const YAGBE_ERROR_STATE: i32 = -6;
+
+/// Width of the screen in pixels.
+pub const YAGBE_SCREEN_WIDTH: usize = 160;
+/// Height of the screen in pixels.
+pub const YAGBE_SCREEN_HEIGHT: usize = 144;
+
+/// Called with the `user_data` given to `yagbe_set_scanline_callback`, the line the PPU finished drawing, and its
+/// `YAGBE_SCREEN_WIDTH` shades. The pixels are only valid during the call.
+pub type YagbeScanlineCallback = Option<unsafe extern "C" fn(user_data: *mut c_void, line: u8, pixels: *const u8)>;
+
+/// A scanline callback and its data, which the caller of `yagbe_set_scanline_callback` vouches can be used from the
+/// thread that runs frames.
+struct ScanlineSink {
+    callback: unsafe extern "C" fn(*mut c_void, u8, *const u8),
+    user_data: *mut c_void,
+}
+
+unsafe impl Send for ScanlineSink {}
+
+impl ScanlineSink {
+    fn call(&self, line: u8, pixels: &[u8]) {
+        unsafe { (self.callback)(self.user_data, line, pixels.as_ptr()) }
+    }
+}
+
+/// An emulator instance, see `yagbe_new`.
+pub struct YagbeGameBoy {
+    gameboy: GameBoy,
+    poisoned: bool,
+}
+
+/// Runs `f` on an instance, turning null pointers, panics and poisoned instances into error codes.
+///
+/// # Safety
+///
+/// `instance` must be null or an instance from `yagbe_new` that wasn't freed.
+unsafe fn with_instance(instance: *mut YagbeGameBoy, f: impl FnOnce(&mut GameBoy) -> i32) -> i32 {
+    let Some(instance) = instance.as_mut() else {
+        return YAGBE_ERROR_NULL;
+    };
+
+    if instance.poisoned {
+        return YAGBE_ERROR_PANIC;
+    }
+
+    match panic::catch_unwind(AssertUnwindSafe(|| f(&mut instance.gameboy))) {
+        Ok(result) => result,
+        Err(_) => {
+            instance.poisoned = true;
+
+            YAGBE_ERROR_PANIC
+        }
+    }
+}
+
+/// Creates an emulator instance without a ROM. Returns null if it can't be created.
+///
+/// The instance must be freed with `yagbe_free`.
+#[no_mangle]
+pub extern "C" fn yagbe_new() -> *mut YagbeGameBoy {
+    panic::catch_unwind(|| {
+        Box::into_raw(Box::new(YagbeGameBoy {
+            gameboy: GameBoy::new(),
+            poisoned: false,
+        }))
+    })
+    .unwrap_or(ptr::null_mut())
+}
+
+/// Frees an instance created by `yagbe_new`. Does nothing if `instance` is null.
+///
+/// # Safety
+///
+/// `instance` must be null or an instance from `yagbe_new` that wasn't freed. It must not be used after this call.
+#[no_mangle]
+pub unsafe extern "C" fn yagbe_free(instance: *mut YagbeGameBoy) {
+    if !instance.is_null() {
+        // A panic while dropping can't be reported, and must not unwind into C.
+        let _ = panic::catch_unwind(AssertUnwindSafe(|| drop(Box::from_raw(instance))));
+    }
+}
+
+/// Loads a ROM of `len` bytes from `data`, which is copied, and starts it from the beginning.
+///
+/// # Safety
+///
+/// `instance` must be null or an instance from `yagbe_new` that wasn't freed, and `data` must point to `len` readable
+/// bytes.
+#[no_mangle]
+pub unsafe extern "C" fn yagbe_load_rom(instance: *mut YagbeGameBoy, data: *const u8, len: usize) -> i32 {
+    if data.is_null() {
+        return YAGBE_ERROR_NULL;
+    }
+
+    let rom = slice::from_raw_parts(data, len).to_vec();
+
+    with_instance(instance, |gameboy| match gameboy.load(rom) {
+        Ok(()) => YAGBE_OK,
+        Err(_) => YAGBE_ERROR_ROM,
+    })
+}
+
+/// Runs until a frame is completed, or for as long as a frame takes while the LCD is off. Does nothing before a ROM
+/// is loaded.
+///
+/// # Safety
+///
+/// `instance` must be null or an instance from `yagbe_new` that wasn't freed.
+#[no_mangle]
+pub unsafe extern "C" fn yagbe_run_frame(instance: *mut YagbeGameBoy) -> i32 {
+    with_instance(instance, |gameboy| {
+        gameboy.run_frame();
+
+        YAGBE_OK
+    })
+}
+
+/// The screen, as `YAGBE_SCREEN_WIDTH` * `YAGBE_SCREEN_HEIGHT` shades from 0 (lightest) to 3 (darkest), row by row.
+/// Returns null if `instance` is null or panicked.
+///
+/// The screen is owned by the instance. The pointer stays valid until the instance is freed, and its contents change
+/// while frames run.
+///
+/// # Safety
+///
+/// `instance` must be null or an instance from `yagbe_new` that wasn't freed.
+#[no_mangle]
+pub unsafe extern "C" fn yagbe_screen_ptr(instance: *const YagbeGameBoy) -> *const u8 {
+    match instance.as_ref() {
+        Some(instance) if !instance.poisoned => instance.gameboy.screen().as_ptr(),
+        _ => ptr::null(),
+    }
+}
+
+/// Calls `callback` with `user_data` as soon as each line is drawn while frames run, for frontends that stream lines
+/// rather than copy whole screens, or stops calling it if `callback` is null. The screen is still drawn.
+///
+/// # Safety
+///
+/// `instance` must be null or an instance from `yagbe_new` that wasn't freed. `callback` must be safe to call with
+/// `user_data` from whichever thread runs frames, until it is replaced or the instance is freed, and must not call back
+/// into the instance.
+#[no_mangle]
+pub unsafe extern "C" fn yagbe_set_scanline_callback(
+    instance: *mut YagbeGameBoy,
+    callback: YagbeScanlineCallback,
+    user_data: *mut c_void,
+) -> i32 {
+    with_instance(instance, |gameboy| {
+        let callback = callback.map(|callback| -> ScanlineCallback {
+            let sink = ScanlineSink { callback, user_data };
+
+            Box::new(move |line, pixels| sink.call(line, pixels))
+        });
+
+        gameboy.set_scanline_callback(callback);
+
+        YAGBE_OK
+    })
+}
+
+/// Sets the pressed buttons, one bit each: Right, Left, Up, Down, A, B, Select and Start from bit 0.
+///
+/// # Safety
+///
+/// `instance` must be null or an instance from `yagbe_new` that wasn't freed.
+#[no_mangle]
+pub unsafe extern "C" fn yagbe_set_buttons(instance: *mut YagbeGameBoy, buttons: u8) -> i32 {
+    with_instance(instance, |gameboy| {
+        gameboy.set_buttons(Buttons::from_bits_truncate(buttons));
+
+        YAGBE_OK
+    })
+}
+
+/// Moves buffered audio into the `len` floats at `out` as interleaved stereo samples at 48 kHz, zero-filling it if
+/// there aren't enough. Returns the number of samples moved, or a negative error code.
+///
+/// # Safety
+///
+/// `instance` must be null or an instance from `yagbe_new` that wasn't freed, and `out` must point to `len` writable
+/// floats.
+#[no_mangle]
+pub unsafe extern "C" fn yagbe_fill_audio(instance: *mut YagbeGameBoy, out: *mut f32, len: usize) -> isize {
+    if out.is_null() {
+        return YAGBE_ERROR_NULL as isize;
+    }
+
+    let out = slice::from_raw_parts_mut(out, len);
+    let mut moved = 0;
+
+    match with_instance(instance, |gameboy| {
+        moved = gameboy.fill_audio(out);
+
+        YAGBE_OK
+    }) {
+        YAGBE_OK => moved as isize,
+        error => error as isize,
+    }
+}
+
+/// Saves the state of the emulator into the `len` bytes at `buffer`, writing its size to `written`. If `buffer` is
+/// null or too small, only the size is written, and `YAGBE_ERROR_BUFFER_TOO_SMALL` is returned.
+///
+/// States are compressed, so their size changes as the game runs. A buffer of `yagbe_state_size_hint` bytes always
+/// fits.
+///
+/// # Safety
+///
+/// `instance` must be null or an instance from `yagbe_new` that wasn't freed, `buffer` must be null or point to `len`
+/// writable bytes, and `written` must be null or writable.
+#[no_mangle]
+pub unsafe extern "C" fn yagbe_save_state(
+    instance: *mut YagbeGameBoy,
+    buffer: *mut u8,
+    len: usize,
+    written: *mut usize,
+) -> i32 {
+    with_instance(instance, |gameboy| {
+        let Some(state) = gameboy.save_state() else {
+            return YAGBE_ERROR_STATE;
+        };
+
+        if !written.is_null() {
+            *written = state.len();
+        }
+
+        if buffer.is_null() || len < state.len() {
+            return YAGBE_ERROR_BUFFER_TOO_SMALL;
+        }
+
+        slice::from_raw_parts_mut(buffer, state.len()).copy_from_slice(&state);
+
+        YAGBE_OK
+    })
+}
+
+/// Restores a state saved by `yagbe_save_state` from the `len` bytes at `buffer`, which are copied. If it can't be
+/// restored, `YAGBE_ERROR_STATE` is returned and the instance is left as it was.
+///
+/// # Safety
+///
+/// `instance` must be null or an instance from `yagbe_new` that wasn't freed, and `buffer` must point to `len`
+/// readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn yagbe_load_state(instance: *mut YagbeGameBoy, buffer: *const u8, len: usize) -> i32 {
+    if buffer.is_null() {
+        return YAGBE_ERROR_NULL;
+    }
+
+    let state = slice::from_raw_parts(buffer, len);
+
+    with_instance(instance, |gameboy| match gameboy.load_state(state) {
+        Ok(()) => YAGBE_OK,
+        Err(_) => YAGBE_ERROR_STATE,
+    })
+}
+
+/// Writes an upper bound of the size of a save state of the loaded ROM to `size`.
+///
+/// # Safety
+///
+/// `instance` must be null or an instance from `yagbe_new` that wasn't freed, and `size` must point to a writable
+/// `size_t`.
+#[no_mangle]
+pub unsafe extern "C" fn yagbe_state_size_hint(instance: *mut YagbeGameBoy, size: *mut usize) -> i32 {
+    if size.is_null() {
+        return YAGBE_ERROR_NULL;
+    }
+
+    with_instance(instance, |gameboy| {
+        *size = gameboy.state_size_hint();
+
+        YAGBE_OK
+    })
+}
@@ -0,0 +1,46 @@
+//! Runs a ROM for 300 frames without a window and writes the last frame as a PPM image.
+//!
+//! Usage: cargo run --example headless_screenshot -- <ROM> [OUTPUT]
+
+use std::fs;
+
+use yagbe::gameboy::{FrameEnd, GameBoy, SCREEN_HEIGHT, SCREEN_WIDTH};
+
+const FRAMES: u64 = 300;
+const DEFAULT_OUTPUT: &str = "screen.ppm";
+
+/// The shades of the screen, from lightest to darkest, as grays.
+const SHADES: [u8; 4] = [0xff, 0xaa, 0x55, 0x00];
+
+fn main() -> Result<(), String> {
+    let mut args = std::env::args().skip(1);
+
+    let rom_path = args.next().ok_or("Usage: headless_screenshot <ROM> [OUTPUT]")?;
+    let output_path = args.next().unwrap_or_else(|| DEFAULT_OUTPUT.to_string());
+
+    let rom = fs::read(&rom_path).map_err(|e| format!("Could not read ROM file: {e}"))?;
+
+    let mut gameboy = GameBoy::new();
+    gameboy.load(rom).map_err(|e| format!("Could not load ROM: {e}"))?;
+
+    for _ in 0..FRAMES {
+        // A frame also ends when the LCD stays off for as long as a frame takes, which counts the same here.
+        if let Some(FrameEnd::Watchpoint(event)) = gameboy.run_frame() {
+            return Err(format!("Unexpected watchpoint: {event:?}"));
+        }
+    }
+
+    let mut ppm = format!("P6\n{SCREEN_WIDTH} {SCREEN_HEIGHT}\n255\n").into_bytes();
+
+    for &shade in gameboy.screen() {
+        let gray = SHADES[shade as usize];
+
+        ppm.extend([gray, gray, gray]);
+    }
+
+    fs::write(&output_path, ppm).map_err(|e| format!("Could not write {output_path}: {e}"))?;
+
+    println!("Wrote frame {} to {output_path}", gameboy.frame_count());
+
+    Ok(())
+}
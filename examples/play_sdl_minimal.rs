@@ -0,0 +1,116 @@
+//! A minimal frontend on SDL2 alone, showing that the core needs neither Tao nor Windows: a window, the keyboard and
+//! audio, paced by the audio queue. The arrow keys are the D-pad, Z and X are B and A, and Enter and Backspace are
+//! Start and Select.
+//!
+//! Usage: cargo run --example play_sdl_minimal -- <ROM>
+
+use std::{fs, thread, time::Duration};
+
+use sdl2::{
+    audio::AudioSpecDesired,
+    event::Event,
+    keyboard::Keycode,
+    pixels::PixelFormatEnum,
+};
+use yagbe::gameboy::{Buttons, GameBoy, AUDIO_SAMPLE_RATE, SCREEN_HEIGHT, SCREEN_WIDTH};
+
+const SCALE: u32 = 4;
+
+/// Queued audio, in samples of both sides, kept ahead of playback. Frames are only run while the queue is shorter.
+const AUDIO_AHEAD: u32 = AUDIO_SAMPLE_RATE as u32 / 20 * 2;
+
+/// The shades of the screen, from lightest to darkest.
+const SHADES: [[u8; 3]; 4] = [[0xe0, 0xf8, 0xd0], [0x88, 0xc0, 0x70], [0x34, 0x68, 0x56], [0x08, 0x18, 0x20]];
+
+const KEYS: [(Keycode, Buttons); 8] = [
+    (Keycode::Up, Buttons::Up),
+    (Keycode::Down, Buttons::Down),
+    (Keycode::Left, Buttons::Left),
+    (Keycode::Right, Buttons::Right),
+    (Keycode::X, Buttons::A),
+    (Keycode::Z, Buttons::B),
+    (Keycode::Return, Buttons::Start),
+    (Keycode::Backspace, Buttons::Select),
+];
+
+fn main() -> Result<(), String> {
+    let rom_path = std::env::args().nth(1).ok_or("Usage: play_sdl_minimal <ROM>")?;
+    let rom = fs::read(&rom_path).map_err(|e| format!("Could not read ROM file: {e}"))?;
+
+    let mut gameboy = GameBoy::new();
+    gameboy.load(rom).map_err(|e| format!("Could not load ROM: {e}"))?;
+
+    let sdl = sdl2::init()?;
+    let video = sdl.video()?;
+    let audio = sdl.audio()?;
+
+    let window = video
+        .window("yagbe", SCREEN_WIDTH as u32 * SCALE, SCREEN_HEIGHT as u32 * SCALE)
+        .position_centered()
+        .build()
+        .map_err(|e| e.to_string())?;
+
+    let mut canvas = window.into_canvas().build().map_err(|e| e.to_string())?;
+    let texture_creator = canvas.texture_creator();
+    let mut texture = texture_creator
+        .create_texture_streaming(PixelFormatEnum::RGB24, SCREEN_WIDTH as u32, SCREEN_HEIGHT as u32)
+        .map_err(|e| e.to_string())?;
+
+    let desired_spec = AudioSpecDesired {
+        freq: Some(AUDIO_SAMPLE_RATE as i32),
+        channels: Some(2),
+        samples: None,
+    };
+
+    let queue = audio.open_queue::<f32, _>(None, &desired_spec)?;
+    queue.resume();
+
+    let mut event_pump = sdl.event_pump()?;
+    let mut buttons = Buttons::empty();
+    let mut samples = Vec::new();
+
+    'running: loop {
+        for event in event_pump.poll_iter() {
+            match event {
+                Event::Quit { .. } | Event::KeyDown { keycode: Some(Keycode::Escape), .. } => break 'running,
+                Event::KeyDown { keycode: Some(keycode), .. } => buttons |= button(keycode),
+                Event::KeyUp { keycode: Some(keycode), .. } => buttons -= button(keycode),
+                _ => {}
+            }
+        }
+
+        if queue.size() / 4 >= AUDIO_AHEAD {
+            thread::sleep(Duration::from_millis(1));
+
+            continue;
+        }
+
+        gameboy.latch_input(buttons);
+        gameboy.run_frame();
+
+        samples.resize(gameboy.audio_buffer_size(), 0.0);
+        gameboy.fill_audio(&mut samples);
+        queue.queue_audio(&samples)?;
+
+        texture.with_lock(None, |buffer: &mut [u8], pitch: usize| {
+            for (y, row) in gameboy.screen().chunks(SCREEN_WIDTH).enumerate() {
+                for (x, &shade) in row.iter().enumerate() {
+                    let offset = y * pitch + x * 3;
+
+                    buffer[offset..offset + 3].copy_from_slice(&SHADES[shade as usize]);
+                }
+            }
+        })?;
+
+        canvas.copy(&texture, None, None)?;
+        canvas.present();
+    }
+
+    Ok(())
+}
+
+fn button(keycode: Keycode) -> Buttons {
+    KEYS.iter()
+        .find(|&&(key, _)| key == keycode)
+        .map_or(Buttons::empty(), |&(_, buttons)| buttons)
+}
@@ -0,0 +1,56 @@
+//! Writes a frame as it is drawn, one line at a time, into a PGM image per line, the way a frontend streaming lines
+//! to a display would get them. The frame written is the one after the given number of frames.
+//!
+//! Usage: cargo run --example scanlines -- <ROM> <OUTPUT DIRECTORY> [FRAMES]
+
+use std::{fs, path::PathBuf};
+
+use yagbe::gameboy::{GameBoy, SCREEN_WIDTH};
+
+const DEFAULT_FRAMES: u64 = 300;
+
+/// The shades of the screen, from lightest to darkest, as grays.
+const SHADES: [u8; 4] = [0xff, 0xaa, 0x55, 0x00];
+
+fn main() -> Result<(), String> {
+    let mut args = std::env::args().skip(1);
+
+    let usage = "Usage: scanlines <ROM> <OUTPUT DIRECTORY> [FRAMES]";
+    let rom_path = args.next().ok_or(usage)?;
+    let output_dir = PathBuf::from(args.next().ok_or(usage)?);
+    let frames = match args.next() {
+        Some(frames) => frames.parse().map_err(|_| format!("Invalid frame count: {frames}"))?,
+        None => DEFAULT_FRAMES,
+    };
+
+    let rom = fs::read(&rom_path).map_err(|e| format!("Could not read ROM file: {e}"))?;
+
+    fs::create_dir_all(&output_dir).map_err(|e| format!("Could not create {}: {e}", output_dir.display()))?;
+
+    let mut gameboy = GameBoy::new();
+    gameboy.load(rom).map_err(|e| format!("Could not load ROM: {e}"))?;
+
+    for _ in 0..frames {
+        gameboy.run_frame();
+    }
+
+    let callback_dir = output_dir.clone();
+
+    gameboy.set_scanline_callback(Some(Box::new(move |line, pixels| {
+        let mut pgm = format!("P5\n{SCREEN_WIDTH} 1\n255\n").into_bytes();
+        pgm.extend(pixels.iter().map(|&shade| SHADES[shade as usize]));
+
+        let path = callback_dir.join(format!("line_{line:03}.pgm"));
+
+        // Callbacks can't fail, so report the error and go on with the next line.
+        if let Err(e) = fs::write(&path, pgm) {
+            eprintln!("Could not write {}: {e}", path.display());
+        }
+    })));
+
+    gameboy.run_frame();
+
+    println!("Wrote the lines of frame {} to {}", gameboy.frame_count(), output_dir.display());
+
+    Ok(())
+}
@@ -0,0 +1,57 @@
+//! Prints the first instructions a ROM runs, each disassembled at the address it ran from. Time spent halted isn't
+//! printed, and tracing ends if the CPU locks up on an invalid opcode.
+//!
+//! Usage: cargo run --example trace -- <ROM> [INSTRUCTIONS]
+
+use std::fs;
+
+use yagbe::gameboy::{DebugEvent, GameBoy};
+
+const DEFAULT_INSTRUCTIONS: usize = 1_000;
+
+fn main() -> Result<(), String> {
+    let mut args = std::env::args().skip(1);
+
+    let rom_path = args.next().ok_or("Usage: trace <ROM> [INSTRUCTIONS]")?;
+    let instructions = match args.next() {
+        Some(instructions) => instructions.parse().map_err(|_| format!("Invalid instruction count: {instructions}"))?,
+        None => DEFAULT_INSTRUCTIONS,
+    };
+
+    let rom = fs::read(&rom_path).map_err(|e| format!("Could not read ROM file: {e}"))?;
+
+    let mut gameboy = GameBoy::new();
+    gameboy.load(rom).map_err(|e| format!("Could not load ROM: {e}"))?;
+
+    let mut traced = 0;
+
+    while traced < instructions {
+        if gameboy.halted() {
+            gameboy.tick();
+
+            continue;
+        }
+
+        let pc = gameboy.pc();
+        let (instruction, len) = gameboy.disassemble(pc);
+
+        let bytes = gameboy.peek_range(pc, len as usize)
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        println!("{pc:04x}: {bytes:<8}  {instruction}");
+
+        gameboy.tick();
+        traced += 1;
+
+        if let Some(event @ DebugEvent::Locked { .. }) = gameboy.take_debug_event() {
+            println!("{event}");
+
+            break;
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,53 @@
+// Plays the interleaved stereo samples posted by main.js, outputting silence when they run out.
+
+// About 100 ms at 48 kHz. Older samples are dropped rather than letting latency grow.
+const MAX_QUEUED_SAMPLES = 48000 * 2 / 10;
+
+class AudioProcessor extends AudioWorkletProcessor {
+    constructor() {
+        super();
+
+        this.queue = [];
+        this.queued = 0;
+        // Position in the first chunk of the queue.
+        this.offset = 0;
+
+        this.port.onmessage = (event) => {
+            this.queue.push(event.data);
+            this.queued += event.data.length;
+
+            while (this.queued - this.offset > MAX_QUEUED_SAMPLES && this.queue.length > 1) {
+                this.queued -= this.queue.shift().length;
+                this.offset = 0;
+            }
+        };
+    }
+
+    process(inputs, outputs) {
+        const [left, right] = outputs[0];
+
+        for (let i = 0; i < left.length; i++) {
+            const chunk = this.queue[0];
+
+            if (!chunk) {
+                left[i] = 0;
+                right[i] = 0;
+                continue;
+            }
+
+            left[i] = chunk[this.offset];
+            right[i] = chunk[this.offset + 1];
+            this.offset += 2;
+
+            if (this.offset >= chunk.length) {
+                this.queue.shift();
+                this.queued -= chunk.length;
+                this.offset = 0;
+            }
+        }
+
+        return true;
+    }
+}
+
+registerProcessor('yagbe-audio', AudioProcessor);
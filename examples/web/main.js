@@ -0,0 +1,111 @@
+import init, { Emulator } from './pkg/yagbe_web.js';
+
+// Bits of each button, as in the core's `Buttons`.
+const KEYS = {
+    ArrowRight: 1 << 0,
+    ArrowLeft: 1 << 1,
+    ArrowUp: 1 << 2,
+    ArrowDown: 1 << 3,
+    KeyX: 1 << 4,
+    KeyZ: 1 << 5,
+    Backspace: 1 << 6,
+    Enter: 1 << 7,
+};
+
+// Frames longer than this, e.g. after switching tabs, are cut short instead of catching up.
+const MAX_FRAME_MS = 50;
+
+await init();
+
+const emulator = new Emulator();
+const canvas = document.getElementById('screen');
+const context = canvas.getContext('2d');
+const error = document.getElementById('error');
+
+// Larger than the samples of a frame at any refresh rate, so the buffer drains every frame.
+const samples = new Float32Array(8192);
+
+let audio = null;
+let buttons = 0;
+let running = false;
+let last = 0;
+
+async function startAudio() {
+    if (audio) {
+        return audio.context.resume();
+    }
+
+    const audioContext = new AudioContext({ sampleRate: Emulator.sample_rate() });
+    await audioContext.audioWorklet.addModule('audio-processor.js');
+
+    const node = new AudioWorkletNode(audioContext, 'yagbe-audio', { outputChannelCount: [2] });
+    node.connect(audioContext.destination);
+
+    audio = { context: audioContext, node };
+}
+
+function frame(now) {
+    if (emulator.run_for(Math.min(now - last, MAX_FRAME_MS))) {
+        context.putImageData(new ImageData(new Uint8ClampedArray(emulator.screen_rgba()), 160, 144), 0, 0);
+    }
+
+    last = now;
+
+    // Drained even without audio, so the buffer doesn't grow.
+    const count = emulator.fill_audio(samples);
+
+    if (audio && count > 0) {
+        audio.node.port.postMessage(samples.slice(0, count));
+    }
+
+    requestAnimationFrame(frame);
+}
+
+document.getElementById('rom').addEventListener('change', async (event) => {
+    const file = event.target.files[0];
+
+    if (!file) {
+        return;
+    }
+
+    try {
+        emulator.load(new Uint8Array(await file.arrayBuffer()));
+        error.textContent = '';
+    } catch (e) {
+        error.textContent = `Could not load ROM: ${e.message}`;
+        return;
+    }
+
+    // Browsers only allow audio to start after the user interacts with the page, which choosing a file is.
+    startAudio().catch((e) => console.warn('Audio is unavailable:', e));
+
+    if (!running) {
+        running = true;
+        last = performance.now();
+        requestAnimationFrame(frame);
+    }
+});
+
+function onKey(event, pressed) {
+    const bit = KEYS[event.code];
+
+    if (bit === undefined) {
+        return;
+    }
+
+    event.preventDefault();
+
+    buttons = pressed ? buttons | bit : buttons & ~bit;
+    emulator.set_buttons(buttons);
+
+    if (audio) {
+        audio.context.resume();
+    }
+}
+
+window.addEventListener('keydown', (event) => onKey(event, true));
+window.addEventListener('keyup', (event) => onKey(event, false));
+window.addEventListener('blur', () => {
+    buttons = 0;
+    emulator.set_buttons(buttons);
+});
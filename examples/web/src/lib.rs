@@ -0,0 +1,91 @@
+//! The core built for the browser, wrapped for JavaScript. See index.html and main.js for the page that drives it.
+//!
+//! Usage: wasm-pack build --target web examples/web, then serve examples/web over HTTP and open index.html.
+
+use wasm_bindgen::prelude::*;
+
+// The yagbe package also depends on the desktop frontend's SDL2 and Tao, which don't build for wasm32, so include the
+// core's source rather than the library.
+#[allow(dead_code)]
+#[path = "../../../src/gameboy/mod.rs"]
+mod gameboy;
+
+#[macro_use]
+extern crate bitflags;
+
+use std::time::Duration;
+use gameboy::{Buttons, GameBoy, RunUntil, SCREEN_HEIGHT, SCREEN_WIDTH};
+
+// The grey palette of the desktop frontend, as RGBA.
+const PALETTE: [[u8; 4]; 4] = [
+    [0xff, 0xff, 0xff, 0xff],
+    [0xc0, 0xc0, 0xc0, 0xff],
+    [0x40, 0x40, 0x40, 0xff],
+    [0, 0, 0, 0xff],
+];
+
+#[wasm_bindgen]
+pub struct Emulator {
+    gameboy: GameBoy,
+}
+
+impl Default for Emulator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[wasm_bindgen]
+impl Emulator {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Self {
+        Self {
+            gameboy: GameBoy::new(),
+        }
+    }
+
+    /// Loads a ROM from a `Uint8Array`, throwing if it can't be loaded.
+    pub fn load(&mut self, rom: &[u8]) -> Result<(), JsError> {
+        self.gameboy.load(rom.to_vec()).map_err(|e| JsError::new(&e.to_string()))
+    }
+
+    /// Runs until a frame is completed, or for as long as a frame takes while the LCD is off.
+    pub fn run_frame(&mut self) {
+        self.gameboy.run_frame();
+    }
+
+    /// Runs for `milliseconds` of emulated time, for pages paced by `requestAnimationFrame` at any refresh rate.
+    /// Returns whether a new frame is ready.
+    pub fn run_for(&mut self, milliseconds: f64) -> bool {
+        let budget = Duration::from_secs_f64(milliseconds.max(0.0) / 1000.0);
+
+        self.gameboy.run_for(budget, RunUntil::BudgetExhausted).frame_ready
+    }
+
+    /// The screen as 160x144 RGBA pixels, ready for `ImageData`.
+    pub fn screen_rgba(&self) -> Vec<u8> {
+        let mut rgba = Vec::with_capacity(SCREEN_WIDTH * SCREEN_HEIGHT * 4);
+
+        for &color in self.gameboy.screen().iter() {
+            rgba.extend_from_slice(&PALETTE[color as usize & 0b11]);
+        }
+
+        rgba
+    }
+
+    /// Sets the pressed buttons, laid out as in `Buttons`: Right, Left, Up, Down, A, B, Select and Start from bit 0.
+    pub fn set_buttons(&mut self, buttons: u8) {
+        self.gameboy.set_buttons(Buttons::from_bits_truncate(buttons));
+    }
+
+    /// Moves buffered audio into a `Float32Array` as interleaved stereo samples, zero-filling it if there aren't
+    /// enough. Returns the number of samples moved.
+    pub fn fill_audio(&mut self, out: &mut [f32]) -> usize {
+        self.gameboy.fill_audio(out)
+    }
+
+    /// The sample rate of `fill_audio`, which the page's `AudioContext` must run at.
+    pub fn sample_rate() -> u32 {
+        gameboy::apu::AUDIO_SAMPLE_RATE as u32
+    }
+}
@@ -0,0 +1,56 @@
+//! Prints the LY at which the window becomes visible, every time it does.
+//!
+//! Usage: cargo run --example window_ly -- <ROM> [FRAMES]
+
+use std::fs;
+
+use yagbe::gameboy::GameBoy;
+
+const DEFAULT_FRAMES: u64 = 600;
+
+fn main() -> Result<(), String> {
+    let mut args = std::env::args().skip(1);
+
+    let rom_path = args.next().ok_or("Usage: window_ly <ROM> [FRAMES]")?;
+    let frames = match args.next() {
+        Some(frames) => frames.parse().map_err(|_| format!("Invalid frame count: {frames}"))?,
+        None => DEFAULT_FRAMES,
+    };
+
+    let rom = fs::read(&rom_path).map_err(|e| format!("Could not read ROM file: {e}"))?;
+
+    let mut gameboy = GameBoy::new();
+    gameboy.load(rom).map_err(|e| format!("Could not load ROM: {e}"))?;
+
+    let mut frame = 0;
+    let mut window_visible = false;
+
+    gameboy.set_hblank_callback(Some(Box::new(move |gameboy: &GameBoy, line| {
+        if line == 0 {
+            frame += 1;
+            window_visible = false;
+        }
+
+        let lcdc = gameboy.peek(0xff40);
+        let wy = gameboy.peek(0xff4a);
+        let wx = gameboy.peek(0xff4b);
+
+        let visible = lcdc & (1 << 5) != 0 && wx < 167 && line >= wy;
+
+        if visible && !window_visible {
+            println!("frame {frame}: window activated at LY {line}");
+        }
+
+        window_visible = visible;
+    })));
+
+    let mut completed_frames = 0;
+
+    while completed_frames < frames {
+        if gameboy.tick() {
+            completed_frames += 1;
+        }
+    }
+
+    Ok(())
+}
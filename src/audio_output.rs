@@ -0,0 +1,256 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+use sdl2::{
+    audio::{AudioCallback, AudioDevice, AudioSpecDesired, AudioStatus},
+    AudioSubsystem,
+};
+use crate::{
+    emulation::AudioQueue,
+    recorder::AudioTap,
+};
+
+// How often opening the default device is tried again while there is none.
+const RETRY_INTERVAL: Duration = Duration::from_secs(1);
+
+// How long the message saying audio is back stays on screen.
+const RESTORED_MESSAGE_DURATION: Duration = Duration::from_secs(3);
+
+#[derive(Clone)]
+pub(crate) struct Callback {
+    pub audio: AudioQueue,
+    /// Audio of the second game of a split view, mixed in.
+    pub linked_audio: Option<AudioQueue>,
+    pub volume: f32,
+    /// Plays silence while set, still taking samples from the queues so that emulation keeps its pace.
+    pub muted: Arc<AtomicBool>,
+    pub paused: Arc<AtomicBool>,
+    pub audio_tap: AudioTap,
+}
+
+impl AudioCallback for Callback {
+    type Channel = f32;
+
+    fn callback(&mut self, buffer: &mut [Self::Channel]) {
+        if self.paused.load(Ordering::Relaxed) {
+            buffer.fill(0.0);
+            return;
+        }
+
+        let volume = if self.muted.load(Ordering::Relaxed) { 0.0 } else { self.volume };
+
+        let count = {
+            let mut audio = self.audio.lock().unwrap();
+            let count = buffer.len().min(audio.len());
+
+            for (out, sample) in buffer.iter_mut().zip(audio.drain(..count)) {
+                *out = sample * volume;
+            }
+
+            count
+        };
+
+        // The emulation thread fell behind, so there is nothing to play for the rest of the buffer.
+        buffer[count..].fill(0.0);
+
+        if let Some(linked_audio) = &self.linked_audio {
+            let mut audio = linked_audio.lock().unwrap();
+            let count = buffer.len().min(audio.len());
+
+            for (out, sample) in buffer.iter_mut().zip(audio.drain(..count)) {
+                *out += sample * volume;
+            }
+        }
+
+        if let Some(tap) = self.audio_tap.lock().unwrap().as_ref() {
+            let _ = tap.send(buffer.to_vec());
+        }
+    }
+}
+
+/// A change of the audio device, which emulation has to follow, see `Emulator::set_audio_output`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum DeviceChange {
+    /// The device was lost, so nothing takes samples out of the queue.
+    Lost,
+    /// A device was opened again after being lost.
+    Restored,
+}
+
+/// What `AudioOutput::poll` does to the device next.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Step {
+    Keep,
+    Close,
+    Open,
+}
+
+/// When the device is lost and when to try opening one again, apart from SDL so that it can be driven by made-up
+/// statuses.
+#[derive(Default)]
+struct Reconnect {
+    /// When to next try opening a device, while there is none.
+    next_attempt: Option<Instant>,
+}
+
+impl Reconnect {
+    /// Decides what to do about a device with `status`, or no device if `None`.
+    fn step(&mut self, status: Option<AudioStatus>, now: Instant) -> Step {
+        match status {
+            // A lost device stays open, but SDL reports it stopped and no longer calls its callback.
+            Some(AudioStatus::Stopped) => {
+                self.next_attempt = Some(now);
+
+                Step::Close
+            }
+            Some(_) => Step::Keep,
+            None if self.next_attempt.is_some_and(|attempt| now >= attempt) => {
+                self.next_attempt = Some(now + RETRY_INTERVAL);
+
+                Step::Open
+            }
+            None => Step::Keep,
+        }
+    }
+
+    /// Records that a device was opened.
+    fn opened(&mut self) {
+        self.next_attempt = None;
+    }
+}
+
+/// The audio device, opened again on the default device when it is lost, such as when a headset is unplugged.
+///
+/// SDL events aren't pumped, since the window belongs to tao, so the device's status is polled once per host frame
+/// instead of waiting for SDL_AUDIODEVICEREMOVED.
+pub(crate) struct AudioOutput {
+    subsystem: AudioSubsystem,
+    spec: AudioSpecDesired,
+    callback: Callback,
+    device: Option<AudioDevice<Callback>>,
+    /// Whether the device should be playing, as set by `pause` and `resume`, for a device opened again.
+    playing: bool,
+    reconnect: Reconnect,
+    restored_at: Option<Instant>,
+}
+
+impl AudioOutput {
+    /// Opens `device`, or the default device if `None`, paused.
+    pub fn open(subsystem: AudioSubsystem, device: Option<&str>, spec: AudioSpecDesired, callback: Callback) -> Result<Self, String> {
+        let opened = subsystem.open_playback(device, &spec, |_spec| callback.clone())?;
+
+        Ok(Self {
+            subsystem,
+            spec,
+            callback,
+            device: Some(opened),
+            playing: false,
+            reconnect: Reconnect::default(),
+            restored_at: None,
+        })
+    }
+
+    pub fn pause(&mut self) {
+        self.playing = false;
+
+        if let Some(device) = &self.device {
+            device.pause();
+        }
+    }
+
+    pub fn resume(&mut self) {
+        self.playing = true;
+
+        if let Some(device) = self.device.as_ref().filter(|device| device.status() != AudioStatus::Playing) {
+            device.resume();
+        }
+    }
+
+    /// Closes the device if it was lost, or tries opening the default device while there is none. Returns the change,
+    /// if any.
+    pub fn poll(&mut self) -> Option<DeviceChange> {
+        let now = Instant::now();
+
+        match self.reconnect.step(self.device.as_ref().map(AudioDevice::status), now) {
+            Step::Keep => None,
+            Step::Close => {
+                self.device = None;
+                self.restored_at = None;
+
+                Some(DeviceChange::Lost)
+            }
+            Step::Open => {
+                let callback = self.callback.clone();
+                let device = self.subsystem.open_playback(None, &self.spec, |_spec| callback).ok()?;
+
+                if self.playing {
+                    device.resume();
+                }
+
+                self.device = Some(device);
+                self.reconnect.opened();
+                self.restored_at = Some(now);
+
+                Some(DeviceChange::Restored)
+            }
+        }
+    }
+
+    /// A message to show on screen while there is no device, and for a moment after one was opened again.
+    pub fn status_line(&self) -> Option<&'static str> {
+        match self.device {
+            None => Some("Audio device lost, reconnecting"),
+            Some(_) if self.restored_at.is_some_and(|at| at.elapsed() < RESTORED_MESSAGE_DURATION) => Some("Audio device reconnected"),
+            Some(_) => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn playing_and_paused_devices_are_kept() {
+        let mut reconnect = Reconnect::default();
+        let now = Instant::now();
+
+        assert_eq!(reconnect.step(Some(AudioStatus::Playing), now), Step::Keep);
+        assert_eq!(reconnect.step(Some(AudioStatus::Paused), now), Step::Keep);
+        // Without a device that was lost, none is opened.
+        assert_eq!(reconnect.step(None, now), Step::Keep);
+    }
+
+    #[test]
+    fn lost_devices_are_closed_and_opened_again_right_away() {
+        let mut reconnect = Reconnect::default();
+        let now = Instant::now();
+
+        assert_eq!(reconnect.step(Some(AudioStatus::Stopped), now), Step::Close);
+        assert_eq!(reconnect.step(None, now), Step::Open);
+
+        reconnect.opened();
+
+        assert_eq!(reconnect.step(Some(AudioStatus::Playing), now), Step::Keep);
+        assert_eq!(reconnect.step(None, now + RETRY_INTERVAL), Step::Keep);
+    }
+
+    #[test]
+    fn opening_is_retried_every_interval_until_it_succeeds() {
+        let mut reconnect = Reconnect::default();
+        let now = Instant::now();
+
+        reconnect.step(Some(AudioStatus::Stopped), now);
+        assert_eq!(reconnect.step(None, now), Step::Open);
+
+        // Opening failed, so `opened` isn't called.
+        assert_eq!(reconnect.step(None, now + RETRY_INTERVAL / 2), Step::Keep);
+        assert_eq!(reconnect.step(None, now + RETRY_INTERVAL), Step::Open);
+        assert_eq!(reconnect.step(None, now + RETRY_INTERVAL * 3 / 2), Step::Keep);
+        assert_eq!(reconnect.step(None, now + RETRY_INTERVAL * 2), Step::Open);
+    }
+}
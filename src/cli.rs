@@ -0,0 +1,247 @@
+use std::{
+    fs::File,
+    io::BufWriter,
+    path::PathBuf,
+};
+use sdl2::pixels::Color;
+use crate::{
+    config,
+    gameboy::{Accuracy, GameBoy},
+    palette,
+};
+
+pub(crate) const USAGE: &str = "\
+Usage: yagbe [ROM] [OPTIONS]
+
+Options:
+  --scale <N>          Window scale factor, 1 to 8
+  --palette <NAME>     Screen palette: grey, dmg-green
+  --volume <0-100>     Audio volume in percent
+  --mute               Disable audio output
+  --accuracy <LIST>    Comma-separated accuracy features to enable on top of the accuracy profile: oam-bug,
+                       stat-write-bug, dmg-prohibited-area, dmg-wave-ram, dot-accurate-ppu, or none, and the
+                       compatibility flag batched-timing for the timing of older versions
+  --link <ROM>         Run a second game next to the first, connected to it by a link cable. The keyboard controls
+                       the first game, and a game controller the second
+  --trace <FILE>       Write the CPU registers and disassembly of every instruction to FILE
+  --log-events         Print interrupt, LCD register, DMA and timer events to stderr
+  --frames <N>         Exit after N emulated frames
+  --headless           Run without a window or audio, requires a ROM and --frames, --script or --timeout-frames
+  --script <FILE>      Run the commands in FILE at the start of the frames they are for, in headless mode. Every line
+                       is like \"frame 60: press A\", with one of press BUTTON, release BUTTON, screenshot FILE,
+                       assert-hash HASH or poke ADDR=VALUE. Without --frames, runs until the last command. Exits with
+                       an error if an assertion fails
+  --exit-on-serial     In headless mode, print what the game sends over the serial port, and exit as soon as it sends
+                       the pass or fail pattern, with an error if it failed. Blargg's test ROMs report that way
+  --pass-pattern <TEXT>
+                       The text --exit-on-serial passes on, \"Passed\" by default
+  --fail-pattern <TEXT>
+                       The text --exit-on-serial fails on, \"Failed\" by default
+  --timeout-frames <N> With --exit-on-serial, fail if neither pattern was sent after N emulated frames
+  --screenshot <FILE>  Save the screen as a PNG on exit, however the run ended; in headless mode without it, the screen
+                       hash is printed, unless running a script or exiting on serial output
+  --frame-blend <PCT>  Mix PCT percent of the previous frame into every frame, on screen and in screenshots. The
+                       screen hash is of the unblended frame
+  --streamer-mode      Hide the menu bar, window borders and overlays, for capturing the window. ROMs are opened by
+                       dropping them on the window or with Ctrl+O, and Ctrl+Shift+S leaves the mode
+  --portable           Keep saves, save states and recordings next to the executable, like the config file, whatever
+                       the save_dir and state_dir settings are
+  --help               Print this message
+
+Scale, palette, volume, accuracy, frame blending and streamer mode default to the values saved in yagbe.cfg, and are not saved when given here.";
+
+pub(crate) struct Options {
+    pub rom_path: Option<PathBuf>,
+    pub scale: Option<u32>,
+    pub palette: Option<[Color; 4]>,
+    pub volume: Option<f32>,
+    pub accuracy: Option<Accuracy>,
+    /// The game shown next to `rom_path`, see `--link`.
+    pub link_rom_path: Option<PathBuf>,
+    pub trace_path: Option<PathBuf>,
+    pub log_events: bool,
+    pub frames: Option<u64>,
+    pub headless: bool,
+    pub script_path: Option<PathBuf>,
+    pub screenshot_path: Option<PathBuf>,
+    /// The patterns to watch the serial output for, see `--exit-on-serial`.
+    pub exit_on_serial: Option<SerialPatterns>,
+    pub timeout_frames: Option<u64>,
+    /// Percent of the previous frame mixed into every frame, see `--frame-blend`.
+    pub frame_blend: Option<u8>,
+    pub streamer_mode: bool,
+    /// Keep every file next to the executable, see `Paths`.
+    pub portable: bool,
+    pub help: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            rom_path: None,
+            scale: None,
+            palette: None,
+            volume: None,
+            accuracy: None,
+            link_rom_path: None,
+            trace_path: None,
+            log_events: false,
+            frames: None,
+            headless: false,
+            script_path: None,
+            screenshot_path: None,
+            exit_on_serial: None,
+            timeout_frames: None,
+            frame_blend: None,
+            streamer_mode: false,
+            portable: false,
+            help: false,
+        }
+    }
+}
+
+/// The text a test ROM sends over the serial port when it passed, and when it failed.
+pub(crate) struct SerialPatterns {
+    pub pass: String,
+    pub fail: String,
+}
+
+impl Default for SerialPatterns {
+    fn default() -> Self {
+        Self {
+            pass: "Passed".to_string(),
+            fail: "Failed".to_string(),
+        }
+    }
+}
+
+pub(crate) fn parse(args: impl IntoIterator<Item = String>) -> Result<Options, String> {
+    let mut options = Options::default();
+    let mut args = args.into_iter();
+    // The patterns can be given before or after --exit-on-serial.
+    let mut exit_on_serial = false;
+    let mut serial_patterns = SerialPatterns::default();
+    let mut serial_patterns_given = false;
+
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| format!("Missing value for {arg}"));
+
+        match arg.as_str() {
+            "--scale" => {
+                options.scale = match value()?.parse() {
+                    Ok(scale @ 1..=8) => Some(scale),
+                    _ => return Err("--scale must be a number from 1 to 8".to_string()),
+                }
+            }
+            "--palette" => {
+                let name = value()?;
+
+                options.palette = Some(palette::by_name(&name).ok_or_else(|| format!("Unknown palette: {name}"))?);
+            }
+            "--volume" => {
+                options.volume = match value()?.parse::<u8>() {
+                    Ok(volume @ 0..=100) => Some(volume as f32 / 100.0),
+                    _ => return Err("--volume must be a number from 0 to 100".to_string()),
+                }
+            }
+            "--mute" => options.volume = Some(0.0),
+            "--accuracy" => {
+                let features = value()?;
+
+                options.accuracy = Some(config::parse_accuracy(&features).ok_or_else(|| format!("Invalid accuracy features: {features}"))?);
+            }
+            "--link" => options.link_rom_path = Some(value()?.into()),
+            "--trace" => options.trace_path = Some(value()?.into()),
+            "--log-events" => options.log_events = true,
+            "--frames" => {
+                let frames = value()?;
+
+                options.frames = Some(frames.parse().map_err(|_| format!("Invalid frame count: {frames}"))?);
+            }
+            "--headless" => options.headless = true,
+            "--script" => options.script_path = Some(value()?.into()),
+            "--screenshot" => options.screenshot_path = Some(value()?.into()),
+            "--exit-on-serial" => exit_on_serial = true,
+            "--pass-pattern" | "--fail-pattern" => {
+                let pattern = value()?;
+
+                if pattern.is_empty() {
+                    return Err(format!("{arg} must not be empty"));
+                }
+
+                match arg.as_str() {
+                    "--pass-pattern" => serial_patterns.pass = pattern,
+                    _ => serial_patterns.fail = pattern,
+                }
+
+                serial_patterns_given = true;
+            }
+            "--timeout-frames" => {
+                let frames = value()?;
+
+                options.timeout_frames = Some(frames.parse().map_err(|_| format!("Invalid frame count: {frames}"))?);
+            }
+            "--frame-blend" => {
+                options.frame_blend = match value()?.parse() {
+                    Ok(weight @ 0..=100) => Some(weight),
+                    _ => return Err("--frame-blend must be a number from 0 to 100".to_string()),
+                }
+            }
+            "--streamer-mode" => options.streamer_mode = true,
+            "--portable" => options.portable = true,
+            "--help" | "-h" => options.help = true,
+            _ if arg.starts_with('-') => return Err(format!("Unknown option: {arg}")),
+            _ if options.rom_path.is_some() => return Err(format!("Unexpected argument: {arg}")),
+            _ => options.rom_path = Some(arg.into()),
+        }
+    }
+
+    if exit_on_serial {
+        options.exit_on_serial = Some(serial_patterns);
+    } else if serial_patterns_given {
+        return Err("--pass-pattern and --fail-pattern require --exit-on-serial".to_string());
+    } else if options.timeout_frames.is_some() {
+        return Err("--timeout-frames requires --exit-on-serial".to_string());
+    }
+
+    if options.link_rom_path.is_some() {
+        if options.rom_path.is_none() {
+            return Err("--link requires a ROM for the first game".to_string());
+        }
+
+        if options.headless {
+            return Err("--link can't be used with --headless".to_string());
+        }
+    }
+
+    if options.headless {
+        if options.rom_path.is_none() {
+            return Err("--headless requires a ROM".to_string());
+        }
+
+        if options.frames.is_none() && options.script_path.is_none() && options.timeout_frames.is_none() {
+            return Err("--headless requires --frames, --script or --timeout-frames".to_string());
+        }
+    } else if options.script_path.is_some() {
+        return Err("--script requires --headless".to_string());
+    } else if options.exit_on_serial.is_some() {
+        return Err("--exit-on-serial requires --headless".to_string());
+    }
+
+    Ok(options)
+}
+
+impl Options {
+    /// Applies the debugging options to a `GameBoy`.
+    pub fn configure(&self, gameboy: &mut GameBoy) -> Result<(), String> {
+        if let Some(trace_path) = &self.trace_path {
+            let file = File::create(trace_path).map_err(|e| format!("Could not create trace file: {e}"))?;
+
+            gameboy.set_trace(Some(Box::new(BufWriter::new(file))));
+        }
+
+        gameboy.set_event_logging(self.log_events);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,620 @@
+use std::{
+    fmt::Write,
+    fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+use tao::keyboard::KeyCode;
+use crate::{
+    emulation::SyncMode,
+    filter::Filter,
+    gameboy::{Accuracy, AccuracyMode, Buttons, CartridgeHeader, Model},
+    input::{self, Control},
+    palette,
+    paths,
+    stretch::StretchMode,
+};
+
+const MAX_RECENT_ROMS: usize = 10;
+
+pub(crate) struct KeyBindings {
+    pub up: KeyCode,
+    pub down: KeyCode,
+    pub left: KeyCode,
+    pub right: KeyCode,
+    pub a: KeyCode,
+    pub b: KeyCode,
+    pub start: KeyCode,
+    pub select: KeyCode,
+    pub turbo_a: KeyCode,
+    pub turbo_b: KeyCode,
+}
+
+// The defaults keep buttons off Ctrl and Alt, which hotkeys are chorded with, see `KeyBindings::is_chord_modifier`.
+impl Default for KeyBindings {
+    fn default() -> Self {
+        Self {
+            up: KeyCode::ArrowUp,
+            down: KeyCode::ArrowDown,
+            left: KeyCode::ArrowLeft,
+            right: KeyCode::ArrowRight,
+            a: KeyCode::KeyX,
+            b: KeyCode::KeyZ,
+            start: KeyCode::Enter,
+            select: KeyCode::ShiftRight,
+            turbo_a: KeyCode::KeyS,
+            turbo_b: KeyCode::KeyA,
+        }
+    }
+}
+
+impl KeyBindings {
+    /// The defaults of earlier versions, with A and B on Alt and Ctrl, and Select on Tab.
+    pub fn classic() -> Self {
+        Self {
+            a: KeyCode::AltLeft,
+            b: KeyCode::ControlLeft,
+            select: KeyCode::Tab,
+            turbo_a: KeyCode::KeyX,
+            turbo_b: KeyCode::KeyZ,
+            ..Self::default()
+        }
+    }
+
+    /// The bindings of a preset, by its name in the config file.
+    pub fn by_preset(name: &str) -> Option<Self> {
+        match name {
+            "default" => Some(Self::default()),
+            "classic" => Some(Self::classic()),
+            _ => None,
+        }
+    }
+
+    /// Whether holding `key` makes the keys pressed with it hotkeys, see `Chords`. Ctrl, Alt and the Windows key
+    /// are, unless bound to a button, so that the classic bindings can still hold B while pressing others.
+    pub fn is_chord_modifier(&self, key: KeyCode) -> bool {
+        let modifier = matches!(
+            key,
+            KeyCode::ControlLeft | KeyCode::ControlRight | KeyCode::AltLeft | KeyCode::AltRight | KeyCode::SuperLeft | KeyCode::SuperRight
+        );
+
+        modifier && self.control_for_key(key).is_none()
+    }
+
+    pub fn control_for_key(&self, key: KeyCode) -> Option<Control> {
+        let control = match key {
+            _ if key == self.up => Control::Button(Buttons::Up),
+            _ if key == self.down => Control::Button(Buttons::Down),
+            _ if key == self.left => Control::Button(Buttons::Left),
+            _ if key == self.right => Control::Button(Buttons::Right),
+            _ if key == self.a => Control::Button(Buttons::A),
+            _ if key == self.b => Control::Button(Buttons::B),
+            _ if key == self.start => Control::Button(Buttons::Start),
+            _ if key == self.select => Control::Button(Buttons::Select),
+            _ if key == self.turbo_a => Control::Turbo(Buttons::A),
+            _ if key == self.turbo_b => Control::Turbo(Buttons::B),
+            _ => return None,
+        };
+
+        Some(control)
+    }
+}
+
+/// User settings, persisted as `key = value` lines in a file next to the executable.
+///
+/// Command-line options override these for a single run, but are never saved.
+pub(crate) struct Config {
+    pub scale: u32,
+    pub stretch_mode: StretchMode,
+    pub filter: Filter,
+    /// How much `filter` darkens the seams between pixels, in percent.
+    pub filter_intensity: u8,
+    /// Mix every frame with the previous one, imitating the ghosting of the LCD.
+    pub frame_blend: bool,
+    /// How much of the previous frame is mixed in, in percent.
+    pub frame_blend_weight: u8,
+    /// Name of the palette, as accepted by `palette::by_name`.
+    pub palette: String,
+    /// Volume in percent.
+    pub volume: u8,
+    pub key_bindings: KeyBindings,
+    /// Most recently opened first.
+    pub recent_roms: Vec<PathBuf>,
+    pub show_fps: bool,
+    /// Pause emulation and audio while the window doesn't have focus.
+    pub pause_when_inactive: bool,
+    /// Size of the audio buffer in samples per channel. Smaller buffers lower latency, but may crackle.
+    pub audio_latency: u16,
+    pub accuracy_profile: AccuracyProfile,
+    /// Accuracy features enabled on top of `accuracy_profile`.
+    pub accuracy: Accuracy,
+    /// Switch to the Fast accuracy profile when emulation can't keep up with the speed.
+    pub auto_fast_profile: bool,
+    /// How many times per second turbo buttons are pressed while held.
+    pub turbo_rate: u8,
+    /// Emulation speed in percent of real hardware.
+    pub speed: u16,
+    /// Mute audio above 150 % speed, rather than playing it faster and higher.
+    pub mute_fast_speeds: bool,
+    pub sync_mode: SyncMode,
+    /// Takes effect when a game is loaded.
+    pub model: ModelSelection,
+    /// Where saves go, in a folder per game, instead of next to the ROM. Relative to the executable, see `Paths`.
+    pub save_dir: Option<PathBuf>,
+    /// Where save states go, like `save_dir`.
+    pub state_dir: Option<PathBuf>,
+    /// Hide the menu bar, the window borders and all overlays, and keep the title as it is, for capturing the window.
+    pub streamer_mode: bool,
+    /// Where the window was when it was last closed, its outer top left corner in physical pixels of the desktop.
+    /// Moved onto a monitor if it isn't on one anymore, see `placement::clamp_to_monitors`.
+    pub window_position: Option<(i32, i32)>,
+    /// The size of a single screen in the window when it was last closed, in logical pixels. `scale` only sizes
+    /// windows opened before that.
+    pub window_size: Option<(u32, u32)>,
+    /// Whether the window was maximized, in which case the position and size are of the window before that.
+    pub window_maximized: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            scale: 2,
+            stretch_mode: StretchMode::default(),
+            filter: Filter::default(),
+            filter_intensity: 50,
+            frame_blend: false,
+            frame_blend_weight: 50,
+            palette: "grey".to_string(),
+            volume: 100,
+            key_bindings: KeyBindings::default(),
+            recent_roms: Vec::new(),
+            show_fps: false,
+            pause_when_inactive: false,
+            audio_latency: 1024,
+            accuracy_profile: AccuracyProfile::default(),
+            accuracy: Accuracy::default(),
+            auto_fast_profile: false,
+            turbo_rate: input::DEFAULT_TURBO_RATE,
+            speed: 100,
+            mute_fast_speeds: false,
+            sync_mode: SyncMode::default(),
+            model: ModelSelection::default(),
+            save_dir: None,
+            state_dir: None,
+            streamer_mode: false,
+            window_position: None,
+            window_size: None,
+            window_maximized: false,
+        }
+    }
+}
+
+impl Config {
+    fn path() -> Option<PathBuf> {
+        paths::config_file()
+    }
+
+    /// Loads the config file, or the defaults if there is none yet.
+    ///
+    /// Values that fail to parse keep their defaults, and their keys are returned so they can be reported.
+    pub fn load() -> (Self, Vec<String>) {
+        let mut config = Self::default();
+        let mut invalid_keys = Vec::new();
+
+        let Some(contents) = Self::path().and_then(|path| fs::read_to_string(path).ok()) else {
+            return (config, invalid_keys);
+        };
+
+        for line in contents.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (key, value) = line.split_once('=').unwrap_or((line, ""));
+            let key = key.trim();
+
+            if config.set(key, value.trim()).is_none() {
+                invalid_keys.push(key.to_string());
+            }
+        }
+
+        (config, invalid_keys)
+    }
+
+    fn set(&mut self, key: &str, value: &str) -> Option<()> {
+        let bindings = &mut self.key_bindings;
+
+        match key {
+            "scale" => self.scale = value.parse().ok().filter(|scale| (1..=8).contains(scale))?,
+            "stretch_mode" => self.stretch_mode = StretchMode::by_name(value)?,
+            "filter" => self.filter = Filter::by_name(value)?,
+            "filter_intensity" => self.filter_intensity = value.parse().ok().filter(|&intensity| intensity <= 100)?,
+            "frame_blend" => self.frame_blend = value.parse().ok()?,
+            "frame_blend_weight" => self.frame_blend_weight = value.parse().ok().filter(|&weight| weight <= 100)?,
+            "palette" => {
+                palette::by_name(value)?;
+
+                self.palette = value.to_string();
+            }
+            "volume" => self.volume = value.parse().ok().filter(|&volume| volume <= 100)?,
+            "show_fps" => self.show_fps = value.parse().ok()?,
+            "pause_when_inactive" => self.pause_when_inactive = value.parse().ok()?,
+            "audio_latency" => {
+                self.audio_latency = value.parse().ok().filter(|latency: &u16| (256..=8192).contains(latency) && latency.is_power_of_two())?
+            }
+            "accuracy_profile" => self.accuracy_profile = AccuracyProfile::by_name(value)?,
+            "accuracy" => self.accuracy = parse_accuracy(value)?,
+            "auto_fast_profile" => self.auto_fast_profile = value.parse().ok()?,
+            "turbo_rate" => self.turbo_rate = value.parse().ok().filter(|rate| (1..=30).contains(rate))?,
+            "speed" => self.speed = value.parse().ok().filter(|speed| (10..=1000).contains(speed))?,
+            "mute_fast_speeds" => self.mute_fast_speeds = value.parse().ok()?,
+            "sync_mode" => self.sync_mode = SyncMode::by_name(value)?,
+            "model" => self.model = ModelSelection::by_name(value)?,
+            // Empty to keep them next to the ROM.
+            "save_dir" => self.save_dir = (!value.is_empty()).then(|| value.into()),
+            "state_dir" => self.state_dir = (!value.is_empty()).then(|| value.into()),
+            "streamer_mode" => self.streamer_mode = value.parse().ok()?,
+            "window_position" => self.window_position = parse_pair(value)?,
+            "window_size" => self.window_size = parse_pair(value)?.filter(|&(width, height)| width > 0 && height > 0),
+            "window_maximized" => self.window_maximized = value.parse().ok()?,
+            "recent_rom" => {
+                if self.recent_roms.len() < MAX_RECENT_ROMS {
+                    self.recent_roms.push(value.into());
+                }
+            }
+            // Replaces every binding, so the key lines after it change single keys of the preset. Saving writes the
+            // keys alone.
+            "key_preset" => *bindings = KeyBindings::by_preset(value)?,
+            "key_up" => bindings.up = parse_key(value)?,
+            "key_down" => bindings.down = parse_key(value)?,
+            "key_left" => bindings.left = parse_key(value)?,
+            "key_right" => bindings.right = parse_key(value)?,
+            "key_a" => bindings.a = parse_key(value)?,
+            "key_b" => bindings.b = parse_key(value)?,
+            "key_start" => bindings.start = parse_key(value)?,
+            "key_select" => bindings.select = parse_key(value)?,
+            "key_turbo_a" => bindings.turbo_a = parse_key(value)?,
+            "key_turbo_b" => bindings.turbo_b = parse_key(value)?,
+            _ => return None,
+        }
+
+        Some(())
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        let path = Self::path().ok_or("Could not find the config file location")?;
+
+        let bindings = &self.key_bindings;
+
+        let mut contents = String::new();
+
+        // Writing to a String can't fail.
+        let _ = writeln!(contents, "scale = {}", self.scale);
+        let _ = writeln!(contents, "stretch_mode = {}", self.stretch_mode.name());
+        let _ = writeln!(contents, "filter = {}", self.filter.name());
+        let _ = writeln!(contents, "filter_intensity = {}", self.filter_intensity);
+        let _ = writeln!(contents, "frame_blend = {}", self.frame_blend);
+        let _ = writeln!(contents, "frame_blend_weight = {}", self.frame_blend_weight);
+        let _ = writeln!(contents, "palette = {}", self.palette);
+        let _ = writeln!(contents, "volume = {}", self.volume);
+        let _ = writeln!(contents, "show_fps = {}", self.show_fps);
+        let _ = writeln!(contents, "pause_when_inactive = {}", self.pause_when_inactive);
+        let _ = writeln!(contents, "audio_latency = {}", self.audio_latency);
+        let _ = writeln!(contents, "accuracy_profile = {}", self.accuracy_profile.name());
+        let _ = writeln!(contents, "accuracy = {}", format_accuracy(self.accuracy));
+        let _ = writeln!(contents, "auto_fast_profile = {}", self.auto_fast_profile);
+        let _ = writeln!(contents, "turbo_rate = {}", self.turbo_rate);
+        let _ = writeln!(contents, "speed = {}", self.speed);
+        let _ = writeln!(contents, "mute_fast_speeds = {}", self.mute_fast_speeds);
+        let _ = writeln!(contents, "sync_mode = {}", self.sync_mode.name());
+        let _ = writeln!(contents, "model = {}", self.model.name());
+        let _ = writeln!(contents, "save_dir = {}", self.save_dir.as_ref().map(|dir| dir.display().to_string()).unwrap_or_default());
+        let _ = writeln!(contents, "state_dir = {}", self.state_dir.as_ref().map(|dir| dir.display().to_string()).unwrap_or_default());
+        let _ = writeln!(contents, "streamer_mode = {}", self.streamer_mode);
+        let _ = writeln!(contents, "window_position = {}", self.window_position.map(|(x, y)| format!("{x}, {y}")).unwrap_or_default());
+        let _ = writeln!(contents, "window_size = {}", self.window_size.map(|(width, height)| format!("{width}, {height}")).unwrap_or_default());
+        let _ = writeln!(contents, "window_maximized = {}", self.window_maximized);
+
+        for (key, code) in [
+            ("key_up", bindings.up),
+            ("key_down", bindings.down),
+            ("key_left", bindings.left),
+            ("key_right", bindings.right),
+            ("key_a", bindings.a),
+            ("key_b", bindings.b),
+            ("key_start", bindings.start),
+            ("key_select", bindings.select),
+            ("key_turbo_a", bindings.turbo_a),
+            ("key_turbo_b", bindings.turbo_b),
+        ] {
+            let _ = writeln!(contents, "{key} = {code:?}");
+        }
+
+        for rom in &self.recent_roms {
+            let _ = writeln!(contents, "recent_rom = {}", rom.display());
+        }
+
+        fs::write(path, contents).map_err(|e| format!("Could not save config: {e}"))
+    }
+
+    pub fn add_recent_rom(&mut self, path: &Path) {
+        let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+
+        self.recent_roms.retain(|rom| *rom != path);
+        self.recent_roms.insert(0, path);
+        self.recent_roms.truncate(MAX_RECENT_ROMS);
+    }
+}
+
+/// Groups of accuracy features, from the fastest to the most accurate, which can be switched while a game runs.
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) enum AccuracyProfile {
+    /// No optional accuracy features.
+    #[default]
+    Fast,
+    /// The DMG behaviors that cost next to nothing: the STAT write bug, and reads from the prohibited area and wave
+    /// RAM as observed on a DMG.
+    Balanced,
+    /// Everything in `Balanced`, plus the OAM bug and drawing every line dot by dot.
+    Accurate,
+}
+
+impl AccuracyProfile {
+    pub const ALL: [Self; 3] = [Self::Fast, Self::Balanced, Self::Accurate];
+
+    pub fn by_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|profile| profile.name() == name)
+    }
+
+    /// The name used in the config file.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Fast => "fast",
+            Self::Balanced => "balanced",
+            Self::Accurate => "accurate",
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::Fast => "Fast",
+            Self::Balanced => "Balanced",
+            Self::Accurate => "Accurate",
+        }
+    }
+
+    /// The next profile, wrapping around from `Accurate` to `Fast`.
+    pub fn next(self) -> Self {
+        match self {
+            Self::Fast => Self::Balanced,
+            Self::Balanced => Self::Accurate,
+            Self::Accurate => Self::Fast,
+        }
+    }
+
+    /// Enables the features of the profile in `accuracy`, keeping those it already has.
+    pub fn apply(self, mut accuracy: Accuracy) -> Accuracy {
+        if self == Self::Fast {
+            return accuracy;
+        }
+
+        accuracy.stat_write_bug = true;
+        accuracy.prohibited_area = AccuracyMode::Dmg;
+        accuracy.wave_ram = AccuracyMode::Dmg;
+
+        if self == Self::Accurate {
+            accuracy.oam_bug = true;
+            accuracy.dot_accurate_ppu = true;
+        }
+
+        accuracy
+    }
+}
+
+/// The model games start on, chosen by the user or from the cartridge header.
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) enum ModelSelection {
+    /// A CGB for games with CGB features, a DMG otherwise.
+    Auto,
+    /// The default until CGB hardware is emulated, since games that find a CGB go on to use its features.
+    #[default]
+    Dmg,
+    Cgb,
+}
+
+impl ModelSelection {
+    pub const ALL: [Self; 3] = [Self::Auto, Self::Dmg, Self::Cgb];
+
+    pub fn by_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|selection| selection.name() == name)
+    }
+
+    /// The name used in the config file.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Auto => "auto",
+            Self::Dmg => "dmg",
+            Self::Cgb => "cgb",
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::Auto => "&Automatic",
+            Self::Dmg => "&DMG",
+            Self::Cgb => "&CGB",
+        }
+    }
+
+    /// The model to start the game with `header` on.
+    pub fn resolve(self, header: &CartridgeHeader) -> Model {
+        match self {
+            Self::Auto => Model::for_cartridge(header.cgb),
+            Self::Dmg => Model::Dmg,
+            Self::Cgb => Model::Cgb,
+        }
+    }
+}
+
+/// Parses a comma-separated list of accuracy features, where "none" or an empty list disables all of them.
+pub(crate) fn parse_accuracy(features: &str) -> Option<Accuracy> {
+    let mut accuracy = Accuracy::default();
+
+    for feature in features.split(',').map(str::trim) {
+        match feature {
+            "" | "none" => {}
+            "oam-bug" => accuracy.oam_bug = true,
+            "stat-write-bug" => accuracy.stat_write_bug = true,
+            "dmg-prohibited-area" => accuracy.prohibited_area = AccuracyMode::Dmg,
+            "dmg-wave-ram" => accuracy.wave_ram = AccuracyMode::Dmg,
+            "dot-accurate-ppu" => accuracy.dot_accurate_ppu = true,
+            "batched-timing" => accuracy.batched_timing = true,
+            _ => return None,
+        }
+    }
+
+    Some(accuracy)
+}
+
+fn format_accuracy(accuracy: Accuracy) -> String {
+    let mut features = Vec::new();
+
+    if accuracy.oam_bug {
+        features.push("oam-bug");
+    }
+
+    if accuracy.stat_write_bug {
+        features.push("stat-write-bug");
+    }
+
+    if accuracy.prohibited_area == AccuracyMode::Dmg {
+        features.push("dmg-prohibited-area");
+    }
+
+    if accuracy.wave_ram == AccuracyMode::Dmg {
+        features.push("dmg-wave-ram");
+    }
+
+    if accuracy.dot_accurate_ppu {
+        features.push("dot-accurate-ppu");
+    }
+
+    if accuracy.batched_timing {
+        features.push("batched-timing");
+    }
+
+    if features.is_empty() {
+        return "none".to_string();
+    }
+
+    features.join(",")
+}
+
+/// Parses a key saved by `save`, which uses the `KeyCode` variant names.
+/// Parses two comma-separated numbers, or nothing for `None`.
+fn parse_pair<T: FromStr>(value: &str) -> Option<Option<(T, T)>> {
+    if value.is_empty() {
+        return Some(None);
+    }
+
+    let (first, second) = value.split_once(',')?;
+
+    Some(Some((first.trim().parse().ok()?, second.trim().parse().ok()?)))
+}
+
+fn parse_key(name: &str) -> Option<KeyCode> {
+    let key = match name {
+        "AltLeft" => KeyCode::AltLeft,
+        "AltRight" => KeyCode::AltRight,
+        "ControlLeft" => KeyCode::ControlLeft,
+        "ControlRight" => KeyCode::ControlRight,
+        "ShiftLeft" => KeyCode::ShiftLeft,
+        "ShiftRight" => KeyCode::ShiftRight,
+        // Otherwise, fall back to tao's accelerator names, which lack the prefixes of letter and digit keys.
+        _ => {
+            let name = name.strip_prefix("Key")
+                .or_else(|| name.strip_prefix("Digit"))
+                .unwrap_or(name);
+
+            KeyCode::from_str(name).ok()?
+        }
+    };
+
+    match key {
+        KeyCode::Unidentified(_) => None,
+        key => Some(key),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{gameboy::GameBoy, screenshot};
+    use super::*;
+
+    /// A game scrolling a striped background and a sprite by a pixel every frame, running in `profile`.
+    fn scrolling_game(profile: AccuracyProfile) -> GameBoy {
+        let mut program = vec![0; 0x100];
+        // LD A,0x93; LDH (0x40),A; then, at every VBlank, INC SCX: LDH A,(0x44); CP 0x90; JR NZ,-6; LDH A,(0x43);
+        // INC A; LDH (0x43),A; LDH A,(0x44); CP 0x90; JR Z,-6; JR -19
+        program.extend([
+            0x3e, 0x93, 0xe0, 0x40, 0xf0, 0x44, 0xfe, 0x90, 0x20, 0xfa, 0xf0, 0x43, 0x3c, 0xe0, 0x43, 0xf0, 0x44, 0xfe,
+            0x90, 0x28, 0xfa, 0x18, 0xed,
+        ]);
+
+        let mut gameboy = GameBoy::new();
+        gameboy.set_accuracy(profile.apply(Accuracy::default()));
+        gameboy.load_flat(program);
+
+        for row in 0..8 {
+            gameboy.poke(0x8000 + row * 2, 0x0f);
+            gameboy.poke(0x8001 + row * 2, 0x33);
+            gameboy.poke(0x8010 + row * 2, 0xff);
+        }
+
+        gameboy.poke(0xfe00, 60);
+        gameboy.poke(0xfe01, 40);
+        gameboy.poke(0xfe02, 1);
+        gameboy.poke(0xff47, 0xe4);
+        gameboy.poke(0xff48, 0xd2);
+
+        gameboy
+    }
+
+    fn frame_hashes(gameboy: &mut GameBoy, frames: usize) -> Vec<u64> {
+        (0..frames)
+            .map(|_| {
+                gameboy.run_frame();
+                screenshot::screen_hash(gameboy.screen())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn switching_profiles_draws_the_frames_a_game_started_in_the_profile_would() {
+        for from in AccuracyProfile::ALL {
+            for to in AccuracyProfile::ALL {
+                let mut switched = scrolling_game(from);
+                frame_hashes(&mut switched, 10);
+                switched.set_accuracy(to.apply(Accuracy::default()));
+
+                let mut started = scrolling_game(to);
+                frame_hashes(&mut started, 10);
+
+                let hashes = frame_hashes(&mut started, 5);
+                assert!(hashes.windows(2).all(|pair| pair[0] != pair[1]));
+
+                assert_eq!(frame_hashes(&mut switched, 5), hashes, "{} to {}", from.name(), to.name());
+            }
+        }
+    }
+
+    #[test]
+    fn profiles_keep_the_features_enabled_on_top_of_them() {
+        let accuracy = parse_accuracy("oam-bug").unwrap();
+
+        assert!(AccuracyProfile::Fast.apply(accuracy).oam_bug);
+        assert!(AccuracyProfile::Balanced.apply(accuracy).oam_bug);
+        assert!(!AccuracyProfile::Balanced.apply(Accuracy::default()).oam_bug);
+        assert!(AccuracyProfile::Balanced.apply(Accuracy::default()).stat_write_bug);
+    }
+}
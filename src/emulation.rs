@@ -0,0 +1,671 @@
+use std::{
+    collections::VecDeque,
+    fs,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc::{self, Receiver, Sender, SyncSender, TryRecvError},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+use crate::{
+    gameboy::{apu::AUDIO_SAMPLE_RATE, Accuracy, CoreError, DebugEvent, FrameEnd, GameBoy, LineStats, Model, FRAMES_PER_SECOND, SCREEN_HEIGHT, SCREEN_WIDTH},
+    input::{Control, Input, InputSnapshot},
+    overlay::{AudioSnapshot, OamSnapshot},
+    triple_buffer::{self, Reader, Writer},
+};
+
+// How long the emulation thread sleeps when enough audio is queued.
+const AUDIO_POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+// How often cartridge RAM is saved while running, so a crash loses little.
+const AUTOSAVE_INTERVAL: Duration = Duration::from_secs(30);
+
+// Interleaved stereo samples in a frame's worth of audio.
+const SAMPLES_PER_FRAME: usize = (AUDIO_SAMPLE_RATE as f64 / FRAMES_PER_SECOND) as usize * 2;
+
+// How far sync to video stretches or squeezes audio to keep the queue at its target, as a fraction of the speed.
+const MAX_RATE_ADJUSTMENT: f64 = 0.005;
+
+// Frames of audio the queue may hold beyond its target, should the audio device stop taking samples, before the
+// oldest are dropped. Audio sync keeps it at the target, but neither sync to video nor a higher speed waits for it.
+const MAX_FRAMES_OVER_TARGET: usize = 4;
+
+// Frames sync to video may fall behind before it gives up catching up, rather than running them all at once.
+const MAX_FRAMES_BEHIND: u32 = 3;
+
+/// What paces emulation, see `Emulator::set_sync_mode`.
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) enum SyncMode {
+    /// Runs frames whenever the audio queue falls below its target. Audio never drops out or gets resampled, but
+    /// frames are made in bursts as the audio device takes samples, so they can be presented unevenly.
+    #[default]
+    Audio,
+    /// Runs frames on a timer at the frame rate of the hardware, so they are made evenly. The audio device's clock
+    /// differs slightly from the timer, so audio is resampled by up to half a percent to keep the queue at its target,
+    /// which can't be heard.
+    Video,
+}
+
+impl SyncMode {
+    pub const ALL: [Self; 2] = [Self::Audio, Self::Video];
+
+    pub fn by_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|mode| mode.name() == name)
+    }
+
+    /// The name used in the config file.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Audio => "audio",
+            Self::Video => "video",
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::Audio => "Sync to &audio",
+            Self::Video => "Sync to &video",
+        }
+    }
+}
+
+/// Interleaved stereo samples made by the emulation thread, waiting for the audio callback.
+pub(crate) type AudioQueue = Arc<Mutex<VecDeque<f32>>>;
+
+/// What the emulation thread publishes after every frame.
+#[derive(Clone)]
+pub(crate) struct Frame {
+    pub screen: [u8; SCREEN_WIDTH * SCREEN_HEIGHT],
+    /// The screen of the frame before, for blending with `screen`.
+    pub previous_screen: [u8; SCREEN_WIDTH * SCREEN_HEIGHT],
+    /// Number of frames emulated so far, which tells a new frame apart from one that was already presented.
+    pub number: u64,
+    /// Whether a ROM is loaded. Until one is, the screen was never drawn.
+    pub loaded: bool,
+    pub audio_channel_levels: [[f32; 2]; 4],
+    /// Only captured while the OAM inspector is shown.
+    pub oam: Option<OamSnapshot>,
+    /// Only captured while the audio channel inspector is shown.
+    pub audio: Option<AudioSnapshot>,
+    /// Only captured while the line statistics are shown.
+    pub line_stats: Option<[LineStats; SCREEN_HEIGHT]>,
+    /// Only captured while the input viewer is shown.
+    pub input: Option<InputSnapshot>,
+}
+
+enum Command {
+    Load { rom: Vec<u8>, save_path: PathBuf, model: Model },
+    FlushSram,
+    SaveState(SyncSender<Option<Vec<u8>>>),
+    LoadState(Vec<u8>),
+    Pause,
+    Resume,
+    Press(Control),
+    Release(Control),
+    ReleaseAllButtons,
+    SetTurboRate(u8),
+    SetAccuracy(Accuracy),
+    SetSpeed { percent: u16, mute: bool },
+    SetSyncMode(SyncMode),
+    SetAudioOutput(bool),
+    InspectOam(bool),
+    InspectAudio(bool),
+    InspectLines(bool),
+    InspectInput(bool),
+    Quit,
+}
+
+/// A command, and where to acknowledge it once it was handled, with whether it succeeded.
+type Message = (Command, Option<SyncSender<Result<(), CoreError>>>);
+
+/// Where the RAM of the loaded game is saved.
+struct SaveFile {
+    path: PathBuf,
+    title_hash: Option<u64>,
+}
+
+impl SaveFile {
+    /// Writes the cartridge RAM if it changed since the last write.
+    fn flush(&self, gameboy: &mut GameBoy) {
+        let Some((title_hash, ram)) = gameboy.take_sram() else {
+            return;
+        };
+
+        // Never write one game's RAM into another's save.
+        if Some(title_hash) != self.title_hash {
+            return;
+        }
+
+        if let Err(e) = fs::write(&self.path, ram) {
+            eprintln!("Could not write save file: {e}");
+        }
+    }
+}
+
+/// How long a frame takes at `speed` percent of real hardware.
+pub(crate) fn frame_duration(speed: u16) -> Duration {
+    Duration::from_secs_f64(100.0 / (FRAMES_PER_SECOND * speed as f64))
+}
+
+/// The CPU locking up, and the diagnostic report written about it, see `Emulator::take_crash`.
+pub(crate) struct Crash {
+    pub event: DebugEvent,
+    /// Where the report was written, or why it couldn't be.
+    pub report: Result<PathBuf, String>,
+}
+
+/// Writes the diagnostic report of a crash next to the save, named after it, which tells apart the games of a split
+/// view.
+fn write_crash_report(gameboy: &GameBoy, save_path: &Path) -> Result<PathBuf, String> {
+    let path = save_path.with_extension("crash.txt");
+
+    fs::write(&path, gameboy.diagnostic_dump().to_string()).map_err(|e| format!("Could not write the report: {e}"))?;
+
+    Ok(path)
+}
+
+/// Runs a `GameBoy` on its own thread, which the frontend only talks to through commands.
+///
+/// Emulation is paced by audio output by default: the thread runs frames while less than `audio_target` samples are
+/// queued, and the audio callback takes them out of the queue at the sample rate. See `SyncMode` for the alternative.
+pub(crate) struct Emulator {
+    commands: Sender<Message>,
+    audio: AudioQueue,
+    /// Stereo samples dropped because audio wasn't played in time, by the core or from the queue.
+    audio_overruns: Arc<AtomicU64>,
+    /// Set when the CPU locks up, until taken.
+    crash: Arc<Mutex<Option<Crash>>>,
+    thread: JoinHandle<()>,
+}
+
+impl Emulator {
+    /// Moves `gameboy` to a new thread, paused until a ROM is loaded and `resume` is called.
+    ///
+    /// Returns the emulator, and the reader of the frames it completes.
+    pub fn start(gameboy: GameBoy, input: Input, audio: AudioQueue, audio_target: usize, emulated_frames: Arc<AtomicU64>) -> (Self, Reader<Frame>) {
+        let (frame_writer, frame_reader) = triple_buffer::triple_buffer(Frame {
+            screen: [0; SCREEN_WIDTH * SCREEN_HEIGHT],
+            previous_screen: [0; SCREEN_WIDTH * SCREEN_HEIGHT],
+            number: 0,
+            loaded: false,
+            audio_channel_levels: [[0.0; 2]; 4],
+            oam: None,
+            audio: None,
+            line_stats: None,
+            input: None,
+        });
+
+        let (commands, command_receiver) = mpsc::channel();
+
+        let audio_overruns = Arc::new(AtomicU64::new(0));
+        let crash = Arc::new(Mutex::new(None));
+
+        let thread_audio = audio.clone();
+        let thread_overruns = audio_overruns.clone();
+        let thread_crash = crash.clone();
+        let thread = thread::spawn(move || {
+            run(gameboy, input, command_receiver, frame_writer, thread_audio, audio_target, thread_overruns, thread_crash, emulated_frames)
+        });
+
+        let emulator = Self {
+            commands,
+            audio,
+            audio_overruns,
+            crash,
+            thread,
+        };
+
+        (emulator, frame_reader)
+    }
+
+    /// Loads a ROM, returning once it replaced the running one. The RAM of the running game is saved first, and the
+    /// RAM of the new one is restored from `save_path` if it exists. The game starts on `model`.
+    ///
+    /// If the ROM can't be loaded, the running game is left as it was.
+    pub fn load(&self, rom: Vec<u8>, save_path: PathBuf, model: Model) -> Result<(), CoreError> {
+        self.request(Command::Load { rom, save_path, model })
+    }
+
+    /// Saves the cartridge RAM if it changed, returning once it is written.
+    pub fn flush_sram(&self) {
+        let _ = self.request(Command::FlushSram);
+    }
+
+    /// Saves a state file of the running game between two frames, see `GameBoy::save_state`. Returns `None` if no
+    /// game is loaded.
+    pub fn save_state(&self) -> Option<Vec<u8>> {
+        let (state, saved) = mpsc::sync_channel(1);
+
+        self.send(Command::SaveState(state));
+
+        saved.recv().ok().flatten()
+    }
+
+    /// Restores a state file made by `save_state` between two frames, returning once the game runs from it. If it
+    /// can't be restored, the running game is left as it was.
+    pub fn load_state(&self, file: Vec<u8>) -> Result<(), CoreError> {
+        self.request(Command::LoadState(file))
+    }
+
+    /// Stops emulation, returning once the frame being emulated is complete.
+    pub fn pause(&self) {
+        let _ = self.request(Command::Pause);
+    }
+
+    /// Continues emulation, if a ROM is loaded.
+    pub fn resume(&self) {
+        self.send(Command::Resume);
+    }
+
+    /// Presses a control, which the game sees from the next frame on.
+    pub fn press(&self, control: Control) {
+        self.send(Command::Press(control));
+    }
+
+    pub fn release(&self, control: Control) {
+        self.send(Command::Release(control));
+    }
+
+    pub fn release_all_buttons(&self) {
+        self.send(Command::ReleaseAllButtons);
+    }
+
+    /// Sets how many times per second turbo buttons are pressed.
+    pub fn set_turbo_rate(&self, turbo_rate: u8) {
+        self.send(Command::SetTurboRate(turbo_rate));
+    }
+
+    /// Sets the accuracy features, which take effect from the next frame on, without restarting the game.
+    pub fn set_accuracy(&self, accuracy: Accuracy) {
+        self.send(Command::SetAccuracy(accuracy));
+    }
+
+    /// Sets the emulation speed in percent of real hardware. Audio plays faster and higher above 100 %, or is muted
+    /// above 150 % if `mute` is set, and slower and lower below.
+    pub fn set_speed(&self, percent: u16, mute: bool) {
+        self.send(Command::SetSpeed { percent, mute });
+    }
+
+    /// Sets what paces emulation, taking effect from the next frame on.
+    pub fn set_sync_mode(&self, mode: SyncMode) {
+        self.send(Command::SetSyncMode(mode));
+    }
+
+    /// Sets whether an audio device takes samples out of the queue. Without one, emulation is paced by the timer that
+    /// syncing to video uses, whatever the sync mode, and its audio is dropped, so it goes on while a lost device is
+    /// opened again.
+    pub fn set_audio_output(&self, available: bool) {
+        self.send(Command::SetAudioOutput(available));
+    }
+
+    /// Sets whether published frames include an `OamSnapshot`.
+    pub fn inspect_oam(&self, inspect: bool) {
+        self.send(Command::InspectOam(inspect));
+    }
+
+    /// Sets whether published frames include an `AudioSnapshot`. Each channel's output is only recorded while they do.
+    pub fn inspect_audio(&self, inspect: bool) {
+        self.send(Command::InspectAudio(inspect));
+    }
+
+    /// Sets whether published frames include the `LineStats` of every line.
+    pub fn inspect_lines(&self, inspect: bool) {
+        self.send(Command::InspectLines(inspect));
+    }
+
+    /// Sets whether published frames include an `InputSnapshot`.
+    pub fn inspect_input(&self, inspect: bool) {
+        self.send(Command::InspectInput(inspect));
+    }
+
+    /// Number of samples waiting for the audio callback.
+    pub fn queued_audio(&self) -> usize {
+        self.audio.lock().unwrap().len()
+    }
+
+    /// Number of stereo samples dropped since the emulator started, because the audio device didn't take them in time.
+    pub fn audio_overruns(&self) -> u64 {
+        self.audio_overruns.load(Ordering::Relaxed)
+    }
+
+    /// Returns the last time the CPU locked up since the last call, if it did. Emulation goes on, showing whatever the
+    /// game left on screen.
+    pub fn take_crash(&self) -> Option<Crash> {
+        self.crash.lock().unwrap().take()
+    }
+
+    /// Ends the emulation thread and waits for it. The cartridge RAM is saved first.
+    pub fn stop(self) {
+        let _ = self.request(Command::Quit);
+
+        let _ = self.thread.join();
+    }
+
+    fn send(&self, command: Command) {
+        // Only fails if the thread is gone, which leaves nothing to control.
+        let _ = self.commands.send((command, None));
+    }
+
+    fn request(&self, command: Command) -> Result<(), CoreError> {
+        let (ack, acknowledged) = mpsc::sync_channel(1);
+
+        if self.commands.send((command, Some(ack))).is_err() {
+            return Ok(());
+        }
+
+        acknowledged.recv().unwrap_or(Ok(()))
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn run(
+    mut gameboy: GameBoy,
+    mut input: Input,
+    commands: Receiver<Message>,
+    mut frames: Writer<Frame>,
+    audio: AudioQueue,
+    audio_target: usize,
+    audio_overruns: Arc<AtomicU64>,
+    crash: Arc<Mutex<Option<Crash>>>,
+    emulated_frames: Arc<AtomicU64>,
+) {
+    let mut loaded = false;
+    let mut running = false;
+    let mut inspect_oam = false;
+    let mut inspect_audio = false;
+    let mut inspect_lines = false;
+    let mut inspect_input = false;
+    let mut speed = 100;
+    let mut mute = false;
+    let mut sync_mode = SyncMode::default();
+    let mut audio_output = true;
+    // When the next frame is due, while syncing to video.
+    let mut next_frame = Instant::now();
+    let mut resampler = Resampler::new();
+    let mut samples = Vec::new();
+    let mut save_file: Option<SaveFile> = None;
+    let mut last_autosave = Instant::now();
+
+    loop {
+        // Wait for commands while there is nothing to run, otherwise handle all the pending ones first.
+        let message = if running {
+            match commands.try_recv() {
+                Ok(message) => Some(message),
+                Err(TryRecvError::Empty) => None,
+                Err(TryRecvError::Disconnected) => return,
+            }
+        } else {
+            match commands.recv() {
+                Ok(message) => Some(message),
+                Err(_) => return,
+            }
+        };
+
+        if let Some((command, ack)) = message {
+            let quit = matches!(command, Command::Quit);
+            let mut result = Ok(());
+
+            match command {
+                Command::Load { rom, save_path, model } => {
+                    if let Some(save_file) = &save_file {
+                        save_file.flush(&mut gameboy);
+                    }
+
+                    let previous_model = gameboy.model();
+
+                    gameboy.set_model(model);
+                    result = gameboy.load(rom);
+
+                    if result.is_err() {
+                        gameboy.set_model(previous_model);
+                    }
+
+                    if result.is_ok() {
+                        // Start the new game from a frame of silence, rather than the end of the previous one's audio.
+                        {
+                            let mut audio = audio.lock().unwrap();
+
+                            audio.clear();
+                            audio.extend(std::iter::repeat(0.0).take(SAMPLES_PER_FRAME));
+                        }
+
+                        // Loading resets the APU, which stops recording the channels.
+                        gameboy.set_audio_channel_taps(inspect_audio);
+
+                        // Replace the previous game's last frame with the blank screen of the new one.
+                        publish_frame(&mut frames, &gameboy, emulated_frames.load(Ordering::Relaxed), inspect_oam, inspect_audio, inspect_lines, inspect_input.then(|| input.snapshot()));
+
+                        if let Ok(data) = fs::read(&save_path) {
+                            if let Err(e) = gameboy.load_sram(&data) {
+                                eprintln!("{e}");
+                            }
+                        }
+
+                        save_file = Some(SaveFile {
+                            path: save_path,
+                            title_hash: gameboy.title_hash(),
+                        });
+                        last_autosave = Instant::now();
+
+                        loaded = true;
+                    }
+                }
+                Command::FlushSram | Command::Quit => {
+                    if let Some(save_file) = &save_file {
+                        save_file.flush(&mut gameboy);
+                    }
+                }
+                Command::SaveState(state) => {
+                    let _ = state.send(gameboy.save_state());
+                }
+                Command::LoadState(file) => {
+                    result = gameboy.load_state(&file);
+
+                    if result.is_ok() {
+                        // Show the restored screen right away, even while paused.
+                        publish_frame(&mut frames, &gameboy, emulated_frames.load(Ordering::Relaxed), inspect_oam, inspect_audio, inspect_lines, inspect_input.then(|| input.snapshot()));
+                    }
+                }
+                Command::Pause => running = false,
+                Command::Resume => {
+                    running = loaded;
+                    next_frame = Instant::now();
+                }
+                Command::Press(control) => input.press(control),
+                Command::Release(control) => input.release(control),
+                Command::ReleaseAllButtons => input.release_all(),
+                Command::SetTurboRate(turbo_rate) => input.set_turbo_rate(turbo_rate),
+                Command::SetAccuracy(accuracy) => gameboy.set_accuracy(accuracy),
+                Command::SetSpeed { percent, mute: mute_fast } => {
+                    speed = percent;
+                    mute = mute_fast && percent > 150;
+                    resampler = Resampler::new();
+                    next_frame = Instant::now();
+                }
+                Command::SetSyncMode(mode) => {
+                    sync_mode = mode;
+                    resampler = Resampler::new();
+                    next_frame = Instant::now();
+                }
+                Command::SetAudioOutput(available) => {
+                    audio_output = available;
+                    resampler = Resampler::new();
+                    next_frame = Instant::now();
+                }
+                Command::InspectOam(inspect) => inspect_oam = inspect,
+                Command::InspectLines(inspect) => inspect_lines = inspect,
+                Command::InspectInput(inspect) => inspect_input = inspect,
+                Command::InspectAudio(inspect) => {
+                    inspect_audio = inspect;
+
+                    gameboy.set_audio_channel_taps(inspect);
+                }
+            }
+
+            if let Some(ack) = ack {
+                let _ = ack.send(result);
+            }
+
+            if quit {
+                return;
+            }
+
+            continue;
+        }
+
+        let frame_duration = frame_duration(speed);
+
+        // The queue isn't drained without an audio device, so it can't pace emulation.
+        let pacing = if audio_output { sync_mode } else { SyncMode::Video };
+
+        match pacing {
+            SyncMode::Audio => {
+                if audio.lock().unwrap().len() >= audio_target {
+                    thread::sleep(AUDIO_POLL_INTERVAL);
+                    continue;
+                }
+            }
+            SyncMode::Video => {
+                let now = Instant::now();
+
+                // Sleep in short steps, so commands are still handled between frames.
+                if now < next_frame {
+                    thread::sleep((next_frame - now).min(AUDIO_POLL_INTERVAL));
+                    continue;
+                }
+
+                next_frame = if now - next_frame > frame_duration * MAX_FRAMES_BEHIND {
+                    now + frame_duration
+                } else {
+                    next_frame + frame_duration
+                };
+            }
+        }
+
+        // Presses handled above only take effect here, so each one lands on a frame boundary.
+        input.apply(&mut gameboy);
+
+        // Watchpoints and lock ups are only reported, and emulation goes on. With the LCD off, a frame's worth still
+        // runs, so audio keeps coming.
+        loop {
+            match gameboy.run_frame() {
+                Some(FrameEnd::Watchpoint(event)) => eprintln!("{event}"),
+                Some(FrameEnd::Locked(event)) => {
+                    eprintln!("{event}");
+
+                    let report = match &save_file {
+                        Some(save_file) => write_crash_report(&gameboy, &save_file.path),
+                        None => Err(String::from("No ROM is loaded")),
+                    };
+
+                    *crash.lock().unwrap() = Some(Crash { event, report });
+                }
+                _ => break,
+            }
+        }
+
+        for event in gameboy.drain_events() {
+            eprintln!("{event}");
+        }
+
+        samples.resize(gameboy.audio_buffer_size(), 0.0);
+        gameboy.fill_audio(&mut samples);
+
+        let mut overruns = gameboy.take_audio_overruns();
+
+        if audio_output {
+            let mut audio = audio.lock().unwrap();
+
+            match sync_mode {
+                // Emulation is paced by the audio queue, so resampling is also what makes it run at another speed.
+                SyncMode::Audio if speed == 100 => audio.extend(&samples),
+                SyncMode::Audio => resampler.resample(&samples, speed as f64 / 100.0, mute, &mut audio),
+                SyncMode::Video => {
+                    // Making fewer samples while the queue is above its target lets it drain, and more lets it fill.
+                    let fill = audio.len() as f64 / audio_target as f64;
+                    let adjustment = 1.0 + MAX_RATE_ADJUSTMENT * (fill - 1.0).clamp(-1.0, 1.0);
+
+                    resampler.resample(&samples, speed as f64 / 100.0 * adjustment, mute, &mut audio);
+                }
+            }
+
+            // Should the audio device stall, the oldest samples are dropped, rather than played late once it recovers.
+            let capacity = audio_target + SAMPLES_PER_FRAME * MAX_FRAMES_OVER_TARGET;
+
+            if audio.len() > capacity {
+                // Whole stereo samples, so the channels stay in order.
+                let excess = (audio.len() - capacity + 1) & !1;
+
+                audio.drain(..excess);
+                overruns += excess as u64 / 2;
+            }
+        }
+
+        audio_overruns.fetch_add(overruns, Ordering::Relaxed);
+
+        let number = emulated_frames.fetch_add(1, Ordering::Relaxed) + 1;
+        publish_frame(&mut frames, &gameboy, number, inspect_oam, inspect_audio, inspect_lines, inspect_input.then(|| input.snapshot()));
+
+        if last_autosave.elapsed() >= AUTOSAVE_INTERVAL {
+            last_autosave = Instant::now();
+
+            if let Some(save_file) = &save_file {
+                save_file.flush(&mut gameboy);
+            }
+        }
+    }
+}
+
+/// Plays audio emulated at another speed in real time, by stretching or squeezing it like a tape played at that
+/// speed.
+struct Resampler {
+    /// Position of the next output sample in the next input, in stereo frames. It carries over from one input to the
+    /// next, so that the output doesn't drift from the speed.
+    position: f64,
+}
+
+impl Resampler {
+    fn new() -> Self {
+        Self { position: 0.0 }
+    }
+
+    /// Resamples interleaved stereo `input` emulated at `speed`, where 1 is real time, into `out`, or queues as much
+    /// silence if `mute` is set.
+    fn resample(&mut self, input: &[f32], speed: f64, mute: bool, out: &mut VecDeque<f32>) {
+        let frames = input.len() / 2;
+
+        while self.position < frames as f64 {
+            let index = self.position as usize;
+            let next = (index + 1).min(frames - 1);
+            let fraction = (self.position - index as f64) as f32;
+
+            for channel in 0..2 {
+                let sample = input[index * 2 + channel] * (1.0 - fraction) + input[next * 2 + channel] * fraction;
+
+                out.push_back(if mute { 0.0 } else { sample });
+            }
+
+            self.position += speed;
+        }
+
+        self.position -= frames as f64;
+    }
+}
+
+fn publish_frame(frames: &mut Writer<Frame>, gameboy: &GameBoy, number: u64, inspect_oam: bool, inspect_audio: bool, inspect_lines: bool, input: Option<InputSnapshot>) {
+    let frame = frames.back();
+    frame.screen = *gameboy.screen();
+    frame.previous_screen = *gameboy.previous_screen();
+    frame.number = number;
+    frame.loaded = gameboy.is_loaded();
+    frame.audio_channel_levels = gameboy.audio_channel_levels();
+    frame.oam = inspect_oam.then(|| OamSnapshot::capture(gameboy));
+    frame.audio = inspect_audio.then(|| AudioSnapshot::capture(gameboy));
+    frame.line_stats = inspect_lines.then(|| *gameboy.line_stats());
+    frame.input = input;
+
+    frames.publish();
+}
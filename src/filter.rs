@@ -0,0 +1,136 @@
+use sdl2::{
+    pixels::PixelFormatEnum,
+    rect::Rect,
+    render::{BlendMode, Texture, TextureCreator, WindowCanvas},
+    video::WindowContext,
+};
+use crate::gameboy::{SCREEN_HEIGHT, SCREEN_WIDTH};
+
+/// Post-processing of the scaled screen, imitating the gaps between the pixels of the LCD.
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) enum Filter {
+    #[default]
+    None,
+    /// Darkens the bottom row of every line of pixels.
+    Scanlines,
+    /// Darkens the bottom row and right column of every pixel.
+    Grid,
+}
+
+impl Filter {
+    pub const ALL: [Self; 3] = [Self::None, Self::Scanlines, Self::Grid];
+
+    pub fn by_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|filter| filter.name() == name)
+    }
+
+    /// The name used in the config file.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::None => "none",
+            Self::Scanlines => "scanlines",
+            Self::Grid => "grid",
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::None => "&None",
+            Self::Scanlines => "&Scanlines",
+            Self::Grid => "&Grid",
+        }
+    }
+}
+
+/// A texture the size of the scaled screen that darkens it where `Filter` says, by being multiplied with it. It is
+/// only generated again when the filter, its intensity or the size of the screen change, so drawing it each frame
+/// costs a single copy.
+pub(crate) struct FilterOverlay<'a> {
+    texture: Option<Texture<'a>>,
+    filter: Filter,
+    intensity: u8,
+}
+
+impl<'a> FilterOverlay<'a> {
+    pub fn new() -> Self {
+        Self {
+            texture: None,
+            filter: Filter::None,
+            intensity: 0,
+        }
+    }
+
+    /// Darkens the screen drawn at `screen` with `filter`, by `intensity` percent at the seams between pixels.
+    pub fn render(
+        &mut self,
+        canvas: &mut WindowCanvas,
+        texture_creator: &'a TextureCreator<WindowContext>,
+        filter: Filter,
+        intensity: u8,
+        screen: Rect,
+    ) -> Result<(), String> {
+        if filter == Filter::None || intensity == 0 {
+            return Ok(());
+        }
+
+        let outdated = match &self.texture {
+            Some(texture) => {
+                let query = texture.query();
+
+                self.filter != filter || self.intensity != intensity || query.width != screen.width() || query.height != screen.height()
+            }
+            None => true,
+        };
+
+        if outdated {
+            self.texture = Some(generate(texture_creator, filter, intensity, screen.width(), screen.height())?);
+            self.filter = filter;
+            self.intensity = intensity;
+        }
+
+        if let Some(texture) = &self.texture {
+            canvas.copy(texture, None, Some(screen))?;
+        }
+
+        Ok(())
+    }
+}
+
+fn generate(texture_creator: &TextureCreator<WindowContext>, filter: Filter, intensity: u8, width: u32, height: u32) -> Result<Texture<'_>, String> {
+    let mut texture = texture_creator
+        .create_texture_streaming(PixelFormatEnum::RGB24, width, height)
+        .map_err(|e| e.to_string())?;
+
+    texture.set_blend_mode(BlendMode::Mod);
+
+    // A full intensity seam is darkened to a quarter, rather than black, so the picture stays readable.
+    let seam = 255 - (intensity.min(100) as u32 * 192 / 100) as u8;
+
+    let rows = (0..height).map(|y| is_seam(y, height, SCREEN_HEIGHT as u32)).collect::<Vec<_>>();
+    let columns = (0..width).map(|x| filter == Filter::Grid && is_seam(x, width, SCREEN_WIDTH as u32)).collect::<Vec<_>>();
+
+    texture.with_lock(None, |buffer: &mut [u8], pitch: usize| {
+        for (y, &seam_row) in rows.iter().enumerate() {
+            for (x, &seam_column) in columns.iter().enumerate() {
+                let value = if seam_row || seam_column { seam } else { 255 };
+                let offset = y * pitch + x * 3;
+
+                buffer[offset..offset + 3].fill(value);
+            }
+        }
+    })?;
+
+    Ok(texture)
+}
+
+/// Whether `position` of `size` output pixels, showing `count` Game Boy pixels, is the last of a pixel's. Pixels
+/// less than 2 output pixels wide have no seam, which would cover them entirely. The seams of a fractional scale
+/// follow the rounding of the scaled screen.
+fn is_seam(position: u32, size: u32, count: u32) -> bool {
+    let pixel = |position: u32| position as u64 * count as u64 / size as u64;
+
+    let last = position + 1 == size || pixel(position + 1) != pixel(position);
+    let wide = position > 0 && pixel(position - 1) == pixel(position);
+
+    last && wide
+}
@@ -1,16 +1,26 @@
-﻿use bitflags::Flags;
+﻿use std::collections::VecDeque;
+use bitflags::Flags;
 use super::{
     io_registers::IoRegisters,
+    state::state_fields,
     Mem,
 };
 
 const APU_FREQUENCY: usize = 1024 * 1024; // Hz
 
-pub(crate) const AUDIO_SAMPLE_RATE: usize = 48_000;
+/// Samples per second of each side of the stereo audio, see `GameBoy::fill_audio`.
+pub const AUDIO_SAMPLE_RATE: usize = 48_000;
 
-// NOTE: This value is actually more-or-less arbitrary. It just worked. Using half of it caused audio popping, using double caused frames to take too long.
-//  Using a value calculated based on expected frame rate resulted in roughly the same results.
-pub(crate) const AUDIO_BUFFER_SIZE: usize = 1024 * 2;
+// Interleaved stereo samples kept until the audio device takes them, twice the largest latency the config allows. Once
+// full, the oldest samples are dropped, so a stalled consumer can't make the buffer grow.
+const AUDIO_BUFFER_CAPACITY: usize = 8192 * 2 * 2;
+
+// Panning changes are ramped over this many output samples, since switching a channel's full-scale signal on or off
+// instantly is heard as a click.
+const PAN_RAMP_SAMPLES: f32 = 32.0;
+
+/// Output samples of each channel kept by `GameBoy::audio_channel_taps`, a few periods of a typical note.
+pub const CHANNEL_TAP_LEN: usize = 160;
 
 bitflags! {
     /// Sound panning
@@ -50,9 +60,59 @@ bitflags! {
     }
 }
 
+/// What a channel is currently set to play, see `Apu::channel_status`.
+#[derive(Clone, Copy)]
+pub struct ChannelStatus {
+    pub dac_enabled: bool,
+    /// The flag in NR52. A channel is only heard while both it and its DAC are on.
+    pub enabled: bool,
+    /// From 0 to 15. For the wave channel, the full volume lowered by its output level.
+    pub volume: u8,
+    /// In Hz: of the square or wave for channels 1-3, and of LFSR shifts for noise.
+    pub frequency: f32,
+    pub shape: ChannelShape,
+}
+
+#[derive(Clone, Copy)]
+pub enum ChannelShape {
+    /// Eighths of the period a square channel is high.
+    Duty(u8),
+    Wave,
+    /// Width of the noise channel's LFSR, 7 or 15 bits.
+    Lfsr(u8),
+}
+
+/// The 8 steps of each duty cycle of the square channels, the first in bit 7: 12.5 %, 25 %, 50 % and 75 %, the last
+/// being 25 % inverted.
+///
+/// https://gbdev.io/pandocs/Audio_Registers.html#ff11--nr11-channel-1-length-timer--duty-cycle
+const DUTY_WAVEFORMS: [u8; 4] = [0b0000_0001, 0b1000_0001, 0b1000_0111, 0b0111_1110];
+
+/// Whether step `step` of the duty cycle selected by NRx1 `length` is high.
+fn duty_step_high(length: u8, step: u8) -> bool {
+    DUTY_WAVEFORMS[(length >> 6) as usize] & (0x80 >> step) != 0
+}
+
+/// The APU ticks between LFSR clocks of the noise channel with `nr43`, or `None` for clock shifts 14 and 15, which
+/// don't clock it at all. The divisor codes divide the 4 MiHz clock by 8, 16, 32, 48 and so on, up to 112, which is
+/// then shifted left by the clock shift.
+///
+/// https://gbdev.io/pandocs/Audio_Registers.html#ff22--nr43-channel-4-frequency--randomness
+fn noise_period(nr43: u8) -> Option<usize> {
+    const DIVISORS: [usize; 8] = [8, 16, 32, 48, 64, 80, 96, 112];
+
+    let clock_shift = nr43 >> 4;
+    let divisor = DIVISORS[(nr43 & 0b0000_0111) as usize];
+
+    // The APU ticks once every 4 clocks.
+    (clock_shift < 14).then(|| (divisor << clock_shift) / 4)
+}
+
 pub struct Apu {
     accumulator: f32,
-    pub buffer: Vec<f32>,
+    pub buffer: VecDeque<f32>,
+    /// Stereo samples dropped from `buffer` because it was full, until taken by `take_overruns`.
+    overruns: u64,
     pub master_volume: f32,
     pub sample_rate: usize,
     div_prev: u8,
@@ -123,6 +183,8 @@ pub struct Apu {
     ch3_length_timer: u8,
     ch3_period_counter: u16,
     ch3_sample_counter: u8,
+    /// Whether channel 3 read a byte of wave RAM during the last M-cycle, see `dmg_wave_ram_index`.
+    ch3_wave_ram_read: bool,
     /// Channel 4 sweep
     pub nr41: u8,
     /// Channel 4 length timer & duty cycle
@@ -146,15 +208,22 @@ pub struct Apu {
     pub nr50: u8,
     pub nr51: SoundPanning,
     pub nr52: SoundEnable,
+    /// Current left and right gain of each channel, moving towards the panning set in NR51.
+    pan_gains: [[f32; 2]; 4],
     /// Wave pattern RAM
     pub wave_ram: [u8; 0x10],
+    /// The last `CHANNEL_TAP_LEN` output samples of each channel on its own, only recorded while taps are on.
+    channel_taps: Option<Box<[VecDeque<f32>; 4]>>,
+    /// Whether samples are mixed into `buffer`, see `set_audio_enabled`.
+    audio_enabled: bool,
 }
 
 impl Apu {
     pub fn new() -> Self {
         Self {
             accumulator: 0.0,
-            buffer: Vec::<f32>::with_capacity(AUDIO_BUFFER_SIZE),
+            buffer: VecDeque::with_capacity(AUDIO_BUFFER_CAPACITY),
+            overruns: 0,
             master_volume: 0.25,
             sample_rate: AUDIO_SAMPLE_RATE,
             div_prev: 0,
@@ -194,6 +263,7 @@ impl Apu {
             ch3_length_timer: 0xff,
             ch3_period_counter: 0x7ff,
             ch3_sample_counter: 0,
+            ch3_wave_ram_read: false,
             nr41: 0xff,
             nr42: 0x00,
             nr43: 0x00,
@@ -208,7 +278,10 @@ impl Apu {
             nr50: 0x77,
             nr51: SoundPanning::from_bits_retain(0xf3),
             nr52: SoundEnable::from_bits_retain(0xf1),
+            pan_gains: [[1.0, 1.0], [1.0, 1.0], [1.0, 0.0], [1.0, 0.0]], // Matches NR51
             wave_ram: [0; 0x10],
+            channel_taps: None,
+            audio_enabled: true,
         }
     }
 
@@ -242,6 +315,8 @@ impl Apu {
 
         // Wave output
         {
+            self.ch3_wave_ram_read = false;
+
             // Clocked at 2x APU_FREQUENCY
             for _ in 0..2 {
                 self.ch3_period_counter = (self.ch3_period_counter + 1) & 0x7ff;
@@ -251,29 +326,20 @@ impl Apu {
                     self.ch3_period_counter = period;
 
                     self.ch3_sample_counter = (self.ch3_sample_counter + 1) % 32;
+                    self.ch3_wave_ram_read = true;
                 }
             }
         }
 
         // Noise
-        {
-            let clock_shift = self.nr43 >> 4;
-            let lsfr_short_mode = self.nr43 & (1 << 3) == 1;
-            let clock_divider = self.nr43 & 0b0000_0111;
-
-            let tick_frequency_denominator = 1usize << clock_shift;
-            let tick_frequency_denominator = if clock_divider == 0 {
-                // 0 is treated as 0.5, so divide by 2.
-                tick_frequency_denominator as f32 * 0.5
-            } else {
-                clock_divider as f32 * tick_frequency_denominator as f32
-            };
+        if let Some(period) = noise_period(self.nr43) {
+            let lsfr_short_mode = self.nr43 & (1 << 3) != 0;
 
-            let tick_frequency = (262_144f32 / tick_frequency_denominator) as usize;
-            let tick_max_count = APU_FREQUENCY / tick_frequency;
+            // The period can be shortened below the count by writing NR43.
+            self.ch4_tick_counter += 1;
+            if self.ch4_tick_counter >= period {
+                self.ch4_tick_counter = 0;
 
-            self.ch4_tick_counter = (self.ch4_tick_counter + 1) % tick_max_count;
-            if self.ch4_tick_counter == 0 {
                 let next_bit = !((self.ch4_lsfr & 1) ^ ((self.ch4_lsfr >> 1) & 1)) & 1;
 
                 self.ch4_lsfr = self.ch4_lsfr & 0x7fff; // Turn off bit 15
@@ -289,112 +355,203 @@ impl Apu {
             }
         }
 
-        fn sample_to_volume(sample: u8) -> f32 {
-            ((0xf - sample) as f32 / 0xf as f32) * 2.0 - 1.0
-        }
-
         // Mixing
         let step = APU_FREQUENCY as f32 / self.sample_rate as f32;
         while self.accumulator > step {
-            // Channel 1
-            let ch1_dac_enabled = self.nr12 & 0xf8 != 0;
-            let ch1_sample = if ch1_dac_enabled && self.nr52.contains(SoundEnable::CH1_ENABLE) {
-                let wave_duty = match self.nr11 >> 6 {
-                    0 => 1, // 12.5% of 8 samples
-                    1 => 2, // 25% of 8 samples
-                    2 => 4, // 50% of 8 samples
-                    3 => 6, // 75% of 8 samples
-                    _ => unreachable!()
-                };
+            self.ramp_pan_gains();
 
-                let sample = if self.ch1_duty_counter < wave_duty {
-                    self.ch1_volume
-                } else {
-                    0
-                };
+            // Without audio, the panning still ramps and the samples are still counted, so that the state stays the
+            // same as with it.
+            if self.audio_enabled {
+                self.mix_sample();
+            }
 
-                sample
-            } else {
-                0
-            };
+            self.accumulator -= step;
+        }
 
-            // Channel 2
-            let ch2_dac_enabled = self.nr22 & 0xf8 != 0;
-            let ch2_sample = if ch2_dac_enabled && self.nr52.contains(SoundEnable::CH2_ENABLE) {
-                // Push one sample
-                let wave_duty = match self.nr21 >> 6 {
-                    0 => 1, // 12.5% of 8 samples
-                    1 => 2, // 25% of 8 samples
-                    2 => 4, // 50% of 8 samples
-                    3 => 6, // 75% of 8 samples
-                    _ => unreachable!()
-                };
+        self.accumulator += 1.0;
 
-                let sample = if self.ch2_duty_counter < wave_duty {
-                    self.ch2_volume
-                } else {
-                    0
-                };
+        self.div_prev = registers.div;
+    }
+
+    /// Mixes the output of the channels at this moment into a stereo sample in `buffer`, and records it in the taps.
+    fn mix_sample(&mut self) {
+        fn sample_to_volume(sample: u8) -> f32 {
+            ((0xf - sample) as f32 / 0xf as f32) * 2.0 - 1.0
+        }
 
-                sample
+        // Channel 1
+        let ch1_dac_enabled = self.nr12 & 0xf8 != 0;
+        let ch1_sample = if ch1_dac_enabled && self.nr52.contains(SoundEnable::CH1_ENABLE) && duty_step_high(self.nr11, self.ch1_duty_counter) {
+            self.ch1_volume
+        } else {
+            0
+        };
+
+        // Channel 2
+        let ch2_dac_enabled = self.nr22 & 0xf8 != 0;
+        let ch2_sample = if ch2_dac_enabled && self.nr52.contains(SoundEnable::CH2_ENABLE) && duty_step_high(self.nr21, self.ch2_duty_counter) {
+            self.ch2_volume
+        } else {
+            0
+        };
+
+        // Channel 3
+        let ch3_dac_enabled = self.nr30 & (1 << 7) != 0;
+        let ch3_sample = if ch3_dac_enabled && self.nr52.contains(SoundEnable::CH3_ENABLE) {
+            let wave_sample_pair = self.wave_ram[(self.ch3_sample_counter >> 1) as usize];
+            let wave_sample = if self.ch3_sample_counter % 2 == 0 {
+                wave_sample_pair >> 4
             } else {
-                0
+                wave_sample_pair & 0xf
             };
 
-            // Channel 3
-            let ch3_dac_enabled = self.nr30 & (1 << 7) != 0;
-            let ch3_sample = if ch3_dac_enabled && self.nr52.contains(SoundEnable::CH3_ENABLE) {
-                let wave_sample_pair = self.mem_read(0xff30 + (self.ch3_sample_counter >> 1) as u16);
-                let wave_sample = if self.ch3_sample_counter % 2 == 0 {
-                    wave_sample_pair >> 4
-                } else {
-                    wave_sample_pair & 0xf
-                };
+            let output_level = match (self.nr32 >> 5) & 0x3 {
+                0 => 0,
+                1 => wave_sample,
+                2 => wave_sample >> 1,
+                3 => wave_sample >> 2,
+                _ => unreachable!()
+            };
 
-                let output_level = match (self.nr32 >> 5) & 0x3 {
-                    0 => 0,
-                    1 => wave_sample,
-                    2 => wave_sample >> 1,
-                    3 => wave_sample >> 2,
-                    _ => unreachable!()
-                };
+            output_level
+        } else {
+            0
+        };
+
+        // Channel 4
+        let ch4_dac_enabled = self.nr42 & 0xf8 != 0;
+        let ch4_sample = if ch4_dac_enabled && self.nr52.contains(SoundEnable::CH4_ENABLE) && (self.ch4_lsfr & 1) != 0 {
+            self.ch4_volume
+        } else {
+            0
+        };
+
+        if let Some(taps) = &mut self.channel_taps {
+            for (tap, sample) in taps.iter_mut().zip([ch1_sample, ch2_sample, ch3_sample, ch4_sample]) {
+                if tap.len() == CHANNEL_TAP_LEN {
+                    tap.pop_front();
+                }
 
-                output_level
-            } else {
-                0
-            };
+                tap.push_back(sample_to_volume(sample));
+            }
+        }
 
-            // Channel 4
-            let ch4_dac_enabled = self.nr42 & 0xf8 != 0;
-            let ch4_sample = if ch4_dac_enabled && self.nr52.contains(SoundEnable::CH4_ENABLE) && (self.ch4_lsfr & 1) != 0 {
-                self.ch4_volume
-            } else {
-                0
-            };
+        let mut sample_left = 0.0;
+        let mut sample_right = 0.0;
+
+        for (&sample, [gain_left, gain_right]) in [ch1_sample, ch2_sample, ch3_sample, ch4_sample].iter().zip(self.pan_gains) {
+            sample_left += sample_to_volume(sample) * gain_left;
+            sample_right += sample_to_volume(sample) * gain_right;
+        }
 
-            let sample_left =
-                sample_to_volume(ch1_sample) * self.nr51.contains(SoundPanning::CH1_LEFT) as u8 as f32 +
-                    sample_to_volume(ch2_sample) * self.nr51.contains(SoundPanning::CH2_LEFT) as u8 as f32 +
-                    sample_to_volume(ch3_sample) * self.nr51.contains(SoundPanning::CH3_LEFT) as u8 as f32 +
-                    sample_to_volume(ch4_sample) * self.nr51.contains(SoundPanning::CH4_LEFT) as u8 as f32;
-            let sample_right =
-                sample_to_volume(ch1_sample) * self.nr51.contains(SoundPanning::CH1_RIGHT) as u8 as f32 +
-                    sample_to_volume(ch2_sample) * self.nr51.contains(SoundPanning::CH2_RIGHT) as u8 as f32 +
-                    sample_to_volume(ch3_sample) * self.nr51.contains(SoundPanning::CH3_RIGHT) as u8 as f32 +
-                    sample_to_volume(ch4_sample) * self.nr51.contains(SoundPanning::CH4_RIGHT) as u8 as f32;
+        let volume_left = (1 + ((self.nr50 >> 4) & 7)) as f32 * 0.125;
+        let volume_right = (1 + ((self.nr50 >> 0) & 7)) as f32 * 0.125;
 
-            let volume_left = (1 + ((self.nr50 >> 4) & 7)) as f32 * 0.125;
-            let volume_right = (1 + ((self.nr50 >> 0) & 7)) as f32 * 0.125;
+        if self.buffer.len() == AUDIO_BUFFER_CAPACITY {
+            self.buffer.drain(..2);
+            self.overruns += 1;
+        }
 
-            self.buffer.push(sample_left * volume_left * 0.25 * self.master_volume);
-            self.buffer.push(sample_right * volume_right * 0.25 * self.master_volume);
+        self.buffer.push_back(sample_left * volume_left * 0.25 * self.master_volume);
+        self.buffer.push_back(sample_right * volume_right * 0.25 * self.master_volume);
+    }
 
-            self.accumulator -= step;
+    fn ramp_pan_gains(&mut self) {
+        let bits = self.nr51.bits();
+
+        for (channel, gains) in self.pan_gains.iter_mut().enumerate() {
+            // Left panning bits are the upper nibble of NR51, right are the lower.
+            for (gain, bit) in gains.iter_mut().zip([channel + 4, channel]) {
+                let target = if bits & (1 << bit) != 0 { 1.0 } else { 0.0 };
+
+                *gain += (target - *gain).clamp(-1.0 / PAN_RAMP_SAMPLES, 1.0 / PAN_RAMP_SAMPLES);
+            }
         }
+    }
 
-        self.accumulator += 1.0;
+    /// Left and right output level of each channel from 0 to 1, combining its volume with its current panning.
+    pub fn channel_levels(&self) -> [[f32; 2]; 4] {
+        let mut levels = self.pan_gains;
 
-        self.div_prev = registers.div;
+        for (level, status) in levels.iter_mut().zip(self.channel_status()) {
+            let volume = if status.dac_enabled && status.enabled { status.volume } else { 0 };
+
+            for gain in level.iter_mut() {
+                *gain *= volume as f32 / 0xf as f32;
+            }
+        }
+
+        levels
+    }
+
+    pub fn channel_status(&self) -> [ChannelStatus; 4] {
+        // Square channels step through 8 duty steps per period, the wave channel through 32 samples at twice the rate.
+        let square_frequency = |high: u8, low: u8| 131_072.0 / (2048 - ((high as u16 & 0x7) << 8 | low as u16)) as f32;
+        let duty = |length: u8| DUTY_WAVEFORMS[(length >> 6) as usize].count_ones() as u8;
+
+        let ch3_volume = match (self.nr32 >> 5) & 0x3 {
+            0 => 0,
+            shift => 0xf >> (shift - 1),
+        };
+
+        [
+            ChannelStatus {
+                dac_enabled: self.nr12 & 0xf8 != 0,
+                enabled: self.nr52.contains(SoundEnable::CH1_ENABLE),
+                volume: self.ch1_volume,
+                frequency: square_frequency(self.nr14, self.nr13),
+                shape: ChannelShape::Duty(duty(self.nr11)),
+            },
+            ChannelStatus {
+                dac_enabled: self.nr22 & 0xf8 != 0,
+                enabled: self.nr52.contains(SoundEnable::CH2_ENABLE),
+                volume: self.ch2_volume,
+                frequency: square_frequency(self.nr24, self.nr23),
+                shape: ChannelShape::Duty(duty(self.nr21)),
+            },
+            ChannelStatus {
+                dac_enabled: self.nr30 & (1 << 7) != 0,
+                enabled: self.nr52.contains(SoundEnable::CH3_ENABLE),
+                volume: ch3_volume,
+                frequency: square_frequency(self.nr34, self.nr33) / 2.0,
+                shape: ChannelShape::Wave,
+            },
+            ChannelStatus {
+                dac_enabled: self.nr42 & 0xf8 != 0,
+                enabled: self.nr52.contains(SoundEnable::CH4_ENABLE),
+                volume: self.ch4_volume,
+                frequency: noise_period(self.nr43).map_or(0.0, |period| APU_FREQUENCY as f32 / period as f32),
+                shape: ChannelShape::Lfsr(if self.nr43 & (1 << 3) != 0 { 7 } else { 15 }),
+            },
+        ]
+    }
+
+    /// Starts or stops recording `channel_taps`. They are cleared when stopped, so they start out empty.
+    pub fn set_channel_taps(&mut self, enabled: bool) {
+        if enabled != self.channel_taps.is_some() {
+            self.channel_taps = enabled.then(|| Box::new(std::array::from_fn(|_| VecDeque::with_capacity(CHANNEL_TAP_LEN))));
+        }
+    }
+
+    /// Starts or stops mixing audio. Without it, the channels, their length timers and NR52 still run as usual, since
+    /// games can see them, but no samples are made, which takes a good part of the time emulation takes. Samples
+    /// buffered when it stops are dropped, and the taps stop filling until it starts again.
+    pub fn set_audio_enabled(&mut self, enabled: bool) {
+        if !enabled {
+            self.buffer.clear();
+        }
+
+        self.audio_enabled = enabled;
+    }
+
+    pub fn audio_enabled(&self) -> bool {
+        self.audio_enabled
+    }
+
+    /// The most recent output of each channel on its own from -1 to 1, oldest first, or `None` unless taps are on.
+    pub fn channel_taps(&self) -> Option<&[VecDeque<f32>; 4]> {
+        self.channel_taps.as_deref()
     }
 
     fn process(&mut self) {
@@ -510,8 +667,94 @@ impl Apu {
         }
     }
 
-    pub fn extract_audio_buffer(&mut self) -> Vec<f32> {
-        return std::mem::replace(&mut self.buffer, Vec::with_capacity(AUDIO_BUFFER_SIZE));
+    /// Where a CPU access to wave RAM at `addr` goes on DMG. While channel 3 plays, it goes to the byte the channel is
+    /// playing instead, but only in the M-cycle the channel read it, and nowhere otherwise: reads return 0xff, and
+    /// writes are lost.
+    pub fn dmg_wave_ram_index(&self, addr: u16) -> Option<usize> {
+        if !self.nr52.contains(SoundEnable::CH3_ENABLE) {
+            return Some((addr - 0xff30) as usize);
+        }
+
+        self.ch3_wave_ram_read.then_some((self.ch3_sample_counter >> 1) as usize)
+    }
+
+    /// Moves as many buffered samples as fit into `out`, zero-filling the rest. Returns the number of samples moved.
+    pub fn fill_audio(&mut self, out: &mut [f32]) -> usize {
+        let count = out.len().min(self.buffer.len());
+
+        for (out, sample) in out.iter_mut().zip(self.buffer.drain(..count)) {
+            *out = sample;
+        }
+
+        out[count..].fill(0.0);
+
+        count
+    }
+
+    /// Returns the number of stereo samples dropped because the buffer was full since the last call.
+    pub fn take_overruns(&mut self) -> u64 {
+        std::mem::take(&mut self.overruns)
+    }
+
+    /// Reads a register as stored, including write-only registers and bits, without masking.
+    pub fn mem_peek(&self, addr: u16) -> u8 {
+        // Bits that are unused or write-only read as ones, as do the write-only registers and the unused addresses
+        // 0xff15, 0xff1f and 0xff27-0xff2f.
+        match addr {
+            0xff10 => self.nr10 | 0b1000_0000,
+            0xff11 => self.nr11 & 0b1100_0000 | 0b0011_1111,
+            0xff12 => self.nr12,
+            0xff13 => 0xff,
+            0xff14 => self.nr14 & (1 << 6) | 0b1011_1111,
+            0xff16 => self.nr21 & 0b1100_0000 | 0b0011_1111,
+            0xff17 => self.nr22,
+            0xff18 => 0xff,
+            0xff19 => self.nr24 & (1 << 6) | 0b1011_1111,
+            0xff1a => self.nr30 | 0b0111_1111,
+            0xff1b => 0xff,
+            0xff1c => self.nr32 | 0b1001_1111,
+            0xff1d => 0xff,
+            0xff1e => self.nr34 & (1 << 6) | 0b1011_1111,
+            0xff20 => 0xff,
+            0xff21 => self.nr42,
+            0xff22 => self.nr43,
+            0xff23 => self.nr44 & (1 << 6) | 0b1011_1111,
+            0xff24 => self.nr50,
+            0xff25 => self.nr51.bits(),
+            0xff26 => self.nr52.bits() | 0b0111_0000,
+            0xff30..=0xff3f => self.wave_ram[(addr - 0xff30) as usize],
+            _ => 0xff,
+        }
+    }
+
+    /// Stores a value in a register without triggering channels or reloading length timers, so values read by
+    /// `mem_peek` can be written back as they were.
+    pub fn mem_poke(&mut self, addr: u16, value: u8) {
+        match addr {
+            0xff10 => self.nr10 = value,
+            0xff11 => self.nr11 = value,
+            0xff12 => self.nr12 = value,
+            0xff13 => self.nr13 = value,
+            0xff14 => self.nr14 = value,
+            0xff16 => self.nr21 = value,
+            0xff17 => self.nr22 = value,
+            0xff18 => self.nr23 = value,
+            0xff19 => self.nr24 = value,
+            0xff1a => self.nr30 = value,
+            0xff1b => self.nr31 = value,
+            0xff1c => self.nr32 = value,
+            0xff1d => self.nr33 = value,
+            0xff1e => self.nr34 = value,
+            0xff20 => self.nr41 = value,
+            0xff21 => self.nr42 = value,
+            0xff22 => self.nr43 = value,
+            0xff23 => self.nr44 = value,
+            0xff24 => self.nr50 = value,
+            0xff25 => self.nr51 = SoundPanning::from_bits_retain(value),
+            0xff26 => self.nr52 = SoundEnable::from_bits_truncate(value),
+            0xff30..=0xff3f => self.wave_ram[(addr - 0xff30) as usize] = value,
+            _ => {}
+        }
     }
 }
 
@@ -523,18 +766,19 @@ impl Mem for Apu {
             0xff10 => self.nr10,
             0xff11 => self.nr11 & 0b1100_0000,
             0xff12 => self.nr12,
-            0xff13 => panic!("cannot read nr13 register"),
+            // Write-only registers and unused addresses read as all ones.
+            0xff13 => 0xff,
             0xff14 => self.nr14 & (1 << 6),
             0xff16 => self.nr21 & 0b1100_0000,
             0xff17 => self.nr22,
-            0xff18 => panic!("cannot read nr23 register"),
+            0xff18 => 0xff,
             0xff19 => self.nr24 & (1 << 6),
             0xff1a => self.nr30,
-            0xff1b => panic!("cannot read nr31 register"),
+            0xff1b => 0xff,
             0xff1c => self.nr32,
-            0xff1d => panic!("cannot read nr33 register"),
+            0xff1d => 0xff,
             0xff1e => self.nr34 & (1 << 6),
-            0xff20 => panic!("cannot read nr41 register"),
+            0xff20 => 0xff,
             0xff21 => self.nr42,
             0xff22 => self.nr43,
             0xff23 => self.nr44 & (1 << 6),
@@ -542,7 +786,7 @@ impl Mem for Apu {
             0xff25 => self.nr51.bits(),
             0xff26 => self.nr52.bits(),
             0xff30..=0xff3f => self.wave_ram[(addr - 0xff30) as usize],
-            _ => unreachable!()
+            _ => 0xff,
         }
     }
 
@@ -637,7 +881,113 @@ impl Mem for Apu {
             0xff25 => self.nr51 = SoundPanning::from_bits_retain(value),
             0xff26 => self.nr52 = SoundEnable::from_bits_retain(value & (1 << 7)),
             0xff30..=0xff3f => self.wave_ram[(addr - 0xff30) as usize] = value,
-            _ => {}, //unreachable!()
+            // Unused addresses ignore writes.
+            _ => {}
         }
     }
-}
\ No newline at end of file
+}
+
+// The buffered output, its overruns, channel taps, volume and sample rate belong to the frontend, not to the machine.
+state_fields!(Apu {
+    accumulator, div_prev, div_apu,
+    nr10, nr11, nr12, nr13, nr14,
+    ch1_freq_sweep_addition, ch1_freq_sweep_slope, ch1_freq_sweep_pace, ch1_freq_sweep_counter, ch1_length_timer,
+    ch1_envelope_sweep_pace, ch1_envelope_sweep_counter, ch1_envelope_sweep_direction_increase, ch1_period_counter,
+    ch1_duty_counter, ch1_volume,
+    nr21, nr22, nr23, nr24,
+    ch2_length_timer, ch2_envelope_sweep_pace, ch2_envelope_sweep_counter, ch2_envelope_sweep_direction_increase,
+    ch2_period_counter, ch2_duty_counter, ch2_volume,
+    nr30, nr31, nr32, nr33, nr34,
+    ch3_length_timer, ch3_period_counter, ch3_sample_counter, ch3_wave_ram_read,
+    nr41, nr42, nr43, nr44,
+    ch4_length_timer, ch4_envelope_sweep_pace, ch4_envelope_sweep_counter, ch4_envelope_sweep_direction_increase,
+    ch4_tick_counter, ch4_lsfr, ch4_volume,
+    nr50, nr51, nr52, pan_gains, wave_ram,
+});
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_initial_panning_matches_nr51() {
+        let mut apu = Apu::new();
+        let gains = apu.pan_gains;
+
+        apu.ramp_pan_gains();
+
+        assert_eq!(apu.pan_gains, gains);
+    }
+
+    #[test]
+    fn panning_changes_ramp_instead_of_clicking() {
+        let mut apu = Apu::new();
+        // Channel 1 moves from both sides to the right only, channel 3 from the left to the right and channel 4 from
+        // the left to neither.
+        apu.nr51 = SoundPanning::from_bits_retain(0b0010_0111);
+
+        let mut previous = apu.pan_gains;
+
+        for _ in 0..PAN_RAMP_SAMPLES as usize {
+            apu.ramp_pan_gains();
+
+            for (index, (gain, previous)) in apu.pan_gains.iter().flatten().zip(previous.iter().flatten()).enumerate() {
+                assert!((gain - previous).abs() <= 1.0 / PAN_RAMP_SAMPLES, "channel {}", index / 2 + 1);
+            }
+
+            previous = apu.pan_gains;
+        }
+
+        assert_eq!(apu.pan_gains, [[0.0, 1.0], [1.0, 1.0], [0.0, 1.0], [0.0, 0.0]]);
+    }
+
+    #[test]
+    fn panning_changed_back_mid_ramp_returns_from_where_it_got() {
+        let mut apu = Apu::new();
+
+        apu.nr51 = SoundPanning::from_bits_retain(0xe3);
+        for _ in 0..8 {
+            apu.ramp_pan_gains();
+        }
+        assert_eq!(apu.pan_gains[0][0], 0.75);
+
+        apu.nr51 = SoundPanning::from_bits_retain(0xf3);
+        for _ in 0..8 {
+            apu.ramp_pan_gains();
+        }
+        assert_eq!(apu.pan_gains[0][0], 1.0);
+    }
+
+    #[test]
+    fn the_noise_lfsr_is_clocked_at_the_rate_nr43_selects() {
+        let registers = IoRegisters::new();
+
+        // The LFSR is clocked 4194304 / (d << s) times a second, for the divisor d NR43 code r selects (8 for code 0,
+        // 16 * r otherwise) and its clock shift s. Shifts 14 and 15 don't clock it.
+        for (nr43, shifts_per_second) in [
+            (0x00, 524_288),
+            (0x01, 262_144),
+            (0x27, 4_194_304 / (112 << 2)),
+            (0x50, 16_384),
+            (0xd3, 4_194_304 / (48 << 13)),
+            (0xe0, 0),
+        ] {
+            let mut apu = Apu::new();
+            apu.nr43 = nr43;
+            // Starting from 0, the LFSR never reaches the all ones state it would stay in, so every clock changes it.
+            apu.ch4_lsfr = 0;
+
+            let mut shifts = 0;
+            for _ in 0..APU_FREQUENCY {
+                let lfsr = apu.ch4_lsfr;
+                apu.tick(&registers);
+
+                if apu.ch4_lsfr != lfsr {
+                    shifts += 1;
+                }
+            }
+
+            assert_eq!(shifts, shifts_per_second, "NR43 {nr43:#04x}");
+        }
+    }
+}
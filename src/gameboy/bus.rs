@@ -1,18 +1,57 @@
-﻿use super::{
+﻿use std::cell::Cell;
+use super::{
     apu::Apu,
-    io_registers::IoRegisters,
+    io_registers::{DmaTransfer, IoRegisters},
     Mem,
-    ppu::Ppu,
+    ppu::{OamCorruption, Ppu, PpuEvent},
     cartridge::Cartridge,
+    error::CoreError,
+    serial::SerialLink,
+    debug::{MemoryAccess, Watchpoint, WatchpointHit},
+    state::{self, Sections, State, StateReader},
+    open_bus::OpenBus,
+    Accuracy,
+    AccuracyMode,
+    Model,
 };
 
+/// Where reads of a 256 byte page of memory go, resolved when the mapping changes rather than on every access.
+#[derive(Clone, Copy)]
+enum Page {
+    /// Part of a ROM bank, by its index in `Cartridge::mapped_rom_banks`.
+    Rom(usize),
+    /// Part of WRAM, or echo RAM, by its offset in WRAM.
+    Wram(usize),
+    /// Anything that depends on more than the address, such as cartridge RAM being enabled or the PPU mode, or has
+    /// side effects, as the registers do.
+    Slow,
+}
+
 pub struct Bus {
     pub ppu: Ppu,
     pub apu: Apu,
     pub io_registers: IoRegisters,
-    cartridge: Option<Cartridge>,
+    pub cartridge: Option<Cartridge>,
     wram: [u8; 0x2000],
     hram: [u8; 0x7f],
+    pub watchpoints: Vec<Watchpoint>,
+    watchpoint_hit: Cell<Option<WatchpointHit>>,
+    accuracy: Accuracy,
+    /// What reads of unmapped addresses return, for the model of the running game and `accuracy`.
+    open_bus: OpenBus,
+    pub serial_link: Option<SerialLink>,
+    /// OAM row to corrupt because of a read during OAM scan. Reads can't modify OAM directly, so the corruption is
+    /// applied after the instruction, see `apply_oam_read_corruption`.
+    oam_read_corruption: Cell<Option<usize>>,
+    /// M-cycles run by the memory accesses of the instruction being executed, see `cpu_read`.
+    elapsed_m_cycles: usize,
+    /// Set when the PPU completes a frame, until taken.
+    frame_completed: bool,
+    /// The last HBlank or VBlank the PPU entered, until taken.
+    ppu_event: Option<PpuEvent>,
+    /// Page table of reads, by the high byte of the address, so the most common ones skip matching it. Writes match
+    /// the address apart from WRAM, since ROM writes switch banks.
+    pages: [Page; 0x100],
 }
 
 impl Bus {
@@ -24,21 +63,230 @@ impl Bus {
             cartridge: None,
             wram: [0; 0x2000],
             hram: [0; 0x7f],
+            watchpoints: Vec::new(),
+            watchpoint_hit: Cell::new(None),
+            accuracy: Accuracy::default(),
+            open_bus: OpenBus::new(Model::Dmg, &Accuracy::default()),
+            serial_link: None,
+            oam_read_corruption: Cell::new(None),
+            elapsed_m_cycles: 0,
+            frame_completed: false,
+            ppu_event: None,
+            pages: Self::initial_pages(),
+        }
+    }
+
+    /// Pages of WRAM and echo RAM, which never move. ROM is mapped by `map_rom_pages` once there is a cartridge.
+    fn initial_pages() -> [Page; 0x100] {
+        let mut pages = [Page::Slow; 0x100];
+
+        for (page, offset) in (0xc0..=0xdf).zip((0..).step_by(0x100)) {
+            pages[page] = Page::Wram(offset);
+        }
+
+        // Echo RAM mirrors 0xc000-0xddff, the last 512 bytes of WRAM have no mirror.
+        for (page, offset) in (0xe0..=0xfd).zip((0..).step_by(0x100)) {
+            pages[page] = Page::Wram(offset);
         }
+
+        pages
+    }
+
+    /// Points the ROM pages at the banks the cartridge maps, after anything that may have switched them.
+    fn map_rom_pages(&mut self) {
+        let Some(cartridge) = &self.cartridge else {
+            return;
+        };
+
+        let [bank0, bank1] = cartridge.mapped_rom_banks();
+
+        self.pages[0x00..0x40].fill(Page::Rom(bank0));
+        self.pages[0x40..0x80].fill(Page::Rom(bank1));
     }
 
     pub fn reset(&mut self) {
+        let watchpoints = std::mem::take(&mut self.watchpoints);
+        let event_log = std::mem::take(&mut self.io_registers.event_log);
+        let accuracy = self.accuracy;
+        let open_bus = self.open_bus;
+        let audio_enabled = self.apu.audio_enabled();
+        let serial_link = self.serial_link.take();
+
         *self = Self::new();
+
+        self.watchpoints = watchpoints;
+        self.io_registers.event_log = event_log;
+        self.accuracy = accuracy;
+        self.open_bus = open_bus;
+        self.apu.set_audio_enabled(audio_enabled);
+        self.serial_link = serial_link;
     }
 
-    pub fn load(&mut self, program: Vec<u8>) {
+    pub fn accuracy(&self) -> &Accuracy {
+        &self.accuracy
+    }
+
+    pub fn set_accuracy(&mut self, accuracy: Accuracy) {
+        self.accuracy = accuracy;
+        self.open_bus.set_accuracy(&accuracy);
+    }
+
+    /// Resets and inserts a cartridge, to run on `model`. Nothing changes if the ROM can't be loaded.
+    pub fn load(&mut self, program: Vec<u8>, model: Model) -> Result<(), CoreError> {
+        let cartridge = Cartridge::load(program)?;
+
+        self.insert(cartridge, model);
+
+        Ok(())
+    }
+
+    /// Resets and inserts a `Cartridge::flat` holding `program`, to run on `model`.
+    pub fn load_flat(&mut self, program: Vec<u8>, model: Model) {
+        self.insert(Cartridge::flat(program), model);
+    }
+
+    fn insert(&mut self, cartridge: Cartridge, model: Model) {
         self.reset();
-        self.cartridge = Some(Cartridge::load(program));
+        self.open_bus.set_model(model);
+        self.cartridge = Some(cartridge);
+        self.map_rom_pages();
     }
-}
 
-impl Mem for Bus {
-    fn mem_read(&self, addr: u16) -> u8 {
+    /// Reads memory for the CPU, which takes an M-cycle. Unless timing is batched, the rest of the system runs for that
+    /// M-cycle first, so that it sees the accesses of an instruction when they happen rather than after it.
+    pub fn cpu_read(&mut self, addr: u16) -> u8 {
+        self.run_access_cycle();
+
+        self.mem_read(addr)
+    }
+
+    /// Writes memory for the CPU, see `cpu_read`.
+    pub fn cpu_write(&mut self, addr: u16, value: u8) {
+        self.run_access_cycle();
+
+        self.mem_write(addr, value);
+    }
+
+    /// Spends an M-cycle of the CPU that doesn't access memory, where the accesses that follow depend on it happening
+    /// first. Other internal cycles are run after the instruction.
+    pub fn cpu_idle(&mut self) {
+        self.run_access_cycle();
+    }
+
+    fn run_access_cycle(&mut self) {
+        if !self.accuracy.batched_timing {
+            self.run_m_cycles(1, true);
+
+            self.elapsed_m_cycles += 1;
+        }
+    }
+
+    /// Runs everything but the CPU for `m_cycles`. The timers only run if `timers` is set, since STOP stops them, and
+    /// with batched timing the CPU runs them itself.
+    pub fn run_m_cycles(&mut self, m_cycles: usize, timers: bool) {
+        let dot_accurate = self.accuracy.dot_accurate_ppu;
+
+        for _ in 0..m_cycles {
+            for _ in 0..4 {
+                match self.ppu.tick(&mut self.io_registers, dot_accurate) {
+                    Some(PpuEvent::FrameCompleted) => self.frame_completed = true,
+                    Some(event) => self.ppu_event = Some(event),
+                    None => {}
+                }
+            }
+
+            self.apu.tick(&self.io_registers);
+
+            if let Some(cartridge) = &mut self.cartridge {
+                cartridge.tick(4);
+            }
+
+            if timers {
+                self.io_registers.tick_timers(4);
+            }
+        }
+
+        if let Some(serial_link) = &mut self.serial_link {
+            serial_link.tick(&mut self.io_registers, m_cycles * 4);
+        }
+    }
+
+    /// Returns the M-cycles run by CPU accesses since the last call.
+    pub fn take_elapsed_m_cycles(&mut self) -> usize {
+        std::mem::take(&mut self.elapsed_m_cycles)
+    }
+
+    pub fn take_frame_completed(&mut self) -> bool {
+        std::mem::take(&mut self.frame_completed)
+    }
+
+    pub fn take_ppu_event(&mut self) -> Option<PpuEvent> {
+        self.ppu_event.take()
+    }
+
+    /// Returns the first watched access since the last call, if any.
+    pub fn take_watchpoint_hit(&mut self) -> Option<WatchpointHit> {
+        self.watchpoint_hit.take()
+    }
+
+    fn check_watchpoints(&self, addr: u16, old_value: u8, new_value: u8, access: MemoryAccess) {
+        // Keep the first hit until it is taken.
+        if self.watchpoint_hit.get().is_some() {
+            return;
+        }
+
+        if self.watchpoints.iter().any(|w| w.matches(addr, access)) {
+            self.watchpoint_hit.set(Some(WatchpointHit {
+                addr,
+                old_value,
+                new_value,
+                access,
+            }));
+        }
+    }
+
+    /// Row of OAM affected by the OAM corruption bug when `addr` is put on the address bus, if enabled.
+    fn oam_bug_row(&self, addr: u16) -> Option<usize> {
+        // The CPU can't access OAM during DMA, which itself doesn't trigger the bug.
+        if !self.accuracy.oam_bug || !(0xfe00..=0xfeff).contains(&addr) || self.io_registers.dma_counter > 0 {
+            return None;
+        }
+
+        self.ppu.oam_scan_row(&self.io_registers)
+    }
+
+    /// Triggers the OAM corruption bug for a 16-bit increment or decrement of a register holding `addr`.
+    pub fn oam_bug_inc_dec(&mut self, addr: u16) {
+        if let Some(row) = self.oam_bug_row(addr) {
+            self.ppu.vram.corrupt_oam_row(row, OamCorruption::Write);
+        }
+    }
+
+    pub fn apply_oam_read_corruption(&mut self) {
+        if let Some(row) = self.oam_read_corruption.take() {
+            self.ppu.vram.corrupt_oam_row(row, OamCorruption::Read);
+        }
+    }
+
+    /// Copies a byte of an OAM DMA transfer. The DMA unit isn't the CPU, so its accesses don't hit watchpoints.
+    pub fn dma_copy(&mut self, src: u16, dst: u16) {
+        let value = self.read(src);
+
+        self.write(dst, value);
+    }
+
+    fn read(&self, addr: u16) -> u8 {
+        match self.pages[(addr >> 8) as usize] {
+            Page::Rom(bank) => match &self.cartridge {
+                Some(cartridge) => cartridge.read_rom_bank(bank, addr & 0x3fff),
+                _ => OpenBus::UNMAPPED
+            },
+            Page::Wram(offset) => self.wram[offset | (addr & 0xff) as usize],
+            Page::Slow => self.read_slow(addr),
+        }
+    }
+
+    fn read_slow(&self, addr: u16) -> u8 {
         // TODO: On DMG, during OAM DMA, the CPU can access only HRAM (memory at $FF80-$FFFE).
         // if self.io_registers.dma_counter > 0 && !(0xff80..=0xfffe).contains(&addr) {
         //     return 0xff;
@@ -47,17 +295,21 @@ impl Mem for Bus {
         return match addr {
             0x0000..=0x7fff | 0xa000..=0xbfff => match &self.cartridge {
                 Some(cartridge) => cartridge.mem_read(addr),
-                _ => 0x00
+                _ => OpenBus::UNMAPPED
             },
             0x8000..=0x9fff => self.ppu.vram.mem_read(addr),
             0xc000..=0xdfff => self.wram[(addr - 0xc000) as usize],
+            // Echo RAM mirrors 0xc000-0xddff, the last 512 bytes of WRAM have no mirror.
             0xe000..=0xfdff => self.wram[(addr - 0xe000) as usize],
-            0xfe00..=0xfe9f => 0,
-            0xfea0..=0xfeff => {
-                // TODO: If OAM blocked
-                // TODO: OAM corruption, return 0?
-                return 0xff;
-            }
+            // OAM reads 0xff while the PPU is using it.
+            0xfe00..=0xfe9f if self.ppu.oam_accessible(&self.io_registers) => self.ppu.vram.mem_read(addr),
+            0xfe00..=0xfe9f => 0xff,
+            // OAM is accessible in HBlank and VBlank, which are also the modes of a disabled LCD.
+            0xfea0..=0xfeff => self.open_bus.prohibited_area(addr, self.io_registers.stat & 0b0000_0011 >= 2),
+            0xff30..=0xff3f if self.accuracy.wave_ram == AccuracyMode::Dmg => match self.apu.dmg_wave_ram_index(addr) {
+                Some(index) => self.apu.wave_ram[index],
+                None => 0xff,
+            },
             0xff10..=0xff3f => self.apu.mem_read(addr),
             0xff00..=0xff0f | 0xff40..=0xff7f => self.io_registers.mem_read(addr),
             0xff80..=0xfffe => self.hram[(addr - 0xff80) as usize],
@@ -66,9 +318,22 @@ impl Mem for Bus {
         };
     }
 
-    fn mem_write(&mut self, addr: u16, value: u8) {
+    fn write(&mut self, addr: u16, value: u8) {
+        if let Page::Wram(offset) = self.pages[(addr >> 8) as usize] {
+            self.wram[offset | (addr & 0xff) as usize] = value;
+
+            return;
+        }
+
         match addr {
-            0x0000..=0x7fff | 0xa000..=0xbfff => match self.cartridge {
+            0x0000..=0x7fff => {
+                if let Some(cartridge) = &mut self.cartridge {
+                    cartridge.mem_write(addr, value);
+                }
+
+                self.map_rom_pages();
+            }
+            0xa000..=0xbfff => match self.cartridge {
                 Some(ref mut cartridge) => cartridge.mem_write(addr, value),
                 _ => {}
             }
@@ -76,12 +341,465 @@ impl Mem for Bus {
             0xc000..=0xdfff => self.wram[(addr - 0xc000) as usize] = value,
             0xe000..=0xfdff => self.wram[(addr - 0xe000) as usize] = value,
             0xfe00..=0xfe9f => self.ppu.vram.mem_write(addr, value),
-            0xfea0..=0xfeff => {} // panic!("not usable"),
+            0xfea0..=0xfeff => {}
+            0xff30..=0xff3f if self.accuracy.wave_ram == AccuracyMode::Dmg => {
+                if let Some(index) = self.apu.dmg_wave_ram_index(addr) {
+                    self.apu.wave_ram[index] = value;
+                }
+            }
             0xff10..=0xff3f => self.apu.mem_write(addr, value),
+            0xff41 if self.accuracy.stat_write_bug => {
+                self.io_registers.stat_write_bug();
+                self.io_registers.mem_write(addr, value);
+            }
             0xff00..=0xff0f | 0xff40..=0xff7f => self.io_registers.mem_write(addr, value),
             0xff80..=0xfffe => self.hram[(addr - 0xff80) as usize] = value,
             0xffff => self.io_registers.mem_write(addr, value),
             _ => unreachable!()
         }
     }
-}
\ No newline at end of file
+}
+
+impl Bus {
+    /// Reads memory without side effects: no watchpoints, no OAM corruption and no blocking by the PPU mode. Registers
+    /// that can't be read by the CPU return their stored value.
+    pub fn mem_peek(&self, addr: u16) -> u8 {
+        match addr {
+            0x0000..=0x7fff | 0xa000..=0xbfff => match &self.cartridge {
+                Some(cartridge) => cartridge.mem_peek(addr),
+                _ => OpenBus::UNMAPPED
+            },
+            0x8000..=0x9fff | 0xfe00..=0xfe9f => self.ppu.vram.mem_read(addr),
+            0xc000..=0xdfff => self.wram[(addr - 0xc000) as usize],
+            0xe000..=0xfdff => self.wram[(addr - 0xe000) as usize],
+            0xfea0..=0xfeff => self.open_bus.prohibited_area(addr, false),
+            0xff10..=0xff3f => self.apu.mem_peek(addr),
+            0xff00..=0xff0f | 0xff40..=0xff7f => self.io_registers.mem_peek(addr),
+            0xff80..=0xfffe => self.hram[(addr - 0xff80) as usize],
+            0xffff => self.io_registers.mem_peek(addr),
+        }
+    }
+
+    /// Writes memory without side effects, see `mem_peek`. ROM and the prohibited area can't be written, nor can
+    /// registers or bits that the hardware computes, like LY's counting or STAT's mode.
+    pub fn mem_poke(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x0000..=0x7fff | 0xa000..=0xbfff => {
+                if let Some(cartridge) = &mut self.cartridge {
+                    cartridge.mem_poke(addr, value);
+                }
+            }
+            0x8000..=0x9fff | 0xfe00..=0xfe9f => self.ppu.vram.mem_write(addr, value),
+            0xc000..=0xdfff => self.wram[(addr - 0xc000) as usize] = value,
+            0xe000..=0xfdff => self.wram[(addr - 0xe000) as usize] = value,
+            0xfea0..=0xfeff => {}
+            0xff10..=0xff3f => self.apu.mem_poke(addr, value),
+            0xff00..=0xff0f | 0xff40..=0xff7f => self.io_registers.mem_poke(addr, value),
+            0xff80..=0xfffe => self.hram[(addr - 0xff80) as usize] = value,
+            0xffff => self.io_registers.mem_poke(addr, value),
+        }
+    }
+}
+
+impl Mem for Bus {
+    fn mem_read(&self, addr: u16) -> u8 {
+        if let Some(row) = self.oam_bug_row(addr) {
+            self.oam_read_corruption.set(Some(row));
+        }
+
+        let value = self.read(addr);
+
+        if !self.watchpoints.is_empty() {
+            self.check_watchpoints(addr, value, value, MemoryAccess::Read);
+        }
+
+        value
+    }
+
+    fn mem_write(&mut self, addr: u16, value: u8) {
+        if let Some(row) = self.oam_bug_row(addr) {
+            self.ppu.vram.corrupt_oam_row(row, OamCorruption::Write);
+        }
+
+        if !self.watchpoints.is_empty() {
+            let old_value = self.read(addr);
+
+            self.write(addr, value);
+
+            self.check_watchpoints(addr, old_value, self.read(addr), MemoryAccess::Write);
+
+            return;
+        }
+
+        self.write(addr, value);
+    }
+}
+
+impl Bus {
+    /// Saves every subsystem in a section of its own, see `state::save_section`.
+    pub fn save_sections(&self, out: &mut Vec<u8>) {
+        state::save_section(b"PPU ", &self.ppu, out);
+        state::save_section(b"APU ", &self.apu, out);
+        state::save_section(b"IO  ", &self.io_registers, out);
+        state::save_section(b"DMA ", &self.io_registers.dma_transfer, out);
+        state::save_section(b"WRAM", &self.wram, out);
+        state::save_section(b"HRAM", &self.hram, out);
+
+        if let Some(cartridge) = &self.cartridge {
+            state::save_section(b"CART", cartridge, out);
+        }
+    }
+
+    /// Makes the DMA transfer of a state saved before transfers were delayed copy from `dma`, without a delay, as it
+    /// did then.
+    fn reset_dma_transfer(&mut self) {
+        self.io_registers.dma_transfer = DmaTransfer {
+            source: self.io_registers.dma,
+            start_delay: 0,
+        };
+    }
+
+    /// Restores the subsystems saved by `save_sections` in a state of format `version`. Those without a section are
+    /// reset to their power on state, apart from the cartridge, which is left as it is.
+    pub fn load_sections(&mut self, sections: &Sections, version: u32) -> Result<(), CoreError> {
+        if !sections.load(b"PPU ", &mut self.ppu)? {
+            self.ppu = Ppu::new();
+        }
+
+        if !sections.load(b"APU ", &mut self.apu)? {
+            let audio_enabled = self.apu.audio_enabled();
+
+            self.apu = Apu::new();
+            self.apu.set_audio_enabled(audio_enabled);
+        }
+
+        let io_loaded = match version {
+            ..=3 => sections.load_with(b"IO  ", |input| self.io_registers.load_with_accumulator(input))?,
+            _ => sections.load(b"IO  ", &mut self.io_registers)?,
+        };
+
+        if !io_loaded {
+            let event_log = std::mem::take(&mut self.io_registers.event_log);
+
+            self.io_registers = IoRegisters::new();
+            self.io_registers.event_log = event_log;
+        }
+
+        if !sections.load(b"DMA ", &mut self.io_registers.dma_transfer)? {
+            self.reset_dma_transfer();
+        }
+
+        if !sections.load(b"WRAM", &mut self.wram)? {
+            self.wram = [0; 0x2000];
+        }
+
+        if !sections.load(b"HRAM", &mut self.hram)? {
+            self.hram = [0; 0x7f];
+        }
+
+        if let Some(cartridge) = &mut self.cartridge {
+            sections.load(b"CART", cartridge)?;
+        }
+
+        self.map_rom_pages();
+
+        Ok(())
+    }
+}
+
+// Watchpoints and accuracy are settings, and the rest is only set during an instruction, between states. This is the
+// layout of version 1 states, without sections.
+impl State for Bus {
+    fn save(&self, out: &mut Vec<u8>) {
+        self.ppu.save(out);
+        self.apu.save(out);
+        self.io_registers.save(out);
+        self.wram.save(out);
+        self.hram.save(out);
+
+        if let Some(cartridge) = &self.cartridge {
+            cartridge.save(out);
+        }
+    }
+
+    fn load(&mut self, input: &mut StateReader) -> Result<(), CoreError> {
+        self.ppu.load(input)?;
+        self.apu.load(input)?;
+        self.io_registers.load_with_accumulator(input)?;
+        self.reset_dma_transfer();
+        self.wram.load(input)?;
+        self.hram.load(input)?;
+
+        if let Some(cartridge) = &mut self.cartridge {
+            cartridge.load(input)?;
+        }
+
+        self.map_rom_pages();
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ops::RangeInclusive;
+
+    use super::*;
+    use crate::gameboy::{debug::MemoryAccess, DebugEvent, GameBoy};
+
+    fn run_program(program: &[u8], ticks: usize, setup: impl FnOnce(&mut GameBoy)) -> GameBoy {
+        let mut rom = vec![0; 0x100];
+        rom.extend_from_slice(program);
+
+        let mut gameboy = GameBoy::new();
+        gameboy.load_flat(rom);
+        setup(&mut gameboy);
+
+        for _ in 0..ticks {
+            gameboy.tick();
+        }
+
+        gameboy
+    }
+
+    #[test]
+    fn watchpoints_hit_on_register_writes() {
+        // LD A,0x11; LDH (0x40),A; JR -2
+        let mut gameboy = run_program(&[0x3e, 0x11, 0xe0, 0x40, 0x18, 0xfe], 2, |gameboy| {
+            gameboy.add_watchpoint(0xff40..=0xff40, false, true);
+        });
+
+        assert!(matches!(
+            gameboy.take_debug_event(),
+            Some(DebugEvent::Watchpoint { pc: 0x0102, addr: 0xff40, old_value: 0x91, new_value: 0x11, access: MemoryAccess::Write }),
+        ));
+    }
+
+    #[test]
+    fn oam_dma_doesnt_hit_watchpoints() {
+        // LD A,0xc0; LDH (0x46),A; JR -2
+        let mut gameboy = run_program(&[0x3e, 0xc0, 0xe0, 0x46, 0x18, 0xfe], 200, |gameboy| {
+            gameboy.poke(0xc000, 0x12);
+            gameboy.add_watchpoint(0xc000..=0xc09f, true, true);
+            gameboy.add_watchpoint(0xfe00..=0xfe9f, true, true);
+        });
+
+        assert!(gameboy.take_debug_event().is_none());
+        assert_eq!(gameboy.peek(0xfe00), 0x12);
+    }
+
+    #[test]
+    fn every_io_address_can_be_read() {
+        let bus = Bus::new();
+
+        for addr in 0xff00..=0xffff {
+            bus.mem_read(addr);
+        }
+
+        for addr in [0xff51, 0xff52, 0xff53, 0xff54, 0xff55, 0xff76, 0xff77] {
+            assert_eq!(bus.mem_read(addr), 0xff, "{addr:04x}");
+        }
+    }
+
+    #[test]
+    fn every_io_register_can_be_read_and_written() {
+        let mut bus = Bus::new();
+
+        for value in [0xff, 0x00] {
+            for addr in 0xff00..=0xff7f {
+                bus.mem_read(addr);
+                bus.mem_write(addr, value);
+                bus.mem_read(addr);
+            }
+        }
+
+        // Unused addresses ignore writes.
+        for addr in [0xff03, 0xff08, 0xff0e, 0xff27, 0xff2f, 0xff4c, 0xff7f] {
+            assert_eq!(bus.mem_read(addr), 0xff, "{addr:04x}");
+        }
+    }
+
+    /// The registers of a DMG, and OPRI, which is wired ahead of CGB mode. The rest of 0xff00-0xff7f is unused, or only
+    /// used on CGB.
+    // https://gbdev.io/pandocs/Hardware_Reg_List.html
+    const DMG_REGISTERS: [RangeInclusive<u16>; 9] = [
+        0xff00..=0xff02,
+        0xff04..=0xff07,
+        0xff0f..=0xff0f,
+        0xff10..=0xff14,
+        0xff16..=0xff1e,
+        0xff20..=0xff26,
+        0xff30..=0xff3f,
+        0xff40..=0xff4b,
+        0xff6c..=0xff6c,
+    ];
+
+    /// What the table of `OpenBus` says `addr` reads, for cartridges without RAM, or `None` for the addresses it doesn't
+    /// cover.
+    fn open_bus_value(addr: u16, cartridge: bool, model: Model, prohibited_area: AccuracyMode) -> Option<u8> {
+        match addr {
+            0x0000..=0x7fff if !cartridge => Some(OpenBus::UNMAPPED),
+            0xa000..=0xbfff => Some(OpenBus::UNMAPPED),
+            0xfea0..=0xfeff => Some(match (prohibited_area, model) {
+                (AccuracyMode::Fast, _) => OpenBus::UNMAPPED,
+                (AccuracyMode::Dmg, Model::Dmg) => 0x00,
+                (AccuracyMode::Dmg, Model::Cgb) => addr as u8 & 0xf0 | addr as u8 >> 4,
+            }),
+            0xff00..=0xff7f if model == Model::Dmg && !DMG_REGISTERS.iter().any(|registers| registers.contains(&addr)) => {
+                Some(OpenBus::UNMAPPED)
+            }
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn every_address_reads_as_the_open_bus_table_says() {
+        for (cartridge, model, prohibited_area) in [
+            (false, Model::Dmg, AccuracyMode::Fast),
+            (true, Model::Dmg, AccuracyMode::Fast),
+            (true, Model::Dmg, AccuracyMode::Dmg),
+            (true, Model::Cgb, AccuracyMode::Dmg),
+        ] {
+            let mut bus = Bus::new();
+            bus.set_accuracy(Accuracy { prohibited_area, ..Default::default() });
+
+            if cartridge {
+                bus.load(vec![0; 0x8000], model).unwrap();
+            }
+
+            // With the LCD off, OAM isn't blocked, so the prohibited area reads by model.
+            bus.mem_write(0xff40, 0x00);
+
+            let case = format!("{model:?}, with{} a cartridge", if cartridge { "" } else { "out" });
+
+            for addr in 0..=0xffff {
+                match open_bus_value(addr, cartridge, model, prohibited_area) {
+                    Some(value) => {
+                        assert_eq!(bus.mem_read(addr), value, "{addr:04x}, {case}");
+
+                        bus.mem_write(addr, !value);
+                        assert_eq!(bus.mem_read(addr), value, "{addr:04x} after a write, {case}");
+                    }
+                    // The ROM is all zeroes, and everything else up to the registers is memory.
+                    None if addr < 0xff00 => {
+                        bus.mem_write(addr, 0x00);
+                        assert_eq!(bus.mem_read(addr), 0x00, "{addr:04x}, {case}");
+                    }
+                    None => {}
+                }
+            }
+        }
+    }
+
+    /// A bus scanning OAM row 2, the second and third rows of OAM set for telling the corruptions apart.
+    fn scanning_oam_row_2(oam_bug: bool) -> Bus {
+        let mut bus = Bus::new();
+        bus.set_accuracy(Accuracy { oam_bug, ..Accuracy::default() });
+        bus.io_registers.stat = 0b1000_0010;
+        bus.ppu.dot_counter = 2 * 4;
+
+        bus.ppu.vram.oam[8..24].copy_from_slice(&[
+            0xf0, 0x0f, 0x11, 0x22, 0x3c, 0x3c, 0x33, 0x44,
+            0xf0, 0xf0, 0x55, 0x66, 0x77, 0x88, 0x99, 0xaa,
+        ]);
+
+        bus
+    }
+
+    #[test]
+    fn writes_to_oam_during_oam_scan_corrupt_the_row_scanned() {
+        let mut bus = scanning_oam_row_2(true);
+
+        bus.mem_write(0xfea0, 0x00);
+
+        assert_eq!(bus.ppu.vram.oam[16..24], [0xf0, 0x3c, 0x11, 0x22, 0x3c, 0x3c, 0x33, 0x44]);
+        assert_eq!(bus.ppu.vram.oam[8..10], [0xf0, 0x0f]);
+    }
+
+    #[test]
+    fn reads_of_oam_during_oam_scan_corrupt_the_row_scanned_after_the_instruction() {
+        let mut bus = scanning_oam_row_2(true);
+
+        assert_eq!(bus.mem_read(0xfe10), 0xff);
+        assert_eq!(bus.ppu.vram.oam[16..18], [0xf0, 0xf0]);
+
+        bus.apply_oam_read_corruption();
+
+        assert_eq!(bus.ppu.vram.oam[16..24], [0xf0, 0x3f, 0x11, 0x22, 0x3c, 0x3c, 0x33, 0x44]);
+    }
+
+    /// OAM after a write to the prohibited area, with the bus set up by `setup`.
+    fn oam_after_write(oam_bug: bool, setup: impl FnOnce(&mut Bus)) -> [u8; 0xa0] {
+        let mut bus = scanning_oam_row_2(oam_bug);
+        setup(&mut bus);
+
+        bus.mem_write(0xfea0, 0x00);
+
+        bus.ppu.vram.oam
+    }
+
+    #[test]
+    fn oam_is_only_corrupted_during_oam_scan_with_the_bug_enabled() {
+        let unchanged = scanning_oam_row_2(false).ppu.vram.oam;
+
+        assert_eq!(oam_after_write(false, |_| {}), unchanged);
+        assert_eq!(oam_after_write(true, |bus| bus.io_registers.stat = 0b1000_0000), unchanged);
+        assert_eq!(oam_after_write(true, |bus| bus.io_registers.dma_counter = 1), unchanged);
+        assert_eq!(oam_after_write(true, |bus| bus.ppu.dot_counter = 0), unchanged);
+    }
+
+    #[test]
+    fn echo_ram_mirrors_wram_up_to_its_last_512_bytes() {
+        let mut bus = Bus::new();
+        bus.load_flat(Vec::new(), Model::Dmg);
+
+        bus.mem_write(0xc000, 0x11);
+        bus.mem_write(0xfdff, 0x22);
+        bus.mem_write(0xde00, 0x33);
+        bus.mem_write(0xdfff, 0x44);
+
+        assert_eq!(bus.mem_read(0xe000), 0x11);
+        assert_eq!(bus.mem_read(0xddff), 0x22);
+
+        // 0xde00-0xdfff would be mirrored at 0xfe00-0xffff, which is OAM and the registers.
+        assert_eq!(bus.mem_read(0xfe00), 0x00);
+        assert_eq!(bus.mem_read(0xdfff), 0x44);
+
+        bus.mem_write(0xfe00, 0x55);
+        assert_eq!(bus.mem_read(0xde00), 0x33);
+    }
+
+    #[test]
+    fn wave_ram_accesses_while_channel_3_plays_go_to_the_byte_it_reads_on_dmg() {
+        let mut bus = Bus::new();
+        bus.set_accuracy(Accuracy { wave_ram: AccuracyMode::Dmg, ..Accuracy::default() });
+
+        bus.mem_write(0xff26, 0x80);
+        bus.mem_write(0xff3a, 0x42);
+        assert_eq!(bus.apu.wave_ram[10], 0x42);
+
+        // DAC on, and a period reading a sample every 8 M-cycles.
+        bus.mem_write(0xff1a, 0x80);
+        bus.mem_write(0xff1d, 0xf0);
+        bus.mem_write(0xff1e, 0x87);
+
+        while bus.apu.dmg_wave_ram_index(0xff3a).is_none() {
+            assert_eq!(bus.mem_read(0xff3a), 0xff);
+            bus.run_m_cycles(1, true);
+        }
+
+        let index = bus.apu.dmg_wave_ram_index(0xff3a).unwrap();
+        assert_ne!(index, 10);
+
+        bus.mem_write(0xff3a, 0x17);
+        assert_eq!(bus.mem_read(0xff3a), 0x17);
+        assert_eq!((bus.apu.wave_ram[index], bus.apu.wave_ram[10]), (0x17, 0x42));
+
+        // Until the next sample, accesses go nowhere.
+        bus.run_m_cycles(1, true);
+        bus.mem_write(0xff3a, 0x99);
+
+        assert_eq!(bus.mem_read(0xff3a), 0xff);
+        assert_eq!((bus.apu.wave_ram[index], bus.apu.wave_ram[10]), (0x17, 0x42));
+    }
+}
@@ -1,15 +1,13 @@
 ﻿use super::{
     cartridge::BankingMode::{AdvancedRomOrRamBanking, Simple},
+    error::CoreError,
+    header::{CartridgeHeader, OFFSET_CHECKSUM, OFFSET_RAM_SIZE, OFFSET_TITLE},
+    open_bus::OpenBus,
+    rtc::Rtc,
+    state::{read, State, StateReader},
     Mem,
 };
 
-const OFFSET_TITLE: usize = 0x0134;
-const OFFSET_CARTRIDGE_TYPE: usize = 0x0147;
-const OFFSET_ROM_SIZE: usize = 0x0148;
-const OFFSET_RAM_SIZE: usize = 0x0149;
-const OFFSET_MASK_ROM_VERSION_NUMBER: usize = 0x014c;
-const OFFSET_CHECKSUM: usize = 0x014d;
-
 pub(crate) enum Mapper {
     None,
     MBC1,
@@ -27,32 +25,10 @@ pub enum BankingMode {
     AdvancedRomOrRamBanking,
 }
 
-pub fn cartridge_ram_size_kib(ram_size_type: u8) -> usize {
-    match ram_size_type {
-        0 => 0,
-        2 => 8,
-        3 => 32,
-        4 => 128,
-        5 => 64,
-        _ => unreachable!(),
-    }
-}
-
-fn verify_checksum(program: &Vec<u8>) -> bool {
-    let mut checksum: u8 = 0;
-
-    for i in OFFSET_TITLE..=OFFSET_MASK_ROM_VERSION_NUMBER {
-        checksum = checksum.wrapping_sub(program[i]).wrapping_sub(1);
-    }
-
-    return checksum == program[OFFSET_CHECKSUM];
-}
-
 pub(crate) struct Cartridge {
     _program: Vec<u8>,
     mapper: Mapper,
     banking_mode: BankingMode,
-    cartridge_rom_size_type: u8,
     rom_current_bank: u8,
     rom_secondary_bank_register: u8,
     rom_banks: Vec<[u8; 0x4000]>,
@@ -60,56 +36,45 @@ pub(crate) struct Cartridge {
     ram_enable: bool,
     ram_current_bank: u8,
     ram_banks: Vec<[u8; 0x2000]>,
+    /// Masks offsets into a RAM bank, so that RAM smaller than a bank, like the 2 KiB of size type 1, mirrors within it.
+    ram_mask: u16,
+    /// Whether RAM is kept by a battery, and so should be saved.
+    battery: bool,
+    /// Set by every write to RAM or the clock, and cleared when it is taken for saving.
+    dirty: bool,
+    rtc: Option<Rtc>,
 }
 
 impl Cartridge {
-    pub fn load(program: Vec<u8>) -> Self {
-        let _checksum = verify_checksum(&program);
+    pub fn load(program: Vec<u8>) -> Result<Self, CoreError> {
+        let header = CartridgeHeader::parse(&program)?;
 
-        let cartridge_type = program[OFFSET_CARTRIDGE_TYPE];
-        let mapper = match cartridge_type {
+        let mapper = match header.cartridge_type {
             0x00 | 0x08 | 0x09 => Mapper::None,
             0x01..=0x03 => Mapper::MBC1,
-            0x05 | 0x06 => unimplemented!("MBC2"),
-            0x0b..=0x0d => unimplemented!("MMM01"),
             0x0f..=0x13 => Mapper::MBC3,
-            0x19..=0x1e => unimplemented!("MBC5"),
-            0x20 => unimplemented!("MBC6"),
-            0x22 => unimplemented!("MBC7"),
-            0xfc => unimplemented!("Pocket Camera"),
-            0xfd => unimplemented!("Bandai TAMA5"),
-            0xfe => unimplemented!("HuC3"),
-            0xff => unimplemented!("HuC1"),
-            _ => unreachable!()
+            _ => return Err(CoreError::UnsupportedMapper(header.mapper())),
         };
 
-        let cartridge_rom_size_type = program[OFFSET_ROM_SIZE];
-        let rom_size_bytes: usize = 32 * 1024 * (1 << cartridge_rom_size_type);
-
-        let bank_count = rom_size_bytes / 0x4000;
-
-        let mut rom_banks = Vec::with_capacity(bank_count);
-        for i in 0..bank_count {
-            let mut bank: [u8; 0x4000] = [0; 0x4000];
-            bank.copy_from_slice(&program[(i * 0x4000)..=(i * 0x4000 + 0x3fff)]);
-
-            rom_banks.push(bank);
+        if program.len() < header.rom_size {
+            return Err(CoreError::RomTooSmall { size: program.len(), expected: header.rom_size });
         }
 
-        let cartridge_ram_size_type = program[OFFSET_RAM_SIZE];
+        let rom_banks = program[..header.rom_size]
+            .chunks_exact(0x4000)
+            .map(|bank| bank.try_into().unwrap())
+            .collect();
 
-        let cartridge_ram_bytes_total = cartridge_ram_size_kib(cartridge_ram_size_type) * 1024;
+        // RAM smaller than a bank is given a whole one, of which only the start is used.
+        let ram_banks = vec![[0; 0x2000]; (header.ram_size + 0x1fff) / 0x2000];
+        let ram_mask = (header.ram_size.clamp(1, 0x2000).next_power_of_two() - 1) as u16;
 
-        let mut ram_banks = Vec::with_capacity(cartridge_ram_bytes_total / 0x2000);
-        while ram_banks.len() < ram_banks.capacity() {
-            ram_banks.push([0; 0x2000]);
-        }
+        let cartridge_ram_size_type = program[OFFSET_RAM_SIZE];
 
-        Self {
+        Ok(Self {
             _program: program,
             mapper,
             banking_mode: Simple,
-            cartridge_rom_size_type,
             rom_current_bank: 1,
             rom_secondary_bank_register: 0,
             rom_banks,
@@ -117,33 +82,161 @@ impl Cartridge {
             ram_enable: false,
             ram_current_bank: 0,
             ram_banks,
+            ram_mask,
+            battery: header.has_battery(),
+            dirty: false,
+            rtc: header.has_rtc().then(Rtc::new),
+        })
+    }
+
+    /// A cartridge without a header or mapper, holding `program` at 0x0000 and 8 KiB of RAM at 0xa000, for running
+    /// hand-assembled programs. The ROM is padded with zeroes to 32 KiB.
+    ///
+    /// Panics if `program` is larger than 32 KiB.
+    pub fn flat(mut program: Vec<u8>) -> Self {
+        assert!(program.len() <= 0x8000, "a flat program is at most 32 KiB, not {} bytes", program.len());
+
+        program.resize(0x8000, 0);
+
+        let rom_banks = program.chunks_exact(0x4000).map(|bank| bank.try_into().unwrap()).collect();
+
+        Self {
+            _program: program,
+            mapper: Mapper::None,
+            banking_mode: Simple,
+            rom_current_bank: 1,
+            rom_secondary_bank_register: 0,
+            rom_banks,
+            cartridge_ram_size_type: 0x02,
+            ram_enable: false,
+            ram_current_bank: 0,
+            ram_banks: vec![[0; 0x2000]],
+            ram_mask: 0x1fff,
+            battery: false,
+            dirty: false,
+            rtc: None,
         }
     }
 
-    fn mem_read_mbc_none(&self, addr: u16) -> u8 {
-        match addr {
-            0x0000..=0x3fff => self.rom_banks[0][addr as usize],
-            0x4000..=0x7fff => self.rom_banks[1][(addr - 0x4000) as usize],
-            0xa000..=0xbfff => {
-                let addr = (addr - 0xa000) as usize;
+    pub fn tick(&mut self, t_cycles: u32) {
+        if let Some(rtc) = &mut self.rtc {
+            rtc.tick(t_cycles);
+        }
+    }
+
+    /// Hash of the header from the title to the header checksum, which tells apart the saves of different games.
+    pub fn title_hash(&self) -> u64 {
+        self.rom_banks[0][OFFSET_TITLE..=OFFSET_CHECKSUM]
+            .iter()
+            .fold(0xcbf2_9ce4_8422_2325, |hash, &byte| (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3))
+    }
+
+    /// The contents of RAM, if it is battery-backed and was written since last taken. With a clock, its state at
+    /// `unix_time` follows, see `Rtc::footer`.
+    pub fn take_ram(&mut self, unix_time: u64) -> Option<Vec<u8>> {
+        if !self.battery || !self.dirty {
+            return None;
+        }
+
+        self.dirty = false;
+
+        let mut data: Vec<u8> = self.ram_banks.iter().flatten().copied().collect();
+
+        if let Some(rtc) = &self.rtc {
+            data.extend_from_slice(&rtc.footer(unix_time));
+        }
+
+        Some(data)
+    }
+
+    /// Restores RAM from a save. Saves of a different size are loaded as far as they fit, and the clock is only
+    /// restored if a footer of a known size follows the RAM, otherwise this returns `CoreError::BadSaveClock`.
+    pub fn load_ram(&mut self, data: &[u8], unix_time: u64) -> Result<(), CoreError> {
+        let ram_size = self.ram_banks.len() * 0x2000;
+        let (ram, footer) = data.split_at(ram_size.min(data.len()));
+
+        for (bank, ram) in self.ram_banks.iter_mut().zip(ram.chunks(0x2000)) {
+            bank[..ram.len()].copy_from_slice(ram);
+        }
 
-                self.ram_banks[0][addr]
+        if let Some(rtc) = &mut self.rtc {
+            if !footer.is_empty() && rtc.load_footer(footer, unix_time).is_none() {
+                return Err(CoreError::BadSaveClock { size: footer.len() });
             }
+        }
+
+        Ok(())
+    }
+
+    /// Reads a ROM bank, see `rom_bank_index`.
+    fn read_rom(&self, bank: usize, offset: u16) -> u8 {
+        self.rom_banks[self.rom_bank_index(bank)][offset as usize]
+    }
+
+    /// Index in `rom_banks` of a bank number. The number is masked to the bits the ROM has address lines for, so banks
+    /// past its end mirror the ones at its start. Sizes that aren't a power of two also wrap around at their end.
+    fn rom_bank_index(&self, bank: usize) -> usize {
+        let bank_count = self.rom_banks.len();
+
+        (bank & (bank_count.next_power_of_two() - 1)) % bank_count
+    }
+
+    /// Indexes in the ROM of the banks mapped to 0x0000-0x3fff and 0x4000-0x7fff, for `read_rom_bank`. They change
+    /// with writes to ROM and restoring a state.
+    pub fn mapped_rom_banks(&self) -> [usize; 2] {
+        let bank = match self.mapper {
+            Mapper::None => 1,
+            Mapper::MBC1 | Mapper::MBC3 => self.rom_current_bank as usize,
+        };
+
+        [self.rom_bank_index(0), self.rom_bank_index(bank)]
+    }
+
+    /// Reads the bank at `index` of `mapped_rom_banks`, skipping the mapper.
+    pub fn read_rom_bank(&self, index: usize, offset: u16) -> u8 {
+        self.rom_banks[index][offset as usize]
+    }
+
+    /// Reads a RAM bank, or `OpenBus::UNMAPPED` where the cartridge has no RAM.
+    fn read_ram(&self, bank: usize, offset: u16) -> u8 {
+        self.ram_banks.get(bank).map_or(OpenBus::UNMAPPED, |bank| bank[(offset & self.ram_mask) as usize])
+    }
+
+    /// Writes a RAM bank, ignoring writes where the cartridge has no RAM.
+    fn write_ram(&mut self, bank: usize, offset: u16, value: u8) {
+        if let Some(bank) = self.ram_banks.get_mut(bank) {
+            bank[(offset & self.ram_mask) as usize] = value;
+            self.dirty = true;
+        }
+    }
+
+    fn mem_read_mbc_none(&self, addr: u16) -> u8 {
+        match addr {
+            0x0000..=0x3fff => self.read_rom(0, addr),
+            0x4000..=0x7fff => self.read_rom(1, addr - 0x4000),
+            0xa000..=0xbfff => self.read_ram(0, addr - 0xa000),
             _ => unreachable!()
         }
     }
 
+    // Without a mapper, RAM is always enabled, and writes to ROM do nothing.
+    fn mem_write_mbc_none(&mut self, addr: u16, value: u8) {
+        if let 0xa000..=0xbfff = addr {
+            self.write_ram(0, addr - 0xa000, value);
+        }
+    }
+
     fn mem_read_mbc1(&self, addr: u16) -> u8 {
         match addr {
-            0x0000..=0x3fff => self.rom_banks[0][addr as usize],
-            0x4000..=0x7fff => self.rom_banks[self.rom_current_bank as usize][(addr - 0x4000) as usize],
+            0x0000..=0x3fff => self.read_rom(0, addr),
+            0x4000..=0x7fff => self.read_rom(self.rom_current_bank as usize, addr - 0x4000),
             0xa000..=0xbfff => {
-                let addr = (addr - 0xa000) as usize;
+                let addr = addr - 0xa000;
 
                 match (self.ram_enable, self.banking_mode) {
-                    (false, _) => 0xff,
-                    (_, Simple) => self.ram_banks[0][addr],
-                    (_, AdvancedRomOrRamBanking) => self.ram_banks[self.ram_current_bank as usize][addr]
+                    (false, _) => OpenBus::UNMAPPED,
+                    (_, Simple) => self.read_ram(0, addr),
+                    (_, AdvancedRomOrRamBanking) => self.read_ram(self.ram_current_bank as usize, addr)
                 }
             }
             _ => unreachable!()
@@ -156,13 +249,7 @@ impl Cartridge {
                 self.ram_enable = value & 0x0f == 0x0a;
             }
             0x2000..=0x3fff => {
-                let bank_count_mask = match self.cartridge_rom_size_type {
-                    0 => 0b0000_0001,
-                    1 => 0b0000_0011,
-                    2 => 0b0000_0111,
-                    3 => 0b0000_1111,
-                    _ => 0b0001_1111,
-                };
+                let bank_count_mask = (self.rom_banks.len().next_power_of_two() - 1).min(0b0001_1111) as u8;
 
                 let mut bank = self.rom_secondary_bank_register << 5 | (value & bank_count_mask);
 
@@ -184,7 +271,7 @@ impl Cartridge {
 
                     if self.cartridge_ram_size_type == 3 {
                         self.ram_current_bank = value;
-                    } else if self.cartridge_rom_size_type >= 5 {
+                    } else if self.rom_banks.len() > 32 {
                         // For 1MiB ROM or larger, use value for bits 4-5 of ROM bank number.
                         self.rom_secondary_bank_register = value;
                     }
@@ -209,9 +296,7 @@ impl Cartridge {
                     AdvancedRomOrRamBanking => self.ram_current_bank,
                 };
 
-                let addr = (addr - 0xa000) as usize;
-
-                self.ram_banks[bank as usize][addr] = value;
+                self.write_ram(bank as usize, addr - 0xa000, value);
             }
             _ => unreachable!()
         }
@@ -220,14 +305,14 @@ impl Cartridge {
 
     pub(crate) fn mem_read_mbc3(&self, addr: u16) -> u8 {
         match addr {
-            0x0000..=0x3fff => self.rom_banks[0][addr as usize],
-            0x4000..=0x7fff => self.rom_banks[self.rom_current_bank as usize][(addr - 0x4000) as usize],
+            0x0000..=0x3fff => self.read_rom(0, addr),
+            0x4000..=0x7fff => self.read_rom(self.rom_current_bank as usize, addr - 0x4000),
             0xa000..=0xbfff => {
-                let addr = (addr - 0xa000) as usize;
-
-                match self.ram_enable {
-                    false => 0xff,
-                    _ => self.ram_banks[self.ram_current_bank as usize][addr]
+                match (self.ram_enable, self.ram_current_bank, &self.rtc) {
+                    (false, _, _) => OpenBus::UNMAPPED,
+                    (_, bank @ 0x08..=0x0c, Some(rtc)) => rtc.read(bank),
+                    (_, 0x08..=0x0c, None) => OpenBus::UNMAPPED,
+                    (_, bank, _) => self.read_ram(bank as usize, addr - 0xa000)
                 }
             }
             _ => unreachable!()
@@ -249,17 +334,17 @@ impl Cartridge {
 
                 self.rom_current_bank = bank;
             }
-            0x4000..=0x5fff => match value {
-                0x00..=0x03 | 0x08..=0x0c => {
-                    self.ram_current_bank = value;
-                }
-                _ => unreachable!()
+            0x4000..=0x5fff => {
+                // Other values select nothing, which reads `OpenBus::UNMAPPED` and ignores writes.
+                self.ram_current_bank = value;
             }
             0x6000..=0x7fff => {
                 // When writing $00, and then $01 to this register, the current time becomes latched into the RTC registers. 
                 // The latched data will not change until it becomes latched again, by repeating the write $00->$01 procedure. 
                 // This provides a way to read the RTC registers while the clock keeps ticking.
-                // TODO: RTC latch
+                if let Some(rtc) = &mut self.rtc {
+                    rtc.write_latch(value);
+                }
             }
             0xa000..=0xbfff => {
                 if !self.ram_enable {
@@ -267,12 +352,8 @@ impl Cartridge {
                 }
 
                 match self.ram_current_bank {
-                    bank @ 0x00..=0x03 => {
-                        let addr = (addr - 0xa000) as usize;
-
-                        self.ram_banks[bank as usize][addr] = value;
-                    }
-                    0x08..=0x0c => {
+                    bank @ 0x00..=0x03 => self.write_ram(bank as usize, addr - 0xa000, value),
+                    bank @ 0x08..=0x0c => {
                         // $08  RTC S   Seconds   0-59 ($00-$3B)
                         // $09  RTC M   Minutes   0-59 ($00-$3B)
                         // $0A  RTC H   Hours     0-23 ($00-$17)
@@ -281,14 +362,56 @@ impl Cartridge {
                         //       Bit 0  Most significant bit of Day Counter (Bit 8)
                         //       Bit 6  Halt (0=Active, 1=Stop Timer)
                         //       Bit 7  Day Counter Carry Bit (1=Counter Overflow)
-                        // TODO: RTC register write
+                        if let Some(rtc) = &mut self.rtc {
+                            rtc.write(bank, value);
+                            self.dirty = true;
+                        }
                     }
-                    _ => unreachable!()
+                    _ => {}
                 }
             }
             _ => unreachable!()
         }
     }
+
+    /// Index of the RAM bank mapped to 0xa000-0xbfff, regardless of whether RAM is enabled.
+    fn mapped_ram_bank(&self) -> Option<usize> {
+        let bank = match self.mapper {
+            Mapper::None => 0,
+            Mapper::MBC1 => match self.banking_mode {
+                Simple => 0,
+                AdvancedRomOrRamBanking => self.ram_current_bank as usize,
+            },
+            // Banks 0x08-0x0c select the RTC registers.
+            Mapper::MBC3 => match self.ram_current_bank {
+                bank @ 0x00..=0x03 => bank as usize,
+                _ => return None,
+            },
+        };
+
+        (bank < self.ram_banks.len()).then_some(bank)
+    }
+
+    /// Reads like `mem_read`, except that RAM is readable even while disabled. Returns `OpenBus::UNMAPPED` where no RAM
+    /// is mapped.
+    pub fn mem_peek(&self, addr: u16) -> u8 {
+        match addr {
+            0xa000..=0xbfff => match self.mapped_ram_bank() {
+                Some(bank) => self.ram_banks[bank][((addr - 0xa000) & self.ram_mask) as usize],
+                None => OpenBus::UNMAPPED,
+            },
+            _ => self.mem_read(addr),
+        }
+    }
+
+    /// Writes to cartridge RAM, even while disabled. ROM can't be changed, and writes to it are ignored rather than
+    /// switching banks.
+    pub fn mem_poke(&mut self, addr: u16, value: u8) {
+        if let (0xa000..=0xbfff, Some(bank)) = (addr, self.mapped_ram_bank()) {
+            self.ram_banks[bank][((addr - 0xa000) & self.ram_mask) as usize] = value;
+            self.dirty = true;
+        }
+    }
 }
 
 impl Mem for Cartridge {
@@ -303,10 +426,220 @@ impl Mem for Cartridge {
 
     fn mem_write(&mut self, addr: u16, value: u8) {
         return match self.mapper {
-            Mapper::None => {}
+            Mapper::None => self.mem_write_mbc_none(addr, value),
             Mapper::MBC1 => self.mem_write_mbc1(addr, value),
             // Mapper::MBC2 => self.mem_write_mbc2(addr, value),
             Mapper::MBC3 => self.mem_write_mbc3(addr, value),
         };
     }
-}
\ No newline at end of file
+}
+
+// The ROM and the layout of the cartridge come from the loaded game, so only banking and RAM are saved.
+impl State for Cartridge {
+    fn save(&self, out: &mut Vec<u8>) {
+        (self.banking_mode == AdvancedRomOrRamBanking).save(out);
+        self.rom_current_bank.save(out);
+        self.rom_secondary_bank_register.save(out);
+        self.ram_enable.save(out);
+        self.ram_current_bank.save(out);
+
+        for bank in &self.ram_banks {
+            bank.save(out);
+        }
+
+        if let Some(rtc) = &self.rtc {
+            rtc.save(out);
+        }
+    }
+
+    fn load(&mut self, input: &mut StateReader) -> Result<(), CoreError> {
+        self.banking_mode = match read(input)? {
+            true => AdvancedRomOrRamBanking,
+            false => Simple,
+        };
+        self.rom_current_bank.load(input)?;
+        self.rom_secondary_bank_register.load(input)?;
+        self.ram_enable.load(input)?;
+        self.ram_current_bank.load(input)?;
+
+        for bank in &mut self.ram_banks {
+            bank.load(input)?;
+        }
+
+        if let Some(rtc) = &mut self.rtc {
+            rtc.load(input)?;
+        }
+
+        // RAM no longer matches what was last saved to the battery file.
+        self.dirty = true;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gameboy::{
+        header::{self, OFFSET_CARTRIDGE_TYPE, OFFSET_ROM_SIZE},
+        rtc,
+    };
+
+    /// A ROM of the given header bytes, where every bank starts with its number.
+    fn rom(cartridge_type: u8, rom_size_type: u8, ram_size_type: u8) -> Vec<u8> {
+        let mut rom = vec![0; header::rom_size(rom_size_type).unwrap()];
+
+        for (bank, data) in rom.chunks_exact_mut(0x4000).enumerate() {
+            data[0] = bank as u8;
+        }
+
+        rom[OFFSET_CARTRIDGE_TYPE] = cartridge_type;
+        rom[OFFSET_ROM_SIZE] = rom_size_type;
+        rom[OFFSET_RAM_SIZE] = ram_size_type;
+
+        rom
+    }
+
+    /// The bank mapped to 0x4000-0x7fff after selecting `bank`.
+    fn switched_bank(cartridge: &mut Cartridge, bank: u8) -> u8 {
+        cartridge.mem_write(0x2000, bank);
+
+        cartridge.mem_read(0x4000)
+    }
+
+    #[test]
+    fn banks_of_sizes_that_arent_a_power_of_two_wrap_around() {
+        // MBC3, 72 banks.
+        let mut cartridge = Cartridge::load(rom(0x11, 0x52, 0)).unwrap();
+
+        assert_eq!(cartridge.rom_bank_index(71), 71);
+        assert_eq!(cartridge.rom_bank_index(72), 0);
+        assert_eq!(cartridge.rom_bank_index(100), 28);
+        assert_eq!(cartridge.rom_bank_index(127), 55);
+
+        assert_eq!(switched_bank(&mut cartridge, 0x47), 71);
+        assert_eq!(switched_bank(&mut cartridge, 0x48), 0);
+        assert_eq!(switched_bank(&mut cartridge, 0x7f), 55);
+    }
+
+    #[test]
+    fn banks_past_the_end_mirror_the_start() {
+        // MBC1 and MBC3, 4 banks.
+        for cartridge_type in [0x01, 0x11] {
+            let mut cartridge = Cartridge::load(rom(cartridge_type, 0x01, 0)).unwrap();
+
+            assert_eq!(switched_bank(&mut cartridge, 3), 3);
+            assert_eq!(switched_bank(&mut cartridge, 5), 1);
+            assert_eq!(switched_bank(&mut cartridge, 6), 2);
+            assert_eq!(switched_bank(&mut cartridge, 0x1f), 3);
+            assert_eq!(cartridge.mapped_rom_banks(), [0, 3]);
+        }
+
+        let mut cartridge = Cartridge::load(rom(0x11, 0x01, 0)).unwrap();
+        assert_eq!(switched_bank(&mut cartridge, 0x44), 0);
+    }
+
+    #[test]
+    fn ram_smaller_than_a_bank_mirrors_within_it() {
+        // MBC1+RAM, 2 KiB of RAM.
+        let mut cartridge = Cartridge::load(rom(0x02, 0x00, 0x01)).unwrap();
+        cartridge.mem_write(0x0000, 0x0a);
+
+        cartridge.mem_write(0xa123, 0x42);
+        cartridge.mem_write(0xbfff, 0x17);
+
+        for addr in [0xa123, 0xa923, 0xb123, 0xb923] {
+            assert_eq!(cartridge.mem_read(addr), 0x42, "{addr:04x}");
+        }
+
+        assert_eq!(cartridge.mem_read(0xa7ff), 0x17);
+        assert_eq!(cartridge.mem_peek(0xafff), 0x17);
+    }
+
+    #[test]
+    fn unknown_ram_sizes_load_without_ram() {
+        let mut cartridge = Cartridge::load(rom(0x02, 0x00, 0x07)).unwrap();
+        cartridge.mem_write(0x0000, 0x0a);
+
+        cartridge.mem_write(0xa000, 0x42);
+        cartridge.mem_poke(0xa000, 0x42);
+
+        assert_eq!(cartridge.mem_read(0xa000), OpenBus::UNMAPPED);
+        assert_eq!(cartridge.mem_peek(0xbfff), OpenBus::UNMAPPED);
+    }
+
+    #[test]
+    fn battery_ram_is_taken_once_written() {
+        // MBC1+RAM+BATTERY, 8 KiB of RAM.
+        let mut cartridge = Cartridge::load(rom(0x03, 0x00, 0x02)).unwrap();
+        cartridge.mem_write(0x0000, 0x0a);
+
+        assert_eq!(cartridge.take_ram(0), None);
+
+        cartridge.mem_write(0xa001, 0x42);
+
+        let ram = cartridge.take_ram(0).unwrap();
+        assert_eq!((ram.len(), ram[1]), (0x2000, 0x42));
+        assert_eq!(cartridge.take_ram(0), None);
+
+        cartridge.mem_poke(0xa002, 0x17);
+        assert_eq!(cartridge.take_ram(0).map(|ram| ram[2]), Some(0x17));
+
+        // Writes while RAM is disabled don't reach it.
+        cartridge.mem_write(0x0000, 0x00);
+        cartridge.mem_write(0xa003, 0x01);
+        assert_eq!(cartridge.take_ram(0), None);
+    }
+
+    #[test]
+    fn clock_writes_make_ram_dirty() {
+        // MBC3+TIMER+RAM+BATTERY, 8 KiB of RAM.
+        let mut cartridge = Cartridge::load(rom(0x10, 0x00, 0x02)).unwrap();
+        cartridge.mem_write(0x0000, 0x0a);
+        cartridge.mem_write(0x4000, 0x09);
+        cartridge.mem_write(0xa000, 30);
+
+        let save = cartridge.take_ram(1234).unwrap();
+        assert_eq!(save.len(), 0x2000 + rtc::FOOTER_SIZE);
+        assert_eq!(save[0x2000 + 4], 30);
+        assert_eq!(save[0x2000 + 40..], 1234u64.to_le_bytes());
+        assert_eq!(cartridge.take_ram(1234), None);
+    }
+
+    #[test]
+    fn saves_with_a_clock_of_an_unknown_size_restore_only_ram() {
+        // MBC3+TIMER+RAM+BATTERY, 8 KiB of RAM.
+        let mut saved = Cartridge::load(rom(0x10, 0x00, 0x02)).unwrap();
+        saved.mem_write(0x0000, 0x0a);
+        saved.mem_write(0x4000, 0x09);
+        saved.mem_write(0xa000, 30);
+        saved.mem_write(0x4000, 0x00);
+        saved.mem_write(0xa000, 0x42);
+
+        let save = saved.take_ram(1234).unwrap();
+
+        let mut cartridge = Cartridge::load(rom(0x10, 0x00, 0x02)).unwrap();
+        assert!(cartridge.load_ram(&save, 1234).is_ok());
+        assert_eq!(cartridge.take_ram(1234), None);
+        assert_eq!(cartridge.ram_banks[0][0], 0x42);
+        assert_eq!(cartridge.rtc.as_ref().unwrap().footer(1234), saved.rtc.as_ref().unwrap().footer(1234));
+
+        let mut cartridge = Cartridge::load(rom(0x10, 0x00, 0x02)).unwrap();
+        let result = cartridge.load_ram(&save[..0x2000 + 10], 1234);
+
+        assert!(matches!(result, Err(CoreError::BadSaveClock { size: 10 })));
+        assert_eq!(cartridge.ram_banks[0][0], 0x42);
+        assert_eq!(cartridge.rtc.as_ref().unwrap().footer(1234), Rtc::new().footer(1234));
+    }
+
+    #[test]
+    fn ram_without_a_battery_is_never_taken() {
+        // MBC1+RAM, 8 KiB of RAM.
+        let mut cartridge = Cartridge::load(rom(0x02, 0x00, 0x02)).unwrap();
+        cartridge.mem_write(0x0000, 0x0a);
+        cartridge.mem_write(0xa000, 0x42);
+        cartridge.mem_poke(0xa001, 0x42);
+
+        assert_eq!(cartridge.take_ram(0), None);
+    }
+}
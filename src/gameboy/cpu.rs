@@ -1,10 +1,15 @@
 ﻿use bitflags::Flags;
 use crate::gameboy::bus::Bus;
-use crate::gameboy::{Mem, SCREEN_WIDTH};
+use crate::gameboy::{Model, SCREEN_WIDTH};
 use super::{
     cpu_registers::{CpuFlags, CpuRegisters},
-    io_registers::InterruptFlags
+    event_log::Event,
+    io_registers::InterruptFlags,
+    state::state_fields,
 };
+pub(crate) use self::opcodes::{cb_opcode, CB_PREFIX, OPCODES};
+
+mod opcodes;
 
 #[derive(Clone, Copy)]
 #[repr(transparent)]
@@ -30,950 +35,187 @@ impl Into<u32> for MCycles {
     }
 }
 
-fn invalid_instruction() {
-    // panic!("invalid instruction")
+/// The opcodes the SM83 doesn't have lock it up, until it is reset.
+fn invalid_instruction(cpu: &mut Cpu) {
+    cpu.locked = true;
+}
+
+/// Number of instructions kept by `PcHistory`.
+pub(crate) const PC_HISTORY_LEN: usize = 64;
+
+/// The addresses of the last instructions run, for diagnosing crashes.
+#[derive(Clone)]
+pub(crate) struct PcHistory {
+    pcs: [u16; PC_HISTORY_LEN],
+    /// Where the next address goes, overwriting the oldest once full.
+    next: usize,
+    len: usize,
+}
+
+impl PcHistory {
+    fn new() -> Self {
+        Self {
+            pcs: [0; PC_HISTORY_LEN],
+            next: 0,
+            len: 0,
+        }
+    }
+
+    fn push(&mut self, pc: u16) {
+        self.pcs[self.next] = pc;
+        self.next = (self.next + 1) % PC_HISTORY_LEN;
+        self.len = (self.len + 1).min(PC_HISTORY_LEN);
+    }
+
+    /// The addresses, oldest first.
+    pub fn iter(&self) -> impl Iterator<Item = u16> + '_ {
+        let start = (self.next + PC_HISTORY_LEN - self.len) % PC_HISTORY_LEN;
+
+        (0..self.len).map(move |index| self.pcs[(start + index) % PC_HISTORY_LEN])
+    }
 }
 
 pub struct Cpu {
     interrupts_master_enable: bool,
     registers: CpuRegisters,
     halted: bool,
+    /// Set by STOP, see `stop`.
+    stopped: bool,
+    /// Set by an invalid opcode, after which no instructions run and interrupts aren't serviced.
+    locked: bool,
+    pc_history: PcHistory,
 }
 
 impl Cpu {
-    pub fn new() -> Self {
+    pub fn new(model: Model) -> Self {
         Self {
             interrupts_master_enable: true,
-            registers: Default::default(),
+            registers: CpuRegisters::after_boot(model),
             halted: false,
+            stopped: false,
+            locked: false,
+            pc_history: PcHistory::new(),
         }
     }
     
-    pub fn reset(&mut self) {
-        *self = Self::new();
+    pub fn reset(&mut self, model: Model) {
+        *self = Self::new(model);
+    }
+
+    pub fn pc(&self) -> u16 {
+        self.registers.pc
+    }
+
+    pub fn registers(&self) -> &CpuRegisters {
+        &self.registers
+    }
+
+    pub fn halted(&self) -> bool {
+        self.halted
+    }
+
+    pub fn stopped(&self) -> bool {
+        self.stopped
+    }
+
+    pub fn locked(&self) -> bool {
+        self.locked
+    }
+
+    /// Runs instructions again after locking up, for a state that was loaded over it.
+    pub fn unlock(&mut self) {
+        self.locked = false;
+    }
+
+    pub fn pc_history(&self) -> &PcHistory {
+        &self.pc_history
     }
 
     pub fn tick(&mut self, bus: &mut Bus) -> MCycles {
-        // Handle DMA copy sequence.
+        if self.stopped {
+            // Only a joypad line going low wakes the CPU from STOP, whatever IE and IME are. The joypad interrupt
+            // requested along with it is then handled as usual, and other interrupts stay pending in IF until then.
+            if !bus.io_registers.joypad_line_low() {
+                return MCycles(1);
+            }
+
+            self.stopped = false;
+        }
+
+        // Handle DMA copy sequence. A transfer restarted by a write to 0xff46 goes on until the new one starts.
         if bus.io_registers.dma_counter > 0 {
-            let src_base_addr = (bus.io_registers.dma as u16) << 8;
+            let src_base_addr = (bus.io_registers.dma_transfer.source as u16) << 8;
 
             for _ in 0..4 {
                 let byte_index: u16 = SCREEN_WIDTH as u16 - bus.io_registers.dma_counter as u16;
 
-                let v = bus.mem_read(src_base_addr + byte_index);
-                bus.mem_write(0xfe00 + byte_index, v);
+                bus.dma_copy(src_base_addr + byte_index, 0xfe00 + byte_index);
 
                 bus.io_registers.dma_counter -= 1;
             }
         }
+
+        bus.io_registers.tick_dma_start();
         
-        if self.interrupt_service_routine(bus) {
+        // A locked CPU only stops running instructions, DMA and the rest of the system go on.
+        if !self.locked && self.interrupt_service_routine(bus) {
             return MCycles(5);
         };
 
         let m_cycles = self.handle_instruction(bus);
 
-        self.handle_timers(bus, m_cycles);
+        // Otherwise, the timers ran along with the memory accesses of the instruction, see `Bus::cpu_read`.
+        if bus.accuracy().batched_timing {
+            bus.io_registers.tick_timers(m_cycles.t_cycles());
+        }
 
         return m_cycles;
     }
 
     fn handle_instruction(&mut self, bus: &mut Bus) -> MCycles {
-        let mut m_cycles = MCycles(1);
-
-        if self.halted {
-            return m_cycles;
+        if self.halted || self.locked {
+            return MCycles(1);
         }
-        
-        let instruction = self.read_u8(bus);
-
-        match instruction {
-            0x00 => {}
-            0x01 => {
-                let value = self.read_u16(bus);
-                self.registers.set_bc(value);
-
-                m_cycles = MCycles(3);
-            }
-            0x02 => {
-                bus.mem_write(self.registers.bc(), self.registers.a);
-
-                m_cycles = MCycles(2);
-            }
-            0x03 => {
-                self.registers.set_bc(self.registers.bc().wrapping_add(1));
-
-                m_cycles = MCycles(2);
-            }
-            0x04 => self.registers.b = self.inc_r8(self.registers.b),
-            0x05 => self.registers.b = self.dec_r8(self.registers.b),
-            0x06 => {
-                self.registers.b = self.read_u8(bus);
-
-                m_cycles = MCycles(2);
-            }
-            0x07 => {
-                self.registers.a = self.rlc(self.registers.a);
-                self.registers.f.remove(CpuFlags::ZERO);
-            }
-            0x08 => {
-                let addr = self.read_u16(bus);
-                bus.mem_write(addr, (self.registers.sp & 0xff) as u8);
-                bus.mem_write(addr + 1, (self.registers.sp >> 8) as u8);
-
-                m_cycles = MCycles(5);
-            }
-            0x09 => {
-                self.add_hl(self.registers.bc());
-
-                m_cycles = MCycles(2);
-            }
-            0x0a => {
-                self.registers.a = bus.mem_read(self.registers.bc());
-
-                m_cycles = MCycles(2);
-            }
-            0x0b => {
-                self.registers.set_bc(self.registers.bc().wrapping_sub(1));
-
-                m_cycles = MCycles(2);
-            }
-            0x0c => self.registers.c = self.inc_r8(self.registers.c),
-            0x0d => self.registers.c = self.dec_r8(self.registers.c),
-            0x0e => {
-                self.registers.c = self.read_u8(bus);
-
-                m_cycles = MCycles(2);
-            }
-            0x0f => {
-                self.registers.a = self.rrc(self.registers.a);
-                self.registers.f.remove(CpuFlags::ZERO);
-            }
-            0x10 => {
-                let _ = self.read_u8(bus);
-
-                bus.io_registers.cpu_clock = 0;
-            }
-            0x11 => {
-                let value = self.read_u16(bus);
-                self.registers.set_de(value);
 
-                m_cycles = MCycles(3);
-            }
-            0x12 => {
-                bus.mem_write(self.registers.de(), self.registers.a);
-
-                m_cycles = MCycles(2);
-            }
-            0x13 => {
-                let value = self.registers.de().wrapping_add(1);
-                self.registers.set_de(value);
-
-                m_cycles = MCycles(2);
-            }
-            0x14 => self.registers.d = self.inc_r8(self.registers.d),
-            0x15 => self.registers.d = self.dec_r8(self.registers.d),
-            0x16 => {
-                self.registers.d = self.read_u8(bus);
-
-                m_cycles = MCycles(2);
-            }
-            0x17 => {
-                self.registers.a = self.rl(self.registers.a);
-                self.registers.f.remove(CpuFlags::ZERO);
-            }
-            0x18 => {
-                let offset = self.read_i8(bus);
-                self.jr(offset);
-
-                m_cycles = MCycles(3);
-            }
-            0x19 => {
-                self.add_hl(self.registers.de());
-
-                m_cycles = MCycles(2);
-            }
-            0x1a => {
-                self.registers.a = bus.mem_read(self.registers.de());
-
-                m_cycles = MCycles(2);
-            }
-            0x1b => {
-                self.registers.set_de(self.registers.de().wrapping_sub(1));
-
-                m_cycles = MCycles(2);
-            }
-            0x1c => self.registers.e = self.inc_r8(self.registers.e),
-            0x1d => self.registers.e = self.dec_r8(self.registers.e),
-            0x1e => {
-                self.registers.e = self.read_u8(bus);
-
-                m_cycles = MCycles(2);
-            }
-            0x1f => {
-                self.registers.a = self.rr(self.registers.a);
-                self.registers.f.remove(CpuFlags::ZERO);
-            }
-            0x20 => {
-                let offset = self.read_i8(bus);
-
-                m_cycles = MCycles(2);
-
-                if !self.registers.f.contains(CpuFlags::ZERO) {
-                    self.jr(offset);
-
-                    m_cycles = MCycles(3);
-                }
-            }
-            0x21 => {
-                let value = self.read_u16(bus);
-                self.registers.set_hl(value);
-
-                m_cycles = MCycles(3);
-            }
-            0x22 => {
-                bus.mem_write(self.registers.hl(), self.registers.a);
-                self.inc_hl();
-
-                m_cycles = MCycles(2);
-            }
-            0x23 => {
-                self.inc_hl();
-
-                m_cycles = MCycles(2);
-            }
-            0x24 => self.registers.h = self.inc_r8(self.registers.h),
-            0x25 => self.registers.h = self.dec_r8(self.registers.h),
-            0x26 => {
-                self.registers.h = self.read_u8(bus);
-
-                m_cycles = MCycles(2);
-            }
-            0x27 => self.daa(),
-            0x28 => {
-                let offset = self.read_i8(bus);
-
-                m_cycles = MCycles(2);
-
-                if self.registers.f.contains(CpuFlags::ZERO) {
-                    self.jr(offset);
-
-                    m_cycles = MCycles(3);
-                }
-            }
-            0x29 => {
-                self.add_hl(self.registers.hl());
-
-                m_cycles = MCycles(2);
-            }
-            0x2a => {
-                self.registers.a = bus.mem_read(self.registers.hl());
-                self.inc_hl();
-
-                m_cycles = MCycles(2);
-            }
-            0x2b => {
-                self.dec_hl();
-
-                m_cycles = MCycles(2);
-            }
-            0x2c => self.registers.l = self.inc_r8(self.registers.l),
-            0x2d => self.registers.l = self.dec_r8(self.registers.l),
-            0x2e => {
-                self.registers.l = self.read_u8(bus);
-
-                m_cycles = MCycles(2);
-            }
-            0x2f => {
-                self.registers.a = !self.registers.a;
-                self.registers.f.insert(CpuFlags::NEGATIVE | CpuFlags::HALF_CARRY);
-            }
-            0x30 => {
-                let offset = self.read_i8(bus);
-
-                m_cycles = MCycles(2);
-
-                if !self.registers.f.contains(CpuFlags::CARRY) {
-                    self.jr(offset);
-
-                    m_cycles = MCycles(3);
-                }
-            }
-            0x31 => {
-                self.registers.sp = self.read_u16(bus);
-
-                m_cycles = MCycles(3);
-            }
-            0x32 => {
-                bus.mem_write(self.registers.hl(), self.registers.a);
-                self.dec_hl();
-
-                m_cycles = MCycles(2);
-            }
-            0x33 => {
-                self.registers.sp = self.registers.sp.wrapping_add(1);
-
-                m_cycles = MCycles(2);
-            }
-            0x34 => {
-                let addr = self.registers.hl();
-                let value = bus.mem_read(addr);
-                let result = value.wrapping_add(1);
-
-                self.registers.f.set(CpuFlags::ZERO, result == 0);
-                self.registers.f.remove(CpuFlags::NEGATIVE);
-                self.registers.f.set(CpuFlags::HALF_CARRY, value & 0x0f == 0x0f);
-
-                bus.mem_write(addr, result);
-
-                m_cycles = MCycles(3);
-            }
-            0x35 => {
-                let addr = self.registers.hl();
-                let value = bus.mem_read(addr);
-                let result = value.wrapping_sub(1);
-
-                self.registers.f.set(CpuFlags::ZERO, result == 0);
-                self.registers.f.insert(CpuFlags::NEGATIVE);
-                self.registers.f.set(CpuFlags::HALF_CARRY, value & 0x0f == 0);
-
-                bus.mem_write(addr, result);
-
-                m_cycles = MCycles(3);
-            }
-            0x36 => {
-                let value = self.read_u8(bus);
-                bus.mem_write(self.registers.hl(), value);
-
-                m_cycles = MCycles(3);
-            }
-            0x37 => {
-                self.registers.f.insert(CpuFlags::CARRY);
-                self.registers.f.remove(CpuFlags::NEGATIVE | CpuFlags::HALF_CARRY);
-            }
-            0x38 => {
-                let offset = self.read_i8(bus);
-
-                m_cycles = MCycles(2);
-
-                if self.registers.f.contains(CpuFlags::CARRY) {
-                    self.jr(offset);
-
-                    m_cycles = MCycles(3);
-                }
-            }
-            0x39 => {
-                self.add_hl(self.registers.sp);
-
-                m_cycles = MCycles(2);
-            }
-            0x3a => {
-                self.registers.a = bus.mem_read(self.registers.hl());
-                self.dec_hl();
-
-                m_cycles = MCycles(2);
-            }
-            0x3b => {
-                self.registers.sp = self.registers.sp.wrapping_sub(1);
-
-                m_cycles = MCycles(2);
-            }
-            0x3c => self.registers.a = self.inc_r8(self.registers.a),
-            0x3d => self.registers.a = self.dec_r8(self.registers.a),
-            0x3e => {
-                self.registers.a = self.read_u8(bus);
-
-                m_cycles = MCycles(2);
-            }
-            0x3f => {
-                self.registers.f.remove(CpuFlags::NEGATIVE | CpuFlags::HALF_CARRY);
-                self.registers.f.toggle(CpuFlags::CARRY);
-            }
-            0x40 => self.registers.b = self.registers.b,
-            0x41 => self.registers.b = self.registers.c,
-            0x42 => self.registers.b = self.registers.d,
-            0x43 => self.registers.b = self.registers.e,
-            0x44 => self.registers.b = self.registers.h,
-            0x45 => self.registers.b = self.registers.l,
-            0x46 => {
-                self.registers.b = bus.mem_read(self.registers.hl());
-
-                m_cycles = MCycles(2);
-            }
-            0x47 => self.registers.b = self.registers.a,
-            0x48 => self.registers.c = self.registers.b,
-            0x49 => self.registers.c = self.registers.c,
-            0x4a => self.registers.c = self.registers.d,
-            0x4b => self.registers.c = self.registers.e,
-            0x4c => self.registers.c = self.registers.h,
-            0x4d => self.registers.c = self.registers.l,
-            0x4e => {
-                self.registers.c = bus.mem_read(self.registers.hl());
-
-                m_cycles = MCycles(2);
-            }
-            0x4f => self.registers.c = self.registers.a,
-            0x50 => self.registers.d = self.registers.b,
-            0x51 => self.registers.d = self.registers.c,
-            0x52 => self.registers.d = self.registers.d,
-            0x53 => self.registers.d = self.registers.e,
-            0x54 => self.registers.d = self.registers.h,
-            0x55 => self.registers.d = self.registers.l,
-            0x56 => {
-                self.registers.d = bus.mem_read(self.registers.hl());
-
-                m_cycles = MCycles(2);
-            }
-            0x57 => self.registers.d = self.registers.a,
-            0x58 => self.registers.e = self.registers.b,
-            0x59 => self.registers.e = self.registers.c,
-            0x5a => self.registers.e = self.registers.d,
-            0x5b => self.registers.e = self.registers.e,
-            0x5c => self.registers.e = self.registers.h,
-            0x5d => self.registers.e = self.registers.l,
-            0x5e => {
-                self.registers.e = bus.mem_read(self.registers.hl());
-
-                m_cycles = MCycles(2);
-            }
-            0x5f => self.registers.e = self.registers.a,
-            0x60 => self.registers.h = self.registers.b,
-            0x61 => self.registers.h = self.registers.c,
-            0x62 => self.registers.h = self.registers.d,
-            0x63 => self.registers.h = self.registers.e,
-            0x64 => self.registers.h = self.registers.h,
-            0x65 => self.registers.h = self.registers.l,
-            0x66 => {
-                self.registers.h = bus.mem_read(self.registers.hl());
-
-                m_cycles = MCycles(2);
-            }
-            0x67 => self.registers.h = self.registers.a,
-            0x68 => self.registers.l = self.registers.b,
-            0x69 => self.registers.l = self.registers.c,
-            0x6a => self.registers.l = self.registers.d,
-            0x6b => self.registers.l = self.registers.e,
-            0x6c => self.registers.l = self.registers.h,
-            0x6d => self.registers.l = self.registers.l,
-            0x6e => {
-                self.registers.l = bus.mem_read(self.registers.hl());
-
-                m_cycles = MCycles(2);
-            }
-            0x6f => self.registers.l = self.registers.a,
-            0x70 => {
-                bus.mem_write(self.registers.hl(), self.registers.b);
-
-                m_cycles = MCycles(2);
-            }
-            0x71 => {
-                bus.mem_write(self.registers.hl(), self.registers.c);
-
-                m_cycles = MCycles(2);
-            }
-            0x72 => {
-                bus.mem_write(self.registers.hl(), self.registers.d);
-
-                m_cycles = MCycles(2);
-            }
-            0x73 => {
-                bus.mem_write(self.registers.hl(), self.registers.e);
-
-                m_cycles = MCycles(2);
-            }
-            0x74 => {
-                bus.mem_write(self.registers.hl(), self.registers.h);
-
-                m_cycles = MCycles(2);
-            }
-            0x75 => {
-                bus.mem_write(self.registers.hl(), self.registers.l);
-
-                m_cycles = MCycles(2);
-            }
-            0x76 => self.halted = true,
-            0x77 => {
-                bus.mem_write(self.registers.hl(), self.registers.a);
-
-                m_cycles = MCycles(2);
-            }
-            0x78 => self.registers.a = self.registers.b,
-            0x79 => self.registers.a = self.registers.c,
-            0x7a => self.registers.a = self.registers.d,
-            0x7b => self.registers.a = self.registers.e,
-            0x7c => self.registers.a = self.registers.h,
-            0x7d => self.registers.a = self.registers.l,
-            0x7e => {
-                self.registers.a = bus.mem_read(self.registers.hl());
-
-                m_cycles = MCycles(2);
-            }
-            0x7f => self.registers.a = self.registers.a,
-            0x80 => self.registers.a = self.add(self.registers.a, self.registers.b),
-            0x81 => self.registers.a = self.add(self.registers.a, self.registers.c),
-            0x82 => self.registers.a = self.add(self.registers.a, self.registers.d),
-            0x83 => self.registers.a = self.add(self.registers.a, self.registers.e),
-            0x84 => self.registers.a = self.add(self.registers.a, self.registers.h),
-            0x85 => self.registers.a = self.add(self.registers.a, self.registers.l),
-            0x86 => {
-                self.registers.a = self.add(self.registers.a, bus.mem_read(self.registers.hl()));
-
-                m_cycles = MCycles(2);
-            }
-            0x87 => self.registers.a = self.add(self.registers.a, self.registers.a),
-            0x88 => self.registers.a = self.adc(self.registers.a, self.registers.b),
-            0x89 => self.registers.a = self.adc(self.registers.a, self.registers.c),
-            0x8a => self.registers.a = self.adc(self.registers.a, self.registers.d),
-            0x8b => self.registers.a = self.adc(self.registers.a, self.registers.e),
-            0x8c => self.registers.a = self.adc(self.registers.a, self.registers.h),
-            0x8d => self.registers.a = self.adc(self.registers.a, self.registers.l),
-            0x8e => {
-                self.registers.a = self.adc(self.registers.a, bus.mem_read(self.registers.hl()));
-
-                m_cycles = MCycles(2);
-            }
-            0x8f => self.registers.a = self.adc(self.registers.a, self.registers.a),
-            0x90 => self.registers.a = self.sub(self.registers.a, self.registers.b),
-            0x91 => self.registers.a = self.sub(self.registers.a, self.registers.c),
-            0x92 => self.registers.a = self.sub(self.registers.a, self.registers.d),
-            0x93 => self.registers.a = self.sub(self.registers.a, self.registers.e),
-            0x94 => self.registers.a = self.sub(self.registers.a, self.registers.h),
-            0x95 => self.registers.a = self.sub(self.registers.a, self.registers.l),
-            0x96 => {
-                self.registers.a = self.sub(self.registers.a, bus.mem_read(self.registers.hl()));
-
-                m_cycles = MCycles(2);
-            }
-            0x97 => self.registers.a = self.sub(self.registers.a, self.registers.a),
-            0x98 => self.registers.a = self.sbc(self.registers.a, self.registers.b),
-            0x99 => self.registers.a = self.sbc(self.registers.a, self.registers.c),
-            0x9a => self.registers.a = self.sbc(self.registers.a, self.registers.d),
-            0x9b => self.registers.a = self.sbc(self.registers.a, self.registers.e),
-            0x9c => self.registers.a = self.sbc(self.registers.a, self.registers.h),
-            0x9d => self.registers.a = self.sbc(self.registers.a, self.registers.l),
-            0x9e => {
-                self.registers.a = self.sbc(self.registers.a, bus.mem_read(self.registers.hl()));
-
-                m_cycles = MCycles(2);
-            }
-            0x9f => self.registers.a = self.sbc(self.registers.a, self.registers.a),
-            0xa0 => self.registers.a = self.and(self.registers.a, self.registers.b),
-            0xa1 => self.registers.a = self.and(self.registers.a, self.registers.c),
-            0xa2 => self.registers.a = self.and(self.registers.a, self.registers.d),
-            0xa3 => self.registers.a = self.and(self.registers.a, self.registers.e),
-            0xa4 => self.registers.a = self.and(self.registers.a, self.registers.h),
-            0xa5 => self.registers.a = self.and(self.registers.a, self.registers.l),
-            0xa6 => {
-                self.registers.a = self.and(self.registers.a, bus.mem_read(self.registers.hl()));
-
-                m_cycles = MCycles(2);
-            }
-            0xa7 => self.registers.a = self.and(self.registers.a, self.registers.a),
-            0xa8 => self.registers.a = self.xor(self.registers.a, self.registers.b),
-            0xa9 => self.registers.a = self.xor(self.registers.a, self.registers.c),
-            0xaa => self.registers.a = self.xor(self.registers.a, self.registers.d),
-            0xab => self.registers.a = self.xor(self.registers.a, self.registers.e),
-            0xac => self.registers.a = self.xor(self.registers.a, self.registers.h),
-            0xad => self.registers.a = self.xor(self.registers.a, self.registers.l),
-            0xae => {
-                self.registers.a = self.xor(self.registers.a, bus.mem_read(self.registers.hl()));
-
-                m_cycles = MCycles(2);
-            }
-            0xaf => self.registers.a = self.xor(self.registers.a, self.registers.a),
-            0xb0 => self.registers.a = self.or(self.registers.a, self.registers.b),
-            0xb1 => self.registers.a = self.or(self.registers.a, self.registers.c),
-            0xb2 => self.registers.a = self.or(self.registers.a, self.registers.d),
-            0xb3 => self.registers.a = self.or(self.registers.a, self.registers.e),
-            0xb4 => self.registers.a = self.or(self.registers.a, self.registers.h),
-            0xb5 => self.registers.a = self.or(self.registers.a, self.registers.l),
-            0xb6 => {
-                self.registers.a = self.or(self.registers.a, bus.mem_read(self.registers.hl()));
-
-                m_cycles = MCycles(2);
-            }
-            0xb7 => self.registers.a = self.or(self.registers.a, self.registers.a),
-            0xb8 => self.cp(self.registers.a, self.registers.b),
-            0xb9 => self.cp(self.registers.a, self.registers.c),
-            0xba => self.cp(self.registers.a, self.registers.d),
-            0xbb => self.cp(self.registers.a, self.registers.e),
-            0xbc => self.cp(self.registers.a, self.registers.h),
-            0xbd => self.cp(self.registers.a, self.registers.l),
-            0xbe => {
-                self.cp(self.registers.a, bus.mem_read(self.registers.hl()));
-
-                m_cycles = MCycles(2);
-            }
-            0xbf => self.cp(self.registers.a, self.registers.a),
-            0xc0 => {
-                m_cycles = MCycles(2);
-
-                if !self.registers.f.contains(CpuFlags::ZERO) {
-                    self.ret(bus);
-
-                    m_cycles = MCycles(5);
-                }
-            }
-            0xc1 => {
-                let value = self.pop(bus);
-                self.registers.set_bc(value);
-
-                m_cycles = MCycles(3);
-            }
-            0xc2 => {
-                let addr = self.read_u16(bus);
-
-                m_cycles = MCycles(3);
-
-                if !self.registers.f.contains(CpuFlags::ZERO) {
-                    self.registers.pc = addr;
-
-                    m_cycles = MCycles(4);
-                }
-            }
-            0xc3 => {
-                self.registers.pc = self.read_u16(bus);
-
-                m_cycles = MCycles(4);
-            }
-            0xc4 => {
-                let addr = self.read_u16(bus);
-
-                m_cycles = MCycles(3);
-
-                if !self.registers.f.contains(CpuFlags::ZERO) {
-                    self.call(bus, addr);
-
-                    m_cycles = MCycles(6);
-                }
-            }
-            0xc5 => {
-                self.push(bus, self.registers.bc());
-
-                m_cycles = MCycles(4);
-            }
-            0xc6 => {
-                let value = self.read_u8(bus);
-                self.registers.a = self.add(self.registers.a, value);
-
-                m_cycles = MCycles(2);
-            }
-            0xc7 => {
-                self.call(bus, 0x0000);
-
-                m_cycles = MCycles(4);
-            }
-            0xc8 => {
-                m_cycles = MCycles(2);
+        self.pc_history.push(self.registers.pc);
 
-                if self.registers.f.contains(CpuFlags::ZERO) {
-                    self.ret(bus);
-
-                    m_cycles = MCycles(5);
-                }
-            }
-            0xc9 => {
-                self.ret(bus);
-
-                m_cycles = MCycles(4);
-            }
-            0xca => {
-                let addr = self.read_u16(bus);
-
-                m_cycles = MCycles(3);
-
-                if self.registers.f.contains(CpuFlags::ZERO) {
-                    self.registers.pc = addr;
-
-                    m_cycles = MCycles(4);
-                }
-            }
-            0xcb => {
-                let value = self.read_u8(bus);
-                m_cycles = self.cb(bus, value);
-            }
-            0xcc => {
-                let addr = self.read_u16(bus);
-
-                m_cycles = MCycles(3);
-
-                if self.registers.f.contains(CpuFlags::ZERO) {
-                    self.call(bus, addr);
-
-                    m_cycles = MCycles(6);
-                }
-            }
-            0xcd => {
-                let addr = self.read_u16(bus);
-                self.call(bus, addr);
-
-                m_cycles = MCycles(6);
-            }
-            0xce => {
-                let value = self.read_u8(bus);
-                self.registers.a = self.adc(self.registers.a, value);
-
-                m_cycles = MCycles(2);
-            }
-            0xcf => {
-                self.call(bus, 0x0008);
-
-                m_cycles = MCycles(4);
-            }
-            0xd0 => {
-                m_cycles = MCycles(2);
-
-                if !self.registers.f.contains(CpuFlags::CARRY) {
-                    self.ret(bus);
-
-                    m_cycles = MCycles(5);
-                }
-            }
-            0xd1 => {
-                let value = self.pop(bus);
-                self.registers.set_de(value);
-
-                m_cycles = MCycles(3);
-            }
-            0xd2 => {
-                let addr = self.read_u16(bus);
-
-                m_cycles = MCycles(3);
-
-                if !self.registers.f.contains(CpuFlags::CARRY) {
-                    self.registers.pc = addr;
-
-                    m_cycles = MCycles(4);
-                }
-            }
-            0xd3 => invalid_instruction(),
-            0xd4 => {
-                let addr = self.read_u16(bus);
-
-                m_cycles = MCycles(3);
-
-                if !self.registers.f.contains(CpuFlags::CARRY) {
-                    self.call(bus, addr);
-
-                    m_cycles = MCycles(6);
-                }
-            }
-            0xd5 => {
-                self.push(bus, self.registers.de());
-
-                m_cycles = MCycles(4);
-            }
-            0xd6 => {
-                let value = self.read_u8(bus);
-                self.registers.a = self.sub(self.registers.a, value);
-
-                m_cycles = MCycles(2);
-            }
-            0xd7 => {
-                self.call(bus, 0x0010);
-
-                m_cycles = MCycles(4);
-            }
-            0xd8 => {
-                m_cycles = MCycles(2);
-
-                if self.registers.f.contains(CpuFlags::CARRY) {
-                    self.ret(bus);
-
-                    m_cycles = MCycles(5);
-                }
-            }
-            0xd9 => {
-                self.reti(bus);
-
-                m_cycles = MCycles(4);
-            }
-            0xda => {
-                let addr = self.read_u16(bus);
-
-                m_cycles = MCycles(3);
-
-                if self.registers.f.contains(CpuFlags::CARRY) {
-                    self.registers.pc = addr;
-
-                    m_cycles = MCycles(4);
-                }
-            }
-            0xdb => invalid_instruction(),
-            0xdc => {
-                let addr = self.read_u16(bus);
-
-                m_cycles = MCycles(3);
-
-                if self.registers.f.contains(CpuFlags::CARRY) {
-                    self.call(bus, addr);
-
-                    m_cycles = MCycles(6);
-                }
-            }
-            0xdd => invalid_instruction(),
-            0xde => {
-                let value = self.read_u8(bus);
-                self.registers.a = self.sbc(self.registers.a, value);
-
-                m_cycles = MCycles(2);
-            }
-            0xdf => {
-                self.call(bus, 0x0018);
-
-                m_cycles = MCycles(4);
-            }
-            0xe0 => {
-                let value = self.read_u8(bus);
-                bus.mem_write(0xff00 + value as u16, self.registers.a);
-
-                m_cycles = MCycles(3);
-            }
-            0xe1 => {
-                let value = self.pop(bus);
-                self.registers.set_hl(value);
-
-                m_cycles = MCycles(3);
-            }
-            0xe2 => {
-                bus.mem_write(0xff00 + self.registers.c as u16, self.registers.a);
-
-                m_cycles = MCycles(2);
-            }
-            0xe3 => invalid_instruction(),
-            0xe4 => invalid_instruction(),
-            0xe5 => {
-                self.push(bus, self.registers.hl());
-
-                m_cycles = MCycles(4);
-            }
-            0xe6 => {
-                let value = self.read_u8(bus);
-                self.registers.a = self.and(self.registers.a, value);
-
-                m_cycles = MCycles(2);
-            }
-            0xe7 => {
-                self.call(bus, 0x0020);
-
-                m_cycles = MCycles(4);
-            }
-            0xe8 => {
-                let value = self.read_i8(bus) as u16;
-
-                // NOTE(grozki): I initially thought this u16::wrapping_add_signed() would work, but it doesn't work with the carry math below.
-                let result = self.registers.sp.wrapping_add(value);
-
-                self.registers.f.remove(CpuFlags::ZERO | CpuFlags::NEGATIVE);
-                self.registers.f.set(CpuFlags::HALF_CARRY, ((self.registers.sp & 0x0f) + (value & 0x0f)) & 0x10 != 0);
-                self.registers.f.set(CpuFlags::CARRY, ((self.registers.sp & 0xff) + (value & 0xff)) & 0x100 != 0);
-
-                self.registers.sp = result;
-
-                m_cycles = MCycles(4);
-            }
-            0xe9 => self.registers.pc = self.registers.hl(),
-            0xea => {
-                let addr = self.read_u16(bus);
-                bus.mem_write(addr, self.registers.a);
-
-                m_cycles = MCycles(4);
-            }
-            0xeb => invalid_instruction(),
-            0xec => invalid_instruction(),
-            0xed => invalid_instruction(),
-            0xee => {
-                let value = self.read_u8(bus);
-                self.registers.a = self.xor(self.registers.a, value);
-
-                m_cycles = MCycles(2);
-            }
-            0xef => {
-                self.call(bus, 0x0028);
-
-                m_cycles = MCycles(4);
-            }
-            0xf0 => {
-                let offset = self.read_u8(bus);
-                self.registers.a = bus.mem_read(0xff00 + offset as u16);
-
-                m_cycles = MCycles(3);
-            }
-            0xf1 => {
-                let value = self.pop(bus);
-                self.registers.set_af(value);
-
-                m_cycles = MCycles(3);
-            }
-            0xf2 => {
-                self.registers.a = bus.mem_read(0xff00 + self.registers.c as u16);
-
-                m_cycles = MCycles(2);
-            }
-            0xf3 => self.interrupts_master_enable = false,
-            0xf4 => invalid_instruction(),
-            0xf5 => {
-                let af = self.registers.af();
-                self.push(bus, af);
-
-                m_cycles = MCycles(4);
-            }
-            0xf6 => {
-                let value = self.read_u8(bus);
-                self.registers.a = self.or(self.registers.a, value);
-
-                m_cycles = MCycles(2);
-            }
-            0xf7 => {
-                self.call(bus, 0x0030);
-
-                m_cycles = MCycles(4);
-            }
-            0xf8 => {
-                let value = self.read_i8(bus) as u16;
-                let result = self.registers.sp.wrapping_add(value);
+        let opcode = match self.read_u8(bus) {
+            CB_PREFIX => cb_opcode(self.read_u8(bus)),
+            instruction => &OPCODES[instruction as usize],
+        };
 
-                self.registers.f.remove(CpuFlags::ZERO | CpuFlags::NEGATIVE);
-                self.registers.f.set(CpuFlags::HALF_CARRY, ((self.registers.sp & 0x0f) + (value & 0x0f)) & 0x10 != 0);
-                self.registers.f.set(CpuFlags::CARRY, ((self.registers.sp & 0xff) + (value & 0xff)) & 0x100 != 0);
+        let taken = (opcode.execute)(self, bus);
 
-                self.registers.set_hl(result);
+        return MCycles(if taken { opcode.taken_cycles } else { opcode.cycles } as usize);
+    }
 
-                m_cycles = MCycles(3);
-            }
-            0xf9 => {
-                self.registers.sp = self.registers.hl();
+    /// Enters the low-power mode of STOP: no instructions execute and the timers, clocked by DIV, stop with it reset
+    /// to 0, until a selected button is pressed.
+    ///
+    /// STOP also switches speeds on CGB, if a switch was prepared in KEY1. The PPU keeps running, unlike on hardware
+    /// where the LCD is expected to be off, so that frames keep completing while stopped.
+    fn stop(&mut self, bus: &mut Bus) {
+        // STOP is followed by a byte that is skipped.
+        let _ = self.read_u8(bus);
 
-                m_cycles = MCycles(2);
-            }
-            0xfa => {
-                let addr = self.read_u16(bus);
-                self.registers.a = bus.mem_read(addr);
+        if self.switch_speed(bus) {
+            return;
+        }
 
-                m_cycles = MCycles(4);
-            }
-            0xfb => self.interrupts_master_enable = true,
-            0xfc => invalid_instruction(),
-            0xfd => invalid_instruction(),
-            0xfe => {
-                let value = self.read_u8(bus);
-                self.cp(self.registers.a, value);
-
-                m_cycles = MCycles(2);
-            }
-            0xff => {
-                self.call(bus, 0x0038);
+        bus.io_registers.cpu_clock = 0;
+        bus.io_registers.div = 0;
 
-                m_cycles = MCycles(4);
-            }
-            _ => panic!("unknown instruction {instruction}")
-        }
+        // With a selected button already held, the line is low to begin with and STOP ends right away.
+        self.stopped = !bus.io_registers.joypad_line_low();
+    }
 
-        return m_cycles;
+    /// Performs a prepared CGB speed switch, returning whether one happened. Only DMG is emulated, where KEY1 doesn't
+    /// exist, so this never switches.
+    fn switch_speed(&mut self, _bus: &mut Bus) -> bool {
+        false
     }
 
-    fn cb(&mut self, bus: &mut Bus, value: u8) -> MCycles {
+    fn execute_cb(&mut self, bus: &mut Bus, value: u8) {
         let register_value = match value & 0x7 {
             0x0 => self.registers.b,
             0x1 => self.registers.c,
@@ -981,7 +223,7 @@ impl Cpu {
             0x3 => self.registers.e,
             0x4 => self.registers.h,
             0x5 => self.registers.l,
-            0x6 => bus.mem_read(self.registers.hl()),
+            0x6 => bus.cpu_read(self.registers.hl()),
             0x7 => self.registers.a,
             _ => unreachable!()
         };
@@ -1012,55 +254,11 @@ impl Cpu {
                 0x3 => self.registers.e = result,
                 0x4 => self.registers.h = result,
                 0x5 => self.registers.l = result,
-                0x6 => bus.mem_write(self.registers.hl(), result),
+                0x6 => bus.cpu_write(self.registers.hl(), result),
                 0x7 => self.registers.a = result,
                 _ => unreachable!()
             };
         }
-
-        let m_cycles = MCycles(match (value >> 4, value & 0x0f) {
-            (0x4..=0x7, 0x6 | 0xe) => 3,
-            (_, 0x6 | 0xe) => 4,
-            _ => 2
-        });
-
-        return m_cycles;
-    }
-
-    fn handle_timers(&mut self, bus: &mut Bus, m_cycles: MCycles) {
-        let t_cycles = m_cycles.t_cycles();
-
-        bus.io_registers.cpu_clock = bus.io_registers.cpu_clock.wrapping_add(t_cycles as u16);
-
-        bus.io_registers.div = (bus.io_registers.cpu_clock >> 8) as u8 % 64;
-
-        let timer_enable = bus.io_registers.tac & 0b0000_0100 != 0;
-
-        let timer_update_freq = match bus.io_registers.tac & 0b0000_0011 {
-            0 => 1024, // CPU clock / 1024
-            1 => 16, // CPU clock / 16
-            2 => 64, // CPU clock / 64
-            3 => 256, // CPU clock / 256
-            _ => unreachable!()
-        };
-
-        if timer_enable {
-            bus.io_registers.clock_accumulator += t_cycles;
-
-            while bus.io_registers.clock_accumulator >= timer_update_freq {
-                bus.io_registers.clock_accumulator -= timer_update_freq;
-
-                let (tima, reset) = bus.io_registers.tima.overflowing_add(1);
-
-                bus.io_registers.tima = tima;
-
-                if reset {
-                    bus.io_registers.tima = bus.io_registers.tma;
-
-                    bus.io_registers.interrupt_flag.insert(InterruptFlags::TIMER);
-                }
-            }
-        }
     }
 
     fn add(&mut self, register_value: u8, value: u8) -> u8 {
@@ -1324,6 +522,44 @@ impl Cpu {
         self.registers.pc = self.registers.pc.wrapping_add_signed(offset as i16);
     }
 
+    fn jr_if(&mut self, bus: &mut Bus, condition: bool) -> bool {
+        let offset = self.read_i8(bus);
+
+        if condition {
+            self.jr(offset);
+        }
+
+        return condition;
+    }
+
+    fn jp_if(&mut self, bus: &mut Bus, condition: bool) -> bool {
+        let addr = self.read_u16(bus);
+
+        if condition {
+            self.registers.pc = addr;
+        }
+
+        return condition;
+    }
+
+    fn call_if(&mut self, bus: &mut Bus, condition: bool) -> bool {
+        let addr = self.read_u16(bus);
+
+        if condition {
+            self.call(bus, addr);
+        }
+
+        return condition;
+    }
+
+    fn ret_if(&mut self, bus: &mut Bus, condition: bool) -> bool {
+        if condition {
+            self.ret(bus);
+        }
+
+        return condition;
+    }
+
     fn ret(&mut self, bus: &mut Bus) {
         self.registers.pc = self.pop(bus);
     }
@@ -1335,11 +571,11 @@ impl Cpu {
     }
 
     fn pop(&mut self, bus: &mut Bus) -> u16 {
-        let lo = bus.mem_read(self.registers.sp);
+        let lo = bus.cpu_read(self.registers.sp);
 
         self.registers.sp = self.registers.sp.wrapping_add(1);
 
-        let hi = bus.mem_read(self.registers.sp);
+        let hi = bus.cpu_read(self.registers.sp);
 
         self.registers.sp = self.registers.sp.wrapping_add(1);
 
@@ -1349,58 +585,77 @@ impl Cpu {
     fn push(&mut self, bus: &mut Bus, register_value: u16) {
         self.registers.sp = self.registers.sp.wrapping_sub(2);
 
-        bus.mem_write(self.registers.sp + 0, (register_value & 0xff) as u8);
-        bus.mem_write(self.registers.sp + 1, (register_value >> 8) as u8);
+        bus.cpu_write(self.registers.sp, (register_value & 0xff) as u8);
+        bus.cpu_write(self.registers.sp.wrapping_add(1), (register_value >> 8) as u8);
     }
 
     fn call(&mut self, bus: &mut Bus, addr: u16) {
         self.registers.sp = self.registers.sp.wrapping_sub(2);
 
-        bus.mem_write(self.registers.sp, (self.registers.pc & 0xff) as u8);
-        bus.mem_write(self.registers.sp.wrapping_add(1), (self.registers.pc >> 8 & 0xff) as u8);
+        bus.cpu_write(self.registers.sp, (self.registers.pc & 0xff) as u8);
+        bus.cpu_write(self.registers.sp.wrapping_add(1), (self.registers.pc >> 8 & 0xff) as u8);
 
         self.registers.pc = addr;
     }
 
+    /// Dispatches the highest priority pending interrupt, if IME is set, taking 5 M-cycles: two idle ones, pushing
+    /// the high and low bytes of PC, and jumping to the handler.
+    ///
+    /// The interrupt is only picked after the high byte was pushed. When that push writes IE at 0xffff and leaves
+    /// no enabled interrupt pending, the dispatch is cancelled: IF is left as it is and PC is set to 0x0000.
     fn interrupt_service_routine(&mut self, bus: &mut Bus) -> bool {
+        let pending = |bus: &Bus| bus.io_registers.interrupt_enable.bits() & bus.io_registers.interrupt_flag.bits() != 0;
+
+        if !pending(bus) {
+            return false;
+        }
+
         if !self.interrupts_master_enable {
             // If IME is not set, CPU returns to normal operation from HALT as soon as an interrupt is pending.
             // The pending interrupt is not handled.
-            if bus.io_registers.interrupt_enable.bits() & bus.io_registers.interrupt_flag.bits() != 0 {
-                self.halted = false;
-            }
+            self.halted = false;
 
             return false;
         }
 
-        let mut handled = false;
-
-        for flag in InterruptFlags::all().iter() {
-            if bus.io_registers.interrupt_enable.contains(flag) && bus.io_registers.interrupt_flag.contains(flag) {
-                self.interrupts_master_enable = false;
-                
-                self.halted = false;
-                
-                bus.io_registers.interrupt_flag.remove(flag);
-
-                let handler_addr = match flag {
-                    InterruptFlags::VBLANK => 0x0040,
-                    InterruptFlags::LCD_STAT => 0x0048,
-                    InterruptFlags::TIMER => 0x0050,
-                    InterruptFlags::SERIAL => 0x0058,
-                    InterruptFlags::JOYPAD => 0x0060,
-                    _ => unreachable!()
-                };
-                
-                self.call(bus, handler_addr);
-                
-                handled = true;
-                
-                break;
-            }
-        }
+        self.interrupts_master_enable = false;
+        self.halted = false;
+
+        bus.cpu_idle();
+        bus.cpu_idle();
+
+        let [pc_hi, pc_lo] = self.registers.pc.to_be_bytes();
+
+        self.registers.sp = self.registers.sp.wrapping_sub(1);
+        bus.cpu_write(self.registers.sp, pc_hi);
+
+        let flag = InterruptFlags::all().iter().find(|&flag| {
+            bus.io_registers.interrupt_enable.contains(flag) && bus.io_registers.interrupt_flag.contains(flag)
+        });
+
+        self.registers.sp = self.registers.sp.wrapping_sub(1);
+        bus.cpu_write(self.registers.sp, pc_lo);
 
-        return handled;
+        let Some(flag) = flag else {
+            self.registers.pc = 0x0000;
+
+            return true;
+        };
+
+        bus.io_registers.interrupt_flag.remove(flag);
+
+        bus.io_registers.log_event(Event::InterruptDispatched(flag));
+
+        self.registers.pc = match flag {
+            InterruptFlags::VBLANK => 0x0040,
+            InterruptFlags::LCD_STAT => 0x0048,
+            InterruptFlags::TIMER => 0x0050,
+            InterruptFlags::SERIAL => 0x0058,
+            InterruptFlags::JOYPAD => 0x0060,
+            _ => unreachable!()
+        };
+
+        return true;
     }
 
     fn read_u8(&mut self, bus: &mut Bus) -> u8 {
@@ -1408,7 +663,7 @@ impl Cpu {
 
         self.registers.pc = self.registers.pc.wrapping_add(1);
 
-        return bus.mem_read(addr);
+        return bus.cpu_read(addr);
     }
 
     fn read_i8(&mut self, bus: &mut Bus) -> i8 {
@@ -1418,4 +673,84 @@ impl Cpu {
     fn read_u16(&mut self, bus: &mut Bus) -> u16 {
         return u16::from_le_bytes([self.read_u8(bus), self.read_u8(bus)]);
     }
-}
\ No newline at end of file
+}
+
+// Locking up isn't saved, so that loading a state made before it is a way out, see `unlock`.
+state_fields!(Cpu { interrupts_master_enable, registers, halted, stopped });
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A CPU about to run `program` from 0x0100, with interrupts enabled.
+    fn run(program: &[u8]) -> (Cpu, Bus) {
+        let mut rom = vec![0; 0x100];
+        rom.extend_from_slice(program);
+
+        let mut bus = Bus::new();
+        bus.load_flat(rom, Model::Dmg);
+
+        (Cpu::new(Model::Dmg), bus)
+    }
+
+    #[test]
+    fn the_lowest_pending_interrupt_is_dispatched_first() {
+        let (mut cpu, mut bus) = run(&[0x00]);
+        bus.io_registers.interrupt_enable = InterruptFlags::all();
+        bus.io_registers.interrupt_flag = InterruptFlags::TIMER | InterruptFlags::VBLANK | InterruptFlags::JOYPAD;
+
+        assert_eq!(cpu.tick(&mut bus).t_cycles(), 20);
+        assert_eq!(cpu.pc(), 0x0040);
+        assert_eq!(bus.io_registers.interrupt_flag, InterruptFlags::TIMER | InterruptFlags::JOYPAD);
+    }
+
+    #[test]
+    fn only_enabled_interrupts_are_dispatched() {
+        let (mut cpu, mut bus) = run(&[0x00]);
+        bus.io_registers.interrupt_enable = InterruptFlags::TIMER | InterruptFlags::SERIAL;
+        bus.io_registers.interrupt_flag = InterruptFlags::VBLANK | InterruptFlags::SERIAL;
+
+        cpu.tick(&mut bus);
+
+        assert_eq!(cpu.pc(), 0x0058);
+        assert_eq!(bus.io_registers.interrupt_flag, InterruptFlags::VBLANK);
+    }
+
+    /// Dispatches the VBlank interrupt with the stack pointer at 0x0000, so that PC high is pushed to IE, after
+    /// `interrupt_flag` is requested. Returns the PC the dispatch ended at, and the bus after it.
+    fn dispatch_with_the_stack_at_ie(pc: u16, interrupt_flag: InterruptFlags) -> (u16, Bus) {
+        let (mut cpu, mut bus) = run(&[]);
+        cpu.registers.pc = pc;
+        cpu.registers.sp = 0x0000;
+        bus.io_registers.interrupt_enable = InterruptFlags::VBLANK;
+        bus.io_registers.interrupt_flag = interrupt_flag;
+
+        cpu.tick(&mut bus);
+
+        assert_eq!(cpu.registers.sp, 0xfffe);
+        assert_eq!(bus.mem_peek(0xfffe), pc as u8);
+
+        (cpu.pc(), bus)
+    }
+
+    #[test]
+    fn pushing_pc_to_ie_can_cancel_the_dispatch() {
+        let (pc, bus) = dispatch_with_the_stack_at_ie(0x0200, InterruptFlags::VBLANK);
+
+        assert_eq!(pc, 0x0000);
+        assert_eq!(bus.io_registers.interrupt_enable.bits(), 0x02);
+        // The interrupt wasn't acknowledged.
+        assert_eq!(bus.io_registers.interrupt_flag, InterruptFlags::VBLANK);
+    }
+
+    #[test]
+    fn pushing_pc_to_ie_picks_the_vector_from_the_new_value() {
+        let (pc, bus) = dispatch_with_the_stack_at_ie(0x0200, InterruptFlags::VBLANK | InterruptFlags::LCD_STAT);
+        assert_eq!(pc, 0x0048);
+        assert_eq!(bus.io_registers.interrupt_flag, InterruptFlags::VBLANK);
+
+        let (pc, bus) = dispatch_with_the_stack_at_ie(0x0100, InterruptFlags::VBLANK);
+        assert_eq!(pc, 0x0040);
+        assert_eq!(bus.io_registers.interrupt_flag, InterruptFlags::empty());
+    }
+}
@@ -0,0 +1,543 @@
+//! Opcode tables of the SM83, used to execute instructions and shared with the disassembler, so that what is traced
+//! can't drift from what is executed.
+
+use crate::gameboy::{bus::Bus, cpu_registers::CpuFlags};
+use super::{invalid_instruction, Cpu};
+
+pub(crate) const CB_PREFIX: u8 = 0xcb;
+
+pub(crate) struct Opcode {
+    /// Operands are written as placeholders: `d8` and `d16` for immediate values, `a8` for an address in 0xff00-0xffff,
+    /// `a16` for an address, `r8` for the target of a relative jump and `s8` for a signed offset.
+    pub mnemonic: &'static str,
+    /// Length in bytes, including the CB prefix.
+    pub length: u8,
+    pub cycles: u8,
+    /// Cycles when the condition of a conditional jump, call or return is met, otherwise the same as `cycles`.
+    pub taken_cycles: u8,
+    /// Executes the instruction once its opcode is read. Returns whether a condition was met.
+    pub execute: fn(&mut Cpu, &mut Bus) -> bool,
+}
+
+macro_rules! opcode {
+    ($mnemonic:literal, $length:literal, $cycles:literal, |$cpu:ident, $bus:ident| $body:expr) => {
+        opcode!(@ $mnemonic, $length, $cycles, $cycles, |$cpu, $bus| {
+            $body;
+
+            false
+        })
+    };
+    ($mnemonic:literal, $length:literal, $cycles:literal / $taken_cycles:literal, |$cpu:ident, $bus:ident| $body:expr) => {
+        opcode!(@ $mnemonic, $length, $cycles, $taken_cycles, |$cpu, $bus| $body)
+    };
+    (@ $mnemonic:expr, $length:literal, $cycles:literal, $taken_cycles:literal, |$cpu:ident, $bus:ident| $body:expr) => {
+        Opcode {
+            mnemonic: $mnemonic,
+            length: $length,
+            cycles: $cycles,
+            taken_cycles: $taken_cycles,
+            execute: {
+                #[allow(unused_variables)]
+                fn execute($cpu: &mut Cpu, $bus: &mut Bus) -> bool {
+                    $body
+                }
+
+                execute
+            },
+        }
+    };
+}
+
+/// Expands to the 8 CB opcodes of one operation, one per operand in opcode order.
+macro_rules! cb_row {
+    ($row:literal, $operation:literal, $hl_cycles:literal) => {
+        [
+            cb_row!(@ $row, 0, $operation, "B", 2),
+            cb_row!(@ $row, 1, $operation, "C", 2),
+            cb_row!(@ $row, 2, $operation, "D", 2),
+            cb_row!(@ $row, 3, $operation, "E", 2),
+            cb_row!(@ $row, 4, $operation, "H", 2),
+            cb_row!(@ $row, 5, $operation, "L", 2),
+            cb_row!(@ $row, 6, $operation, "(HL)", $hl_cycles),
+            cb_row!(@ $row, 7, $operation, "A", 2),
+        ]
+    };
+    (@ $row:literal, $column:literal, $operation:literal, $operand:literal, $cycles:literal) => {
+        opcode!(@ concat!($operation, $operand), 2, $cycles, $cycles, |cpu, bus| {
+            cpu.execute_cb(bus, $row << 3 | $column);
+
+            false
+        })
+    };
+}
+
+pub(crate) static OPCODES: [Opcode; 256] = [
+    /* 0x00 */ opcode!("NOP", 1, 1, |cpu, bus| {}),
+    /* 0x01 */ opcode!("LD BC, d16", 3, 3, |cpu, bus| {
+        let value = cpu.read_u16(bus);
+        cpu.registers.set_bc(value);
+    }),
+    /* 0x02 */ opcode!("LD (BC), A", 1, 2, |cpu, bus| bus.cpu_write(cpu.registers.bc(), cpu.registers.a)),
+    /* 0x03 */ opcode!("INC BC", 1, 2, |cpu, bus| {
+        bus.oam_bug_inc_dec(cpu.registers.bc());
+        cpu.registers.set_bc(cpu.registers.bc().wrapping_add(1));
+    }),
+    /* 0x04 */ opcode!("INC B", 1, 1, |cpu, bus| cpu.registers.b = cpu.inc_r8(cpu.registers.b)),
+    /* 0x05 */ opcode!("DEC B", 1, 1, |cpu, bus| cpu.registers.b = cpu.dec_r8(cpu.registers.b)),
+    /* 0x06 */ opcode!("LD B, d8", 2, 2, |cpu, bus| cpu.registers.b = cpu.read_u8(bus)),
+    /* 0x07 */ opcode!("RLCA", 1, 1, |cpu, bus| {
+        cpu.registers.a = cpu.rlc(cpu.registers.a);
+        cpu.registers.f.remove(CpuFlags::ZERO);
+    }),
+    /* 0x08 */ opcode!("LD (a16), SP", 3, 5, |cpu, bus| {
+        let addr = cpu.read_u16(bus);
+        bus.cpu_write(addr, (cpu.registers.sp & 0xff) as u8);
+        bus.cpu_write(addr.wrapping_add(1), (cpu.registers.sp >> 8) as u8);
+    }),
+    /* 0x09 */ opcode!("ADD HL, BC", 1, 2, |cpu, bus| cpu.add_hl(cpu.registers.bc())),
+    /* 0x0a */ opcode!("LD A, (BC)", 1, 2, |cpu, bus| cpu.registers.a = bus.cpu_read(cpu.registers.bc())),
+    /* 0x0b */ opcode!("DEC BC", 1, 2, |cpu, bus| {
+        bus.oam_bug_inc_dec(cpu.registers.bc());
+        cpu.registers.set_bc(cpu.registers.bc().wrapping_sub(1));
+    }),
+    /* 0x0c */ opcode!("INC C", 1, 1, |cpu, bus| cpu.registers.c = cpu.inc_r8(cpu.registers.c)),
+    /* 0x0d */ opcode!("DEC C", 1, 1, |cpu, bus| cpu.registers.c = cpu.dec_r8(cpu.registers.c)),
+    /* 0x0e */ opcode!("LD C, d8", 2, 2, |cpu, bus| cpu.registers.c = cpu.read_u8(bus)),
+    /* 0x0f */ opcode!("RRCA", 1, 1, |cpu, bus| {
+        cpu.registers.a = cpu.rrc(cpu.registers.a);
+        cpu.registers.f.remove(CpuFlags::ZERO);
+    }),
+    /* 0x10 */ opcode!("STOP", 2, 1, |cpu, bus| cpu.stop(bus)),
+    /* 0x11 */ opcode!("LD DE, d16", 3, 3, |cpu, bus| {
+        let value = cpu.read_u16(bus);
+        cpu.registers.set_de(value);
+    }),
+    /* 0x12 */ opcode!("LD (DE), A", 1, 2, |cpu, bus| bus.cpu_write(cpu.registers.de(), cpu.registers.a)),
+    /* 0x13 */ opcode!("INC DE", 1, 2, |cpu, bus| {
+        bus.oam_bug_inc_dec(cpu.registers.de());
+        cpu.registers.set_de(cpu.registers.de().wrapping_add(1));
+    }),
+    /* 0x14 */ opcode!("INC D", 1, 1, |cpu, bus| cpu.registers.d = cpu.inc_r8(cpu.registers.d)),
+    /* 0x15 */ opcode!("DEC D", 1, 1, |cpu, bus| cpu.registers.d = cpu.dec_r8(cpu.registers.d)),
+    /* 0x16 */ opcode!("LD D, d8", 2, 2, |cpu, bus| cpu.registers.d = cpu.read_u8(bus)),
+    /* 0x17 */ opcode!("RLA", 1, 1, |cpu, bus| {
+        cpu.registers.a = cpu.rl(cpu.registers.a);
+        cpu.registers.f.remove(CpuFlags::ZERO);
+    }),
+    /* 0x18 */ opcode!("JR r8", 2, 3, |cpu, bus| {
+        let offset = cpu.read_i8(bus);
+        cpu.jr(offset);
+    }),
+    /* 0x19 */ opcode!("ADD HL, DE", 1, 2, |cpu, bus| cpu.add_hl(cpu.registers.de())),
+    /* 0x1a */ opcode!("LD A, (DE)", 1, 2, |cpu, bus| cpu.registers.a = bus.cpu_read(cpu.registers.de())),
+    /* 0x1b */ opcode!("DEC DE", 1, 2, |cpu, bus| {
+        bus.oam_bug_inc_dec(cpu.registers.de());
+        cpu.registers.set_de(cpu.registers.de().wrapping_sub(1));
+    }),
+    /* 0x1c */ opcode!("INC E", 1, 1, |cpu, bus| cpu.registers.e = cpu.inc_r8(cpu.registers.e)),
+    /* 0x1d */ opcode!("DEC E", 1, 1, |cpu, bus| cpu.registers.e = cpu.dec_r8(cpu.registers.e)),
+    /* 0x1e */ opcode!("LD E, d8", 2, 2, |cpu, bus| cpu.registers.e = cpu.read_u8(bus)),
+    /* 0x1f */ opcode!("RRA", 1, 1, |cpu, bus| {
+        cpu.registers.a = cpu.rr(cpu.registers.a);
+        cpu.registers.f.remove(CpuFlags::ZERO);
+    }),
+    /* 0x20 */ opcode!("JR NZ, r8", 2, 2 / 3, |cpu, bus| cpu.jr_if(bus, !cpu.registers.f.contains(CpuFlags::ZERO))),
+    /* 0x21 */ opcode!("LD HL, d16", 3, 3, |cpu, bus| {
+        let value = cpu.read_u16(bus);
+        cpu.registers.set_hl(value);
+    }),
+    /* 0x22 */ opcode!("LD (HL+), A", 1, 2, |cpu, bus| {
+        bus.cpu_write(cpu.registers.hl(), cpu.registers.a);
+        cpu.inc_hl();
+    }),
+    /* 0x23 */ opcode!("INC HL", 1, 2, |cpu, bus| {
+        bus.oam_bug_inc_dec(cpu.registers.hl());
+        cpu.inc_hl();
+    }),
+    /* 0x24 */ opcode!("INC H", 1, 1, |cpu, bus| cpu.registers.h = cpu.inc_r8(cpu.registers.h)),
+    /* 0x25 */ opcode!("DEC H", 1, 1, |cpu, bus| cpu.registers.h = cpu.dec_r8(cpu.registers.h)),
+    /* 0x26 */ opcode!("LD H, d8", 2, 2, |cpu, bus| cpu.registers.h = cpu.read_u8(bus)),
+    /* 0x27 */ opcode!("DAA", 1, 1, |cpu, bus| cpu.daa()),
+    /* 0x28 */ opcode!("JR Z, r8", 2, 2 / 3, |cpu, bus| cpu.jr_if(bus, cpu.registers.f.contains(CpuFlags::ZERO))),
+    /* 0x29 */ opcode!("ADD HL, HL", 1, 2, |cpu, bus| cpu.add_hl(cpu.registers.hl())),
+    /* 0x2a */ opcode!("LD A, (HL+)", 1, 2, |cpu, bus| {
+        cpu.registers.a = bus.cpu_read(cpu.registers.hl());
+        cpu.inc_hl();
+    }),
+    /* 0x2b */ opcode!("DEC HL", 1, 2, |cpu, bus| {
+        bus.oam_bug_inc_dec(cpu.registers.hl());
+        cpu.dec_hl();
+    }),
+    /* 0x2c */ opcode!("INC L", 1, 1, |cpu, bus| cpu.registers.l = cpu.inc_r8(cpu.registers.l)),
+    /* 0x2d */ opcode!("DEC L", 1, 1, |cpu, bus| cpu.registers.l = cpu.dec_r8(cpu.registers.l)),
+    /* 0x2e */ opcode!("LD L, d8", 2, 2, |cpu, bus| cpu.registers.l = cpu.read_u8(bus)),
+    /* 0x2f */ opcode!("CPL", 1, 1, |cpu, bus| {
+        cpu.registers.a = !cpu.registers.a;
+        cpu.registers.f.insert(CpuFlags::NEGATIVE | CpuFlags::HALF_CARRY);
+    }),
+    /* 0x30 */ opcode!("JR NC, r8", 2, 2 / 3, |cpu, bus| cpu.jr_if(bus, !cpu.registers.f.contains(CpuFlags::CARRY))),
+    /* 0x31 */ opcode!("LD SP, d16", 3, 3, |cpu, bus| cpu.registers.sp = cpu.read_u16(bus)),
+    /* 0x32 */ opcode!("LD (HL-), A", 1, 2, |cpu, bus| {
+        bus.cpu_write(cpu.registers.hl(), cpu.registers.a);
+        cpu.dec_hl();
+    }),
+    /* 0x33 */ opcode!("INC SP", 1, 2, |cpu, bus| {
+        bus.oam_bug_inc_dec(cpu.registers.sp);
+        cpu.registers.sp = cpu.registers.sp.wrapping_add(1);
+    }),
+    /* 0x34 */ opcode!("INC (HL)", 1, 3, |cpu, bus| {
+        let addr = cpu.registers.hl();
+        let value = bus.cpu_read(addr);
+        let result = value.wrapping_add(1);
+
+        cpu.registers.f.set(CpuFlags::ZERO, result == 0);
+        cpu.registers.f.remove(CpuFlags::NEGATIVE);
+        cpu.registers.f.set(CpuFlags::HALF_CARRY, value & 0x0f == 0x0f);
+
+        bus.cpu_write(addr, result);
+    }),
+    /* 0x35 */ opcode!("DEC (HL)", 1, 3, |cpu, bus| {
+        let addr = cpu.registers.hl();
+        let value = bus.cpu_read(addr);
+        let result = value.wrapping_sub(1);
+
+        cpu.registers.f.set(CpuFlags::ZERO, result == 0);
+        cpu.registers.f.insert(CpuFlags::NEGATIVE);
+        cpu.registers.f.set(CpuFlags::HALF_CARRY, value & 0x0f == 0);
+
+        bus.cpu_write(addr, result);
+    }),
+    /* 0x36 */ opcode!("LD (HL), d8", 2, 3, |cpu, bus| {
+        let value = cpu.read_u8(bus);
+        bus.cpu_write(cpu.registers.hl(), value);
+    }),
+    /* 0x37 */ opcode!("SCF", 1, 1, |cpu, bus| {
+        cpu.registers.f.insert(CpuFlags::CARRY);
+        cpu.registers.f.remove(CpuFlags::NEGATIVE | CpuFlags::HALF_CARRY);
+    }),
+    /* 0x38 */ opcode!("JR C, r8", 2, 2 / 3, |cpu, bus| cpu.jr_if(bus, cpu.registers.f.contains(CpuFlags::CARRY))),
+    /* 0x39 */ opcode!("ADD HL, SP", 1, 2, |cpu, bus| cpu.add_hl(cpu.registers.sp)),
+    /* 0x3a */ opcode!("LD A, (HL-)", 1, 2, |cpu, bus| {
+        cpu.registers.a = bus.cpu_read(cpu.registers.hl());
+        cpu.dec_hl();
+    }),
+    /* 0x3b */ opcode!("DEC SP", 1, 2, |cpu, bus| {
+        bus.oam_bug_inc_dec(cpu.registers.sp);
+        cpu.registers.sp = cpu.registers.sp.wrapping_sub(1);
+    }),
+    /* 0x3c */ opcode!("INC A", 1, 1, |cpu, bus| cpu.registers.a = cpu.inc_r8(cpu.registers.a)),
+    /* 0x3d */ opcode!("DEC A", 1, 1, |cpu, bus| cpu.registers.a = cpu.dec_r8(cpu.registers.a)),
+    /* 0x3e */ opcode!("LD A, d8", 2, 2, |cpu, bus| cpu.registers.a = cpu.read_u8(bus)),
+    /* 0x3f */ opcode!("CCF", 1, 1, |cpu, bus| {
+        cpu.registers.f.remove(CpuFlags::NEGATIVE | CpuFlags::HALF_CARRY);
+        cpu.registers.f.toggle(CpuFlags::CARRY);
+    }),
+    /* 0x40 */ opcode!("LD B, B", 1, 1, |cpu, bus| {}),
+    /* 0x41 */ opcode!("LD B, C", 1, 1, |cpu, bus| cpu.registers.b = cpu.registers.c),
+    /* 0x42 */ opcode!("LD B, D", 1, 1, |cpu, bus| cpu.registers.b = cpu.registers.d),
+    /* 0x43 */ opcode!("LD B, E", 1, 1, |cpu, bus| cpu.registers.b = cpu.registers.e),
+    /* 0x44 */ opcode!("LD B, H", 1, 1, |cpu, bus| cpu.registers.b = cpu.registers.h),
+    /* 0x45 */ opcode!("LD B, L", 1, 1, |cpu, bus| cpu.registers.b = cpu.registers.l),
+    /* 0x46 */ opcode!("LD B, (HL)", 1, 2, |cpu, bus| cpu.registers.b = bus.cpu_read(cpu.registers.hl())),
+    /* 0x47 */ opcode!("LD B, A", 1, 1, |cpu, bus| cpu.registers.b = cpu.registers.a),
+    /* 0x48 */ opcode!("LD C, B", 1, 1, |cpu, bus| cpu.registers.c = cpu.registers.b),
+    /* 0x49 */ opcode!("LD C, C", 1, 1, |cpu, bus| {}),
+    /* 0x4a */ opcode!("LD C, D", 1, 1, |cpu, bus| cpu.registers.c = cpu.registers.d),
+    /* 0x4b */ opcode!("LD C, E", 1, 1, |cpu, bus| cpu.registers.c = cpu.registers.e),
+    /* 0x4c */ opcode!("LD C, H", 1, 1, |cpu, bus| cpu.registers.c = cpu.registers.h),
+    /* 0x4d */ opcode!("LD C, L", 1, 1, |cpu, bus| cpu.registers.c = cpu.registers.l),
+    /* 0x4e */ opcode!("LD C, (HL)", 1, 2, |cpu, bus| cpu.registers.c = bus.cpu_read(cpu.registers.hl())),
+    /* 0x4f */ opcode!("LD C, A", 1, 1, |cpu, bus| cpu.registers.c = cpu.registers.a),
+    /* 0x50 */ opcode!("LD D, B", 1, 1, |cpu, bus| cpu.registers.d = cpu.registers.b),
+    /* 0x51 */ opcode!("LD D, C", 1, 1, |cpu, bus| cpu.registers.d = cpu.registers.c),
+    /* 0x52 */ opcode!("LD D, D", 1, 1, |cpu, bus| {}),
+    /* 0x53 */ opcode!("LD D, E", 1, 1, |cpu, bus| cpu.registers.d = cpu.registers.e),
+    /* 0x54 */ opcode!("LD D, H", 1, 1, |cpu, bus| cpu.registers.d = cpu.registers.h),
+    /* 0x55 */ opcode!("LD D, L", 1, 1, |cpu, bus| cpu.registers.d = cpu.registers.l),
+    /* 0x56 */ opcode!("LD D, (HL)", 1, 2, |cpu, bus| cpu.registers.d = bus.cpu_read(cpu.registers.hl())),
+    /* 0x57 */ opcode!("LD D, A", 1, 1, |cpu, bus| cpu.registers.d = cpu.registers.a),
+    /* 0x58 */ opcode!("LD E, B", 1, 1, |cpu, bus| cpu.registers.e = cpu.registers.b),
+    /* 0x59 */ opcode!("LD E, C", 1, 1, |cpu, bus| cpu.registers.e = cpu.registers.c),
+    /* 0x5a */ opcode!("LD E, D", 1, 1, |cpu, bus| cpu.registers.e = cpu.registers.d),
+    /* 0x5b */ opcode!("LD E, E", 1, 1, |cpu, bus| {}),
+    /* 0x5c */ opcode!("LD E, H", 1, 1, |cpu, bus| cpu.registers.e = cpu.registers.h),
+    /* 0x5d */ opcode!("LD E, L", 1, 1, |cpu, bus| cpu.registers.e = cpu.registers.l),
+    /* 0x5e */ opcode!("LD E, (HL)", 1, 2, |cpu, bus| cpu.registers.e = bus.cpu_read(cpu.registers.hl())),
+    /* 0x5f */ opcode!("LD E, A", 1, 1, |cpu, bus| cpu.registers.e = cpu.registers.a),
+    /* 0x60 */ opcode!("LD H, B", 1, 1, |cpu, bus| cpu.registers.h = cpu.registers.b),
+    /* 0x61 */ opcode!("LD H, C", 1, 1, |cpu, bus| cpu.registers.h = cpu.registers.c),
+    /* 0x62 */ opcode!("LD H, D", 1, 1, |cpu, bus| cpu.registers.h = cpu.registers.d),
+    /* 0x63 */ opcode!("LD H, E", 1, 1, |cpu, bus| cpu.registers.h = cpu.registers.e),
+    /* 0x64 */ opcode!("LD H, H", 1, 1, |cpu, bus| {}),
+    /* 0x65 */ opcode!("LD H, L", 1, 1, |cpu, bus| cpu.registers.h = cpu.registers.l),
+    /* 0x66 */ opcode!("LD H, (HL)", 1, 2, |cpu, bus| cpu.registers.h = bus.cpu_read(cpu.registers.hl())),
+    /* 0x67 */ opcode!("LD H, A", 1, 1, |cpu, bus| cpu.registers.h = cpu.registers.a),
+    /* 0x68 */ opcode!("LD L, B", 1, 1, |cpu, bus| cpu.registers.l = cpu.registers.b),
+    /* 0x69 */ opcode!("LD L, C", 1, 1, |cpu, bus| cpu.registers.l = cpu.registers.c),
+    /* 0x6a */ opcode!("LD L, D", 1, 1, |cpu, bus| cpu.registers.l = cpu.registers.d),
+    /* 0x6b */ opcode!("LD L, E", 1, 1, |cpu, bus| cpu.registers.l = cpu.registers.e),
+    /* 0x6c */ opcode!("LD L, H", 1, 1, |cpu, bus| cpu.registers.l = cpu.registers.h),
+    /* 0x6d */ opcode!("LD L, L", 1, 1, |cpu, bus| {}),
+    /* 0x6e */ opcode!("LD L, (HL)", 1, 2, |cpu, bus| cpu.registers.l = bus.cpu_read(cpu.registers.hl())),
+    /* 0x6f */ opcode!("LD L, A", 1, 1, |cpu, bus| cpu.registers.l = cpu.registers.a),
+    /* 0x70 */ opcode!("LD (HL), B", 1, 2, |cpu, bus| bus.cpu_write(cpu.registers.hl(), cpu.registers.b)),
+    /* 0x71 */ opcode!("LD (HL), C", 1, 2, |cpu, bus| bus.cpu_write(cpu.registers.hl(), cpu.registers.c)),
+    /* 0x72 */ opcode!("LD (HL), D", 1, 2, |cpu, bus| bus.cpu_write(cpu.registers.hl(), cpu.registers.d)),
+    /* 0x73 */ opcode!("LD (HL), E", 1, 2, |cpu, bus| bus.cpu_write(cpu.registers.hl(), cpu.registers.e)),
+    /* 0x74 */ opcode!("LD (HL), H", 1, 2, |cpu, bus| bus.cpu_write(cpu.registers.hl(), cpu.registers.h)),
+    /* 0x75 */ opcode!("LD (HL), L", 1, 2, |cpu, bus| bus.cpu_write(cpu.registers.hl(), cpu.registers.l)),
+    /* 0x76 */ opcode!("HALT", 1, 1, |cpu, bus| cpu.halted = true),
+    /* 0x77 */ opcode!("LD (HL), A", 1, 2, |cpu, bus| bus.cpu_write(cpu.registers.hl(), cpu.registers.a)),
+    /* 0x78 */ opcode!("LD A, B", 1, 1, |cpu, bus| cpu.registers.a = cpu.registers.b),
+    /* 0x79 */ opcode!("LD A, C", 1, 1, |cpu, bus| cpu.registers.a = cpu.registers.c),
+    /* 0x7a */ opcode!("LD A, D", 1, 1, |cpu, bus| cpu.registers.a = cpu.registers.d),
+    /* 0x7b */ opcode!("LD A, E", 1, 1, |cpu, bus| cpu.registers.a = cpu.registers.e),
+    /* 0x7c */ opcode!("LD A, H", 1, 1, |cpu, bus| cpu.registers.a = cpu.registers.h),
+    /* 0x7d */ opcode!("LD A, L", 1, 1, |cpu, bus| cpu.registers.a = cpu.registers.l),
+    /* 0x7e */ opcode!("LD A, (HL)", 1, 2, |cpu, bus| cpu.registers.a = bus.cpu_read(cpu.registers.hl())),
+    /* 0x7f */ opcode!("LD A, A", 1, 1, |cpu, bus| {}),
+    /* 0x80 */ opcode!("ADD A, B", 1, 1, |cpu, bus| cpu.registers.a = cpu.add(cpu.registers.a, cpu.registers.b)),
+    /* 0x81 */ opcode!("ADD A, C", 1, 1, |cpu, bus| cpu.registers.a = cpu.add(cpu.registers.a, cpu.registers.c)),
+    /* 0x82 */ opcode!("ADD A, D", 1, 1, |cpu, bus| cpu.registers.a = cpu.add(cpu.registers.a, cpu.registers.d)),
+    /* 0x83 */ opcode!("ADD A, E", 1, 1, |cpu, bus| cpu.registers.a = cpu.add(cpu.registers.a, cpu.registers.e)),
+    /* 0x84 */ opcode!("ADD A, H", 1, 1, |cpu, bus| cpu.registers.a = cpu.add(cpu.registers.a, cpu.registers.h)),
+    /* 0x85 */ opcode!("ADD A, L", 1, 1, |cpu, bus| cpu.registers.a = cpu.add(cpu.registers.a, cpu.registers.l)),
+    /* 0x86 */ opcode!("ADD A, (HL)", 1, 2, |cpu, bus| cpu.registers.a = cpu.add(cpu.registers.a, bus.cpu_read(cpu.registers.hl()))),
+    /* 0x87 */ opcode!("ADD A, A", 1, 1, |cpu, bus| cpu.registers.a = cpu.add(cpu.registers.a, cpu.registers.a)),
+    /* 0x88 */ opcode!("ADC A, B", 1, 1, |cpu, bus| cpu.registers.a = cpu.adc(cpu.registers.a, cpu.registers.b)),
+    /* 0x89 */ opcode!("ADC A, C", 1, 1, |cpu, bus| cpu.registers.a = cpu.adc(cpu.registers.a, cpu.registers.c)),
+    /* 0x8a */ opcode!("ADC A, D", 1, 1, |cpu, bus| cpu.registers.a = cpu.adc(cpu.registers.a, cpu.registers.d)),
+    /* 0x8b */ opcode!("ADC A, E", 1, 1, |cpu, bus| cpu.registers.a = cpu.adc(cpu.registers.a, cpu.registers.e)),
+    /* 0x8c */ opcode!("ADC A, H", 1, 1, |cpu, bus| cpu.registers.a = cpu.adc(cpu.registers.a, cpu.registers.h)),
+    /* 0x8d */ opcode!("ADC A, L", 1, 1, |cpu, bus| cpu.registers.a = cpu.adc(cpu.registers.a, cpu.registers.l)),
+    /* 0x8e */ opcode!("ADC A, (HL)", 1, 2, |cpu, bus| cpu.registers.a = cpu.adc(cpu.registers.a, bus.cpu_read(cpu.registers.hl()))),
+    /* 0x8f */ opcode!("ADC A, A", 1, 1, |cpu, bus| cpu.registers.a = cpu.adc(cpu.registers.a, cpu.registers.a)),
+    /* 0x90 */ opcode!("SUB B", 1, 1, |cpu, bus| cpu.registers.a = cpu.sub(cpu.registers.a, cpu.registers.b)),
+    /* 0x91 */ opcode!("SUB C", 1, 1, |cpu, bus| cpu.registers.a = cpu.sub(cpu.registers.a, cpu.registers.c)),
+    /* 0x92 */ opcode!("SUB D", 1, 1, |cpu, bus| cpu.registers.a = cpu.sub(cpu.registers.a, cpu.registers.d)),
+    /* 0x93 */ opcode!("SUB E", 1, 1, |cpu, bus| cpu.registers.a = cpu.sub(cpu.registers.a, cpu.registers.e)),
+    /* 0x94 */ opcode!("SUB H", 1, 1, |cpu, bus| cpu.registers.a = cpu.sub(cpu.registers.a, cpu.registers.h)),
+    /* 0x95 */ opcode!("SUB L", 1, 1, |cpu, bus| cpu.registers.a = cpu.sub(cpu.registers.a, cpu.registers.l)),
+    /* 0x96 */ opcode!("SUB (HL)", 1, 2, |cpu, bus| cpu.registers.a = cpu.sub(cpu.registers.a, bus.cpu_read(cpu.registers.hl()))),
+    /* 0x97 */ opcode!("SUB A", 1, 1, |cpu, bus| cpu.registers.a = cpu.sub(cpu.registers.a, cpu.registers.a)),
+    /* 0x98 */ opcode!("SBC A, B", 1, 1, |cpu, bus| cpu.registers.a = cpu.sbc(cpu.registers.a, cpu.registers.b)),
+    /* 0x99 */ opcode!("SBC A, C", 1, 1, |cpu, bus| cpu.registers.a = cpu.sbc(cpu.registers.a, cpu.registers.c)),
+    /* 0x9a */ opcode!("SBC A, D", 1, 1, |cpu, bus| cpu.registers.a = cpu.sbc(cpu.registers.a, cpu.registers.d)),
+    /* 0x9b */ opcode!("SBC A, E", 1, 1, |cpu, bus| cpu.registers.a = cpu.sbc(cpu.registers.a, cpu.registers.e)),
+    /* 0x9c */ opcode!("SBC A, H", 1, 1, |cpu, bus| cpu.registers.a = cpu.sbc(cpu.registers.a, cpu.registers.h)),
+    /* 0x9d */ opcode!("SBC A, L", 1, 1, |cpu, bus| cpu.registers.a = cpu.sbc(cpu.registers.a, cpu.registers.l)),
+    /* 0x9e */ opcode!("SBC A, (HL)", 1, 2, |cpu, bus| cpu.registers.a = cpu.sbc(cpu.registers.a, bus.cpu_read(cpu.registers.hl()))),
+    /* 0x9f */ opcode!("SBC A, A", 1, 1, |cpu, bus| cpu.registers.a = cpu.sbc(cpu.registers.a, cpu.registers.a)),
+    /* 0xa0 */ opcode!("AND B", 1, 1, |cpu, bus| cpu.registers.a = cpu.and(cpu.registers.a, cpu.registers.b)),
+    /* 0xa1 */ opcode!("AND C", 1, 1, |cpu, bus| cpu.registers.a = cpu.and(cpu.registers.a, cpu.registers.c)),
+    /* 0xa2 */ opcode!("AND D", 1, 1, |cpu, bus| cpu.registers.a = cpu.and(cpu.registers.a, cpu.registers.d)),
+    /* 0xa3 */ opcode!("AND E", 1, 1, |cpu, bus| cpu.registers.a = cpu.and(cpu.registers.a, cpu.registers.e)),
+    /* 0xa4 */ opcode!("AND H", 1, 1, |cpu, bus| cpu.registers.a = cpu.and(cpu.registers.a, cpu.registers.h)),
+    /* 0xa5 */ opcode!("AND L", 1, 1, |cpu, bus| cpu.registers.a = cpu.and(cpu.registers.a, cpu.registers.l)),
+    /* 0xa6 */ opcode!("AND (HL)", 1, 2, |cpu, bus| cpu.registers.a = cpu.and(cpu.registers.a, bus.cpu_read(cpu.registers.hl()))),
+    /* 0xa7 */ opcode!("AND A", 1, 1, |cpu, bus| cpu.registers.a = cpu.and(cpu.registers.a, cpu.registers.a)),
+    /* 0xa8 */ opcode!("XOR B", 1, 1, |cpu, bus| cpu.registers.a = cpu.xor(cpu.registers.a, cpu.registers.b)),
+    /* 0xa9 */ opcode!("XOR C", 1, 1, |cpu, bus| cpu.registers.a = cpu.xor(cpu.registers.a, cpu.registers.c)),
+    /* 0xaa */ opcode!("XOR D", 1, 1, |cpu, bus| cpu.registers.a = cpu.xor(cpu.registers.a, cpu.registers.d)),
+    /* 0xab */ opcode!("XOR E", 1, 1, |cpu, bus| cpu.registers.a = cpu.xor(cpu.registers.a, cpu.registers.e)),
+    /* 0xac */ opcode!("XOR H", 1, 1, |cpu, bus| cpu.registers.a = cpu.xor(cpu.registers.a, cpu.registers.h)),
+    /* 0xad */ opcode!("XOR L", 1, 1, |cpu, bus| cpu.registers.a = cpu.xor(cpu.registers.a, cpu.registers.l)),
+    /* 0xae */ opcode!("XOR (HL)", 1, 2, |cpu, bus| cpu.registers.a = cpu.xor(cpu.registers.a, bus.cpu_read(cpu.registers.hl()))),
+    /* 0xaf */ opcode!("XOR A", 1, 1, |cpu, bus| cpu.registers.a = cpu.xor(cpu.registers.a, cpu.registers.a)),
+    /* 0xb0 */ opcode!("OR B", 1, 1, |cpu, bus| cpu.registers.a = cpu.or(cpu.registers.a, cpu.registers.b)),
+    /* 0xb1 */ opcode!("OR C", 1, 1, |cpu, bus| cpu.registers.a = cpu.or(cpu.registers.a, cpu.registers.c)),
+    /* 0xb2 */ opcode!("OR D", 1, 1, |cpu, bus| cpu.registers.a = cpu.or(cpu.registers.a, cpu.registers.d)),
+    /* 0xb3 */ opcode!("OR E", 1, 1, |cpu, bus| cpu.registers.a = cpu.or(cpu.registers.a, cpu.registers.e)),
+    /* 0xb4 */ opcode!("OR H", 1, 1, |cpu, bus| cpu.registers.a = cpu.or(cpu.registers.a, cpu.registers.h)),
+    /* 0xb5 */ opcode!("OR L", 1, 1, |cpu, bus| cpu.registers.a = cpu.or(cpu.registers.a, cpu.registers.l)),
+    /* 0xb6 */ opcode!("OR (HL)", 1, 2, |cpu, bus| cpu.registers.a = cpu.or(cpu.registers.a, bus.cpu_read(cpu.registers.hl()))),
+    /* 0xb7 */ opcode!("OR A", 1, 1, |cpu, bus| cpu.registers.a = cpu.or(cpu.registers.a, cpu.registers.a)),
+    /* 0xb8 */ opcode!("CP B", 1, 1, |cpu, bus| cpu.cp(cpu.registers.a, cpu.registers.b)),
+    /* 0xb9 */ opcode!("CP C", 1, 1, |cpu, bus| cpu.cp(cpu.registers.a, cpu.registers.c)),
+    /* 0xba */ opcode!("CP D", 1, 1, |cpu, bus| cpu.cp(cpu.registers.a, cpu.registers.d)),
+    /* 0xbb */ opcode!("CP E", 1, 1, |cpu, bus| cpu.cp(cpu.registers.a, cpu.registers.e)),
+    /* 0xbc */ opcode!("CP H", 1, 1, |cpu, bus| cpu.cp(cpu.registers.a, cpu.registers.h)),
+    /* 0xbd */ opcode!("CP L", 1, 1, |cpu, bus| cpu.cp(cpu.registers.a, cpu.registers.l)),
+    /* 0xbe */ opcode!("CP (HL)", 1, 2, |cpu, bus| cpu.cp(cpu.registers.a, bus.cpu_read(cpu.registers.hl()))),
+    /* 0xbf */ opcode!("CP A", 1, 1, |cpu, bus| cpu.cp(cpu.registers.a, cpu.registers.a)),
+    /* 0xc0 */ opcode!("RET NZ", 1, 2 / 5, |cpu, bus| cpu.ret_if(bus, !cpu.registers.f.contains(CpuFlags::ZERO))),
+    /* 0xc1 */ opcode!("POP BC", 1, 3, |cpu, bus| {
+        let value = cpu.pop(bus);
+        cpu.registers.set_bc(value);
+    }),
+    /* 0xc2 */ opcode!("JP NZ, a16", 3, 3 / 4, |cpu, bus| cpu.jp_if(bus, !cpu.registers.f.contains(CpuFlags::ZERO))),
+    /* 0xc3 */ opcode!("JP a16", 3, 4, |cpu, bus| cpu.registers.pc = cpu.read_u16(bus)),
+    /* 0xc4 */ opcode!("CALL NZ, a16", 3, 3 / 6, |cpu, bus| cpu.call_if(bus, !cpu.registers.f.contains(CpuFlags::ZERO))),
+    /* 0xc5 */ opcode!("PUSH BC", 1, 4, |cpu, bus| cpu.push(bus, cpu.registers.bc())),
+    /* 0xc6 */ opcode!("ADD A, d8", 2, 2, |cpu, bus| {
+        let value = cpu.read_u8(bus);
+        cpu.registers.a = cpu.add(cpu.registers.a, value);
+    }),
+    /* 0xc7 */ opcode!("RST $00", 1, 4, |cpu, bus| cpu.call(bus, 0x0000)),
+    /* 0xc8 */ opcode!("RET Z", 1, 2 / 5, |cpu, bus| cpu.ret_if(bus, cpu.registers.f.contains(CpuFlags::ZERO))),
+    /* 0xc9 */ opcode!("RET", 1, 4, |cpu, bus| cpu.ret(bus)),
+    /* 0xca */ opcode!("JP Z, a16", 3, 3 / 4, |cpu, bus| cpu.jp_if(bus, cpu.registers.f.contains(CpuFlags::ZERO))),
+    /* 0xcb */ opcode!("PREFIX CB", 1, 1 / 1, |cpu, bus| unreachable!("CB prefixed opcodes are looked up in CB_OPCODES")),
+    /* 0xcc */ opcode!("CALL Z, a16", 3, 3 / 6, |cpu, bus| cpu.call_if(bus, cpu.registers.f.contains(CpuFlags::ZERO))),
+    /* 0xcd */ opcode!("CALL a16", 3, 6, |cpu, bus| {
+        let addr = cpu.read_u16(bus);
+        cpu.call(bus, addr);
+    }),
+    /* 0xce */ opcode!("ADC A, d8", 2, 2, |cpu, bus| {
+        let value = cpu.read_u8(bus);
+        cpu.registers.a = cpu.adc(cpu.registers.a, value);
+    }),
+    /* 0xcf */ opcode!("RST $08", 1, 4, |cpu, bus| cpu.call(bus, 0x0008)),
+    /* 0xd0 */ opcode!("RET NC", 1, 2 / 5, |cpu, bus| cpu.ret_if(bus, !cpu.registers.f.contains(CpuFlags::CARRY))),
+    /* 0xd1 */ opcode!("POP DE", 1, 3, |cpu, bus| {
+        let value = cpu.pop(bus);
+        cpu.registers.set_de(value);
+    }),
+    /* 0xd2 */ opcode!("JP NC, a16", 3, 3 / 4, |cpu, bus| cpu.jp_if(bus, !cpu.registers.f.contains(CpuFlags::CARRY))),
+    /* 0xd3 */ opcode!("INVALID", 1, 1, |cpu, bus| invalid_instruction(cpu)),
+    /* 0xd4 */ opcode!("CALL NC, a16", 3, 3 / 6, |cpu, bus| cpu.call_if(bus, !cpu.registers.f.contains(CpuFlags::CARRY))),
+    /* 0xd5 */ opcode!("PUSH DE", 1, 4, |cpu, bus| cpu.push(bus, cpu.registers.de())),
+    /* 0xd6 */ opcode!("SUB d8", 2, 2, |cpu, bus| {
+        let value = cpu.read_u8(bus);
+        cpu.registers.a = cpu.sub(cpu.registers.a, value);
+    }),
+    /* 0xd7 */ opcode!("RST $10", 1, 4, |cpu, bus| cpu.call(bus, 0x0010)),
+    /* 0xd8 */ opcode!("RET C", 1, 2 / 5, |cpu, bus| cpu.ret_if(bus, cpu.registers.f.contains(CpuFlags::CARRY))),
+    /* 0xd9 */ opcode!("RETI", 1, 4, |cpu, bus| cpu.reti(bus)),
+    /* 0xda */ opcode!("JP C, a16", 3, 3 / 4, |cpu, bus| cpu.jp_if(bus, cpu.registers.f.contains(CpuFlags::CARRY))),
+    /* 0xdb */ opcode!("INVALID", 1, 1, |cpu, bus| invalid_instruction(cpu)),
+    /* 0xdc */ opcode!("CALL C, a16", 3, 3 / 6, |cpu, bus| cpu.call_if(bus, cpu.registers.f.contains(CpuFlags::CARRY))),
+    /* 0xdd */ opcode!("INVALID", 1, 1, |cpu, bus| invalid_instruction(cpu)),
+    /* 0xde */ opcode!("SBC A, d8", 2, 2, |cpu, bus| {
+        let value = cpu.read_u8(bus);
+        cpu.registers.a = cpu.sbc(cpu.registers.a, value);
+    }),
+    /* 0xdf */ opcode!("RST $18", 1, 4, |cpu, bus| cpu.call(bus, 0x0018)),
+    /* 0xe0 */ opcode!("LDH (a8), A", 2, 3, |cpu, bus| {
+        let value = cpu.read_u8(bus);
+        bus.cpu_write(0xff00 + value as u16, cpu.registers.a);
+    }),
+    /* 0xe1 */ opcode!("POP HL", 1, 3, |cpu, bus| {
+        let value = cpu.pop(bus);
+        cpu.registers.set_hl(value);
+    }),
+    /* 0xe2 */ opcode!("LD (C), A", 1, 2, |cpu, bus| bus.cpu_write(0xff00 + cpu.registers.c as u16, cpu.registers.a)),
+    /* 0xe3 */ opcode!("INVALID", 1, 1, |cpu, bus| invalid_instruction(cpu)),
+    /* 0xe4 */ opcode!("INVALID", 1, 1, |cpu, bus| invalid_instruction(cpu)),
+    /* 0xe5 */ opcode!("PUSH HL", 1, 4, |cpu, bus| cpu.push(bus, cpu.registers.hl())),
+    /* 0xe6 */ opcode!("AND d8", 2, 2, |cpu, bus| {
+        let value = cpu.read_u8(bus);
+        cpu.registers.a = cpu.and(cpu.registers.a, value);
+    }),
+    /* 0xe7 */ opcode!("RST $20", 1, 4, |cpu, bus| cpu.call(bus, 0x0020)),
+    /* 0xe8 */ opcode!("ADD SP, s8", 2, 4, |cpu, bus| {
+        let value = cpu.read_i8(bus) as u16;
+
+        // NOTE(grozki): I initially thought this u16::wrapping_add_signed() would work, but it doesn't work with the carry math below.
+        let result = cpu.registers.sp.wrapping_add(value);
+
+        cpu.registers.f.remove(CpuFlags::ZERO | CpuFlags::NEGATIVE);
+        cpu.registers.f.set(CpuFlags::HALF_CARRY, ((cpu.registers.sp & 0x0f) + (value & 0x0f)) & 0x10 != 0);
+        cpu.registers.f.set(CpuFlags::CARRY, ((cpu.registers.sp & 0xff) + (value & 0xff)) & 0x100 != 0);
+
+        cpu.registers.sp = result;
+    }),
+    /* 0xe9 */ opcode!("JP HL", 1, 1, |cpu, bus| cpu.registers.pc = cpu.registers.hl()),
+    /* 0xea */ opcode!("LD (a16), A", 3, 4, |cpu, bus| {
+        let addr = cpu.read_u16(bus);
+        bus.cpu_write(addr, cpu.registers.a);
+    }),
+    /* 0xeb */ opcode!("INVALID", 1, 1, |cpu, bus| invalid_instruction(cpu)),
+    /* 0xec */ opcode!("INVALID", 1, 1, |cpu, bus| invalid_instruction(cpu)),
+    /* 0xed */ opcode!("INVALID", 1, 1, |cpu, bus| invalid_instruction(cpu)),
+    /* 0xee */ opcode!("XOR d8", 2, 2, |cpu, bus| {
+        let value = cpu.read_u8(bus);
+        cpu.registers.a = cpu.xor(cpu.registers.a, value);
+    }),
+    /* 0xef */ opcode!("RST $28", 1, 4, |cpu, bus| cpu.call(bus, 0x0028)),
+    /* 0xf0 */ opcode!("LDH A, (a8)", 2, 3, |cpu, bus| {
+        let offset = cpu.read_u8(bus);
+        cpu.registers.a = bus.cpu_read(0xff00 + offset as u16);
+    }),
+    /* 0xf1 */ opcode!("POP AF", 1, 3, |cpu, bus| {
+        let value = cpu.pop(bus);
+        cpu.registers.set_af(value);
+    }),
+    /* 0xf2 */ opcode!("LD A, (C)", 1, 2, |cpu, bus| cpu.registers.a = bus.cpu_read(0xff00 + cpu.registers.c as u16)),
+    /* 0xf3 */ opcode!("DI", 1, 1, |cpu, bus| cpu.interrupts_master_enable = false),
+    /* 0xf4 */ opcode!("INVALID", 1, 1, |cpu, bus| invalid_instruction(cpu)),
+    /* 0xf5 */ opcode!("PUSH AF", 1, 4, |cpu, bus| {
+        let af = cpu.registers.af();
+        cpu.push(bus, af);
+    }),
+    /* 0xf6 */ opcode!("OR d8", 2, 2, |cpu, bus| {
+        let value = cpu.read_u8(bus);
+        cpu.registers.a = cpu.or(cpu.registers.a, value);
+    }),
+    /* 0xf7 */ opcode!("RST $30", 1, 4, |cpu, bus| cpu.call(bus, 0x0030)),
+    /* 0xf8 */ opcode!("LD HL, SP+s8", 2, 3, |cpu, bus| {
+        let value = cpu.read_i8(bus) as u16;
+        let result = cpu.registers.sp.wrapping_add(value);
+
+        cpu.registers.f.remove(CpuFlags::ZERO | CpuFlags::NEGATIVE);
+        cpu.registers.f.set(CpuFlags::HALF_CARRY, ((cpu.registers.sp & 0x0f) + (value & 0x0f)) & 0x10 != 0);
+        cpu.registers.f.set(CpuFlags::CARRY, ((cpu.registers.sp & 0xff) + (value & 0xff)) & 0x100 != 0);
+
+        cpu.registers.set_hl(result);
+    }),
+    /* 0xf9 */ opcode!("LD SP, HL", 1, 2, |cpu, bus| cpu.registers.sp = cpu.registers.hl()),
+    /* 0xfa */ opcode!("LD A, (a16)", 3, 4, |cpu, bus| {
+        let addr = cpu.read_u16(bus);
+        cpu.registers.a = bus.cpu_read(addr);
+    }),
+    /* 0xfb */ opcode!("EI", 1, 1, |cpu, bus| cpu.interrupts_master_enable = true),
+    /* 0xfc */ opcode!("INVALID", 1, 1, |cpu, bus| invalid_instruction(cpu)),
+    /* 0xfd */ opcode!("INVALID", 1, 1, |cpu, bus| invalid_instruction(cpu)),
+    /* 0xfe */ opcode!("CP d8", 2, 2, |cpu, bus| {
+        let value = cpu.read_u8(bus);
+        cpu.cp(cpu.registers.a, value);
+    }),
+    /* 0xff */ opcode!("RST $38", 1, 4, |cpu, bus| cpu.call(bus, 0x0038)),
+];
+
+/// Opcodes following the CB prefix, indexed by bits 3-7 (the operation) and bits 0-2 (the operand), see `cb_opcode`.
+static CB_OPCODES: [[Opcode; 8]; 32] = [
+    cb_row!(0x00, "RLC ", 4),
+    cb_row!(0x01, "RRC ", 4),
+    cb_row!(0x02, "RL ", 4),
+    cb_row!(0x03, "RR ", 4),
+    cb_row!(0x04, "SLA ", 4),
+    cb_row!(0x05, "SRA ", 4),
+    cb_row!(0x06, "SWAP ", 4),
+    cb_row!(0x07, "SRL ", 4),
+    // BIT only reads (HL), so it takes a cycle less.
+    cb_row!(0x08, "BIT 0, ", 3),
+    cb_row!(0x09, "BIT 1, ", 3),
+    cb_row!(0x0a, "BIT 2, ", 3),
+    cb_row!(0x0b, "BIT 3, ", 3),
+    cb_row!(0x0c, "BIT 4, ", 3),
+    cb_row!(0x0d, "BIT 5, ", 3),
+    cb_row!(0x0e, "BIT 6, ", 3),
+    cb_row!(0x0f, "BIT 7, ", 3),
+    cb_row!(0x10, "RES 0, ", 4),
+    cb_row!(0x11, "RES 1, ", 4),
+    cb_row!(0x12, "RES 2, ", 4),
+    cb_row!(0x13, "RES 3, ", 4),
+    cb_row!(0x14, "RES 4, ", 4),
+    cb_row!(0x15, "RES 5, ", 4),
+    cb_row!(0x16, "RES 6, ", 4),
+    cb_row!(0x17, "RES 7, ", 4),
+    cb_row!(0x18, "SET 0, ", 4),
+    cb_row!(0x19, "SET 1, ", 4),
+    cb_row!(0x1a, "SET 2, ", 4),
+    cb_row!(0x1b, "SET 3, ", 4),
+    cb_row!(0x1c, "SET 4, ", 4),
+    cb_row!(0x1d, "SET 5, ", 4),
+    cb_row!(0x1e, "SET 6, ", 4),
+    cb_row!(0x1f, "SET 7, ", 4),
+];
+
+/// Looks up the opcode following a CB prefix.
+pub(crate) fn cb_opcode(opcode: u8) -> &'static Opcode {
+    &CB_OPCODES[(opcode >> 3) as usize][(opcode & 0x7) as usize]
+}
@@ -1,6 +1,7 @@
 ﻿use std::fmt;
 use std::fmt::Formatter;
 use bitflags::Flags;
+use super::{state::state_fields, Model};
 
 bitflags! {
     #[derive(Copy, Clone)]
@@ -78,6 +79,28 @@ impl fmt::Display for CpuRegisters {
     }
 }
 
+impl CpuRegisters {
+    /// The registers as the boot ROM of `model` leaves them. A CGB is always in CGB mode, even for a DMG game.
+    // https://gbdev.io/pandocs/Power_Up_Sequence.html#cpu-registers
+    pub fn after_boot(model: Model) -> Self {
+        match model {
+            Model::Dmg => Self::default(),
+            Model::Cgb => Self {
+                a: 0x11,
+                f: CpuFlags::ZERO,
+                b: 0x00,
+                c: 0x00,
+                d: 0xff,
+                e: 0x56,
+                h: 0x00,
+                l: 0x0d,
+                pc: 0x0100,
+                sp: 0xfffe,
+            },
+        }
+    }
+}
+
 impl Default for CpuRegisters {
     // https://gbdev.io/pandocs/Power_Up_Sequence.html#cpu-registers
     fn default() -> Self {
@@ -95,3 +118,5 @@ impl Default for CpuRegisters {
         }
     }
 }
+
+state_fields!(CpuRegisters { a, f, b, c, d, e, h, l, sp, pc });
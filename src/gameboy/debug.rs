@@ -0,0 +1,67 @@
+﻿use std::fmt;
+use std::fmt::Formatter;
+use std::ops::RangeInclusive;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum MemoryAccess {
+    Read,
+    Write,
+}
+
+pub struct Watchpoint {
+    pub range: RangeInclusive<u16>,
+    pub on_read: bool,
+    pub on_write: bool,
+}
+
+impl Watchpoint {
+    pub fn matches(&self, addr: u16, access: MemoryAccess) -> bool {
+        let enabled = match access {
+            MemoryAccess::Read => self.on_read,
+            MemoryAccess::Write => self.on_write,
+        };
+
+        enabled && self.range.contains(&addr)
+    }
+}
+
+/// A watched memory access, as recorded by the bus. The PC is filled in by the caller, which knows the instruction.
+#[derive(Clone, Copy, Debug)]
+pub struct WatchpointHit {
+    pub addr: u16,
+    pub old_value: u8,
+    pub new_value: u8,
+    pub access: MemoryAccess,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum DebugEvent {
+    Watchpoint {
+        /// Address of the instruction that performed the access.
+        pc: u16,
+        addr: u16,
+        old_value: u8,
+        new_value: u8,
+        access: MemoryAccess,
+    },
+    /// The CPU ran an invalid opcode, and locked up until the game is reset or a state loaded.
+    Locked {
+        /// Address of the invalid opcode.
+        pc: u16,
+        opcode: u8,
+    },
+}
+
+impl fmt::Display for DebugEvent {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match *self {
+            DebugEvent::Watchpoint { pc, addr, new_value, access: MemoryAccess::Read, .. } => {
+                write!(f, "Watchpoint: PC={pc:04X} read ${addr:04X} = {new_value:02X}")
+            }
+            DebugEvent::Watchpoint { pc, addr, old_value, new_value, access: MemoryAccess::Write } => {
+                write!(f, "Watchpoint: PC={pc:04X} write ${addr:04X} {old_value:02X} -> {new_value:02X}")
+            }
+            DebugEvent::Locked { pc, opcode } => write!(f, "Locked up: PC={pc:04X} invalid opcode {opcode:02X}"),
+        }
+    }
+}
@@ -0,0 +1,60 @@
+use std::fmt::{self, Formatter};
+
+/// Bytes per line of the memory dumps.
+const DUMP_LINE_LEN: usize = 16;
+
+/// The state of the machine when something went wrong, for attaching to a bug report, see `GameBoy::diagnostic_dump`.
+/// It displays as text, with a section for each part.
+pub struct DiagnosticReport {
+    /// The CPU registers, as printed by the trace.
+    pub registers: String,
+    /// Address of the invalid opcode the CPU locked up on, if it did.
+    pub locked_at: Option<u16>,
+    /// The last instructions run, oldest first, with their addresses. They are disassembled from memory as it is now,
+    /// so an instruction in a bank that was switched out since shows what replaced it.
+    pub recent_instructions: Vec<(u16, String)>,
+    pub interrupt_flag: u8,
+    pub interrupt_enable: u8,
+    pub lcdc: u8,
+    pub stat: u8,
+    /// 0xc000-0xdfff.
+    pub wram: Vec<u8>,
+    /// 0xff80-0xfffe.
+    pub hram: Vec<u8>,
+    /// 0x8000-0x9fff.
+    pub vram: Vec<u8>,
+    /// 0xfe00-0xfe9f.
+    pub oam: Vec<u8>,
+}
+
+impl fmt::Display for DiagnosticReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        writeln!(f, "[CPU]")?;
+        writeln!(f, "{}", self.registers)?;
+
+        if let Some(pc) = self.locked_at {
+            writeln!(f, "Locked up on the invalid opcode at {pc:04X}")?;
+        }
+
+        writeln!(f, "\n[Recent instructions]")?;
+
+        for (pc, instruction) in &self.recent_instructions {
+            writeln!(f, "{pc:04X}: {instruction}")?;
+        }
+
+        writeln!(f, "\n[Registers]")?;
+        writeln!(f, "IF={:02X} IE={:02X} LCDC={:02X} STAT={:02X}", self.interrupt_flag, self.interrupt_enable, self.lcdc, self.stat)?;
+
+        for (name, start, bytes) in [("WRAM", 0xc000, &self.wram), ("HRAM", 0xff80, &self.hram), ("VRAM", 0x8000, &self.vram), ("OAM", 0xfe00, &self.oam)] {
+            writeln!(f, "\n[{name}]")?;
+
+            for (index, line) in bytes.chunks(DUMP_LINE_LEN).enumerate() {
+                let hex = line.iter().map(|byte| format!("{byte:02X}")).collect::<Vec<_>>().join(" ");
+
+                writeln!(f, "{:04X}: {hex}", start + index * DUMP_LINE_LEN)?;
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,30 @@
+use super::{
+    bus::Bus,
+    cpu::{cb_opcode, CB_PREFIX, OPCODES},
+};
+
+/// Disassembles the instruction at `addr` without side effects. Returns its text and length in bytes.
+pub(crate) fn disassemble(bus: &Bus, addr: u16) -> (String, u16) {
+    let byte = |offset: u16| bus.mem_peek(addr.wrapping_add(offset));
+
+    let opcode = match byte(0) {
+        CB_PREFIX => cb_opcode(byte(1)),
+        instruction => &OPCODES[instruction as usize],
+    };
+
+    let length = opcode.length as u16;
+
+    let d8 = byte(1);
+    let d16 = u16::from_le_bytes([byte(1), byte(2)]);
+    let jump_target = addr.wrapping_add(length).wrapping_add_signed(d8 as i8 as i16);
+
+    let text = opcode.mnemonic
+        .replace("d16", &format!("${d16:04X}"))
+        .replace("a16", &format!("${d16:04X}"))
+        .replace("d8", &format!("${d8:02X}"))
+        .replace("a8", &format!("$FF{d8:02X}"))
+        .replace("r8", &format!("${jump_target:04X}"))
+        .replace("s8", &format!("{}", d8 as i8));
+
+    (text, length)
+}
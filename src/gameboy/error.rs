@@ -0,0 +1,42 @@
+use std::{error::Error, fmt};
+
+/// Why the core couldn't do what was asked of it.
+#[derive(Debug)]
+pub enum CoreError {
+    /// The ROM is too short to hold its header, or as much as its header says it holds.
+    RomTooSmall { size: usize, expected: usize },
+    /// A header field holds a value no cartridge uses.
+    BadHeader { field: &'static str, value: u8 },
+    /// The cartridge uses a mapper that isn't emulated.
+    UnsupportedMapper(&'static str),
+    /// A save state can't be restored, for the given reason.
+    InvalidState(&'static str),
+    /// A save state is of a newer format than this version reads, made by the given version of the core.
+    StateVersion { version: u32, core: String },
+    /// The clock that follows the RAM in a save is of a size no footer has, so it wasn't restored. The RAM was.
+    BadSaveClock { size: usize },
+}
+
+impl fmt::Display for CoreError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CoreError::RomTooSmall { size, expected } => write!(f, "The ROM is {size} bytes, but should be at least {expected}"),
+            CoreError::BadHeader { field, value } => write!(f, "The ROM header has an invalid {field}: {value:#04x}"),
+            CoreError::UnsupportedMapper(mapper) => write!(f, "The cartridge uses the {mapper} mapper, which isn't supported"),
+            CoreError::InvalidState(reason) => write!(f, "The save state can't be loaded, {reason}"),
+            CoreError::StateVersion { version, core } => write!(
+                f,
+                "The save state can't be loaded, it was made by version {core} in format {version}, which is newer than this version reads ({})",
+                super::state::VERSION,
+            ),
+            CoreError::BadSaveClock { size } => write!(
+                f,
+                "Ignoring the clock in the save, it is {size} bytes but should be {} or {}",
+                super::rtc::FOOTER_SIZE,
+                super::rtc::SHORT_FOOTER_SIZE,
+            ),
+        }
+    }
+}
+
+impl Error for CoreError {}
@@ -0,0 +1,96 @@
+﻿use std::collections::VecDeque;
+use std::collections::vec_deque::Drain;
+use std::fmt;
+use std::fmt::Formatter;
+use super::io_registers::InterruptFlags;
+
+const EVENT_LOG_CAPACITY: usize = 4096;
+
+#[derive(Clone, Copy, Debug)]
+pub enum Event {
+    InterruptRequested(InterruptFlags),
+    InterruptDispatched(InterruptFlags),
+    /// A write to one of the LCD registers that affect rendering (LCDC, STAT, SCY, SCX, WY, WX).
+    RegisterWrite {
+        addr: u16,
+        value: u8,
+    },
+    DmaStarted {
+        source: u16,
+    },
+    TimerOverflow,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct LoggedEvent {
+    pub frame: u64,
+    pub scanline: u8,
+    pub dot: u16,
+    pub event: Event,
+}
+
+/// Bounded log of timing-related events. Once full, the oldest events are dropped.
+#[derive(Default)]
+pub struct EventLog {
+    pub enabled: bool,
+    /// Position of the PPU, kept up to date by the PPU while logging is enabled.
+    pub frame: u64,
+    pub dot: u16,
+    events: VecDeque<LoggedEvent>,
+}
+
+impl EventLog {
+    pub fn push(&mut self, scanline: u8, event: Event) {
+        if self.events.len() == EVENT_LOG_CAPACITY {
+            self.events.pop_front();
+        }
+
+        self.events.push_back(LoggedEvent {
+            frame: self.frame,
+            scanline,
+            dot: self.dot,
+            event,
+        });
+    }
+
+    pub fn drain(&mut self) -> Drain<'_, LoggedEvent> {
+        self.events.drain(..)
+    }
+}
+
+fn interrupt_name(flag: InterruptFlags) -> &'static str {
+    match flag {
+        InterruptFlags::VBLANK => "VBlank",
+        InterruptFlags::LCD_STAT => "STAT",
+        InterruptFlags::TIMER => "Timer",
+        InterruptFlags::SERIAL => "Serial",
+        InterruptFlags::JOYPAD => "Joypad",
+        _ => "?",
+    }
+}
+
+fn register_name(addr: u16) -> &'static str {
+    match addr {
+        0xff40 => "LCDC",
+        0xff41 => "STAT",
+        0xff42 => "SCY",
+        0xff43 => "SCX",
+        0xff4a => "WY",
+        0xff4b => "WX",
+        _ => "?",
+    }
+}
+
+impl fmt::Display for LoggedEvent {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "frame {} line {:3} dot {:3}: ", self.frame, self.scanline, self.dot)?;
+
+        match self.event {
+            Event::InterruptRequested(flag) => write!(f, "{} interrupt requested", interrupt_name(flag)),
+            Event::InterruptDispatched(flag) => write!(f, "{} interrupt dispatched", interrupt_name(flag)),
+            Event::RegisterWrite { addr, value } => write!(f, "{} = {value:02X}", register_name(addr)),
+            Event::DmaStarted { source } => write!(f, "OAM DMA from ${source:04X}"),
+            Event::TimerOverflow => write!(f, "TIMA overflow"),
+        }
+    }
+}
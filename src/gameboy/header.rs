@@ -0,0 +1,462 @@
+use super::error::CoreError;
+
+pub(super) const OFFSET_TITLE: usize = 0x0134;
+const OFFSET_MANUFACTURER_CODE: usize = 0x013f;
+const OFFSET_CGB_FLAG: usize = 0x0143;
+const OFFSET_NEW_LICENSEE_CODE: usize = 0x0144;
+const OFFSET_SGB_FLAG: usize = 0x0146;
+pub(super) const OFFSET_CARTRIDGE_TYPE: usize = 0x0147;
+pub(super) const OFFSET_ROM_SIZE: usize = 0x0148;
+pub(super) const OFFSET_RAM_SIZE: usize = 0x0149;
+const OFFSET_OLD_LICENSEE_CODE: usize = 0x014b;
+const OFFSET_MASK_ROM_VERSION_NUMBER: usize = 0x014c;
+pub(super) const OFFSET_CHECKSUM: usize = 0x014d;
+const OFFSET_GLOBAL_CHECKSUM: usize = 0x014e;
+// The header ends with the global checksum.
+pub(super) const HEADER_END: usize = 0x0150;
+
+// An old licensee code of 0x33 means the new licensee code is used instead.
+const USE_NEW_LICENSEE_CODE: u8 = 0x33;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum CgbSupport {
+    None,
+    /// Runs on DMG, with extra features on CGB.
+    Enhanced,
+    Only,
+}
+
+impl CgbSupport {
+    /// Whether the game runs on a DMG, the only model emulated. Games that require a CGB usually show a screen
+    /// saying so, or crash.
+    pub fn runs_on_dmg(self) -> bool {
+        self != CgbSupport::Only
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum LicenseeCode {
+    /// A single byte, used by earlier games.
+    Old(u8),
+    /// Two ASCII characters, used by games from after the SGB.
+    New(String),
+}
+
+/// The cartridge header, at 0x0100-0x014f of the ROM.
+#[derive(Clone, Debug)]
+pub struct CartridgeHeader {
+    pub title: String,
+    /// Only present in later games, in the last 4 bytes of what used to be the title.
+    pub manufacturer_code: Option<String>,
+    pub cgb: CgbSupport,
+    pub sgb: bool,
+    pub cartridge_type: u8,
+    /// In bytes.
+    pub rom_size: usize,
+    /// In bytes.
+    pub ram_size: usize,
+    /// Whether the RAM size is a known one. Bad dumps and some homebrew have others, which are read as no RAM.
+    pub ram_size_valid: bool,
+    pub licensee_code: LicenseeCode,
+    pub version: u8,
+    pub header_checksum: u8,
+    pub header_checksum_valid: bool,
+    pub global_checksum: u16,
+    /// Games boot with a wrong global checksum, and some ship with one.
+    pub global_checksum_valid: bool,
+}
+
+impl CartridgeHeader {
+    pub fn parse(rom: &[u8]) -> Result<Self, CoreError> {
+        if rom.len() < HEADER_END {
+            return Err(CoreError::RomTooSmall { size: rom.len(), expected: HEADER_END });
+        }
+
+        let cartridge_type = rom[OFFSET_CARTRIDGE_TYPE];
+        if cartridge_type_name(cartridge_type).is_none() {
+            return Err(CoreError::BadHeader { field: "cartridge type", value: cartridge_type });
+        }
+
+        let rom_size_type = rom[OFFSET_ROM_SIZE];
+        let rom_size = rom_size(rom_size_type)
+            .ok_or(CoreError::BadHeader { field: "ROM size", value: rom_size_type })?;
+
+        let ram_size_kib = ram_size_kib(rom[OFFSET_RAM_SIZE]);
+
+        let cgb = match rom[OFFSET_CGB_FLAG] {
+            0xc0 => CgbSupport::Only,
+            0x80 => CgbSupport::Enhanced,
+            _ => CgbSupport::None,
+        };
+
+        // The CGB flag and manufacturer code were carved out of the end of the title.
+        let (title_end, manufacturer_code) = match cgb {
+            CgbSupport::None => (OFFSET_CGB_FLAG + 1, None),
+            _ => {
+                let code = &rom[OFFSET_MANUFACTURER_CODE..OFFSET_CGB_FLAG];
+
+                if code.iter().all(u8::is_ascii_uppercase) {
+                    (OFFSET_MANUFACTURER_CODE, Some(ascii(code)))
+                } else {
+                    (OFFSET_CGB_FLAG, None)
+                }
+            }
+        };
+
+        let licensee_code = match rom[OFFSET_OLD_LICENSEE_CODE] {
+            USE_NEW_LICENSEE_CODE => LicenseeCode::New(ascii(&rom[OFFSET_NEW_LICENSEE_CODE..OFFSET_SGB_FLAG])),
+            code => LicenseeCode::Old(code),
+        };
+
+        let header_checksum = rom[OFFSET_CHECKSUM];
+        let computed_header_checksum = rom[OFFSET_TITLE..=OFFSET_MASK_ROM_VERSION_NUMBER]
+            .iter()
+            .fold(0u8, |checksum, &byte| checksum.wrapping_sub(byte).wrapping_sub(1));
+
+        let global_checksum = u16::from_be_bytes([rom[OFFSET_GLOBAL_CHECKSUM], rom[OFFSET_GLOBAL_CHECKSUM + 1]]);
+        let computed_global_checksum = rom.iter()
+            .enumerate()
+            .filter(|&(offset, _)| offset != OFFSET_GLOBAL_CHECKSUM && offset != OFFSET_GLOBAL_CHECKSUM + 1)
+            .fold(0u16, |checksum, (_, &byte)| checksum.wrapping_add(byte as u16));
+
+        Ok(Self {
+            title: ascii(&rom[OFFSET_TITLE..title_end]),
+            manufacturer_code,
+            cgb,
+            // Without the old licensee code that goes with it, SGB functions are not enabled.
+            sgb: rom[OFFSET_SGB_FLAG] == 0x03 && rom[OFFSET_OLD_LICENSEE_CODE] == USE_NEW_LICENSEE_CODE,
+            cartridge_type,
+            rom_size,
+            ram_size: ram_size_kib.unwrap_or(0) * 1024,
+            ram_size_valid: ram_size_kib.is_some(),
+            licensee_code,
+            version: rom[OFFSET_MASK_ROM_VERSION_NUMBER],
+            header_checksum,
+            header_checksum_valid: header_checksum == computed_header_checksum,
+            global_checksum,
+            global_checksum_valid: global_checksum == computed_global_checksum,
+        })
+    }
+
+    /// The name of the cartridge type, as listed in Pan Docs, e.g. "MBC1+RAM+BATTERY".
+    pub fn cartridge_type_name(&self) -> &'static str {
+        cartridge_type_name(self.cartridge_type).unwrap()
+    }
+
+    /// The name of the chip that maps the ROM and RAM into memory, or "None" for ROMs of up to 32 KiB.
+    pub fn mapper(&self) -> &'static str {
+        match self.cartridge_type {
+            0x00 | 0x08 | 0x09 => "None",
+            0x01..=0x03 => "MBC1",
+            0x05 | 0x06 => "MBC2",
+            0x0b..=0x0d => "MMM01",
+            0x0f..=0x13 => "MBC3",
+            0x19..=0x1e => "MBC5",
+            0x20 => "MBC6",
+            0x22 => "MBC7",
+            0xfc => "Pocket Camera",
+            0xfd => "Bandai TAMA5",
+            0xfe => "HuC3",
+            _ => "HuC1",
+        }
+    }
+
+    /// Whether RAM, or the clock, is kept by a battery.
+    pub fn has_battery(&self) -> bool {
+        matches!(self.cartridge_type, 0x03 | 0x06 | 0x09 | 0x0d | 0x0f | 0x10 | 0x13 | 0x1b | 0x1e | 0x22 | 0xff)
+    }
+
+    pub fn has_rtc(&self) -> bool {
+        matches!(self.cartridge_type, 0x0f | 0x10)
+    }
+
+    /// The name of the licensee, if the code is a known one.
+    pub fn licensee(&self) -> Option<&'static str> {
+        match &self.licensee_code {
+            LicenseeCode::Old(code) => old_licensee_name(*code),
+            LicenseeCode::New(code) => new_licensee_name(code),
+        }
+    }
+}
+
+/// Sizes go from 32 KiB to 8 MiB, besides the 72, 80 and 96 banks of 16 KiB used by a few multicarts.
+pub fn rom_size(rom_size_type: u8) -> Option<usize> {
+    match rom_size_type {
+        0..=8 => Some((32 * 1024) << rom_size_type),
+        0x52 => Some(72 * 0x4000),
+        0x53 => Some(80 * 0x4000),
+        0x54 => Some(96 * 0x4000),
+        _ => None,
+    }
+}
+
+pub fn ram_size_kib(ram_size_type: u8) -> Option<usize> {
+    match ram_size_type {
+        0 => Some(0),
+        // Listed as unused, but some documentation describes it as 2 KiB.
+        1 => Some(2),
+        2 => Some(8),
+        3 => Some(32),
+        4 => Some(128),
+        5 => Some(64),
+        _ => None,
+    }
+}
+
+/// Printable ASCII up to the first NUL, which pads titles and codes.
+fn ascii(bytes: &[u8]) -> String {
+    bytes.iter()
+        .take_while(|&&byte| byte != 0)
+        .map(|&byte| if byte.is_ascii_graphic() || byte == b' ' { byte as char } else { '?' })
+        .collect::<String>()
+        .trim_end()
+        .to_owned()
+}
+
+fn cartridge_type_name(cartridge_type: u8) -> Option<&'static str> {
+    let name = match cartridge_type {
+        0x00 => "ROM ONLY",
+        0x01 => "MBC1",
+        0x02 => "MBC1+RAM",
+        0x03 => "MBC1+RAM+BATTERY",
+        0x05 => "MBC2",
+        0x06 => "MBC2+BATTERY",
+        0x08 => "ROM+RAM",
+        0x09 => "ROM+RAM+BATTERY",
+        0x0b => "MMM01",
+        0x0c => "MMM01+RAM",
+        0x0d => "MMM01+RAM+BATTERY",
+        0x0f => "MBC3+TIMER+BATTERY",
+        0x10 => "MBC3+TIMER+RAM+BATTERY",
+        0x11 => "MBC3",
+        0x12 => "MBC3+RAM",
+        0x13 => "MBC3+RAM+BATTERY",
+        0x19 => "MBC5",
+        0x1a => "MBC5+RAM",
+        0x1b => "MBC5+RAM+BATTERY",
+        0x1c => "MBC5+RUMBLE",
+        0x1d => "MBC5+RUMBLE+RAM",
+        0x1e => "MBC5+RUMBLE+RAM+BATTERY",
+        0x20 => "MBC6",
+        0x22 => "MBC7+SENSOR+RUMBLE+RAM+BATTERY",
+        0xfc => "POCKET CAMERA",
+        0xfd => "BANDAI TAMA5",
+        0xfe => "HuC3",
+        0xff => "HuC1+RAM+BATTERY",
+        _ => return None,
+    };
+
+    Some(name)
+}
+
+fn new_licensee_name(code: &str) -> Option<&'static str> {
+    let name = match code {
+        "00" => "None",
+        "01" => "Nintendo R&D1",
+        "08" => "Capcom",
+        "13" => "Electronic Arts",
+        "18" => "Hudson Soft",
+        "19" => "b-ai",
+        "20" => "KSS",
+        "22" => "pow",
+        "24" => "PCM Complete",
+        "25" => "San-X",
+        "28" => "Kemco Japan",
+        "29" => "SETA",
+        "30" => "Viacom",
+        "31" => "Nintendo",
+        "32" => "Bandai",
+        "33" => "Ocean/Acclaim",
+        "34" => "Konami",
+        "35" => "Hector",
+        "37" => "Taito",
+        "38" => "Hudson",
+        "39" => "Banpresto",
+        "41" => "Ubi Soft",
+        "42" => "Atlus",
+        "44" => "Malibu",
+        "46" => "angel",
+        "47" => "Bullet-Proof",
+        "49" => "irem",
+        "50" => "Absolute",
+        "51" => "Acclaim",
+        "52" => "Activision",
+        "53" => "American sammy",
+        "54" => "Konami",
+        "55" => "Hi tech entertainment",
+        "56" => "LJN",
+        "57" => "Matchbox",
+        "58" => "Mattel",
+        "59" => "Milton Bradley",
+        "60" => "Titus",
+        "61" => "Virgin",
+        "64" => "LucasArts",
+        "67" => "Ocean",
+        "69" => "Electronic Arts",
+        "70" => "Infogrames",
+        "71" => "Interplay",
+        "72" => "Broderbund",
+        "73" => "sculptured",
+        "75" => "sci",
+        "78" => "THQ",
+        "79" => "Accolade",
+        "80" => "misawa",
+        "83" => "lozc",
+        "86" => "Tokuma Shoten Intermedia",
+        "87" => "Tsukuda Original",
+        "91" => "Chunsoft",
+        "92" => "Video system",
+        "93" => "Ocean/Acclaim",
+        "95" => "Varie",
+        "96" => "Yonezawa/s'pal",
+        "97" => "Kaneko",
+        "99" => "Pack in soft",
+        "A4" => "Konami (Yu-Gi-Oh!)",
+        _ => return None,
+    };
+
+    Some(name)
+}
+
+fn old_licensee_name(code: u8) -> Option<&'static str> {
+    let name = match code {
+        0x00 => "None",
+        0x01 | 0x31 => "Nintendo",
+        0x08 | 0x38 => "Capcom",
+        0x09 => "Hot-B",
+        0x0a | 0xe0 => "Jaleco",
+        0x0b => "Coconuts",
+        0x0c | 0x6e => "Elite Systems",
+        0x13 | 0x69 => "Electronic Arts",
+        0x18 => "Hudsonsoft",
+        0x19 => "ITC Entertainment",
+        0x1a => "Yanoman",
+        0x1d => "Clary",
+        0x1f | 0x4a | 0x61 => "Virgin",
+        0x24 => "PCM Complete",
+        0x25 => "San-X",
+        0x28 => "Kotobuki Systems",
+        0x29 => "SETA",
+        0x30 | 0x70 => "Infogrames",
+        0x32 | 0xa2 | 0xb2 => "Bandai",
+        0x34 | 0xa4 => "Konami",
+        0x35 => "Hector",
+        0x39 | 0x9d | 0xd9 => "Banpresto",
+        0x3c => "Entertainment i",
+        0x3e => "Gremlin",
+        0x41 => "Ubi Soft",
+        0x42 | 0xeb => "Atlus",
+        0x44 | 0x4d => "Malibu",
+        0x46 | 0xcf => "Angel",
+        0x47 => "Spectrum Holoby",
+        0x49 => "Irem",
+        0x4f => "U.S. Gold",
+        0x50 => "Absolute",
+        0x51 | 0xb0 => "Acclaim",
+        0x52 => "Activision",
+        0x53 => "American Sammy",
+        0x54 => "GameTek",
+        0x55 => "Park Place",
+        0x56 | 0xdb | 0xff => "LJN",
+        0x57 => "Matchbox",
+        0x59 => "Milton Bradley",
+        0x5a => "Mindscape",
+        0x5b => "Romstar",
+        0x5c | 0xd6 => "Naxat Soft",
+        0x5d => "Tradewest",
+        0x60 => "Titus",
+        0x67 => "Ocean",
+        0x6f => "Electro Brain",
+        0x71 => "Interplay",
+        0x72 | 0xaa => "Broderbund",
+        0x73 => "Sculptered Soft",
+        0x75 => "The Sales Curve",
+        0x78 => "THQ",
+        0x79 => "Accolade",
+        0x7a => "Triffix Entertainment",
+        0x7c => "Microprose",
+        0x7f | 0xc2 => "Kemco",
+        0x80 => "Misawa Entertainment",
+        0x83 => "Lozc",
+        0x86 | 0xc4 => "Tokuma Shoten Intermedia",
+        0x8b => "Bullet-Proof Software",
+        0x8c => "Vic Tokai",
+        0x8e => "Ape",
+        0x8f => "I'Max",
+        0x91 => "Chunsoft",
+        0x92 => "Video System",
+        0x93 => "Tsubaraya Productions",
+        0x95 | 0xe3 => "Varie",
+        0x96 => "Yonezawa/s'pal",
+        0x97 => "Kaneko",
+        0x99 => "Arc",
+        0x9a => "Nihon Bussan",
+        0x9b => "Tecmo",
+        0x9c => "Imagineer",
+        0x9f => "Nova",
+        0xa1 => "Hori Electric",
+        0xa6 => "Kawada",
+        0xa7 => "Takara",
+        0xa9 => "Technos Japan",
+        0xac => "Toei Animation",
+        0xad => "Toho",
+        0xaf => "Namco",
+        0xb1 => "ASCII or Nexsoft",
+        0xb4 => "Square Enix",
+        0xb6 => "HAL Laboratory",
+        0xb7 => "SNK",
+        0xb9 | 0xce => "Pony Canyon",
+        0xba => "Culture Brain",
+        0xbb => "Sunsoft",
+        0xbd => "Sony Imagesoft",
+        0xbf => "Sammy",
+        0xc0 | 0xd0 => "Taito",
+        0xc3 => "Squaresoft",
+        0xc5 => "Data East",
+        0xc6 => "Tonkinhouse",
+        0xc8 => "Koei",
+        0xc9 => "UFL",
+        0xca => "Ultra",
+        0xcb => "Vap",
+        0xcc => "Use Corporation",
+        0xcd => "Meldac",
+        0xd1 => "Sofel",
+        0xd2 => "Quest",
+        0xd3 => "Sigma Enterprises",
+        0xd4 => "ASK Kodansha",
+        0xd7 => "Copya System",
+        0xda => "Tomy",
+        0xdd => "NCS",
+        0xde => "Human",
+        0xdf => "Altron",
+        0xe1 => "Towa Chiki",
+        0xe2 => "Yutaka",
+        0xe5 => "Epoch",
+        0xe7 => "Athena",
+        0xe8 => "Asmik Ace Entertainment",
+        0xe9 => "Natsume",
+        0xea => "King Records",
+        0xec => "Epic/Sony Records",
+        0xee => "IGS",
+        0xf0 => "A Wave",
+        0xf3 => "Extreme Entertainment",
+        _ => return None,
+    };
+
+    Some(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn games_run_on_dmg_unless_they_require_a_cgb() {
+        for (flag, runs) in [(0x00, true), (0x80, true), (0xc0, false)] {
+            let mut rom = vec![0; 0x8000];
+            rom[OFFSET_CGB_FLAG] = flag;
+
+            assert_eq!(CartridgeHeader::parse(&rom).unwrap().cgb.runs_on_dmg(), runs, "CGB flag {flag:#04x}");
+        }
+    }
+}
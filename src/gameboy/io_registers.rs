@@ -1,5 +1,11 @@
 ﻿use bitflags::Flags;
-use super::Mem;
+use super::{
+    error::CoreError,
+    event_log::{Event, EventLog},
+    open_bus::OpenBus,
+    state::{state_fields, State, StateReader},
+    Mem,
+};
 
 bitflags! {
     #[derive(Default, Copy, Clone, PartialEq, Eq, Debug)]
@@ -26,19 +32,38 @@ bitflags! {
     }
 }
 
+/// Steps of the DMA copy, of 4 bytes each, between a write to 0xff46 and its transfer starting. A transfer that was
+/// running goes on until then, see `IoRegisters::tick_dma_start`.
+const DMA_START_DELAY: u8 = 1;
+
+/// The OAM DMA transfer copying, which a write to 0xff46 only replaces after `DMA_START_DELAY`. It is saved in a
+/// section of its own, so that states from before it still load.
+#[derive(Default, Clone, Copy)]
+pub struct DmaTransfer {
+    /// The page copied from, which only becomes `dma` once a transfer written starts.
+    pub source: u8,
+    /// The steps left before the transfer written last starts, 0 once it has.
+    pub start_delay: u8,
+}
+
 #[derive(Default)]
 pub struct IoRegisters {
     pub joyp_directions: u8,
     pub joyp_actions: u8,
+    /// Only the selection bits (4-5) as written, the rest of JOYP is composed on read, see `joyp_value`.
     pub joyp: u8,
     pub sb: u8,
     pub sc: u8,
+    /// Byte the game started sending over the serial port since it was last taken. Transfers only complete with a
+    /// link cable plugged in, see `SerialLink`.
+    pub serial_transfer: Option<u8>,
     pub div: u8,
+    /// The system counter DIV is the top of, which TIMA also counts from, see `tick_timers`.
     pub cpu_clock: u16,
     pub tima: u8,
-    pub clock_accumulator: usize,
     pub tma: u8,
     pub tac: u8,
+    /// Only the 5 interrupt bits are stored, the unused upper bits always read as set.
     pub interrupt_flag: InterruptFlags,
     pub lcdc: LCDControl,
     pub stat: u8,
@@ -48,6 +73,7 @@ pub struct IoRegisters {
     pub lyc: u8,
     pub dma: u8,
     pub dma_counter: u8,
+    pub dma_transfer: DmaTransfer,
     pub bgp: u8,
     pub obp0: u8,
     pub obp1: u8,
@@ -68,7 +94,11 @@ pub struct IoRegisters {
     pub ocpd: u8,
     pub opri: u8,
     pub svbk: u8,
+    /// Stored as written, since all 8 bits of IE read back, even though only the lower 5 enable interrupts.
     pub interrupt_enable: InterruptFlags,
+    /// Set by writes to the registers the PPU reads while drawing a line, see `Ppu::draw_line_ahead`.
+    pub drawing_registers_written: bool,
+    pub event_log: EventLog,
 }
 
 impl IoRegisters {
@@ -77,16 +107,16 @@ impl IoRegisters {
             // https://gbdev.io/pandocs/Power_Up_Sequence.html
             joyp_directions: 0x0f,
             joyp_actions: 0x0f,
-            joyp: 0xcf,
+            joyp: 0x00,
             sb: 0x00,
             sc: 0x7e,
+            serial_transfer: None,
             div: 0xab,
             cpu_clock: 0,
             tima: 0x00,
-            clock_accumulator: 0,
             tma: 0x00,
             tac: 0xf8,
-            interrupt_flag: InterruptFlags::from_bits_retain(0xe1),
+            interrupt_flag: InterruptFlags::VBLANK,
             lcdc: LCDControl::from_bits_retain(0x91),
             stat: 0x85,
             scy: 0x00,
@@ -95,6 +125,7 @@ impl IoRegisters {
             lyc: 0x00,
             dma: 0xff,
             dma_counter: 0,
+            dma_transfer: DmaTransfer { source: 0xff, start_delay: 0 },
             bgp: 0xfc,
             obp0: 0x00,
             obp1: 0x00,
@@ -116,6 +147,215 @@ impl IoRegisters {
             opri: 0xff, // Unknown value on power-up. Extrapolating.
             svbk: 0xff,
             interrupt_enable: InterruptFlags::from_bits_retain(0x00),
+            drawing_registers_written: false,
+            event_log: EventLog::default(),
+        }
+    }
+
+    /// Advances DIV and TIMA, requesting the timer interrupt when TIMA overflows.
+    ///
+    /// TIMA increments on the falling edges of a bit of the system counter, chosen by TAC, which happen every time the
+    /// counter passes a multiple of the period.
+    pub fn tick_timers(&mut self, t_cycles: usize) {
+        let start = self.cpu_clock as usize;
+
+        self.cpu_clock = self.cpu_clock.wrapping_add(t_cycles as u16);
+
+        self.div = (self.cpu_clock >> 8) as u8 % 64;
+
+        if self.timer_enabled() {
+            let timer_update_freq = self.timer_update_freq();
+
+            for _ in 0..(start + t_cycles) / timer_update_freq - start / timer_update_freq {
+                self.increment_tima();
+            }
+        }
+    }
+
+    fn timer_enabled(&self) -> bool {
+        self.tac & 0b0000_0100 != 0
+    }
+
+    /// T-cycles per TIMA increment.
+    fn timer_update_freq(&self) -> usize {
+        match self.tac & 0b0000_0011 {
+            0 => 1024, // CPU clock / 1024
+            1 => 16, // CPU clock / 16
+            2 => 64, // CPU clock / 64
+            3 => 256, // CPU clock / 256
+            _ => unreachable!()
+        }
+    }
+
+    fn increment_tima(&mut self) {
+        let (tima, reset) = self.tima.overflowing_add(1);
+
+        self.tima = tima;
+
+        if reset {
+            self.tima = self.tma;
+
+            self.log_event(Event::TimerOverflow);
+            self.request_interrupt(InterruptFlags::TIMER);
+        }
+    }
+
+    /// Writing DIV clears the whole counter that DIV is the top of, and TIMA counts its falling edges. So TIMA starts
+    /// counting again from the write, and the bit it watches falling to 0 increments it once more if it was set.
+    fn reset_div(&mut self) {
+        if self.timer_enabled() && self.cpu_clock as usize & (self.timer_update_freq() / 2) != 0 {
+            self.increment_tima();
+        }
+
+        self.div = 0;
+        self.cpu_clock = 0;
+    }
+
+    /// Ends a serial transfer, with the byte shifted in from the other side in SB.
+    pub fn complete_serial_transfer(&mut self, byte: u8) {
+        self.sb = byte;
+        self.sc &= !0b1000_0000;
+
+        self.request_interrupt(InterruptFlags::SERIAL);
+    }
+
+    pub fn request_interrupt(&mut self, flag: InterruptFlags) {
+        self.interrupt_flag.insert(flag);
+
+        self.log_event(Event::InterruptRequested(flag));
+    }
+
+    /// The DMG bug where writing STAT enables every STAT interrupt source for a cycle, before the written value takes
+    /// effect. The interrupt is requested on a rising edge of the STAT line, so it is only spurious if HBlank, VBlank
+    /// or the LYC coincidence holds while none of the enabled sources already held the line high.
+    pub fn stat_write_bug(&mut self) {
+        if !self.lcdc.contains(LCDControl::LCD_PPU_ENABLE) {
+            return;
+        }
+
+        let mode = self.stat & 0b0000_0011;
+        let coincidence = self.stat & (1 << 2) != 0;
+
+        if !self.stat_line_high() && (mode <= 1 || coincidence) {
+            self.request_interrupt(InterruptFlags::LCD_STAT);
+        }
+    }
+
+    /// Whether an enabled STAT interrupt source holds the STAT line high, so that another source becoming active
+    /// doesn't request the interrupt again.
+    pub fn stat_line_high(&self) -> bool {
+        let coincidence = self.stat & (1 << 2) != 0;
+
+        let mode_high = match self.stat & 0b0000_0011 {
+            0 => self.stat & (1 << 3) != 0,
+            1 => self.stat & (1 << 4) != 0,
+            2 => self.stat & (1 << 5) != 0,
+            // No source holds the line high while drawing.
+            _ => false,
+        };
+
+        mode_high || coincidence && self.stat & (1 << 6) != 0
+    }
+
+    /// Composes JOYP from the selection bits and the state of the selected button groups.
+    /// Values of JOYP are 0 for selected/pressed, so when both groups are selected, a button reads as pressed if it is
+    /// pressed in either.
+    pub fn joyp_value(&self) -> u8 {
+        let mut buttons = 0x0f;
+
+        if self.joyp & 0b0001_0000 == 0 {
+            buttons &= self.joyp_directions;
+        }
+
+        if self.joyp & 0b0010_0000 == 0 {
+            buttons &= self.joyp_actions;
+        }
+
+        0b1100_0000 | self.joyp | buttons
+    }
+
+    /// Whether a selected button is pressed, which pulls its JOYP line low.
+    pub fn joypad_line_low(&self) -> bool {
+        self.joyp_value() & 0b0000_1111 != 0b0000_1111
+    }
+
+    /// Counts down the setup delay of a transfer written to 0xff46, then starts it over whatever transfer was running,
+    /// from the first byte of OAM.
+    ///
+    /// https://gbdev.io/pandocs/OAM_DMA_Transfer.html
+    pub fn tick_dma_start(&mut self) {
+        let transfer = &mut self.dma_transfer;
+
+        if transfer.start_delay == 0 {
+            return;
+        }
+
+        transfer.start_delay -= 1;
+
+        if transfer.start_delay == 0 {
+            transfer.source = self.dma;
+            self.dma_counter = 160;
+        }
+    }
+
+    pub fn log_event(&mut self, event: Event) {
+        if self.event_log.enabled {
+            self.event_log.push(self.ly, event);
+        }
+    }
+
+    /// Reads a register like `mem_read`, but returns the stored value of registers the CPU can't read.
+    pub fn mem_peek(&self, addr: u16) -> u8 {
+        match addr {
+            0xff51 => self.hdma1,
+            0xff52 => self.hdma2,
+            0xff53 => self.hdma3,
+            0xff54 => self.hdma4,
+            0xff55 => self.hdma5,
+            0xff76 | 0xff77 => OpenBus::UNMAPPED,
+            _ => self.mem_read(addr),
+        }
+    }
+
+    /// Stores a value in a register without the side effects of a CPU write: no interrupts, DMA, timer or DIV resets
+    /// and no logged events. Read-only bits, like the PPU mode in STAT, are kept.
+    pub fn mem_poke(&mut self, addr: u16, value: u8) {
+        if is_drawing_register(addr) {
+            self.drawing_registers_written = true;
+        }
+
+        match addr {
+            0xff00 => self.joyp = value & 0b0011_0000,
+            0xff01 => self.sb = value,
+            0xff02 => self.sc = value,
+            // DIV is the top of the system counter, which keeps counting from there.
+            0xff04 => {
+                self.div = value;
+                self.cpu_clock = (value as u16) << 8 | self.cpu_clock & 0xff;
+            }
+            0xff05 => self.tima = value,
+            0xff06 => self.tma = value,
+            0xff07 => self.tac = 0xf8 | value,
+            0xff0f => self.interrupt_flag = InterruptFlags::from_bits_truncate(value),
+            0xff40 => self.lcdc = LCDControl::from_bits_retain(value),
+            0xff41 => self.stat = value & 0b0111_1000 | self.stat & 0b0000_0111 | 0b1000_0000,
+            0xff42 => self.scy = value,
+            0xff43 => self.scx = value,
+            0xff44 => self.ly = value,
+            0xff45 => self.lyc = value,
+            0xff46 => self.dma = value,
+            0xff47 => self.bgp = value,
+            0xff48 => self.obp0 = value,
+            0xff49 => self.obp1 = value,
+            0xff4a => self.wy = value,
+            0xff4b => self.wx = value,
+            0xff51 => self.hdma1 = value,
+            0xff52 => self.hdma2 = value,
+            0xff53 => self.hdma3 = value,
+            0xff54 => self.hdma4 = value,
+            0xff55 => self.hdma5 = value,
+            0xffff => self.interrupt_enable = InterruptFlags::from_bits_retain(value),
+            _ => {}
         }
     }
 }
@@ -123,14 +363,15 @@ impl IoRegisters {
 impl Mem for IoRegisters {
     fn mem_read(&self, addr: u16) -> u8 {
         return match addr {
-            0xff00 => self.joyp,
+            0xff00 => self.joyp_value(),
             0xff01 => self.sb,
-            0xff02 => self.sc,
+            // Only the transfer start and clock select bits are used on DMG.
+            0xff02 => self.sc | 0b0111_1110,
             0xff04 => self.div,
             0xff05 => self.tima,
             0xff06 => self.tma,
             0xff07 => self.tac,
-            0xff0f => self.interrupt_flag.bits(),
+            0xff0f => 0b1110_0000 | self.interrupt_flag.bits(),
             0xff40 => self.lcdc.bits(),
             0xff41 => self.stat,
             0xff42 => self.scy,
@@ -145,7 +386,9 @@ impl Mem for IoRegisters {
             0xff4b => self.wx,
             0xff4d => self.key1,
             0xff4f => self.vbk,
-            0xff51..=0xff55 => panic!("cannot read hdma registers"),
+            // CGB registers, which read as all ones on DMG. In CGB mode, HDMA1-HDMA4 stay write-only, and HDMA5 reads
+            // the remaining length and whether a transfer is active.
+            0xff51..=0xff55 => OpenBus::UNMAPPED,
             0xff56 => self.rp,
             0xff68 => self.bcps,
             0xff69 => self.bcpd,
@@ -153,51 +396,50 @@ impl Mem for IoRegisters {
             0xff6b => self.ocpd,
             0xff6c => self.opri,
             0xff70 => self.svbk,
-            0xff76 => panic!("cgb only"),
-            0xff77 => panic!("cgb only"),
+            // PCM12 and PCM34, which would read the current outputs of the audio channels in CGB mode.
+            0xff76 | 0xff77 => OpenBus::UNMAPPED,
             0xffff => self.interrupt_enable.bits(),
-            _ => 0xff, //panic!("invalid IO register address")
+            // Unused addresses: 0xff03, 0xff08-0xff0e, 0xff4c, 0xff4e, 0xff50 (there is no boot ROM to unmap),
+            // 0xff57-0xff67, 0xff6d-0xff6f, 0xff71-0xff75 and 0xff78-0xff7f.
+            _ => OpenBus::UNMAPPED,
         };
     }
 
     fn mem_write(&mut self, addr: u16, value: u8) {
+        if let 0xff40..=0xff43 | 0xff4a | 0xff4b = addr {
+            self.log_event(Event::RegisterWrite { addr, value });
+        }
+
+        if is_drawing_register(addr) {
+            self.drawing_registers_written = true;
+        }
+
         return match addr {
             0xff00 => {
-                // NOTE: Values of JOYP are 0 for selected/pressed, so everything is inversed.
-                let prev_joy = self.joyp;
-                
-                self.joyp = 0b1100_0000 | match value {
-                    0x20 => 0x20 | self.joyp_directions,
-                    0x10 => 0x10 | self.joyp_actions,
-                    _ => self.joyp,
-                };
-                
-                // When either action or direction bits are on, but not both.
-                if (self.joyp & 0b0001_0000) ^ (self.joyp & 0b0010_0000) != 0 {
-                    for bit in 0..=3 {
-                        let mask = 1 << bit;
-                        
-                        // Joypad interrupt is set whenever joypad bits 0-3 go from high to low, when one of the selection bits (4-5) are set.
-                        if (prev_joy & mask) != 0 && (self.joyp & mask) == 0 {
-                            self.interrupt_flag.insert(InterruptFlags::JOYPAD);
-                            
-                            break;
-                        }
-                    }
+                let prev_joy = self.joyp_value();
+
+                self.joyp = value & 0b0011_0000;
+
+                // Joypad interrupt is set whenever joypad bits 0-3 go from high to low.
+                if prev_joy & !self.joyp_value() & 0b0000_1111 != 0 {
+                    self.request_interrupt(InterruptFlags::JOYPAD);
                 }
             },
             0xff01 => self.sb = value,
-            0xff02 => self.sc = value,
-            0xff04 => {
-                self.div = 0;
-                self.cpu_clock = 0;
+            0xff02 => {
+                self.sc = value;
+
+                // A transfer starts when bit 7 is set, and with the internal clock (bit 0), doesn't wait for the other side.
+                if value & 0b1000_0001 == 0b1000_0001 {
+                    self.serial_transfer = Some(self.sb);
+                }
             }
+            0xff04 => self.reset_div(),
             0xff05 => self.tima = value,
             0xff06 => self.tma = value,
             0xff07 => {
                 if (self.tac & 0b0000_0011) != (value & 0b0000_0011) {
                     self.tima = self.tma;
-                    self.clock_accumulator = 0;
                 }
 
                 self.tac = 0xf8 | value;
@@ -214,7 +456,9 @@ impl Mem for IoRegisters {
             0xff45 => self.lyc = value,
             0xff46 => {
                 self.dma = value;
-                self.dma_counter = 160;
+                self.dma_transfer.start_delay = DMA_START_DELAY;
+
+                self.log_event(Event::DmaStarted { source: (value as u16) << 8 });
             }
             0xff47 => self.bgp = value,
             0xff48 => self.obp0 = value,
@@ -235,12 +479,232 @@ impl Mem for IoRegisters {
             0xff69 => {} // self.bcpd = value,
             0xff6a => {} // self.ocps = value,
             0xff6b => {} // self.ocpd = value,
-            0xff6c => {} // self.opri = value,
+            // Only bit 0 is used, see `ObjectPriority`.
+            0xff6c => self.opri = 0b1111_1110 | value,
             0xff70 => {} // self.svbk = value,
-            0xff76 => {} // panic!("cgb only"),
-            0xff77 => {} // panic!("cgb only"),
-            0xffff => self.interrupt_enable = InterruptFlags::from_bits_retain(0b1110_0000 | value),
-            _ => {} // panic!("invalid IO register address")
+            // PCM12 and PCM34 are read-only.
+            0xff76 | 0xff77 => {}
+            0xffff => self.interrupt_enable = InterruptFlags::from_bits_retain(value),
+            // Unused addresses ignore writes, see `mem_read`.
+            _ => {}
         };
     }
 }
+
+/// LCDC, the scroll and window positions, and the palettes, which the PPU reads while drawing a line.
+fn is_drawing_register(addr: u16) -> bool {
+    matches!(addr, 0xff40 | 0xff42 | 0xff43 | 0xff47..=0xff4b)
+}
+
+state_fields!(DmaTransfer { source, start_delay });
+
+// The buttons held are input rather than state, and serial transfers and the event log are taken by the frontend.
+state_fields!(IoRegisters {
+    joyp, sb, sc, div, cpu_clock, tima, tma, tac, interrupt_flag, lcdc, stat, scy, scx, ly, lyc,
+    dma, dma_counter, bgp, obp0, obp1, wy, window_ly, wx, key1, vbk, hdma1, hdma2, hdma3, hdma4, hdma5, rp, bcps,
+    bcpd, ocps, ocpd, opri, svbk, interrupt_enable, drawing_registers_written,
+});
+
+impl IoRegisters {
+    /// Restores the layout of states before version 4, where TIMA counted from an accumulator of its own, saved after
+    /// it. TIMA now counts from `cpu_clock`, so the accumulator is skipped.
+    pub fn load_with_accumulator(&mut self, input: &mut StateReader) -> Result<(), CoreError> {
+        // JOYP, SB, SC, DIV, `cpu_clock` and TIMA.
+        const BEFORE_ACCUMULATOR: usize = 7;
+
+        let mut layout = Vec::new();
+        self.save(&mut layout);
+
+        let mut fields = input.bytes(BEFORE_ACCUMULATOR)?.to_vec();
+        input.bytes(8)?;
+        fields.extend_from_slice(input.bytes(layout.len() - BEFORE_ACCUMULATOR)?);
+
+        self.load(&mut StateReader::new(&fields))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// TAC values enabling the timer, with the T-cycles per TIMA increment of each.
+    const TIMER_FREQUENCIES: [(u8, usize); 4] = [(0b100, 1024), (0b101, 16), (0b110, 64), (0b111, 256)];
+
+    fn timer(tac: u8) -> IoRegisters {
+        let mut registers = IoRegisters::new();
+        registers.mem_write(0xff07, tac);
+        registers.mem_write(0xff04, 0);
+        registers.tima = 0;
+
+        registers
+    }
+
+    #[test]
+    fn tima_counts_the_same_in_any_steps() {
+        for (tac, freq) in TIMER_FREQUENCIES {
+            let mut stepped = timer(tac);
+            let mut batched = timer(tac);
+
+            for _ in 0..freq * 5 / 4 {
+                stepped.tick_timers(4);
+            }
+
+            batched.tick_timers(freq * 5);
+
+            assert_eq!(stepped.tima, 5, "TAC {tac:03b}");
+            assert_eq!(batched.tima, 5, "TAC {tac:03b}");
+        }
+    }
+
+    #[test]
+    fn div_writes_restart_tima_on_the_counter() {
+        for (tac, freq) in TIMER_FREQUENCIES {
+            let mut registers = timer(tac);
+
+            // Half way through a period, the bit TIMA watches is set, and falls with the write.
+            registers.tick_timers(freq * 3 + freq / 2);
+            assert_eq!(registers.tima, 3, "TAC {tac:03b}");
+
+            registers.mem_write(0xff04, 0);
+            assert_eq!(registers.tima, 4, "TAC {tac:03b}");
+
+            registers.tick_timers(freq - 4);
+            assert_eq!(registers.tima, 4, "TAC {tac:03b}");
+
+            registers.tick_timers(4);
+            assert_eq!(registers.tima, 5, "TAC {tac:03b}");
+
+            // Early in a period, the bit is clear, and the write only restarts the period.
+            registers.tick_timers(freq / 4);
+            registers.mem_write(0xff04, 0);
+            registers.tick_timers(freq - 4);
+            assert_eq!(registers.tima, 5, "TAC {tac:03b}");
+
+            registers.tick_timers(4);
+            assert_eq!(registers.tima, 6, "TAC {tac:03b}");
+        }
+    }
+
+    /// Whether writing STAT requests an interrupt, in PPU `mode` with `enabled` the sources enabled before the write.
+    fn stat_write_interrupts(mode: u8, coincidence: bool, enabled: u8) -> bool {
+        let mut registers = IoRegisters::new();
+        registers.stat = 0b1000_0000 | enabled | (coincidence as u8) << 2 | mode;
+        registers.interrupt_flag = InterruptFlags::empty();
+
+        registers.stat_write_bug();
+
+        registers.interrupt_flag.contains(InterruptFlags::LCD_STAT)
+    }
+
+    #[test]
+    fn stat_writes_interrupt_unless_a_source_already_held_the_line() {
+        assert!(stat_write_interrupts(0, false, 0));
+        assert!(!stat_write_interrupts(0, false, 1 << 3));
+        assert!(stat_write_interrupts(1, false, 1 << 3));
+        assert!(!stat_write_interrupts(1, false, 1 << 4));
+        assert!(!stat_write_interrupts(2, false, 0));
+        assert!(stat_write_interrupts(2, true, 0));
+        assert!(!stat_write_interrupts(2, true, 1 << 5));
+        assert!(!stat_write_interrupts(2, true, 1 << 6));
+    }
+
+    #[test]
+    fn stat_writes_while_drawing_dont_count_the_oam_source() {
+        assert!(!stat_write_interrupts(3, false, 1 << 5));
+        // The OAM scan source enabled doesn't hold the line high in mode 3, so the coincidence raises it.
+        assert!(stat_write_interrupts(3, true, 1 << 5));
+        assert!(!stat_write_interrupts(3, true, 1 << 6));
+    }
+
+    /// JOYP read after writing `select`, with Right and Up held, and A and Start.
+    fn joyp_with_buttons_held(select: u8) -> u8 {
+        let mut registers = IoRegisters::new();
+        registers.joyp_directions = 0b1010;
+        registers.joyp_actions = 0b0110;
+
+        registers.mem_write(0xff00, select);
+
+        registers.mem_read(0xff00)
+    }
+
+    #[test]
+    fn joyp_reads_the_selected_button_groups() {
+        assert_eq!(joyp_with_buttons_held(0x30), 0b1111_1111);
+        assert_eq!(joyp_with_buttons_held(0x20), 0b1110_1010);
+        assert_eq!(joyp_with_buttons_held(0x10), 0b1101_0110);
+        assert_eq!(joyp_with_buttons_held(0x00), 0b1100_0010);
+        // Only the selection bits are written.
+        assert_eq!(joyp_with_buttons_held(0xef), 0b1110_1010);
+    }
+
+    #[test]
+    fn joyp_reads_buttons_pressed_after_the_selection() {
+        let mut registers = IoRegisters::new();
+        registers.mem_write(0xff00, 0x20);
+        registers.interrupt_flag = InterruptFlags::empty();
+
+        registers.joyp_directions = 0b1110;
+
+        assert_eq!(registers.mem_read(0xff00), 0b1110_1110);
+        assert!(registers.joypad_line_low());
+    }
+
+    #[test]
+    fn selecting_a_held_button_requests_the_joypad_interrupt() {
+        let mut registers = IoRegisters::new();
+        registers.joyp_actions = 0b0111;
+        registers.mem_write(0xff00, 0x20);
+        registers.interrupt_flag = InterruptFlags::empty();
+
+        registers.mem_write(0xff00, 0x10);
+        assert_eq!(registers.interrupt_flag, InterruptFlags::JOYPAD);
+
+        registers.interrupt_flag = InterruptFlags::empty();
+        registers.mem_write(0xff00, 0x00);
+        assert_eq!(registers.interrupt_flag, InterruptFlags::empty());
+    }
+
+    #[test]
+    fn if_reads_its_unused_bits_as_set_and_ie_as_written() {
+        let mut registers = IoRegisters::new();
+
+        for value in 0..=0xff {
+            registers.mem_write(0xff0f, value);
+            registers.mem_write(0xffff, value);
+
+            assert_eq!(registers.mem_read(0xff0f), 0b1110_0000 | value, "IF {value:02x}");
+            assert_eq!(registers.mem_read(0xffff), value, "IE {value:02x}");
+        }
+    }
+
+    #[test]
+    fn poked_div_keeps_counting() {
+        let mut registers = IoRegisters::new();
+        registers.tick_timers(0x1234);
+
+        registers.mem_poke(0xff04, 0x20);
+        assert_eq!(registers.mem_read(0xff04), 0x20);
+
+        registers.tick_timers(0xcc);
+        assert_eq!(registers.mem_read(0xff04), 0x21);
+    }
+
+    #[test]
+    fn loads_states_with_the_accumulator() {
+        let mut registers = IoRegisters::new();
+        registers.tick_timers(1234);
+        registers.tima = 0x42;
+        registers.interrupt_enable = InterruptFlags::TIMER;
+
+        let mut state = Vec::new();
+        registers.save(&mut state);
+        state.splice(7..7, 321u64.to_le_bytes());
+
+        let mut loaded = IoRegisters::new();
+        let mut input = StateReader::new(&state);
+        loaded.load_with_accumulator(&mut input).unwrap();
+
+        assert!(input.is_empty());
+        assert_eq!((loaded.cpu_clock, loaded.tima, loaded.interrupt_enable), (1234, 0x42, InterruptFlags::TIMER));
+    }
+}
@@ -1,20 +1,64 @@
-﻿use std::time::Duration;
+﻿use std::{
+    collections::{vec_deque::Drain, VecDeque},
+    io::Write,
+    ops::RangeInclusive,
+    time::Duration,
+};
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::{SystemTime, UNIX_EPOCH};
 use self::{
     cpu::Cpu,
-    bus::Bus
+    bus::Bus,
+    debug::Watchpoint,
+    io_registers::{InterruptFlags, LCDControl},
+    ppu::PpuEvent,
+    serial::SerialLink,
+    state::{Sections, State, StateReader},
+};
+
+pub use self::{
+    apu::{ChannelShape, ChannelStatus, AUDIO_SAMPLE_RATE, CHANNEL_TAP_LEN},
+    debug::DebugEvent,
+    diagnostic::DiagnosticReport,
+    error::CoreError,
+    event_log::LoggedEvent,
+    header::{CartridgeHeader, CgbSupport, LicenseeCode},
+    open_bus::OpenBus,
+    ppu::{LineStats, OamAttributes, OamEntry, Tile, OAM_ENTRY_COUNT},
+    serial::SerialEndpoint,
+    state::{Rewind, StatePreview},
 };
 
-mod cpu;
-mod bus;
-mod ppu;
-mod io_registers;
+pub(crate) mod cpu;
+pub(crate) mod bus;
+pub(crate) mod ppu;
+pub(crate) mod io_registers;
 mod cpu_registers;
 mod cartridge;
+mod header;
+mod rtc;
 pub(crate) mod apu;
 mod pixel_fetcher;
+mod disassembler;
+pub(crate) mod debug;
+mod diagnostic;
+mod open_bus;
+pub(crate) mod event_log;
+mod error;
+mod serial;
+mod state;
+
+pub const SCREEN_WIDTH: usize = 160;
+pub const SCREEN_HEIGHT: usize = 144;
 
-pub(crate) const SCREEN_WIDTH: usize = 160;
-pub(crate) const SCREEN_HEIGHT: usize = 144;
+/// T-cycles per second of real hardware, 4 194 304 Hz, for converting between wall time and `cycles_elapsed`.
+pub const CLOCK_SPEED: u64 = 4_194_304;
+
+/// T-cycles, or dots, per frame.
+pub(crate) const DOTS_PER_FRAME: usize = 70_224;
+
+/// Frames per second of real hardware: the 4.194304 MHz clock divided by 70224 dots per frame.
+pub const FRAMES_PER_SECOND: f64 = CLOCK_SPEED as f64 / DOTS_PER_FRAME as f64;
 
 // pub(crate) const FRAME_DURATION: Duration = Duration::from_micros(16_742);
 // const MCYCLE_DURATION: Duration = Duration::from_nanos((1e9 / 1.048576e6) as u64);
@@ -24,100 +68,1639 @@ pub(crate) trait Mem {
     fn mem_write(&mut self, addr: u16, value: u8);
 }
 
-pub enum Buttons {
-    Right,
-    Left,
-    Up,
-    Down,
+/// Optional hardware behaviors that are off by default, either for speed or because few games depend on them, and a
+/// compatibility flag that restores older, less accurate behavior.
+#[derive(Clone, Copy, Default)]
+pub struct Accuracy {
+    /// Emulate the DMG bug corrupting OAM when the CPU puts an OAM address on the bus during OAM scan.
+    pub oam_bug: bool,
+    /// Emulate the DMG bug where writing STAT can request a STAT interrupt during HBlank, VBlank or while LY matches
+    /// LYC, whatever the interrupt sources written. Some games, like Road Rash, depend on it.
+    pub stat_write_bug: bool,
+    /// Behavior of reads from the prohibited area at 0xfea0-0xfeff, see `OpenBus`. With `AccuracyMode::Dmg`, a game
+    /// loaded on `Model::Cgb` reads it as on a CGB instead.
+    pub prohibited_area: AccuracyMode,
+    /// Behavior of accesses to wave RAM while channel 3 plays. By default, they go to the addressed byte as if the
+    /// channel was off.
+    pub wave_ram: AccuracyMode,
+    /// Draw every line dot by dot. Otherwise, lines are drawn in one pass, and only lines where LCDC, the scroll or
+    /// window positions or the palettes are written while drawing fall back to dots.
+    pub dot_accurate_ppu: bool,
+    /// Compatibility flag for the timing of versions before interleaving: run the PPU, APU and timers for a whole
+    /// instruction after it executed, instead of for every M-cycle along with its memory accesses. Slightly faster,
+    /// but the PPU sees writes late, which breaks mid-line effects and timing tests.
+    pub batched_timing: bool,
+}
+
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub enum AccuracyMode {
+    /// A fixed value, regardless of the state of the hardware.
+    #[default]
+    Fast,
+    /// As observed on a DMG.
+    Dmg,
+}
+
+/// The hardware model a game starts on, see `GameBoy::set_model`.
+///
+/// Only DMG hardware is emulated. For now, a CGB only differs in the registers the boot ROM leaves behind, which some
+/// games that run on both check to pick their colors and assets.
+// TODO: VRAM and WRAM banking, palette RAM and the CGB defaults of the IO registers.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub enum Model {
+    #[default]
+    Dmg,
+    Cgb,
+}
+
+impl Model {
+    /// The model a cartridge is meant for: a CGB for a game with CGB features, whether it also runs on a DMG or not.
+    pub fn for_cartridge(cgb: CgbSupport) -> Self {
+        match cgb {
+            CgbSupport::None => Self::Dmg,
+            CgbSupport::Enhanced | CgbSupport::Only => Self::Cgb,
+        }
+    }
+}
+
+/// Called with the emulator when the screen is complete, at the start of VBlank.
+pub type VBlankCallback = Box<dyn FnMut(&GameBoy) + Send>;
+/// Called with the emulator and the line the PPU finished drawing, at the start of HBlank.
+pub type HBlankCallback = Box<dyn FnMut(&GameBoy, u8) + Send>;
+/// Called with the line the PPU finished drawing and its shades, as in `GameBoy::screen`, at the start of HBlank.
+pub type ScanlineCallback = Box<dyn FnMut(u8, &[u8; SCREEN_WIDTH]) + Send>;
+/// Called with the emulator and the byte the game started sending over the serial port.
+pub type SerialCallback = Box<dyn FnMut(&GameBoy, u8) + Send>;
+
+#[derive(Default)]
+struct Callbacks {
+    vblank: Option<VBlankCallback>,
+    hblank: Option<HBlankCallback>,
+    scanline: Option<ScanlineCallback>,
+    serial: Option<SerialCallback>,
+}
+
+impl Callbacks {
+    fn is_empty(&self) -> bool {
+        self.vblank.is_none() && self.hblank.is_none() && self.scanline.is_none() && self.serial.is_none()
+    }
+}
+
+bitflags! {
+    /// A set of buttons, laid out like the two halves of JOYP with pressed buttons set, so the state of every button
+    /// fits in a byte:
+    ///
+    /// | Bit | 7     | 6      | 5 | 4 | 3    | 2  | 1    | 0     |
+    /// |-----|-------|--------|---|---|------|----|------|-------|
+    /// |     | Start | Select | B | A | Down | Up | Left | Right |
+    #[derive(Default, Copy, Clone, PartialEq, Eq, Debug)]
+    pub struct Buttons : u8 {
+        #[allow(non_upper_case_globals)]
+        const Right = 1 << 0;
+        #[allow(non_upper_case_globals)]
+        const Left = 1 << 1;
+        #[allow(non_upper_case_globals)]
+        const Up = 1 << 2;
+        #[allow(non_upper_case_globals)]
+        const Down = 1 << 3;
+
+        const A = 1 << 4;
+        const B = 1 << 5;
+        #[allow(non_upper_case_globals)]
+        const Select = 1 << 6;
+        #[allow(non_upper_case_globals)]
+        const Start = 1 << 7;
+    }
+}
 
-    B,
-    A,
-    Select,
-    Start,
+/// Why `run_frame` returned.
+#[derive(Clone, Copy, Debug)]
+pub enum FrameEnd {
+    /// A frame completed.
+    Completed,
+    /// The LCD was off for as long as a frame takes. Frames don't complete while it is off.
+    LcdOff,
+    /// A watchpoint was hit first, with its event.
+    Watchpoint(DebugEvent),
+    /// The CPU locked up on an invalid opcode, with the `DebugEvent::Locked` saying where. The rest of the system keeps
+    /// running, so later frames complete, showing whatever the game left on screen.
+    Locked(DebugEvent),
 }
 
+/// When `run_for` and `run_cycles` return early.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum RunUntil {
+    /// Only once the budget is exhausted.
+    BudgetExhausted,
+    /// As soon as a frame completes, if that happens before the budget is exhausted.
+    FrameCompleted,
+}
+
+/// What a call to `run_for` or `run_cycles` did.
+#[derive(Clone, Copy, Debug)]
+pub struct RunResult {
+    /// T-cycles run by this call, exactly. Instructions aren't split, so this can be a few more than asked for.
+    pub t_cycles: u64,
+    /// Whether a frame completed during this call, so `screen` holds a new frame.
+    pub frame_ready: bool,
+}
+
+/// The emulated machine. The examples of its methods run a ROM of only zeroes, which the CPU runs as NOPs with the LCD
+/// on, so frames complete.
+///
+/// # Examples
+///
+/// ```
+/// use yagbe::gameboy::{FrameEnd, GameBoy};
+///
+/// let mut gameboy = GameBoy::new();
+/// gameboy.load(vec![0; 0x8000])?;
+///
+/// assert!(matches!(gameboy.run_frame(), Some(FrameEnd::Completed)));
+/// # Ok::<(), yagbe::gameboy::CoreError>(())
+/// ```
 pub struct GameBoy {
     bus: Bus,
     cpu: Cpu,
+    model: Model,
     loaded: bool,
-    accumulator: Duration,
+    /// Time given to `run_for` since the ROM was loaded, and the t-cycles it ran, kept as totals so that converting
+    /// budgets to cycles never accumulates rounding errors.
+    budget: Duration,
+    budget_t_cycles: u64,
+    /// T-cycles run since the ROM was loaded, see `cycles_elapsed`.
+    t_cycles: u64,
+    debug_event: Option<DebugEvent>,
+    trace: Option<Box<dyn Write + Send>>,
+    callbacks: Callbacks,
+    /// Whether any callback is set, so `tick` only checks for events when they would be reported.
+    has_callbacks: bool,
 }
 
 impl GameBoy {
     pub fn new() -> Self {
+        Self::with_model(Model::Dmg)
+    }
+
+    /// An emulator that starts games on `model`.
+    pub fn with_model(model: Model) -> Self {
         Self {
             bus: Bus::new(),
-            cpu: Cpu::new(),
+            cpu: Cpu::new(model),
+            model,
             loaded: false,
-            accumulator: Duration::ZERO,
+            budget: Duration::ZERO,
+            budget_t_cycles: 0,
+            t_cycles: 0,
+            debug_event: None,
+            trace: None,
+            callbacks: Callbacks::default(),
+            has_callbacks: false,
         }
     }
 
-    pub fn load(&mut self, program: Vec<u8>) {
-        self.accumulator = Duration::ZERO;
-        self.cpu.reset();
-        self.bus.load(program);
+    /// Loads a ROM and starts it from the beginning. If the ROM can't be loaded, the running game is left as it is.
+    ///
+    /// The whole machine is reset, so the screen is blank and no audio of the previous game stays buffered. Only the
+    /// model, accuracy, watchpoints, event logging and whether audio is enabled are kept; the audio channels stop
+    /// being recorded.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use yagbe::gameboy::{CoreError, GameBoy};
+    ///
+    /// let mut gameboy = GameBoy::new();
+    ///
+    /// assert!(matches!(gameboy.load(vec![0; 0x100]), Err(CoreError::RomTooSmall { .. })));
+    /// assert!(gameboy.load(vec![0; 0x8000]).is_ok());
+    /// ```
+    pub fn load(&mut self, program: Vec<u8>) -> Result<(), CoreError> {
+        self.bus.load(program, self.model)?;
+
+        self.start();
+
+        Ok(())
+    }
+
+    /// Loads `program` flat at 0x0000 and starts it, like `load` but without a header or mapper, for running small
+    /// hand-assembled programs. The ROM is padded with zeroes to 32 KiB, and 8 KiB of cartridge RAM at 0xa000-0xbfff
+    /// is always writable. The CPU starts at 0x0100 as it does after the boot ROM.
+    ///
+    /// Panics if `program` is larger than 32 KiB.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use yagbe::gameboy::GameBoy;
+    ///
+    /// let mut program = vec![0; 0x100];
+    /// // LD A, $42; LD ($C000), A; LD ($A000), A; JR -2
+    /// program.extend([0x3e, 0x42, 0xea, 0x00, 0xc0, 0xea, 0x00, 0xa0, 0x18, 0xfe]);
+    ///
+    /// let mut gameboy = GameBoy::new();
+    /// gameboy.load_flat(program);
+    ///
+    /// for _ in 0..3 {
+    ///     gameboy.tick();
+    /// }
+    ///
+    /// assert_eq!(gameboy.peek(0xc000), 0x42);
+    /// assert_eq!(gameboy.peek(0xa000), 0x42);
+    /// assert_eq!(gameboy.peek(0x0108), 0x18);
+    /// assert_eq!(gameboy.peek(0x7fff), 0x00);
+    /// ```
+    pub fn load_flat(&mut self, program: Vec<u8>) {
+        self.bus.load_flat(program, self.model);
+
+        self.start();
+    }
+
+    /// Starts the cartridge that was just inserted from the beginning.
+    fn start(&mut self) {
+        self.budget = Duration::ZERO;
+        self.budget_t_cycles = 0;
+        self.t_cycles = 0;
+        self.cpu.reset(self.model);
 
         self.loaded = true;
     }
 
+    /// The model games start on.
+    pub fn model(&self) -> Model {
+        self.model
+    }
+
+    /// Sets the model the next game starts on, from the next `load`. The running game carries on as it is.
+    pub fn set_model(&mut self, model: Model) {
+        self.model = model;
+    }
+
+    /// Whether a ROM was loaded, so that there is a game to run.
+    pub fn is_loaded(&self) -> bool {
+        self.loaded
+    }
+
+    /// Takes the battery-backed RAM of the cartridge for saving, with the `title_hash` of the game it belongs to.
+    /// Returns `None` if there is nothing to save, because the cartridge has no battery or RAM didn't change since
+    /// it was last taken.
+    pub fn take_sram(&mut self) -> Option<(u64, Vec<u8>)> {
+        let cartridge = self.bus.cartridge.as_mut()?;
+
+        cartridge.take_ram(unix_time()).map(|ram| (cartridge.title_hash(), ram))
+    }
+
+    /// Restores cartridge RAM from a save made with `take_sram`. A clock in the save is advanced by the time that
+    /// passed since. A clock that can't be read is ignored, with `CoreError::BadSaveClock`, but RAM is restored anyway.
+    pub fn load_sram(&mut self, data: &[u8]) -> Result<(), CoreError> {
+        match &mut self.bus.cartridge {
+            Some(cartridge) => cartridge.load_ram(data, unix_time()),
+            None => Ok(()),
+        }
+    }
+
+    /// Identifies the loaded game, see `take_sram`.
+    pub fn title_hash(&self) -> Option<u64> {
+        self.bus.cartridge.as_ref().map(|cartridge| cartridge.title_hash())
+    }
+
+    /// Saves the whole machine into a compressed state file that `load_state` restores, or returns `None` before a
+    /// ROM is loaded. The file starts with the current screen and time, which `StatePreview::read` reads back.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use yagbe::gameboy::GameBoy;
+    ///
+    /// let mut gameboy = GameBoy::new();
+    /// gameboy.load(vec![0; 0x8000])?;
+    /// gameboy.run_frame();
+    ///
+    /// let state = gameboy.save_state().unwrap();
+    /// let screen = *gameboy.screen();
+    ///
+    /// gameboy.run_frame();
+    /// gameboy.load_state(&state)?;
+    ///
+    /// assert_eq!(gameboy.screen(), &screen);
+    /// # Ok::<(), yagbe::gameboy::CoreError>(())
+    /// ```
+    pub fn save_state(&self) -> Option<Vec<u8>> {
+        let title_hash = self.title_hash()?;
+
+        let mut state = Vec::new();
+        self.save_raw(&mut state);
+
+        let preview = StatePreview {
+            saved_at: Some(unix_time()),
+            screen: Some(Box::new(*self.screen())),
+        };
+
+        Some(state::write_file(title_hash, &preview, &state))
+    }
+
+    /// Restores a state file made by `save_state` with the loaded game. If it can't be restored, the emulator is left
+    /// as it was.
+    pub fn load_state(&mut self, file: &[u8]) -> Result<(), CoreError> {
+        let title_hash = self.title_hash().ok_or(CoreError::InvalidState("no game is loaded"))?;
+
+        let mut state = Vec::new();
+        let version = state::read_file(file, title_hash, &mut state)?;
+
+        self.load_raw_version(&state, version)
+    }
+
+    /// Upper bound of the size of a state file of the loaded game, for budgeting how many states to keep.
+    pub fn state_size_hint(&self) -> usize {
+        let mut state = Vec::new();
+        self.save_raw(&mut state);
+
+        state::file_size_bound(state.len())
+    }
+
+    /// Serializes the machine, starting with the title hash of the game so that a state of another game is refused,
+    /// followed by a section for every subsystem.
+    pub(crate) fn save_raw(&self, out: &mut Vec<u8>) {
+        self.title_hash().unwrap_or(0).save(out);
+        state::save_section(b"CPU ", &self.cpu, out);
+        state::save_section(b"CLK ", &self.t_cycles, out);
+        self.bus.save_sections(out);
+    }
+
+    /// Restores a state serialized by `save_raw`.
+    pub(crate) fn load_raw(&mut self, state: &[u8]) -> Result<(), CoreError> {
+        self.load_raw_version(state, state::VERSION)
+    }
+
+    /// Restores a state serialized by `save_raw` of the given format version. Fields are restored in order, so on
+    /// failure the machine is put back as it was rather than left half-loaded.
+    fn load_raw_version(&mut self, state: &[u8], version: u32) -> Result<(), CoreError> {
+        let mut backup = Vec::new();
+        self.save_raw(&mut backup);
+
+        let result = self.load_fields(state, version);
+
+        if result.is_err() {
+            self.load_fields(&backup, state::VERSION).expect("a state that was just saved loads");
+        } else {
+            self.cpu.unlock();
+        }
+
+        result
+    }
+
+    fn load_fields(&mut self, state: &[u8], version: u32) -> Result<(), CoreError> {
+        let mut input = StateReader::new(state);
+
+        let title_hash: u64 = state::read(&mut input)?;
+
+        if !self.loaded || title_hash != self.title_hash().unwrap_or(0) {
+            return Err(CoreError::InvalidState("it was made with a different game"));
+        }
+
+        // States from before the counter was saved restart it.
+        self.t_cycles = 0;
+
+        if version == 1 {
+            // The bus has a `load` of its own for ROMs.
+            self.cpu.load(&mut input)?;
+            State::load(&mut self.bus, &mut input)?;
+
+            if !input.is_empty() {
+                return Err(CoreError::InvalidState("it is corrupt"));
+            }
+        } else {
+            let sections = Sections::read(&mut input)?;
+
+            if !sections.load(b"CPU ", &mut self.cpu)? {
+                self.cpu.reset(self.model);
+            }
+
+            sections.load(b"CLK ", &mut self.t_cycles)?;
+
+            self.bus.load_sections(&sections, version)?;
+        }
+
+        self.budget = Duration::ZERO;
+        self.budget_t_cycles = 0;
+
+        Ok(())
+    }
+
     pub fn tick(&mut self) -> bool {
         if !self.loaded {
             return false;
         }
 
-        let mut result = false;
+        self.step().0
+    }
+
+    /// Runs an instruction, returning whether a frame completed and the t-cycles it took.
+    fn step(&mut self) -> (bool, usize) {
+
+        let pc = self.cpu.pc();
+
+        if let Some(trace) = &mut self.trace {
+            if !self.cpu.halted() && !self.cpu.stopped() && !self.cpu.locked() {
+                let (instruction, _) = disassembler::disassemble(&self.bus, pc);
+
+                // Tracing is best effort, a failing writer shouldn't stop emulation.
+                let _ = writeln!(trace, "{} {instruction}", self.cpu.registers());
+            }
+        }
+
+        let was_locked = self.cpu.locked();
 
         let m_cycles = self.cpu.tick(&mut self.bus);
         let t_cycles = m_cycles.t_cycles();
 
-        for _ in 0..t_cycles {
-            if self.bus.ppu.tick(&mut self.bus.io_registers) {
-                result = true;
+        self.t_cycles += t_cycles as u64;
+
+        if self.cpu.locked() && !was_locked {
+            self.debug_event.get_or_insert(DebugEvent::Locked { pc, opcode: self.bus.mem_peek(pc) });
+        }
+
+        self.bus.apply_oam_read_corruption();
+
+        if let Some(hit) = self.bus.take_watchpoint_hit() {
+            self.debug_event.get_or_insert(DebugEvent::Watchpoint {
+                pc,
+                addr: hit.addr,
+                old_value: hit.old_value,
+                new_value: hit.new_value,
+                access: hit.access,
+            });
+        }
+
+        // With batched timing, nothing else ran during the instruction, and the CPU ran the timers itself.
+        let batched_timing = self.bus.accuracy().batched_timing;
+        let m_cycles: u32 = m_cycles.into();
+        let remaining = (m_cycles as usize).saturating_sub(self.bus.take_elapsed_m_cycles());
+
+        self.bus.run_m_cycles(remaining, !batched_timing && !self.cpu.stopped());
+
+        let result = self.bus.take_frame_completed();
+
+        // An instruction is much shorter than a line, so it sees at most one HBlank or VBlank.
+        let ppu_event = self.bus.take_ppu_event();
+
+        if self.has_callbacks {
+            self.run_callbacks(ppu_event);
+        }
+
+        (result, t_cycles)
+    }
+
+    fn run_callbacks(&mut self, ppu_event: Option<PpuEvent>) {
+        // Taken out while running, since callbacks borrow the whole emulator.
+        let mut callbacks = std::mem::take(&mut self.callbacks);
+
+        match (ppu_event, &mut callbacks) {
+            (Some(PpuEvent::VBlank), Callbacks { vblank: Some(callback), .. }) => callback(self),
+            (Some(PpuEvent::HBlank(line)), Callbacks { hblank: Some(callback), .. }) => callback(self, line),
+            _ => {}
+        }
+
+        if let (Some(PpuEvent::HBlank(line)), Some(callback)) = (ppu_event, &mut callbacks.scanline) {
+            let start = line as usize * SCREEN_WIDTH;
+
+            callback(line, self.bus.ppu.screen[start..start + SCREEN_WIDTH].try_into().unwrap());
+        }
+
+        if let Some(byte) = self.bus.io_registers.serial_transfer.take() {
+            if let Some(callback) = &mut callbacks.serial {
+                callback(self, byte);
             }
         }
 
-        for _ in 0..m_cycles.into() {
-            self.bus.apu.tick(&self.bus.io_registers);
+        self.callbacks = callbacks;
+    }
+
+    /// Calls `callback` whenever a frame is complete, or stops calling it if `None`. Replaces any previous callback.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::sync::{atomic::{AtomicU64, Ordering}, Arc};
+    /// use yagbe::gameboy::GameBoy;
+    ///
+    /// let mut gameboy = GameBoy::new();
+    /// gameboy.load(vec![0; 0x8000])?;
+    ///
+    /// let frames = Arc::new(AtomicU64::new(0));
+    /// let counter = frames.clone();
+    ///
+    /// gameboy.set_vblank_callback(Some(Box::new(move |_| {
+    ///     counter.fetch_add(1, Ordering::Relaxed);
+    /// })));
+    ///
+    /// for _ in 0..3 {
+    ///     gameboy.run_frame();
+    /// }
+    ///
+    /// assert_eq!(frames.load(Ordering::Relaxed), 3);
+    /// # Ok::<(), yagbe::gameboy::CoreError>(())
+    /// ```
+    pub fn set_vblank_callback(&mut self, callback: Option<VBlankCallback>) {
+        self.callbacks.vblank = callback;
+        self.has_callbacks = !self.callbacks.is_empty();
+    }
+
+    /// Calls `callback` after every drawn line, or stops calling it if `None`. Replaces any previous callback.
+    pub fn set_hblank_callback(&mut self, callback: Option<HBlankCallback>) {
+        self.callbacks.hblank = callback;
+        self.has_callbacks = !self.callbacks.is_empty();
+    }
+
+    /// Calls `callback` with the pixels of every drawn line as soon as it is done, or stops calling it if `None`, for
+    /// frontends that stream lines rather than wait for whole frames. `screen` is still drawn. Replaces any previous
+    /// callback.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::sync::{Arc, Mutex};
+    /// use yagbe::gameboy::{GameBoy, SCREEN_HEIGHT, SCREEN_WIDTH};
+    ///
+    /// let mut gameboy = GameBoy::new();
+    /// gameboy.load(vec![0; 0x8000])?;
+    /// gameboy.run_frame();
+    ///
+    /// let lines = Arc::new(Mutex::new(Vec::new()));
+    /// let received = lines.clone();
+    ///
+    /// gameboy.set_scanline_callback(Some(Box::new(move |line, pixels| {
+    ///     received.lock().unwrap().push((line, *pixels));
+    /// })));
+    ///
+    /// gameboy.run_frame();
+    ///
+    /// let lines = lines.lock().unwrap();
+    /// assert_eq!(lines.len(), SCREEN_HEIGHT);
+    /// assert_eq!(lines[10].1[..], gameboy.screen()[10 * SCREEN_WIDTH..11 * SCREEN_WIDTH]);
+    /// # Ok::<(), yagbe::gameboy::CoreError>(())
+    /// ```
+    pub fn set_scanline_callback(&mut self, callback: Option<ScanlineCallback>) {
+        self.callbacks.scanline = callback;
+        self.has_callbacks = !self.callbacks.is_empty();
+    }
+
+    /// Calls `callback` with every byte sent over the serial port, or stops calling it if `None`. Replaces any previous
+    /// callback.
+    pub fn set_serial_callback(&mut self, callback: Option<SerialCallback>) {
+        // Bytes sent without a callback aren't reported late.
+        self.bus.io_registers.serial_transfer = None;
+
+        self.callbacks.serial = callback;
+        self.has_callbacks = !self.callbacks.is_empty();
+    }
+
+    /// Plugs a link cable into the serial port, connecting it to the emulator with the other end of `endpoint`, or
+    /// unplugs it if `None`. The cable stays plugged in when another ROM is loaded, and isn't part of save states.
+    pub fn connect_serial(&mut self, endpoint: Option<SerialEndpoint>) {
+        self.bus.serial_link = endpoint.map(SerialLink::new);
+    }
+
+    /// Runs until a frame is completed, or for as long as a frame takes while the LCD is off, so that callers waiting
+    /// for frames don't wait forever. Stops early when a watchpoint is hit. Returns `None` before a ROM is loaded.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use yagbe::gameboy::{FrameEnd, GameBoy};
+    ///
+    /// let mut gameboy = GameBoy::new();
+    /// assert!(gameboy.run_frame().is_none());
+    ///
+    /// // LD A,0x42; LD (0xc000),A; JR -2, at the entry point.
+    /// let mut rom = vec![0; 0x8000];
+    /// rom[0x100..0x107].copy_from_slice(&[0x3e, 0x42, 0xea, 0x00, 0xc0, 0x18, 0xfe]);
+    ///
+    /// gameboy.load(rom)?;
+    /// gameboy.add_watchpoint(0xc000..=0xc000, false, true);
+    ///
+    /// match gameboy.run_frame() {
+    ///     Some(FrameEnd::Watchpoint(event)) => println!("{event:?}"),
+    ///     end => panic!("expected a watchpoint, got {end:?}"),
+    /// }
+    ///
+    /// assert_eq!(gameboy.peek(0xc000), 0x42);
+    /// # Ok::<(), yagbe::gameboy::CoreError>(())
+    /// ```
+    pub fn run_frame(&mut self) -> Option<FrameEnd> {
+        if !self.loaded {
+            return None;
+        }
+
+        let mut t_cycles = 0;
+
+        loop {
+            let (frame_completed, cycles) = self.step();
+
+            t_cycles += cycles;
+
+            if let Some(event) = self.take_debug_event() {
+                return Some(match event {
+                    DebugEvent::Locked { .. } => FrameEnd::Locked(event),
+                    DebugEvent::Watchpoint { .. } => FrameEnd::Watchpoint(event),
+                });
+            }
+
+            if frame_completed {
+                return Some(FrameEnd::Completed);
+            }
+
+            if t_cycles >= DOTS_PER_FRAME && !self.bus.io_registers.lcdc.contains(LCDControl::LCD_PPU_ENABLE) {
+                return Some(FrameEnd::LcdOff);
+            }
+        }
+    }
+
+    /// Number of frames drawn since the ROM was loaded, counted when VBlank starts. Frontends can compare it to tell a
+    /// new frame is ready, without relying on `tick`. It doesn't advance while the LCD is off.
+    pub fn frame_count(&self) -> u64 {
+        return self.bus.ppu.frame_counter;
+    }
+
+    /// Runs for `budget` of emulated time at the speed of real hardware, for frontends that aren't paced by frames,
+    /// or until a frame completes if asked to. Like `run_frame`, stops early when a watchpoint is hit or the CPU
+    /// locks up, leaving the event to `take_debug_event`.
+    ///
+    /// Budgets accumulate: a call that returns early leaves the rest of its budget to the next, and the few cycles of
+    /// the last instruction that overshot a budget are taken from the next one. Over many calls, the cycles run match
+    /// the total budget exactly.
+    pub fn run_for(&mut self, budget: Duration, until: RunUntil) -> RunResult {
+        let mut result = RunResult {
+            t_cycles: 0,
+            frame_ready: false,
+        };
+
+        if !self.loaded {
+            return result;
+        }
+
+        self.budget += budget;
+
+        let target = (self.budget.as_nanos() * CLOCK_SPEED as u128 / 1_000_000_000) as u64;
+
+        while self.budget_t_cycles < target {
+            let (frame_completed, t_cycles) = self.step();
+
+            self.budget_t_cycles += t_cycles as u64;
+            result.t_cycles += t_cycles as u64;
+            result.frame_ready |= frame_completed;
+
+            if self.debug_event.is_some() || (frame_completed && until == RunUntil::FrameCompleted) {
+                break;
+            }
+        }
+
+        result
+    }
+
+    /// Runs whole instructions until at least `t_cycles` have run, or until a frame completes if asked to, for
+    /// frontends that do their own pacing, e.g. running `elapsed * CLOCK_SPEED` cycles for every refresh of a 144 Hz
+    /// display. Like `run_for`, stops early when a watchpoint is hit or the CPU locks up.
+    ///
+    /// Unlike `run_for`, nothing carries over between calls: the last instruction can overshoot `t_cycles`, and callers
+    /// that keep to a target count take `RunResult::t_cycles`, or `cycles_elapsed`, into account for the next call.
+    pub fn run_cycles(&mut self, t_cycles: u64, until: RunUntil) -> RunResult {
+        let mut result = RunResult {
+            t_cycles: 0,
+            frame_ready: false,
+        };
+
+        if !self.loaded {
+            return result;
+        }
+
+        while result.t_cycles < t_cycles {
+            let (frame_completed, cycles) = self.step();
+
+            result.t_cycles += cycles as u64;
+            result.frame_ready |= frame_completed;
+
+            if self.debug_event.is_some() || (frame_completed && until == RunUntil::FrameCompleted) {
+                break;
+            }
         }
 
         result
     }
 
+    /// T-cycles run since the ROM was loaded, at `CLOCK_SPEED` per second of emulated time. It only ever grows while
+    /// the game runs, however it is run, and loading a state sets it back to when the state was saved.
+    pub fn cycles_elapsed(&self) -> u64 {
+        self.t_cycles
+    }
+
+    /// Returns the first debug event raised since the last call, if any.
+    pub fn take_debug_event(&mut self) -> Option<DebugEvent> {
+        return self.debug_event.take();
+    }
+
+    /// Watches CPU accesses to `range`. Hits are reported by `run_frame` and `take_debug_event`.
+    pub fn add_watchpoint(&mut self, range: RangeInclusive<u16>, on_read: bool, on_write: bool) {
+        self.bus.watchpoints.push(Watchpoint {
+            range,
+            on_read,
+            on_write,
+        });
+    }
+
+    pub fn clear_watchpoints(&mut self) {
+        self.bus.watchpoints.clear();
+    }
+
+    /// Reads memory as the CPU sees it, but without side effects, for tools inspecting a running game.
+    ///
+    /// Unlike the CPU, this can read OAM and VRAM in any PPU mode, cartridge RAM while it is disabled, and the stored
+    /// values of write-only registers.
+    pub fn peek(&self, addr: u16) -> u8 {
+        return self.bus.mem_peek(addr);
+    }
+
+    /// Reads `len` bytes starting at `addr`, see `peek`. Addresses wrap around after 0xffff.
+    pub fn peek_range(&self, addr: u16, len: usize) -> Vec<u8> {
+        return (0..len).map(|offset| self.bus.mem_peek(addr.wrapping_add(offset as u16))).collect();
+    }
+
+    /// Writes memory without side effects: writing a register doesn't raise interrupts, start DMA, trigger audio
+    /// channels or reset timers, and writing cartridge RAM works while it is disabled.
+    ///
+    /// Some writes have no meaningful effect:
+    /// - ROM (0x0000-0x7fff) is never changed; patching it needs a cheat device, not a poke, and mapper registers in
+    ///   that range can't be written this way either.
+    /// - The prohibited area (0xfea0-0xfeff) and unused registers ignore writes.
+    /// - LY and DIV can be set, but keep counting from the poked value. DIV sets the top of the counter TIMA also counts
+    ///   from, without the increment a CPU write can cause. The mode bits of STAT are kept.
+    /// - Cartridge RAM banks holding MBC3 RTC registers ignore writes.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use yagbe::gameboy::GameBoy;
+    ///
+    /// let mut gameboy = GameBoy::new();
+    /// gameboy.load(vec![0; 0x8000])?;
+    ///
+    /// gameboy.poke(0xc000, 0x42);
+    /// gameboy.poke(0x0000, 0x42);
+    ///
+    /// assert_eq!(gameboy.peek(0xc000), 0x42);
+    /// assert_eq!(gameboy.peek(0x0000), 0x00);
+    /// # Ok::<(), yagbe::gameboy::CoreError>(())
+    /// ```
+    pub fn poke(&mut self, addr: u16, value: u8) {
+        self.bus.mem_poke(addr, value);
+    }
+
+    pub fn set_accuracy(&mut self, accuracy: Accuracy) {
+        self.bus.set_accuracy(accuracy);
+    }
+
+    /// Writes the CPU registers before every executed instruction to `trace`, or stops tracing if `None`.
+    pub fn set_trace(&mut self, trace: Option<Box<dyn Write + Send>>) {
+        self.trace = trace;
+    }
+
+    /// The address of the next instruction the CPU runs.
+    pub fn pc(&self) -> u16 {
+        return self.cpu.pc();
+    }
+
+    /// Whether the CPU is waiting for an interrupt or a button after HALT or STOP, so `tick` runs no instruction.
+    pub fn halted(&self) -> bool {
+        return self.cpu.halted() || self.cpu.stopped();
+    }
+
+    /// Captures the CPU, the instructions it last ran and memory, for a bug report about a crash, such as the CPU
+    /// locking up on an invalid opcode. Memory is read without side effects, as by `peek`.
+    pub fn diagnostic_dump(&self) -> DiagnosticReport {
+        let history = self.cpu.pc_history();
+
+        DiagnosticReport {
+            registers: self.cpu.registers().to_string(),
+            // The invalid opcode is the last instruction that ran.
+            locked_at: history.iter().last().filter(|_| self.cpu.locked()),
+            recent_instructions: history.iter().map(|pc| (pc, disassembler::disassemble(&self.bus, pc).0)).collect(),
+            interrupt_flag: self.peek(0xff0f),
+            interrupt_enable: self.peek(0xffff),
+            lcdc: self.peek(0xff40),
+            stat: self.peek(0xff41),
+            wram: self.peek_range(0xc000, 0x2000),
+            hram: self.peek_range(0xff80, 0x7f),
+            vram: self.peek_range(0x8000, 0x2000),
+            oam: self.peek_range(0xfe00, 0xa0),
+        }
+    }
+
+    /// Disassembles the instruction at `addr`, returning it with its length in bytes, as in the trace.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use yagbe::gameboy::GameBoy;
+    ///
+    /// let mut rom = vec![0; 0x8000];
+    /// rom[0x100..0x103].copy_from_slice(&[0xc3, 0x50, 0x01]);
+    ///
+    /// let mut gameboy = GameBoy::new();
+    /// gameboy.load(rom)?;
+    ///
+    /// let (instruction, len) = gameboy.disassemble(gameboy.pc());
+    /// println!("{instruction}");
+    ///
+    /// assert_eq!(len, 3);
+    ///
+    /// gameboy.tick();
+    /// assert_eq!(gameboy.pc(), 0x150);
+    /// # Ok::<(), yagbe::gameboy::CoreError>(())
+    /// ```
+    pub fn disassemble(&self, addr: u16) -> (String, u16) {
+        return disassembler::disassemble(&self.bus, addr);
+    }
+
+    /// Enables recording interrupt, LCD register, DMA and timer events into a bounded log, see `drain_events`.
+    pub fn set_event_logging(&mut self, enabled: bool) {
+        self.bus.io_registers.event_log.enabled = enabled;
+    }
+
+    pub fn drain_events(&mut self) -> Drain<'_, LoggedEvent> {
+        return self.bus.io_registers.event_log.drain();
+    }
+
+    /// The shade of every pixel, row by row, from 0 for the lightest to 3 for the darkest. It is drawn over as the
+    /// frame progresses, so it holds a whole frame once `run_frame` completes one.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use yagbe::gameboy::{GameBoy, SCREEN_HEIGHT, SCREEN_WIDTH};
+    ///
+    /// let mut gameboy = GameBoy::new();
+    /// gameboy.load(vec![0; 0x8000])?;
+    /// gameboy.run_frame();
+    ///
+    /// let top_left = gameboy.screen()[0];
+    /// let bottom_right = gameboy.screen()[SCREEN_WIDTH * SCREEN_HEIGHT - 1];
+    ///
+    /// assert!(top_left <= 3 && bottom_right <= 3);
+    /// # Ok::<(), yagbe::gameboy::CoreError>(())
+    /// ```
     pub fn screen(&self) -> &[u8; SCREEN_WIDTH * SCREEN_HEIGHT] {
         return &self.bus.ppu.screen;
     }
 
+    /// The screen of the frame before `screen`, for frontends that blend the two to imitate the slow response of the
+    /// LCD.
+    pub fn previous_screen(&self) -> &[u8; SCREEN_WIDTH * SCREEN_HEIGHT] {
+        return &self.bus.ppu.previous_screen;
+    }
+
+    /// OAM as the PPU sees it.
+    pub fn oam(&self) -> &[u8; 0xa0] {
+        return &self.bus.ppu.vram.oam;
+    }
+
+    pub fn ly(&self) -> u8 {
+        return self.bus.io_registers.ly;
+    }
+
+    pub fn obj_palettes(&self) -> [u8; 2] {
+        return [self.bus.io_registers.obp0, self.bus.io_registers.obp1];
+    }
+
+    pub fn sprite_height(&self) -> u8 {
+        return if self.bus.io_registers.lcdc.contains(LCDControl::OBJ_SIZE) { 16 } else { 8 };
+    }
+
+    /// OAM entries the PPU selects for the current scanline, limited to 10 per line.
+    pub fn line_sprites(&self) -> Vec<usize> {
+        return ppu::select_line_sprites(self.oam(), self.ly(), self.sprite_height());
+    }
+
+    /// How each line of the current frame was drawn, filled in as the lines are drawn.
+    pub fn line_stats(&self) -> &[LineStats; SCREEN_HEIGHT] {
+        return &self.bus.ppu.line_stats;
+    }
+
+    pub fn tile_row(&self, tile_index: u8, row: u8) -> [u8; 8] {
+        return self.bus.ppu.vram.tile_row(tile_index, row);
+    }
+
+    /// The 40 OAM entries, decoded. Like `oam`, this reads OAM in any PPU mode.
+    pub fn oam_entries(&self) -> [OamEntry; OAM_ENTRY_COUNT] {
+        return std::array::from_fn(|index| self.bus.ppu.vram.oam_entry(index));
+    }
+
+    /// Decodes tile `index` of the 384 in tile data, counting from 0x8000 whatever LCDC selects, in any PPU mode.
+    /// `bank` is the VRAM bank, of which there is only bank 0 without CGB support, so tiles of other banks are blank.
+    ///
+    /// Panics if `index` is 384 or more.
+    pub fn tile(&self, index: u16, bank: u8) -> Tile {
+        assert!(index < 384, "tile index {index} out of range");
+
+        return match bank {
+            0 => self.bus.ppu.vram.tile(index),
+            _ => [[0; 8]; 8],
+        };
+    }
+
+    /// The rows of the sprite of an object using tile `tile`, in the current object size. 8x16 objects ignore bit 0 of
+    /// the tile index and take their bottom half from the next tile, and 8x8 objects leave the last 8 rows blank.
+    pub fn sprite_rows(&self, tile: u8) -> [[u8; 8]; 16] {
+        let mut rows = [[0; 8]; 16];
+
+        for row in 0..self.sprite_height() {
+            let tile = if self.sprite_height() == 16 {
+                (tile & 0xfe) | (row / 8)
+            } else {
+                tile
+            };
+
+            rows[row as usize] = self.tile_row(tile, row % 8);
+        }
+
+        return rows;
+    }
+
     pub fn audio_buffer_size(&self) -> usize {
         return self.bus.apu.buffer.len();
     }
-    pub fn extract_audio_buffer(&mut self) -> Vec<f32> {
-        return self.bus.apu.extract_audio_buffer();
+
+    /// Returns the number of stereo samples dropped since the last call because audio wasn't taken with `fill_audio`
+    /// in time. The buffer holds a third of a second, and once full, the oldest samples make room for new ones, so a
+    /// stalled frontend neither grows it nor hears a backlog later.
+    pub fn take_audio_overruns(&mut self) -> u64 {
+        return self.bus.apu.take_overruns();
+    }
+
+    /// Moves buffered audio into `out` as interleaved stereo samples, zero-filling it if there aren't enough. Returns
+    /// the number of samples moved.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use yagbe::gameboy::GameBoy;
+    ///
+    /// let mut gameboy = GameBoy::new();
+    /// gameboy.load(vec![0; 0x8000])?;
+    /// gameboy.run_frame();
+    ///
+    /// let mut samples = vec![0.0; gameboy.audio_buffer_size()];
+    /// let moved = gameboy.fill_audio(&mut samples);
+    ///
+    /// assert_eq!(moved, samples.len());
+    /// assert_eq!(gameboy.audio_buffer_size(), 0);
+    /// # Ok::<(), yagbe::gameboy::CoreError>(())
+    /// ```
+    pub fn fill_audio(&mut self, out: &mut [f32]) -> usize {
+        return self.bus.apu.fill_audio(out);
+    }
+
+    /// Starts or stops making audio, which is on by default. Without it, `fill_audio` only returns silence, but
+    /// everything a game can see of the APU, like NR52 and the length timers, runs as usual, and so does emulation,
+    /// only faster. For running without anything to play the audio on, like tests.
+    ///
+    /// It can be switched back on at any time, and audio goes on from there.
+    pub fn set_audio_enabled(&mut self, enabled: bool) {
+        self.bus.apu.set_audio_enabled(enabled);
+    }
+
+    pub fn audio_enabled(&self) -> bool {
+        return self.bus.apu.audio_enabled();
+    }
+
+    /// Left and right levels of the four audio channels, from 0 to 1.
+    pub fn audio_channel_levels(&self) -> [[f32; 2]; 4] {
+        return self.bus.apu.channel_levels();
+    }
+
+    /// What each audio channel is set to play.
+    pub fn audio_channel_status(&self) -> [ChannelStatus; 4] {
+        return self.bus.apu.channel_status();
+    }
+
+    /// Records the output of each audio channel on its own for `audio_channel_taps`. Off by default, since it costs
+    /// time for every sample.
+    pub fn set_audio_channel_taps(&mut self, enabled: bool) {
+        self.bus.apu.set_channel_taps(enabled);
+    }
+
+    /// The last samples of each channel's own output, see `set_audio_channel_taps`.
+    pub fn audio_channel_taps(&self) -> Option<&[VecDeque<f32>; 4]> {
+        return self.bus.apu.channel_taps();
+    }
+
+    /// The buttons currently held.
+    pub fn buttons(&self) -> Buttons {
+        let io = &self.bus.io_registers;
+
+        // JOYP bits are cleared while held.
+        Buttons::from_bits_retain(!((io.joyp_directions & 0x0f) | (io.joyp_actions & 0x0f) << 4))
+    }
+
+    /// Sets the state of every button at once, requesting the joypad interrupt if that pressed a selected button.
+    pub fn set_buttons(&mut self, buttons: Buttons) {
+        let io = &mut self.bus.io_registers;
+
+        let previous = io.joyp_value();
+
+        io.joyp_directions = !buttons.bits() & 0x0f;
+        io.joyp_actions = !buttons.bits() >> 4;
+
+        // Joypad interrupt is set whenever joypad bits 0-3 go from high to low.
+        if previous & !io.joyp_value() & 0b0000_1111 != 0 {
+            io.request_interrupt(InterruptFlags::JOYPAD);
+        }
+    }
+
+    /// Sets the buttons held for the whole of the next frame. Frontends that call it once before every `run_frame`,
+    /// rather than as input arrives, present a press on the same frame however the host schedules emulation, so
+    /// recording the latched buttons of every frame is enough to replay a run exactly.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use yagbe::gameboy::{Buttons, GameBoy};
+    ///
+    /// let mut gameboy = GameBoy::new();
+    /// gameboy.load(vec![0; 0x8000])?;
+    ///
+    /// for frame in 0..10 {
+    ///     let buttons = if frame < 5 { Buttons::Start } else { Buttons::empty() };
+    ///
+    ///     gameboy.latch_input(buttons);
+    ///     gameboy.run_frame();
+    /// }
+    ///
+    /// assert_eq!(gameboy.buttons(), Buttons::empty());
+    /// # Ok::<(), yagbe::gameboy::CoreError>(())
+    /// ```
+    pub fn latch_input(&mut self, buttons: Buttons) {
+        self.set_buttons(buttons);
+    }
+
+    /// Presses `buttons`, keeping the others as they are. Pressing a button of a group selected in JOYP requests the
+    /// joypad interrupt, whenever it happens, which wakes a CPU waiting for it in HALT.
+    pub fn button_pressed(&mut self, buttons: Buttons) {
+        self.set_buttons(self.buttons() | buttons);
+    }
+
+    pub fn button_released(&mut self, buttons: Buttons) {
+        self.set_buttons(self.buttons() - buttons);
+    }
+
+    /// Releases every button, for when releases may have been missed, such as when the window loses focus.
+    pub fn release_all_buttons(&mut self) {
+        self.set_buttons(Buttons::empty());
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn unix_time() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |time| time.as_secs())
+}
+
+// The system clock panics in the browser. Without it, a cartridge clock doesn't advance between saves.
+#[cfg(target_arch = "wasm32")]
+fn unix_time() -> u64 {
+    0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A machine running `program` from 0x0100.
+    fn running(program: &[u8]) -> GameBoy {
+        let mut rom = vec![0; 0x100];
+        rom.extend_from_slice(program);
+
+        let mut gameboy = GameBoy::new();
+        gameboy.load_flat(rom);
+
+        gameboy
+    }
+
+    /// Games that run on both models can tell them apart by the A register the boot ROM leaves behind.
+    #[test]
+    fn set_model_takes_effect_from_the_next_load() {
+        let mut gameboy = running(&[]);
+        assert_eq!(gameboy.cpu.registers().af(), 0x01b0);
+
+        gameboy.set_model(Model::Cgb);
+        assert_eq!(gameboy.cpu.registers().af(), 0x01b0);
+
+        gameboy.load(vec![0; 0x8000]).unwrap();
+        assert_eq!(gameboy.cpu.registers().af(), 0x1180);
+        assert_eq!(gameboy.cpu.registers().de(), 0xff56);
+    }
+
+    /// A flat program with a byte of every address it covers, up to 24 KiB. The header says it has an MBC1 and no RAM.
+    fn flat_program(program: &[u8]) -> Vec<u8> {
+        let mut rom = (0..0x6000).map(|addr: usize| (addr % 251) as u8).collect::<Vec<_>>();
+        rom[0x0100..0x0100 + program.len()].copy_from_slice(program);
+        rom[0x0147] = 0x01;
+        rom[0x0149] = 0x00;
+
+        rom
+    }
+
+    #[test]
+    fn flat_programs_read_back_where_they_are_loaded_padded_with_zeroes() {
+        let rom = flat_program(&[]);
+
+        let mut gameboy = GameBoy::new();
+        gameboy.load_flat(rom.clone());
+
+        for addr in 0..0x8000 {
+            assert_eq!(gameboy.peek(addr), rom.get(addr as usize).copied().unwrap_or(0), "{addr:04x}");
+        }
+    }
+
+    #[test]
+    fn flat_programs_write_cartridge_ram_but_not_rom() {
+        // LD A,0x55; LD (0x0150),A; LD (0x4000),A; LD (0xa000),A; LD (0xbfff),A; JR -2
+        let rom = flat_program(&[0x3e, 0x55, 0xea, 0x50, 0x01, 0xea, 0x00, 0x40, 0xea, 0x00, 0xa0, 0xea, 0xff, 0xbf, 0x18, 0xfe]);
+
+        let mut gameboy = GameBoy::new();
+        gameboy.load_flat(rom.clone());
+
+        for _ in 0..6 {
+            gameboy.tick();
+        }
+
+        assert_eq!(gameboy.pc(), 0x010e);
+        assert_eq!(gameboy.peek(0x0150), rom[0x0150]);
+        assert_eq!(gameboy.peek(0x4000), rom[0x4000]);
+        // Without enabling it first.
+        assert_eq!(gameboy.peek(0xa000), 0x55);
+        assert_eq!(gameboy.peek(0xbfff), 0x55);
+    }
+
+    #[test]
+    fn flat_programs_have_no_mapper_whatever_the_header_says() {
+        // Selects ROM bank 2 and RAM banking mode for an MBC1: LD A,0x02; LD (0x2000),A; LD A,0x01; LD (0x6000),A;
+        // LD A,0x03; LD (0x4000),A; JR -2
+        let rom = flat_program(&[0x3e, 0x02, 0xea, 0x00, 0x20, 0x3e, 0x01, 0xea, 0x00, 0x60, 0x3e, 0x03, 0xea, 0x00, 0x40, 0x18, 0xfe]);
+
+        let mut gameboy = GameBoy::new();
+        gameboy.load_flat(rom.clone());
+        gameboy.poke(0xa000, 0x42);
+
+        for _ in 0..7 {
+            gameboy.tick();
+        }
+
+        assert_eq!(gameboy.pc(), 0x010f);
+        for addr in [0x0000, 0x3fff, 0x4000, 0x5fff] {
+            assert_eq!(gameboy.peek(addr), rom[addr as usize], "{addr:04x}");
+        }
+        assert_eq!(gameboy.peek(0xa000), 0x42);
+    }
+
+    #[test]
+    #[should_panic(expected = "at most 32 KiB")]
+    fn flat_programs_are_at_most_32_kib() {
+        GameBoy::new().load_flat(vec![0; 0x8001]);
+    }
+
+    #[test]
+    fn stop_holds_div_at_0_until_a_selected_button_is_pressed() {
+        // LD A,0x10; LDH (0x00),A; STOP; INC A; LD (0xc000),A; JR -2
+        let mut gameboy = running(&[0x3e, 0x10, 0xe0, 0x00, 0x10, 0x00, 0x3c, 0xea, 0x00, 0xc0, 0x18, 0xfe]);
+
+        for _ in 0..3 {
+            gameboy.tick();
+        }
+
+        assert!(gameboy.halted());
+
+        for _ in 0..10_000 {
+            gameboy.tick();
+            assert_eq!(gameboy.peek(0xff04), 0);
+        }
+
+        // Directions aren't selected.
+        gameboy.set_buttons(Buttons::Right);
+        gameboy.tick();
+        assert_eq!(gameboy.pc(), 0x0106);
+
+        gameboy.set_buttons(Buttons::A);
+        gameboy.tick();
+        gameboy.tick();
+
+        assert!(!gameboy.halted());
+        assert_eq!(gameboy.peek(0xc000), 0x11);
+    }
+
+    #[test]
+    fn pressing_a_selected_button_wakes_the_cpu_from_halt() {
+        // DI; LD A, $10; LDH (IE), A; LD A, $20; LDH (P1), A; XOR A; LDH (IF), A; HALT; LD A, $42; LD ($C000), A; JR -2
+        let mut gameboy = running(&[
+            0xf3, 0x3e, 0x10, 0xe0, 0xff, 0x3e, 0x20, 0xe0, 0x00, 0xaf, 0xe0, 0x0f, 0x76, 0x3e, 0x42, 0xea, 0x00, 0xc0, 0x18, 0xfe,
+        ]);
+        gameboy.run_frame();
+
+        // Only the directions are selected, so A doesn't wake the CPU. With IME off, it goes on after HALT once woken.
+        gameboy.button_pressed(Buttons::A);
+        gameboy.run_frame();
+        assert_eq!(gameboy.peek(0xc000), 0x00);
+
+        gameboy.button_pressed(Buttons::Right);
+        gameboy.run_frame();
+        assert_eq!(gameboy.peek(0xc000), 0x42);
+    }
+
+    #[test]
+    fn oam_written_by_dma_reads_back_in_vblank() {
+        // Waits for LY 144, then copies 0xc000-0xc09f to OAM: LDH A,(0x44); CP 0x90; JR NZ,-6; LD A,0xc0;
+        // LDH (0x46),A; JR -2
+        let mut gameboy = running(&[0xf0, 0x44, 0xfe, 0x90, 0x20, 0xfa, 0x3e, 0xc0, 0xe0, 0x46, 0x18, 0xfe]);
+
+        for offset in 0..0xa0 {
+            gameboy.poke(0xc000 + offset, offset as u8 + 1);
+        }
+
+        while gameboy.pc() != 0x010a {
+            gameboy.tick();
+        }
+
+        for _ in 0..4 {
+            gameboy.tick();
+        }
+
+        assert!(gameboy.bus.io_registers.dma_counter > 0);
+        assert_eq!(gameboy.bus.mem_read(0xfe00), 0x01);
+        assert_eq!(gameboy.bus.mem_read(0xfe9f), 0x00);
+
+        for _ in 0..100 {
+            gameboy.tick();
+        }
+
+        assert_eq!(gameboy.bus.io_registers.stat & 0b11, 1);
+        assert_eq!(gameboy.bus.io_registers.dma_counter, 0);
+
+        for offset in 0..0xa0 {
+            assert_eq!(gameboy.bus.mem_read(0xfe00 + offset), offset as u8 + 1, "{offset:02x}");
+        }
+    }
+
+    /// A write to the DMA register during a transfer lets it go on for the setup delay, and the new transfer then copies
+    /// over OAM from the start. The DMA copies 4 bytes per instruction.
+    #[test]
+    fn dma_restarted_during_a_transfer_copies_over_oam_from_the_start() {
+        // LD A,0xc0; LDH (0x46),A; LD A,0xc1; LDH (0x46),A; JR -2
+        let mut gameboy = running(&[0x3e, 0xc0, 0xe0, 0x46, 0x3e, 0xc1, 0xe0, 0x46, 0x18, 0xfe]);
+
+        for i in 0..0xa0 {
+            gameboy.poke(0xc000 + i, 0x11);
+            gameboy.poke(0xc100 + i, 0x22);
+        }
+
+        // Up to the instruction after the second write, the first transfer copied 8 bytes.
+        for _ in 0..5 {
+            gameboy.tick();
+        }
+
+        assert_eq!(gameboy.oam()[..9], [0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x00]);
+
+        gameboy.tick();
+        assert_eq!(gameboy.oam()[..9], [0x22, 0x22, 0x22, 0x22, 0x11, 0x11, 0x11, 0x11, 0x00]);
+
+        gameboy.run_frame();
+        assert!(gameboy.oam().iter().all(|&byte| byte == 0x22));
+    }
+
+    #[test]
+    fn oam_entries_decode_each_entry_of_oam() {
+        let mut gameboy = running(&[]);
+
+        for (i, byte) in [0x20, 0x18, 0x05, 0b1011_0000].into_iter().enumerate() {
+            gameboy.poke(0xfe04 + i as u16, byte);
+        }
+
+        let entry = gameboy.oam_entries()[1];
+
+        assert_eq!((entry.y, entry.x, entry.tile), (0x20, 0x18, 0x05));
+        assert_eq!(entry.attributes, OamAttributes::BG_OVER_OBJ | OamAttributes::X_FLIP | OamAttributes::DMG_PALETTE);
+    }
+
+    #[test]
+    fn tiles_decode_rows_from_their_low_and_high_bits() {
+        let mut gameboy = running(&[]);
+
+        // The low bits of a row come first, then the high bits, leftmost pixel in bit 7.
+        let tile = [0x3c, 0x7e, 0x42, 0x42, 0x42, 0x42, 0x42, 0x42, 0x7e, 0x5e, 0x7e, 0x0a, 0x7c, 0x56, 0x38, 0x7c];
+
+        for (i, byte) in tile.into_iter().enumerate() {
+            gameboy.poke(0x8010 + i as u16, byte);
+        }
+
+        assert_eq!(gameboy.tile(1, 0), [
+            [0, 2, 3, 3, 3, 3, 2, 0],
+            [0, 3, 0, 0, 0, 0, 3, 0],
+            [0, 3, 0, 0, 0, 0, 3, 0],
+            [0, 3, 0, 0, 0, 0, 3, 0],
+            [0, 3, 1, 3, 3, 3, 3, 0],
+            [0, 1, 1, 1, 3, 1, 3, 0],
+            [0, 3, 1, 3, 1, 3, 2, 0],
+            [0, 2, 3, 3, 3, 2, 0, 0],
+        ]);
+        // Without CGB support, there is only VRAM bank 0.
+        assert_eq!(gameboy.tile(1, 1), [[0; 8]; 8]);
+    }
+
+    #[test]
+    fn run_frame_returns_after_a_frame_of_time_while_the_lcd_is_off() {
+        // XOR A; LDH (0x40),A; JR -2
+        let mut gameboy = running(&[0xaf, 0xe0, 0x40, 0x18, 0xfe]);
+
+        for frame in 1..=3 {
+            assert!(matches!(gameboy.run_frame(), Some(FrameEnd::LcdOff)));
+
+            // The frame ends on the first instruction past it, of at most 24 T-cycles.
+            let elapsed = gameboy.cycles_elapsed();
+            assert!((frame * DOTS_PER_FRAME as u64..frame * (DOTS_PER_FRAME as u64 + 24)).contains(&elapsed), "{elapsed}");
+        }
+
+        assert_eq!(gameboy.frame_count(), 0);
+    }
+
+    #[test]
+    fn diagnostic_dumps_capture_where_the_cpu_locked_up() {
+        // NOP; invalid opcode 0xd3
+        let mut gameboy = running(&[0x00, 0xd3]);
+
+        assert!(matches!(gameboy.run_frame(), Some(FrameEnd::Locked(_))));
+
+        let report = gameboy.diagnostic_dump();
+        assert_eq!(report.locked_at, Some(0x101));
+        assert_eq!(report.recent_instructions.len(), 2);
+
+        let text = report.to_string();
+
+        for section in ["[CPU]", "[Recent instructions]", "[Registers]", "[WRAM]", "[HRAM]", "[VRAM]", "[OAM]"] {
+            assert!(text.contains(section), "{section}");
+        }
+    }
+
+    /// Whether `t_cycles` is what `seconds` of emulated time takes, give or take the longest instruction.
+    fn matches_time(t_cycles: u64, seconds: u64) -> bool {
+        (seconds * CLOCK_SPEED..seconds * CLOCK_SPEED + 24).contains(&t_cycles)
+    }
+
+    #[test]
+    fn run_for_doesnt_drift_over_many_calls() {
+        let mut gameboy = running(&[]);
+
+        // A refresh of 60 Hz isn't a whole number of T-cycles. Each 60 of these calls add up to exactly a second.
+        for _ in 0..3 {
+            for _ in 0..59 {
+                gameboy.run_for(Duration::from_nanos(16_666_667), RunUntil::BudgetExhausted);
+            }
+
+            gameboy.run_for(Duration::from_nanos(16_666_667 - 20), RunUntil::BudgetExhausted);
+        }
+
+        assert!(matches_time(gameboy.cycles_elapsed(), 3), "{}", gameboy.cycles_elapsed());
+    }
+
+    #[test]
+    fn run_for_keeps_the_budget_left_when_a_frame_completes() {
+        let mut gameboy = running(&[]);
+        let mut frames = 0;
+
+        let mut result = gameboy.run_for(Duration::from_secs(1), RunUntil::FrameCompleted);
+
+        while result.t_cycles > 0 {
+            frames += result.frame_ready as u32;
+            result = gameboy.run_for(Duration::ZERO, RunUntil::FrameCompleted);
+        }
+
+        assert!((59..=60).contains(&frames), "{frames}");
+        assert!(matches_time(gameboy.cycles_elapsed(), 1), "{}", gameboy.cycles_elapsed());
+    }
+
+    #[test]
+    fn run_cycles_runs_whole_instructions_until_the_count_is_reached() {
+        let mut gameboy = running(&[]);
+
+        let result = gameboy.run_cycles(1001, RunUntil::BudgetExhausted);
+
+        // NOPs take 4 T-cycles each.
+        assert_eq!(result.t_cycles, 1004);
+        assert_eq!(gameboy.cycles_elapsed(), 1004);
+    }
+
+    #[test]
+    fn cycles_elapsed_counts_the_time_run_however_the_game_is_run() {
+        let mut gameboy = running(&[]);
+        assert_eq!(gameboy.cycles_elapsed(), 0);
+
+        gameboy.tick();
+        assert_eq!(gameboy.cycles_elapsed(), 4);
+
+        // Frames complete a full frame of dots apart.
+        gameboy.run_frame();
+        let start = gameboy.cycles_elapsed();
+        gameboy.run_frame();
+        assert_eq!(gameboy.cycles_elapsed() - start, DOTS_PER_FRAME as u64);
+
+        let before = gameboy.cycles_elapsed();
+        let result = gameboy.run_for(Duration::from_secs(1), RunUntil::BudgetExhausted);
+        assert_eq!(result.t_cycles, CLOCK_SPEED);
+        assert_eq!(gameboy.cycles_elapsed(), before + CLOCK_SPEED);
+    }
+
+    #[test]
+    fn cycles_elapsed_is_restored_by_loading_a_state_and_reset_by_loading_a_rom() {
+        let mut gameboy = running(&[]);
+        gameboy.run_frame();
+
+        let state = gameboy.save_state().unwrap();
+        let saved = gameboy.cycles_elapsed();
+
+        gameboy.run_frame();
+        gameboy.load_state(&state).unwrap();
+        assert_eq!(gameboy.cycles_elapsed(), saved);
+
+        gameboy.load(vec![0; 0x8000]).unwrap();
+        assert_eq!(gameboy.cycles_elapsed(), 0);
+    }
+
+    /// The ROM the state files in `STATE_FIXTURES` were saved with, after `STATE_FIXTURE_TICKS` instructions of
+    /// counting at 0xc000 with the timer running.
+    fn state_fixture_rom() -> Vec<u8> {
+        let mut rom = vec![0; 0x8000];
+        // NOP; JP 0x0150
+        rom[0x0100..0x0104].copy_from_slice(&[0x00, 0xc3, 0x50, 0x01]);
+        rom[0x0134..0x013d].copy_from_slice(b"STATETEST");
+        // LD A,0x05; LDH (0x07),A; LD HL,0xc000; INC (HL); JR -3
+        rom[0x0150..0x015a].copy_from_slice(&[0x3e, 0x05, 0xe0, 0x07, 0x21, 0x00, 0xc0, 0x34, 0x18, 0xfd]);
+
+        rom
+    }
+
+    const STATE_FIXTURE_TICKS: usize = 12345;
+
+    /// State files saved by older versions of the core, by the version of the state format.
+    const STATE_FIXTURES: [(u32, &[u8]); 3] = [
+        (1, include_bytes!("fixtures/state_v1.bin")),
+        (2, include_bytes!("fixtures/state_v2.bin")),
+        (3, include_bytes!("fixtures/state_v3.bin")),
+    ];
+
+    #[test]
+    fn state_files_of_older_versions_load() {
+        for (version, file) in STATE_FIXTURES {
+            let mut gameboy = GameBoy::new();
+            gameboy.load(state_fixture_rom()).unwrap();
+            gameboy.load_state(file).unwrap_or_else(|error| panic!("version {version}: {error}"));
+
+            assert_eq!(gameboy.peek(0xc000), 0x1a, "version {version}");
+            assert_eq!(gameboy.peek(0xff04), 0x02, "version {version}");
+            assert_eq!(gameboy.peek(0xff05), 0x27, "version {version}");
+            assert_eq!(gameboy.peek(0xff07) & 0b111, 0x05, "version {version}");
+            assert_eq!(gameboy.peek(0xff44), 0x10, "version {version}");
+            assert!((0x0157..=0x015a).contains(&gameboy.pc()), "version {version}: {:04x}", gameboy.pc());
+        }
+    }
+
+    #[test]
+    fn state_files_of_older_versions_keep_running_like_the_game_that_saved_them() {
+        let mut fresh = GameBoy::new();
+        fresh.load(state_fixture_rom()).unwrap();
+
+        for _ in 0..STATE_FIXTURE_TICKS + 1000 {
+            fresh.tick();
+        }
+
+        for (version, file) in STATE_FIXTURES {
+            let mut gameboy = GameBoy::new();
+            gameboy.load(state_fixture_rom()).unwrap();
+            gameboy.load_state(file).unwrap();
+
+            for _ in 0..1000 {
+                gameboy.tick();
+            }
+
+            for addr in [0xc000, 0xff04, 0xff44] {
+                assert_eq!(gameboy.peek(addr), fresh.peek(addr), "version {version}, {addr:04x}");
+            }
+            assert_eq!(gameboy.pc(), fresh.pc(), "version {version}");
+
+            // Before version 4 TIMA counted from when TAC was written. The count saved for that is dropped on loading,
+            // and TIMA then counts from the system counter, so it can be an increment off.
+            let tima_difference = gameboy.peek(0xff05).wrapping_sub(fresh.peek(0xff05)) as i8;
+            assert!((-1..=1).contains(&tima_difference), "version {version}: {tima_difference}");
+        }
     }
 
-    pub fn button_pressed(&mut self, button: Buttons) {
-        match button {
-            Buttons::Right => self.bus.io_registers.joyp_directions &= !(1 << 0),
-            Buttons::Left => self.bus.io_registers.joyp_directions &= !(1 << 1),
-            Buttons::Up => self.bus.io_registers.joyp_directions &= !(1 << 2),
-            Buttons::Down => self.bus.io_registers.joyp_directions &= !(1 << 3),
-            Buttons::B => self.bus.io_registers.joyp_actions &= !(1 << 0),
-            Buttons::A => self.bus.io_registers.joyp_actions &= !(1 << 1),
-            Buttons::Select => self.bus.io_registers.joyp_actions &= !(1 << 2),
-            Buttons::Start => self.bus.io_registers.joyp_actions &= !(1 << 3),
+    #[test]
+    fn loading_a_rom_blanks_the_screen_and_drops_buffered_audio() {
+        let mut gameboy = running(&[0x18, 0xfe]);
+        gameboy.poke(0xff47, 0xff);
+
+        gameboy.run_frame();
+        gameboy.run_frame();
+
+        assert!(gameboy.screen().iter().all(|&shade| shade == 3));
+        assert!(gameboy.audio_buffer_size() > 0);
+
+        gameboy.load(vec![0; 0x8000]).unwrap();
+
+        assert!(gameboy.screen().iter().all(|&shade| shade == 0));
+        assert_eq!(gameboy.audio_buffer_size(), 0);
+    }
+
+    #[test]
+    fn audio_that_isnt_taken_is_dropped_instead_of_buffered() {
+        let mut gameboy = running(&[0x18, 0xfe]);
+
+        gameboy.run_for(Duration::from_secs(1), RunUntil::BudgetExhausted);
+
+        let size = gameboy.audio_buffer_size();
+        let capacity = gameboy.bus.apu.buffer.capacity();
+        let overruns = gameboy.take_audio_overruns();
+
+        gameboy.run_for(Duration::from_secs(10), RunUntil::BudgetExhausted);
+
+        assert_eq!(gameboy.audio_buffer_size(), size);
+        assert_eq!(gameboy.bus.apu.buffer.capacity(), capacity);
+
+        // Every stereo sample of the 10 seconds is dropped, give or take one.
+        let dropped = gameboy.take_audio_overruns();
+        assert!(overruns > 0);
+        assert!(dropped.abs_diff(10 * AUDIO_SAMPLE_RATE as u64) <= 1, "{dropped}");
+    }
+
+    /// Channel 2 at the slowest period, where a duty step lasts 2048 / 48 samples and a cycle 750, captured with each of
+    /// the duty cycles. High steps play at full volume, which comes out as negative samples.
+    #[test]
+    fn square_duty_cycles_are_high_for_their_share_of_the_period() {
+        let capture = |duty: u8| -> Vec<bool> {
+            // LD A, $80; LDH (NR24), A; JR -2
+            let mut gameboy = running(&[0x3e, 0x80, 0xe0, 0x19, 0x18, 0xfe]);
+            gameboy.poke(0xff16, duty << 6);
+            gameboy.poke(0xff17, 0xf0);
+            gameboy.poke(0xff18, 0x00);
+            gameboy.poke(0xff25, 0x22);
+            gameboy.run_frame();
+
+            let mut samples = vec![0.0; 1600];
+            gameboy.fill_audio(&mut samples);
+
+            // The left samples of a full cycle, after the trigger has settled.
+            samples.chunks(2).skip(32).take(750).map(|sample| sample[0] < 0.0).collect()
         };
+        let [eighth, quarter, half, three_quarters] = [0, 1, 2, 3].map(capture);
+
+        for (capture, high_steps) in [&eighth, &quarter, &half, &three_quarters].into_iter().zip([1, 2, 4, 6]) {
+            let high = capture.iter().filter(|&&high| high).count();
+
+            assert!(high.abs_diff(750 * high_steps / 8) <= 1, "{high} samples high of {high_steps} steps");
+        }
+
+        // The 75 % duty cycle is the 25 % one inverted, and each other one is high where the shorter ones are.
+        assert!(three_quarters.iter().zip(&quarter).all(|(high, quarter_high)| high != quarter_high));
+        assert!(eighth.iter().zip(&quarter).all(|(&high, &quarter_high)| !high || quarter_high));
+        assert!(quarter.iter().zip(&half).all(|(&high, &half_high)| !high || half_high));
     }
 
-    pub fn button_released(&mut self, button: Buttons) {
-        match button {
-            Buttons::Right => self.bus.io_registers.joyp_directions |= 1 << 0,
-            Buttons::Left => self.bus.io_registers.joyp_directions |= 1 << 1,
-            Buttons::Up => self.bus.io_registers.joyp_directions |= 1 << 2,
-            Buttons::Down => self.bus.io_registers.joyp_directions |= 1 << 3,
-            Buttons::B => self.bus.io_registers.joyp_actions |= 1 << 0,
-            Buttons::A => self.bus.io_registers.joyp_actions |= 1 << 1,
-            Buttons::Select => self.bus.io_registers.joyp_actions |= 1 << 2,
-            Buttons::Start => self.bus.io_registers.joyp_actions |= 1 << 3,
+    /// A game polling NR52 for the end of a sound sees it at the same time with audio on or off.
+    #[test]
+    fn turning_audio_off_keeps_the_apu_registers_running() {
+        let mut rom = vec![0; 0x100];
+        rom.extend([
+            // Sound on, then play channel 1 until its length runs out.
+            0x3e, 0x80, 0xe0, 0x26, 0x3e, 0xf0, 0xe0, 0x12, 0x3e, 0x00, 0xe0, 0x11, 0x3e, 0xc0, 0xe0, 0x14,
+            // LD HL,0; then LDH A,(NR52); INC HL; AND 1; JR NZ until channel 1 stops.
+            0x21, 0x00, 0x00, 0xf0, 0x26, 0x23, 0xe6, 0x01, 0x20, 0xf9,
+            // Store the number of polls in HRAM, then JR -2.
+            0x7d, 0xe0, 0x80, 0x7c, 0xe0, 0x81, 0x18, 0xfe,
+        ]);
+
+        let polls = |audio_enabled| {
+            let mut gameboy = GameBoy::new();
+            gameboy.set_audio_enabled(audio_enabled);
+            gameboy.load_flat(rom.clone());
+
+            for _ in 0..90 {
+                gameboy.run_frame();
+            }
+
+            assert_eq!(gameboy.audio_buffer_size() > 0, audio_enabled);
+
+            u16::from_le_bytes([gameboy.peek(0xff80), gameboy.peek(0xff81)])
         };
+
+        let polls_with_audio = polls(true);
+
+        assert!(polls_with_audio > 0);
+        assert_eq!(polls(false), polls_with_audio);
     }
-}
\ No newline at end of file
+
+}
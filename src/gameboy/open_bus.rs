@@ -0,0 +1,97 @@
+use super::{Accuracy, AccuracyMode, Model};
+
+/// What reads of addresses that nothing answers return, so that the bus, the registers and the cartridge agree:
+///
+/// | Addresses                                   | Value                                                  |
+/// |---------------------------------------------|--------------------------------------------------------|
+/// | 0x0000-0x7fff, 0xa000-0xbfff, no cartridge  | `UNMAPPED`                                             |
+/// | 0xa000-0xbfff, RAM disabled or missing      | `UNMAPPED`                                             |
+/// | 0xfea0-0xfeff                               | `UNMAPPED`, or by model with `AccuracyMode::Dmg`       |
+/// | Unused IO registers, CGB registers on DMG   | `UNMAPPED`, apart from OPRI, see `ObjectPriority`      |
+///
+/// Writes to all of them are ignored. The model is the one the running game was loaded on, and the behavior of the
+/// prohibited area follows `Accuracy::prohibited_area`.
+///
+/// # Examples
+///
+/// Every address can be read by the CPU, and those above read `UNMAPPED`.
+///
+/// ```
+/// use yagbe::gameboy::{GameBoy, OpenBus};
+///
+/// // A cartridge without RAM, the unused registers 0xff03, 0xff08, 0xff4c, 0xff50, 0xff57 and 0xff7f, and the CGB
+/// // registers KEY1, VBK, HDMA5 and SVBK.
+/// let unmapped = [0xa000, 0xbfff, 0xfea0, 0xfeff, 0xff03, 0xff08, 0xff4c, 0xff50, 0xff57, 0xff7f, 0xff4d, 0xff4f, 0xff55, 0xff70];
+///
+/// // JP 0x150, past the header.
+/// let mut rom = vec![0; 0x8000];
+/// rom[0x100..0x103].copy_from_slice(&[0xc3, 0x50, 0x01]);
+///
+/// // LD HL,0; then LD A,(HL+) until HL wraps around to 0.
+/// let mut code = vec![0x21, 0x00, 0x00, 0x2a, 0x7c, 0xb5, 0x20, 0xfb];
+///
+/// // LD A,(addr); LDH (0x80 + index),A, for each address.
+/// for (index, addr) in unmapped.iter().enumerate() {
+///     let [low, high] = u16::to_le_bytes(*addr);
+///     code.extend([0xfa, low, high, 0xe0, 0x80 + index as u8]);
+/// }
+///
+/// // JR -2
+/// code.extend([0x18, 0xfe]);
+/// rom[0x150..0x150 + code.len()].copy_from_slice(&code);
+///
+/// let mut gameboy = GameBoy::new();
+/// gameboy.load(rom)?;
+///
+/// for _ in 0..40 {
+///     gameboy.run_frame();
+/// }
+///
+/// for (index, addr) in unmapped.iter().enumerate() {
+///     assert_eq!(gameboy.peek(0xff80 + index as u16), OpenBus::UNMAPPED, "{addr:04X}");
+///     assert_eq!(gameboy.peek(*addr), OpenBus::UNMAPPED, "{addr:04X}");
+/// }
+/// # Ok::<(), yagbe::gameboy::CoreError>(())
+/// ```
+#[derive(Clone, Copy)]
+pub struct OpenBus {
+    model: Model,
+    prohibited_area: AccuracyMode,
+}
+
+impl OpenBus {
+    /// The value of the data bus when nothing drives it, pulled up to all ones.
+    pub const UNMAPPED: u8 = 0xff;
+
+    pub(crate) fn new(model: Model, accuracy: &Accuracy) -> Self {
+        Self {
+            model,
+            prohibited_area: accuracy.prohibited_area,
+        }
+    }
+
+    pub(crate) fn set_model(&mut self, model: Model) {
+        self.model = model;
+    }
+
+    pub(crate) fn set_accuracy(&mut self, accuracy: &Accuracy) {
+        self.prohibited_area = accuracy.prohibited_area;
+    }
+
+    /// Reads the prohibited area at 0xfea0-0xfeff, while the PPU blocks OAM or not.
+    // https://gbdev.io/pandocs/Memory_Map.html#fea0feff-range
+    pub(crate) fn prohibited_area(self, addr: u16, oam_blocked: bool) -> u8 {
+        match (self.prohibited_area, self.model) {
+            (AccuracyMode::Fast, _) => Self::UNMAPPED,
+            // Like the rest of OAM, including triggering the OAM corruption bug if it is enabled.
+            (AccuracyMode::Dmg, _) if oam_blocked => 0xff,
+            (AccuracyMode::Dmg, Model::Dmg) => 0x00,
+            // As on a CGB revision E: the high nibble of the low byte of the address, twice.
+            (AccuracyMode::Dmg, Model::Cgb) => {
+                let nibble = (addr as u8) >> 4;
+
+                nibble << 4 | nibble
+            }
+        }
+    }
+}
@@ -6,6 +6,8 @@ use super::{
     pixel_fetcher::PixelFetcherState::{GetSpriteAttributes, GetTileId, GetTileRowHigh, GetTileRowLow, PushPixels},
     pixel_fetcher::PixelFetcherMode::{Background, Object},
     io_registers::{IoRegisters, LCDControl},
+    state::{read, state_fields, State, StateReader},
+    CoreError,
 };
 
 /// Memory Map
@@ -36,6 +38,7 @@ use super::{
 /// $FF68	$FF69	CGB	            BG / OBJ Palettes
 /// $FF70		    CGB	            WRAM Bank Select
 
+#[derive(Clone)]
 pub enum PixelFetcherState {
     GetTileId,
     GetSpriteAttributes {
@@ -48,15 +51,14 @@ pub enum PixelFetcherState {
     GetTileRowHigh {
         sprite_attributes: Option<u8>,
         tile_address: u16,
-        tile_byte_lo: u8,
     },
     PushPixels {
         sprite_attributes: Option<u8>,
-        tile_byte_lo: u8,
-        tile_byte_hi: u8,
+        pixels: [u8; 8],
     },
 }
 
+#[derive(Clone)]
 pub enum PixelFetcherMode {
     Background,
     Object {
@@ -65,22 +67,53 @@ pub enum PixelFetcherMode {
     },
 }
 
+#[derive(Clone, Default)]
 pub struct SpritePixel {
     pub x: isize,
     pub color: u8,
     pub palette: u8,
     pub bg_over_obj: bool,
+    /// Address of the sprite's OAM entry, which decides overlaps under `ObjectPriority::OamIndex`.
+    pub oam_addr: u16,
 }
 
+/// Which sprite is drawn where opaque pixels of two sprites overlap, selected by bit 0 of OPRI.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ObjectPriority {
+    /// The sprite with the lower X, then the one earlier in OAM, as on DMG. Sprites are fetched in that order, so the
+    /// one fetched first wins.
+    Coordinate,
+    /// The sprite earlier in OAM, whatever the X, as on CGB.
+    OamIndex,
+}
+
+impl ObjectPriority {
+    pub fn from_opri(opri: u8) -> Self {
+        match opri & 1 {
+            0 => ObjectPriority::OamIndex,
+            _ => ObjectPriority::Coordinate,
+        }
+    }
+
+    /// Whether a pixel of the sprite being fetched replaces the one already in the FIFO. Transparent pixels never
+    /// replace, and are always replaced.
+    pub fn replaces(self, existing: &SpritePixel, color: u8, oam_addr: u16) -> bool {
+        color != 0 && (existing.color == 0 || self == ObjectPriority::OamIndex && oam_addr < existing.oam_addr)
+    }
+}
+
+#[derive(Clone, Default)]
 pub struct BgPixel {
     pub x: isize,
     pub color: u8,
 }
 
+#[derive(Clone)]
 pub struct PixelFetcher {
     dot_counter: usize,
     current_tile_map_line_addr: u16,
-    current_tile_index: u8,
+    /// The background tile being fetched, counted from the start of the line or of the window.
+    current_tile_x: u8,
     current_tile_row_offset: u8,
     state: PixelFetcherState,
     pub mode: PixelFetcherMode,
@@ -93,7 +126,7 @@ impl PixelFetcher {
         Self {
             dot_counter: 2,
             current_tile_map_line_addr: 0x9800,
-            current_tile_index: 0,
+            current_tile_x: 0,
             current_tile_row_offset: 0,
             state: GetTileId,
             mode: Background,
@@ -113,7 +146,8 @@ impl PixelFetcher {
         self.obj_fifo.clear();
     }
 
-    pub fn tick(&mut self, vram: &Vram, registers: &IoRegisters) {
+    /// Runs a dot of the fetcher. `is_window` is whether background tiles are fetched from the window.
+    pub fn tick(&mut self, vram: &Vram, registers: &IoRegisters, is_window: bool) {
         self.dot_counter -= 1;
         if self.dot_counter == 0 {
             self.dot_counter = 2;
@@ -124,7 +158,11 @@ impl PixelFetcher {
         match self.state {
             GetTileId => {
                 let tile_index = match self.mode {
-                    Background => vram.mem_read(self.current_tile_map_line_addr + self.current_tile_index as u16),
+                    Background => {
+                        let tile_column = self.locate_bg_tile(registers, is_window);
+
+                        vram.mem_read(self.current_tile_map_line_addr + tile_column as u16)
+                    }
                     Object { ref oam, .. } => {
                         vram.mem_read(oam.oam_addr + 2)
                     }
@@ -192,34 +230,31 @@ impl PixelFetcher {
                     }
                 };
 
-                let tile_byte_lo = vram.mem_read(tile_address);
-
+                // The row is decoded from both bytes at once in the next step, from the cache kept by `Vram`.
                 self.state = GetTileRowHigh {
-                    tile_byte_lo,
                     tile_address,
                     sprite_attributes,
                 };
             }
-            GetTileRowHigh { tile_byte_lo, tile_address, sprite_attributes } => {
-                let tile_byte_hi = vram.mem_read(tile_address + 1);
+            GetTileRowHigh { tile_address, sprite_attributes } => {
+                let pixels = vram.decoded_row(tile_address);
 
-                if matches!(self.mode, Background) && self.push_pixels(registers, tile_byte_lo, tile_byte_hi, sprite_attributes) {
+                if matches!(self.mode, Background) && self.push_pixels(registers, pixels, sprite_attributes) {
                     self.state = GetTileId;
-                    self.current_tile_index = (self.current_tile_index + 1) % 32;
+                    self.current_tile_x = (self.current_tile_x + 1) % 32;
 
                     return;
                 }
 
                 self.state = PushPixels {
-                    tile_byte_lo,
-                    tile_byte_hi,
+                    pixels,
                     sprite_attributes,
                 };
             }
-            PushPixels { tile_byte_lo, tile_byte_hi, sprite_attributes } => {
-                if self.push_pixels(registers, tile_byte_lo, tile_byte_hi, sprite_attributes) {
+            PushPixels { pixels, sprite_attributes } => {
+                if self.push_pixels(registers, pixels, sprite_attributes) {
                     if matches!(self.mode, Background) {
-                        self.current_tile_index = (self.current_tile_index + 1) % 32;
+                        self.current_tile_x = (self.current_tile_x + 1) % 32;
                     }
 
                     self.state = GetTileId;
@@ -229,56 +264,53 @@ impl PixelFetcher {
         }
     }
 
-    fn push_pixels(&mut self, registers: &IoRegisters, tile_byte_lo: u8, tile_byte_hi: u8, sprite_attributes: Option<u8>) -> bool {
-        if let Object { oam: Oam { x, .. }, sprite_offset } = self.mode {
+    fn push_pixels(&mut self, registers: &IoRegisters, pixels: [u8; 8], sprite_attributes: Option<u8>) -> bool {
+        if let Object { oam: Oam { x, oam_addr, .. }, sprite_offset } = self.mode {
             let attributes = sprite_attributes.unwrap();
+            let priority = ObjectPriority::from_opri(registers.opri);
 
             let mut insert_pixel = |color: u8, i: u8| {
                 let x = x as isize - 8 + i as isize;
 
                 let j = i - sprite_offset;
 
-                if self.obj_fifo.get(j as usize).is_some() {
-                    return;
-                }
+                let pixel = SpritePixel {
+                    x,
+                    color,
+                    bg_over_obj: attributes & (1 << 7) != 0,
+                    palette: if attributes & (1 << 4) == 0 {
+                        registers.obp0
+                    } else {
+                        registers.obp1
+                    },
+                    oam_addr,
+                };
 
-                self.obj_fifo.push_back(
-                    SpritePixel {
-                        x,
-                        color,
-                        bg_over_obj: attributes & (1 << 7) != 0,
-                        palette: if attributes & (1 << 4) == 0 {
-                            registers.obp0
-                        } else {
-                            registers.obp1
-                        },
-                    });
+                match self.obj_fifo.get_mut(j as usize) {
+                    Some(existing) if priority.replaces(existing, color, oam_addr) => *existing = pixel,
+                    Some(_) => {}
+                    None => self.obj_fifo.push_back(pixel),
+                }
             };
 
             let flip_sprite_h = attributes & (1 << 5) != 0;
-            if flip_sprite_h {
-                for i in sprite_offset..=7 {
-                    let pixel = (((tile_byte_hi >> i) & 1) << 1) | (tile_byte_lo >> i & 1);
-
-                    insert_pixel(pixel, i);
-                }
-            } else {
-                for i in sprite_offset..=7 {
-                    let pixel = (((tile_byte_hi >> (7 - i)) & 1) << 1) | (tile_byte_lo >> (7 - i) & 1);
+            for i in sprite_offset..=7 {
+                let pixel = if flip_sprite_h {
+                    pixels[7 - i as usize]
+                } else {
+                    pixels[i as usize]
+                };
 
-                    insert_pixel(pixel, i);
-                }
+                insert_pixel(pixel, i);
             }
 
             return true;
         }
 
         if self.is_empty() {
-            for i in 0..=7 {
-                let color = (((tile_byte_hi >> (7 - i)) & 1) << 1) | (tile_byte_lo >> (7 - i) & 1);
-
+            for (i, color) in pixels.into_iter().enumerate() {
                 let x = if let Background = self.mode {
-                    self.current_tile_index * 8
+                    self.current_tile_x * 8
                 } else { 0 };
 
                 self.bg_fifo.push_back(BgPixel {
@@ -293,17 +325,38 @@ impl PixelFetcher {
         return false;
     }
 
-    pub fn fetch_bg_tile(&mut self, tile_map_line_addr: u16, tile_x: u8, tile_row_offset: u8) {
+    /// Starts fetching background tiles from `tile_x`, the first tile of the line or window to fetch.
+    pub fn fetch_bg_tile(&mut self, tile_x: u8) {
         self.dot_counter = 2;
-        self.current_tile_map_line_addr = tile_map_line_addr;
-        self.current_tile_index = tile_x;
-        self.current_tile_row_offset = tile_row_offset;
+        self.current_tile_x = tile_x;
         self.state = GetTileId;
         self.mode = Background;
 
         self.bg_fifo.clear();
     }
 
+    /// Finds the tile map row and the row within the tile of the current tile, returning its column in the tile map.
+    ///
+    /// SCX and SCY are read again for every tile, as the hardware does, so writing them mid-line scrolls the rest of
+    /// the line from the next tile fetched.
+    fn locate_bg_tile(&mut self, registers: &IoRegisters, is_window: bool) -> u8 {
+        let (tilemap_area, line, tile_column) = match is_window {
+            true => (LCDControl::WINDOW_TILEMAP_AREA, registers.window_ly, self.current_tile_x),
+            false => (
+                LCDControl::BG_TILEMAP_AREA,
+                registers.ly.wrapping_add(registers.scy),
+                (registers.scx / 8 + self.current_tile_x) % 32,
+            ),
+        };
+
+        let bit_10: u16 = if registers.lcdc.contains(tilemap_area) { 1 } else { 0 };
+
+        self.current_tile_map_line_addr = 0b1001_1000_0000_0000 | (bit_10 << 10) | (line as u16 / 8) << 5;
+        self.current_tile_row_offset = line % 8;
+
+        tile_column
+    }
+
     pub fn fetch_obj_tile(&mut self, oam: Oam, sprite_offset: u8) {
         self.state = GetTileId;
         self.mode = Object {
@@ -312,3 +365,73 @@ impl PixelFetcher {
         };
     }
 }
+
+// Variants are saved as their position in the enum, followed by their fields.
+impl State for PixelFetcherState {
+    fn save(&self, out: &mut Vec<u8>) {
+        match self {
+            GetTileId => 0u8.save(out),
+            GetSpriteAttributes { tile_index } => {
+                1u8.save(out);
+                tile_index.save(out);
+            }
+            GetTileRowLow { sprite_attributes, tile_index } => {
+                2u8.save(out);
+                sprite_attributes.save(out);
+                tile_index.save(out);
+            }
+            GetTileRowHigh { sprite_attributes, tile_address } => {
+                3u8.save(out);
+                sprite_attributes.save(out);
+                tile_address.save(out);
+            }
+            PushPixels { sprite_attributes, pixels } => {
+                4u8.save(out);
+                sprite_attributes.save(out);
+                pixels.save(out);
+            }
+        }
+    }
+
+    fn load(&mut self, input: &mut StateReader) -> Result<(), CoreError> {
+        *self = match read::<u8>(input)? {
+            0 => GetTileId,
+            1 => GetSpriteAttributes { tile_index: read(input)? },
+            2 => GetTileRowLow { sprite_attributes: read(input)?, tile_index: read(input)? },
+            3 => GetTileRowHigh { sprite_attributes: read(input)?, tile_address: read(input)? },
+            4 => PushPixels { sprite_attributes: read(input)?, pixels: read(input)? },
+            _ => return Err(CoreError::InvalidState("a value is out of range")),
+        };
+
+        Ok(())
+    }
+}
+
+impl State for PixelFetcherMode {
+    fn save(&self, out: &mut Vec<u8>) {
+        match self {
+            Background => 0u8.save(out),
+            Object { oam, sprite_offset } => {
+                1u8.save(out);
+                oam.save(out);
+                sprite_offset.save(out);
+            }
+        }
+    }
+
+    fn load(&mut self, input: &mut StateReader) -> Result<(), CoreError> {
+        *self = match read::<u8>(input)? {
+            0 => Background,
+            1 => Object { oam: read(input)?, sprite_offset: read(input)? },
+            _ => return Err(CoreError::InvalidState("a value is out of range")),
+        };
+
+        Ok(())
+    }
+}
+
+state_fields!(SpritePixel { x, color, palette, bg_over_obj, oam_addr });
+state_fields!(BgPixel { x, color });
+state_fields!(PixelFetcher {
+    dot_counter, current_tile_map_line_addr, current_tile_x, current_tile_row_offset, state, mode, bg_fifo, obj_fifo,
+});
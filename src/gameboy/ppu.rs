@@ -7,12 +7,48 @@ use super::{
     pixel_fetcher::PixelFetcher,
     pixel_fetcher::PixelFetcherMode::{Object},
     ppu::PpuMode::{PixelTransfer, HBlank, OamLookup, VBlank},
+    state::{state_fields, State, StateReader},
+    CoreError,
     Mem,
 };
 
 const VRAM_BASE_ADDR: u16 = 0x8000;
 const OAM_BASE_ADDR: u16 = 0xfe00;
 
+// Tile data takes 0x8000-0x97ff, 384 tiles of 8 rows, each row being 2 bytes.
+const TILE_ROW_COUNT: usize = 384 * 8;
+
+/// Entries in OAM, see `GameBoy::oam_entries`.
+pub const OAM_ENTRY_COUNT: usize = 40;
+pub(crate) const SPRITES_PER_LINE: usize = 10;
+
+bitflags! {
+    /// The attribute byte of an OAM entry. Bits 0-3 select the VRAM bank and palette on CGB, and are kept as they
+    /// are.
+    #[derive(Default, Copy, Clone, PartialEq, Eq, Debug)]
+    pub struct OamAttributes : u8 {
+        const DMG_PALETTE = 1 << 4; // 0=OBP0, 1=OBP1
+        const X_FLIP = 1 << 5;
+        const Y_FLIP = 1 << 6;
+        const BG_OVER_OBJ = 1 << 7;
+    }
+}
+
+/// An OAM entry as the game wrote it, see `GameBoy::oam_entries`.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct OamEntry {
+    /// The screen position of the top row plus 16, so that objects can be partly above the screen.
+    pub y: u8,
+    /// The screen position of the left column plus 8.
+    pub x: u8,
+    pub tile: u8,
+    pub attributes: OamAttributes,
+}
+
+/// The color indices of a tile, 0 to 3, by row and then column, see `GameBoy::tile`.
+pub type Tile = [[u8; 8]; 8];
+
+#[derive(Clone, Default)]
 pub struct Oam {
     pub y: u8,
     pub x: u8,
@@ -22,6 +58,8 @@ pub struct Oam {
 pub struct Vram {
     pub vram: [u8; 0x2000],
     pub oam: [u8; 0xa0],
+    /// Tile data decoded into color indices, a row of 8 pixels for every 2 bytes. Kept up to date by `mem_write`.
+    tile_rows: [[u8; 8]; TILE_ROW_COUNT],
 }
 
 impl Vram {
@@ -29,8 +67,95 @@ impl Vram {
         Self {
             vram: [0; 0x2000],
             oam: [0; 0xa0],
+            tile_rows: [[0; 8]; TILE_ROW_COUNT],
+        }
+    }
+
+    /// Decodes a row of a tile using the 0x8000 addressing mode (always used by objects) into 8 color indices.
+    pub fn tile_row(&self, tile_index: u8, row: u8) -> [u8; 8] {
+        self.decoded_row(VRAM_BASE_ADDR | (tile_index as u16) << 4 | (row as u16 & 0x7) << 1)
+    }
+
+    /// Decodes tile `index` of the 384 in tile data, counting from 0x8000.
+    pub fn tile(&self, index: u16) -> Tile {
+        let first_row = index as usize * 8;
+
+        self.tile_rows[first_row..first_row + 8].try_into().unwrap()
+    }
+
+    pub fn oam_entry(&self, index: usize) -> OamEntry {
+        let [y, x, tile, attributes] = self.oam[index * 4..index * 4 + 4].try_into().unwrap();
+
+        OamEntry {
+            y,
+            x,
+            tile,
+            attributes: OamAttributes::from_bits_retain(attributes),
+        }
+    }
+
+    /// The color indices of the tile row starting at `addr`, leftmost pixel first.
+    pub fn decoded_row(&self, addr: u16) -> [u8; 8] {
+        self.tile_rows[(addr - VRAM_BASE_ADDR) as usize >> 1]
+    }
+
+    fn decode_row(&mut self, addr: u16) {
+        let index = (addr - VRAM_BASE_ADDR) as usize & !1;
+
+        let tile_byte_lo = self.vram[index];
+        let tile_byte_hi = self.vram[index + 1];
+
+        for (i, pixel) in self.tile_rows[index >> 1].iter_mut().enumerate() {
+            *pixel = (((tile_byte_hi >> (7 - i)) & 1) << 1) | (tile_byte_lo >> (7 - i) & 1);
         }
     }
+
+    /// Applies the DMG OAM corruption bug to `row`, one of the 20 rows of 8 bytes the PPU reads during OAM scan.
+    /// The first word of the row is replaced by a mix of its value and the preceding row, and the remaining three words
+    /// are copied from the preceding row. The first row is never corrupted.
+    ///
+    /// See https://gbdev.io/pandocs/OAM_Corruption_Bug.html
+    pub fn corrupt_oam_row(&mut self, row: usize, access: OamCorruption) {
+        if row == 0 || row >= OAM_ENTRY_COUNT / 2 {
+            return;
+        }
+
+        let word = |oam: &[u8; 0xa0], row: usize, index: usize| u16::from_le_bytes([oam[row * 8 + index * 2], oam[row * 8 + index * 2 + 1]]);
+
+        let a = word(&self.oam, row, 0);
+        let b = word(&self.oam, row - 1, 0);
+        let c = word(&self.oam, row - 1, 2);
+
+        let first = match access {
+            OamCorruption::Write => ((a ^ c) & (b ^ c)) ^ c,
+            OamCorruption::Read => b | (a & c),
+        };
+
+        self.oam[row * 8..row * 8 + 2].copy_from_slice(&first.to_le_bytes());
+        self.oam.copy_within((row - 1) * 8 + 2..row * 8, row * 8 + 2);
+    }
+}
+
+#[derive(Clone, Copy)]
+pub enum OamCorruption {
+    Read,
+    /// Also caused by 16-bit increments and decrements of a register holding an OAM address.
+    Write,
+}
+
+/// Whether an object at the given OAM coordinates is visible on scanline `ly`.
+pub(crate) fn is_sprite_on_line(ly: u8, sprite_y: u8, sprite_x: u8, sprite_height: u8) -> bool {
+    let line = ly as u16 + 16;
+
+    sprite_x != 0 && line >= sprite_y as u16 && line < sprite_y as u16 + sprite_height as u16
+}
+
+/// Indices of the OAM entries selected for scanline `ly`, in OAM order, up to the 10 sprites per line limit.
+pub(crate) fn select_line_sprites(oam: &[u8; 0xa0], ly: u8, sprite_height: u8) -> Vec<usize> {
+    (0..OAM_ENTRY_COUNT)
+        .filter(|&i| is_sprite_on_line(ly, oam[i * 4], oam[i * 4 + 1], sprite_height))
+        .take(SPRITES_PER_LINE)
+        .collect()
 }
 
 impl Mem for Vram {
@@ -44,7 +169,12 @@ impl Mem for Vram {
 
     fn mem_write(&mut self, addr: u16, value: u8) {
         match addr {
-            0x8000..=0x9fff => {
+            0x8000..=0x97ff => {
+                self.vram[(addr - 0x8000) as usize] = value;
+
+                self.decode_row(addr);
+            }
+            0x9800..=0x9fff => {
                 self.vram[(addr - 0x8000) as usize] = value;
             }
             0xfe00..=0xfe9f => self.oam[(addr - 0xfe00) as usize] = value,
@@ -53,6 +183,17 @@ impl Mem for Vram {
     }
 }
 
+/// Points in the frame reported by `Ppu::tick`.
+#[derive(Clone, Copy)]
+pub enum PpuEvent {
+    /// The PPU finished drawing the given line.
+    HBlank(u8),
+    /// The PPU finished drawing the last line, so the screen is complete.
+    VBlank,
+    /// The frame, including VBlank, is over and the PPU starts the next one.
+    FrameCompleted,
+}
+
 #[derive(Clone, Copy)]
 #[repr(u8)]
 enum PpuMode {
@@ -74,33 +215,126 @@ impl From<u8> for PpuMode {
     }
 }
 
+/// What drawing a line took, to find lines that are slow to draw or hit the sprite limit, see `GameBoy::line_stats`.
+#[derive(Clone, Copy, Default)]
+pub struct LineStats {
+    /// Length of pixel transfer in dots, from 172 up, longer with every sprite fetch and window start.
+    pub transfer_dots: u16,
+    /// Sprites found during OAM scan, at most 10.
+    pub sprites: u8,
+    /// Whether more sprites were on the line than the 10 that are drawn.
+    pub sprite_limit_hit: bool,
+    pub window: bool,
+}
+
 pub struct Ppu {
     pub dot_counter: usize,
+    /// Frames drawn since power on, counted when VBlank starts.
+    pub frame_counter: u64,
     pub vram: Vram,
     sprites: Vec<Oam>,
     pub screen: [u8; SCREEN_WIDTH * SCREEN_HEIGHT],
+    /// The screen of the previous frame, kept when drawing the next one starts.
+    pub previous_screen: [u8; SCREEN_WIDTH * SCREEN_HEIGHT],
     screen_x: u8,
-    skipped_pixels: u8,
+    /// The pixels of the first tile still to discard for SCX's fine scroll, latched when pixel transfer starts.
+    pixels_to_skip: u8,
     pixel_fetcher: PixelFetcher,
     is_window: bool,
+    line_ahead: Option<LineAhead>,
+    /// Statistics of every line drawn so far in the frame, cleared when it starts.
+    pub line_stats: [LineStats; SCREEN_HEIGHT],
+}
+
+/// A line drawn in one pass when pixel transfer started, see `Ppu::draw_line_ahead`.
+#[derive(Default)]
+struct LineAhead {
+    /// The dot pixel transfer ends at.
+    transfer_end: usize,
+    start: LineStart,
+}
+
+/// The state pixel transfer started from, to draw the line again dot by dot.
+struct LineStart {
+    registers: DrawingRegisters,
+    sprites: Vec<Oam>,
+    is_window: bool,
+    /// The line's pixels from the previous frame, which are kept until drawn over.
+    pixels: [u8; SCREEN_WIDTH],
+}
+
+/// The registers tracked by `IoRegisters::drawing_registers_written`.
+#[derive(Default)]
+struct DrawingRegisters {
+    lcdc: LCDControl,
+    scy: u8,
+    scx: u8,
+    bgp: u8,
+    obp0: u8,
+    obp1: u8,
+    wy: u8,
+    wx: u8,
+}
+
+impl DrawingRegisters {
+    fn from_registers(registers: &IoRegisters) -> Self {
+        Self {
+            lcdc: registers.lcdc,
+            scy: registers.scy,
+            scx: registers.scx,
+            bgp: registers.bgp,
+            obp0: registers.obp0,
+            obp1: registers.obp1,
+            wy: registers.wy,
+            wx: registers.wx,
+        }
+    }
+
+    fn swap(&mut self, registers: &mut IoRegisters) {
+        std::mem::swap(&mut self.lcdc, &mut registers.lcdc);
+        std::mem::swap(&mut self.scy, &mut registers.scy);
+        std::mem::swap(&mut self.scx, &mut registers.scx);
+        std::mem::swap(&mut self.bgp, &mut registers.bgp);
+        std::mem::swap(&mut self.obp0, &mut registers.obp0);
+        std::mem::swap(&mut self.obp1, &mut registers.obp1);
+        std::mem::swap(&mut self.wy, &mut registers.wy);
+        std::mem::swap(&mut self.wx, &mut registers.wx);
+    }
 }
 
 impl Ppu {
     pub fn new() -> Ppu {
         Ppu {
             dot_counter: 0,
+            frame_counter: 0,
             vram: Vram::new(),
-            sprites: Vec::with_capacity(10),
+            sprites: Vec::with_capacity(SPRITES_PER_LINE),
             screen: [0; SCREEN_WIDTH * SCREEN_HEIGHT],
+            previous_screen: [0; SCREEN_WIDTH * SCREEN_HEIGHT],
             screen_x: 0,
-            skipped_pixels: 0,
+            pixels_to_skip: 0,
             pixel_fetcher: PixelFetcher::new(),
             is_window: false,
+            line_ahead: None,
+            line_stats: [LineStats::default(); SCREEN_HEIGHT],
         }
     }
 
-    pub fn tick(&mut self, registers: &mut IoRegisters) -> bool {
-        let mut result = false;
+    /// The row of OAM being read by the PPU, if it is currently scanning OAM.
+    pub fn oam_scan_row(&self, registers: &IoRegisters) -> Option<usize> {
+        let scanning = registers.lcdc.contains(LCDControl::LCD_PPU_ENABLE) && matches!(PpuMode::from(registers.stat & 0b0000_0011), OamLookup);
+
+        scanning.then(|| (self.dot_counter % 456) / 4)
+    }
+
+    /// Whether the CPU can access OAM, which the PPU owns during OAM scan and pixel transfer while the LCD is on.
+    pub fn oam_accessible(&self, registers: &IoRegisters) -> bool {
+        !registers.lcdc.contains(LCDControl::LCD_PPU_ENABLE) || matches!(PpuMode::from(registers.stat & 0b0000_0011), HBlank | VBlank)
+    }
+
+    /// Advances the PPU by a dot. Unless `dot_accurate`, lines are drawn in one pass when possible.
+    pub fn tick(&mut self, registers: &mut IoRegisters, dot_accurate: bool) -> Option<PpuEvent> {
+        let mut result = None;
 
         let lcd_enable = registers.lcdc.contains(LCDControl::LCD_PPU_ENABLE);
 
@@ -108,18 +342,38 @@ impl Ppu {
             registers.stat = registers.stat & 0b1111_1000;
         }
 
-        if let Some(mode) = self.handle_step(registers, lcd_enable) {
+        if let Some(mode) = self.handle_step(registers, lcd_enable, dot_accurate) {
+            let prev_mode = registers.stat & 0b0000_0011;
+
             registers.stat = (registers.stat & 0b1111_1100) | (mode as u8 & 0b0000_0011);
+
+            if lcd_enable && prev_mode != mode as u8 {
+                result = match mode {
+                    HBlank => Some(PpuEvent::HBlank(registers.ly)),
+                    VBlank => {
+                        self.frame_counter += 1;
+
+                        Some(PpuEvent::VBlank)
+                    }
+                    _ => None,
+                };
+            }
         };
 
         if lcd_enable {
             self.dot_counter += 1;
         }
 
+        if registers.event_log.enabled {
+            registers.event_log.dot = (self.dot_counter % 456) as u16;
+        }
+
         if self.dot_counter == 70224 {
             self.dot_counter = 0;
 
-            result = true;
+            result = Some(PpuEvent::FrameCompleted);
+
+            registers.event_log.frame += 1;
 
             registers.ly = 0;
             registers.window_ly = 0;
@@ -132,7 +386,7 @@ impl Ppu {
         return result;
     }
 
-    fn handle_step(&mut self, registers: &mut IoRegisters, lcd_enable: bool) -> Option<PpuMode> {
+    fn handle_step(&mut self, registers: &mut IoRegisters, lcd_enable: bool, dot_accurate: bool) -> Option<PpuMode> {
         if registers.lyc == registers.ly {
             registers.stat = registers.stat | (1 << 2);
         } else {
@@ -143,11 +397,12 @@ impl Ppu {
 
         let line_dot = self.dot_counter % 456;
 
-        let bg_enable = registers.lcdc.contains(LCDControl::BG_WINDOW_ENABLE);
-        let sprites_enable = registers.lcdc.contains(LCDControl::OBJ_ENABLE);
-        let window_enable = registers.lcdc.contains(LCDControl::WINDOW_ENABLE) && registers.wx < 167 && registers.wy < SCREEN_HEIGHT as u8;
+        let is_window_scanline = Self::is_window_scanline(registers);
 
-        let is_window_scanline = window_enable && registers.ly >= registers.wy;
+        // Pixel transfer is also cut short by writing STAT, which clears the mode, or by turning off the LCD.
+        if self.line_ahead.is_some() && (registers.drawing_registers_written || !matches!(mode, PixelTransfer)) {
+            self.redraw_line_until(registers, line_dot);
+        }
 
         match mode {
             HBlank => {
@@ -166,22 +421,20 @@ impl Ppu {
                         mode = VBlank;
 
                         if lcd_enable {
-                            registers.interrupt_flag.insert(InterruptFlags::VBLANK);
+                            registers.request_interrupt(InterruptFlags::VBLANK);
 
                             // According to The Cycle-Accurate Game Boy Docs, OAM bit also triggers the interrupt on VBlank.
                             if registers.stat & (1 << 4) != 0 || registers.stat & (1 << 5) != 0 {
-                                registers.interrupt_flag.insert(InterruptFlags::LCD_STAT);
+                                registers.request_interrupt(InterruptFlags::LCD_STAT);
                             }
                         }
                     } else if registers.ly < SCREEN_HEIGHT as u8 {
                         mode = OamLookup;
 
-                        self.skipped_pixels = 0;
-
                         self.sprites.clear();
 
                         if lcd_enable && registers.stat & (1 << 5) != 0 {
-                            registers.interrupt_flag.insert(InterruptFlags::LCD_STAT);
+                            registers.request_interrupt(InterruptFlags::LCD_STAT);
                         }
                     }
                 }
@@ -190,10 +443,12 @@ impl Ppu {
                 if self.dot_counter == 0 {
                     mode = OamLookup;
 
-                    self.skipped_pixels = 0;
-
                     self.sprites.clear();
 
+                    self.line_stats = [LineStats::default(); SCREEN_HEIGHT];
+
+                    self.previous_screen = self.screen;
+
                     Self::set_lyc_interrupt(registers);
                 }
 
@@ -205,126 +460,249 @@ impl Ppu {
                 if line_dot == 80 {
                     mode = PixelTransfer;
 
-                    self.screen_x = 0;
-
-                    self.pixel_fetcher.clear();
-                    self.fetch_bg_pixels(registers, false);
+                    self.line_stats[registers.ly as usize].sprites = self.sprites.len() as u8;
 
+                    // Sprites are fetched as pixel transfer reaches them, and `ObjectPriority` decides overlaps.
                     self.sprites.sort_by(|a, b| match a.x.cmp(&b.x) {
                         Ordering::Equal => a.oam_addr.cmp(&b.oam_addr),
                         ord => ord
                     });
+
+                    self.start_pixel_transfer(registers);
+
+                    if !dot_accurate {
+                        self.draw_line_ahead(registers);
+                    }
                 }
             }
             PixelTransfer => {
-                self.pixel_fetcher.tick(&self.vram, &registers);
-
-                if bg_enable {
-                    if !self.is_window && window_enable && is_window_scanline && self.screen_x + 7 >= registers.wx {
-                        self.is_window = true;
-
-                        self.fetch_bg_pixels(registers, true);
-                        
-                        return None;
+                // According to mooneye-gb, the HBlank interrupt is requested a dot before the mode switches, as the
+                // last pixel is about to shift out.
+                // https://github.com/wilbertpol/mooneye-gb/blob/b78dd21f0b6d00513bdeab20f7950e897a0379b3/src/hardware/gpu/mod.rs#L391
+                let (line_done, hblank_requested, hblank_next_dot) = match &self.line_ahead {
+                    Some(line) => (line_dot == line.transfer_end, true, line_dot + 1 == line.transfer_end),
+                    None => {
+                        // Unless the registers were written since, this was already known a dot ago.
+                        let hblank_requested = self.last_pixel_next(registers);
+                        let line_done = self.transfer_pixel(registers);
+
+                        (line_done, hblank_requested, !line_done && self.last_pixel_next(registers))
                     }
+                };
 
-                    if self.pixel_fetcher.is_empty() {
-                        return None;
-                    }
+                // It is only requested as the mode switches when a register written meanwhile ended the line early.
+                if hblank_next_dot || line_done && !hblank_requested {
+                    Self::set_hblank_interrupt(registers, lcd_enable);
+                }
 
-                    if self.skipped_pixels < registers.scx & 0x7 {
-                        self.pixel_fetcher.bg_fifo.pop_front();
-                        self.pixel_fetcher.obj_fifo.pop_front();
+                if line_done {
+                    mode = HBlank;
 
-                        self.skipped_pixels += 1;
+                    self.line_ahead = None;
 
-                        return None;
-                    }
+                    let stats = &mut self.line_stats[registers.ly as usize];
+                    stats.transfer_dots = (line_dot - 80) as u16;
+                    stats.window = is_window_scanline && registers.lcdc.contains(LCDControl::BG_WINDOW_ENABLE);
                 }
+            }
+        }
 
-                if sprites_enable {
-                    if matches!(self.pixel_fetcher.mode, Object {..}) {
-                        return None;
-                    }
+        Some(mode)
+    }
 
-                    if let Some(index) = self.sprites.iter().position(|s| self.screen_x + 8 == s.x || self.screen_x == 0 && s.x < 8) {
-                        let &Oam { x: sprite_x, .. } = self.sprites.get(index).unwrap();
+    fn is_window_scanline(registers: &IoRegisters) -> bool {
+        let window_enable = registers.lcdc.contains(LCDControl::WINDOW_ENABLE) && registers.wx < 167 && registers.wy < SCREEN_HEIGHT as u8;
 
-                        if self.screen_x == 0 && sprite_x < 8 {
-                            let sprite = self.sprites.remove(index);
+        window_enable && registers.ly >= registers.wy
+    }
 
-                            let sprite_offset = 8 - sprite_x;
-                            self.pixel_fetcher.fetch_obj_tile(sprite, sprite_offset);
+    fn start_pixel_transfer(&mut self, registers: &IoRegisters) {
+        self.screen_x = 0;
+        self.pixels_to_skip = registers.scx & 0x7;
 
-                            return None;
-                        } else if self.screen_x + 8 == sprite_x {
-                            let sprite = self.sprites.remove(index);
+        self.pixel_fetcher.clear();
+        self.fetch_bg_pixels(registers, false);
+    }
 
-                            self.pixel_fetcher.fetch_obj_tile(sprite, 0);
+    /// Draws the whole line as soon as pixel transfer starts, after which the PPU only waits for the dot it would have
+    /// finished on, so pixels and interrupts are the same as when drawing dot by dot.
+    ///
+    /// This relies on the registers the PPU reads while drawing not being written until the line is done. When they
+    /// are, or pixel transfer ends early, `redraw_line_until` draws the line again from its start, and the rest of the
+    /// line is drawn dot by dot. VRAM and OAM writes aren't tracked, since the hardware blocks the CPU from accessing
+    /// them during pixel transfer.
+    fn draw_line_ahead(&mut self, registers: &mut IoRegisters) {
+        let start = LineStart {
+            registers: DrawingRegisters::from_registers(registers),
+            sprites: self.sprites.clone(),
+            is_window: self.is_window,
+            pixels: self.screen_line(registers.ly).try_into().unwrap(),
+        };
 
-                            return None;
-                        }
-                    }
-                }
+        registers.drawing_registers_written = false;
 
-                let bg_pixel = self.pixel_fetcher.bg_fifo.pop_front();
-                let sprite_pixel = self.pixel_fetcher.obj_fifo.pop_front();
-
-                let mut pixel = 0;
-                let mut palette = registers.bgp;
-
-                match (bg_pixel, sprite_pixel) {
-                    (Some(bg_pixel), Some(sprite_pixel)) => {
-                        if !bg_enable {
-                            pixel = sprite_pixel.color;
-                            palette = sprite_pixel.palette;
-                        } else if sprites_enable {
-                            if sprite_pixel.bg_over_obj && bg_pixel.color != 0 || sprite_pixel.color == 0 {
-                                pixel = bg_pixel.color;
-                            } else {
-                                pixel = sprite_pixel.color;
-                                palette = sprite_pixel.palette;
-                            }
-                        }
-                    }
-                    (Some(bg_pixel), _) => {
-                        if bg_enable {
-                            pixel = bg_pixel.color;
-                        }
-                    }
-                    _ => pixel = 0,
-                }
+        // Pixel transfer takes at most 289 dots, but should it not end within the line, it is left to be drawn dot by dot.
+        match (81..456).find(|_| self.transfer_pixel(registers)) {
+            Some(transfer_end) => self.line_ahead = Some(LineAhead { transfer_end, start }),
+            None => {
+                self.line_ahead = Some(LineAhead { transfer_end: 0, start });
 
-                if self.screen_x < SCREEN_WIDTH as u8 && registers.ly < SCREEN_HEIGHT as u8 {
-                    let color = (palette >> (pixel * 2)) & 0b0000_0011;
+                self.redraw_line_until(registers, 81);
+            }
+        }
+    }
 
-                    self.screen[registers.ly as usize * SCREEN_WIDTH + self.screen_x as usize] = color;
+    /// Draws the line drawn ahead again, dot by dot up to `line_dot`, with the registers as they were when it started.
+    fn redraw_line_until(&mut self, registers: &mut IoRegisters, line_dot: usize) {
+        let Some(LineAhead { start: LineStart { registers: mut written, sprites, is_window, pixels }, .. }) = self.line_ahead.take() else {
+            return;
+        };
 
-                    self.screen_x = (self.screen_x + 1) % SCREEN_WIDTH as u8;
+        written.swap(registers);
 
-                    if self.screen_x == 0 {
-                        mode = HBlank;
-                        
-                        self.is_window = false;
+        self.screen_line(registers.ly).copy_from_slice(&pixels);
 
-                        // TODO: According to mooneye-gb, HBLANK interrupt occurs one cycle before mode switch
-                        // https://github.com/wilbertpol/mooneye-gb/blob/b78dd21f0b6d00513bdeab20f7950e897a0379b3/src/hardware/gpu/mod.rs#L391
-                        if lcd_enable && registers.stat & (1 << 3) != 0 {
-                            registers.interrupt_flag.insert(InterruptFlags::LCD_STAT);
-                        }
+        self.sprites = sprites;
+        self.is_window = is_window;
 
-                        // result = true;
-                    }
+        self.start_pixel_transfer(registers);
+
+        for _ in 81..line_dot {
+            self.transfer_pixel(registers);
+        }
+
+        written.swap(registers);
+    }
+
+    fn screen_line(&mut self, ly: u8) -> &mut [u8] {
+        let start = (ly as usize % SCREEN_HEIGHT) * SCREEN_WIDTH;
+
+        &mut self.screen[start..start + SCREEN_WIDTH]
+    }
+
+    /// Runs a dot of pixel transfer, returning whether the line is done.
+    fn transfer_pixel(&mut self, registers: &IoRegisters) -> bool {
+        let bg_enable = registers.lcdc.contains(LCDControl::BG_WINDOW_ENABLE);
+        let sprites_enable = registers.lcdc.contains(LCDControl::OBJ_ENABLE);
+
+        let is_window_scanline = Self::is_window_scanline(registers);
+
+        self.pixel_fetcher.tick(&self.vram, registers, self.is_window);
+
+        // The window is ignored while BG_WINDOW_ENABLE is clear, so it isn't fetched. The background still is, and
+        // shifts out as usual, only it isn't drawn.
+        if bg_enable && !self.is_window && is_window_scanline && self.screen_x + 7 >= registers.wx {
+            self.is_window = true;
+
+            self.fetch_bg_pixels(registers, true);
+
+            return false;
+        }
+
+        if self.pixel_fetcher.is_empty() {
+            return false;
+        }
+
+        if self.pixels_to_skip > 0 {
+            self.pixel_fetcher.bg_fifo.pop_front();
+            self.pixel_fetcher.obj_fifo.pop_front();
+
+            self.pixels_to_skip -= 1;
+
+            return false;
+        }
+
+        // A sprite fetch that started goes on if OBJ_ENABLE is cleared meanwhile, so its pixels still line up with the
+        // background when it is set again.
+        if matches!(self.pixel_fetcher.mode, Object {..}) {
+            return false;
+        }
+
+        // Sprites reached while OBJ_ENABLE is clear aren't fetched.
+        if sprites_enable {
+            if let Some(index) = self.sprites.iter().position(|s| self.screen_x + 8 == s.x || self.screen_x == 0 && s.x < 8) {
+                let &Oam { x: sprite_x, .. } = self.sprites.get(index).unwrap();
+
+                if self.screen_x == 0 && sprite_x < 8 {
+                    let sprite = self.sprites.remove(index);
+
+                    let sprite_offset = 8 - sprite_x;
+                    self.pixel_fetcher.fetch_obj_tile(sprite, sprite_offset);
+
+                    return false;
+                } else if self.screen_x + 8 == sprite_x {
+                    let sprite = self.sprites.remove(index);
+
+                    self.pixel_fetcher.fetch_obj_tile(sprite, 0);
+
+                    return false;
                 }
             }
         }
 
-        Some(mode)
+        // Both FIFOs shift out together whatever OBJ_ENABLE is, but a sprite pixel shifted out while it is clear is
+        // ignored, and the background is drawn instead. Sprite pixels still queued show once it is set again.
+        //
+        // While BG_WINDOW_ENABLE is clear, the background is white whatever BGP is, and sprites show over it whatever
+        // their priority.
+        // https://gbdev.io/pandocs/LCDC.html#lcdc0--bg-and-window-enablepriority
+        let bg_pixel = self.pixel_fetcher.bg_fifo.pop_front().filter(|_| bg_enable);
+        let sprite_pixel = self.pixel_fetcher.obj_fifo.pop_front().filter(|_| sprites_enable);
+
+        let bg_color = bg_pixel.as_ref().map_or(0, |bg_pixel| bg_pixel.color);
+
+        let color = match sprite_pixel {
+            Some(sprite_pixel) if sprite_pixel.color != 0 && !(sprite_pixel.bg_over_obj && bg_color != 0) => {
+                (sprite_pixel.palette >> (sprite_pixel.color * 2)) & 0b0000_0011
+            }
+            _ if bg_pixel.is_some() => (registers.bgp >> (bg_color * 2)) & 0b0000_0011,
+            _ => 0,
+        };
+
+        if self.screen_x < SCREEN_WIDTH as u8 && registers.ly < SCREEN_HEIGHT as u8 {
+            self.screen[registers.ly as usize * SCREEN_WIDTH + self.screen_x as usize] = color;
+
+            self.screen_x = (self.screen_x + 1) % SCREEN_WIDTH as u8;
+
+            if self.screen_x == 0 {
+                self.is_window = false;
+
+                return true;
+            }
+        }
+
+        false
+    }
+
+    /// Whether the next dot shifts out the last pixel of the line, unless the registers are written meanwhile.
+    fn last_pixel_next(&self, registers: &IoRegisters) -> bool {
+        if self.screen_x != SCREEN_WIDTH as u8 - 1 {
+            return false;
+        }
+
+        // The fetcher runs before the pixel shifts out, and may finish a sprite or refill the FIFO, so a copy of it
+        // is run to know whether the pixel can.
+        let mut pixel_fetcher = self.pixel_fetcher.clone();
+        pixel_fetcher.tick(&self.vram, registers, self.is_window);
+
+        let window_next = registers.lcdc.contains(LCDControl::BG_WINDOW_ENABLE) && !self.is_window
+            && Self::is_window_scanline(registers) && self.screen_x + 7 >= registers.wx;
+        let sprite_next = registers.lcdc.contains(LCDControl::OBJ_ENABLE) && self.sprites.iter().any(|s| self.screen_x + 8 == s.x);
+
+        !window_next && !pixel_fetcher.is_empty() && !matches!(pixel_fetcher.mode, Object {..}) && !sprite_next
+    }
+
+    /// Requests the HBlank interrupt, unless another source already holds the STAT line high. Only LYC can while
+    /// drawing.
+    fn set_hblank_interrupt(registers: &mut IoRegisters, lcd_enable: bool) {
+        if lcd_enable && registers.stat & (1 << 3) != 0 && !registers.stat_line_high() {
+            registers.request_interrupt(InterruptFlags::LCD_STAT);
+        }
     }
 
     fn set_lyc_interrupt(registers: &mut IoRegisters) {
         if registers.stat & (1 << 2) != 0 && registers.stat & (1 << 6) != 0 {
-            registers.interrupt_flag.insert(InterruptFlags::LCD_STAT);
+            registers.request_interrupt(InterruptFlags::LCD_STAT);
         }
     }
 
@@ -333,10 +711,6 @@ impl Ppu {
             return;
         }
 
-        if self.sprites.len() == self.sprites.capacity() {
-            return;
-        }
-
         let ly = registers.ly;
 
         let sprite_16 = registers.lcdc.contains(LCDControl::OBJ_SIZE);
@@ -350,7 +724,13 @@ impl Ppu {
         let sprite_y = self.vram.mem_read(oam_addr);
         let sprite_x = self.vram.mem_read(oam_addr + 1);
 
-        if sprite_x != 0 && ly + 16 >= sprite_y && ly + 16 < sprite_y + sprite_height {
+        if !is_sprite_on_line(ly, sprite_y, sprite_x, sprite_height) {
+            return;
+        }
+
+        if self.sprites.len() == SPRITES_PER_LINE {
+            self.line_stats[ly as usize].sprite_limit_hit = true;
+        } else {
             self.sprites.push(Oam {
                 y: sprite_y,
                 x: sprite_x,
@@ -359,34 +739,477 @@ impl Ppu {
         }
     }
 
+    /// Starts fetching the background, or the window, from the tile under the next pixel. Which tiles are fetched
+    /// is worked out by the fetcher from SCX, SCY and the window line as each tile is fetched.
     fn fetch_bg_pixels(&mut self, registers: &IoRegisters, is_window: bool) {
-        let (tile_map_row_addr, tile_offset_x, tile_row_offset) = match is_window {
-            true => {
-                let bit_10: u16 = if registers.lcdc.contains(LCDControl::WINDOW_TILEMAP_AREA) { 1 } else { 0 };
-                let offset_y = registers.window_ly as u16 / 8;
+        let tile_x = match is_window {
+            true => (self.screen_x + 7).wrapping_sub(registers.wx) / 8,
+            false => self.screen_x / 8,
+        };
+
+        self.pixel_fetcher.fetch_bg_tile(tile_x);
+    }
+}
+
+// Default is needed to load a `LineAhead`, and arrays this long don't implement it.
+impl Default for LineStart {
+    fn default() -> Self {
+        Self {
+            registers: DrawingRegisters::default(),
+            sprites: Vec::new(),
+            is_window: false,
+            pixels: [0; SCREEN_WIDTH],
+        }
+    }
+}
+
+impl State for Vram {
+    fn save(&self, out: &mut Vec<u8>) {
+        self.vram.save(out);
+        self.oam.save(out);
+    }
+
+    fn load(&mut self, input: &mut StateReader) -> Result<(), CoreError> {
+        self.vram.load(input)?;
+        self.oam.load(input)?;
 
-                let tile_map_row_addr = 0b1001_1000_0000_0000 | (bit_10 << 10) | offset_y << 5;
-                let tile_offset_x = (self.screen_x + 7).wrapping_sub(registers.wx) / 8;
-                let tile_row_offset = registers.window_ly % 8;
+        for addr in (VRAM_BASE_ADDR..VRAM_BASE_ADDR + 2 * TILE_ROW_COUNT as u16).step_by(2) {
+            self.decode_row(addr);
+        }
+
+        Ok(())
+    }
+}
 
-                (tile_map_row_addr, tile_offset_x, tile_row_offset)
+state_fields!(Oam { y, x, oam_addr });
+state_fields!(DrawingRegisters { lcdc, scy, scx, bgp, obp0, obp1, wy, wx });
+state_fields!(LineStart { registers, sprites, is_window, pixels });
+state_fields!(LineAhead { transfer_end, start });
+state_fields!(Ppu { dot_counter, frame_counter, vram, sprites, screen, screen_x, pixels_to_skip, pixel_fetcher, is_window, line_ahead });
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::hash_map::DefaultHasher,
+        hash::{Hash, Hasher},
+        ops::Range,
+    };
+
+    use crate::gameboy::{
+        Accuracy, GameBoy, SCREEN_HEIGHT, SCREEN_WIDTH,
+        io_registers::{InterruptFlags, IoRegisters, LCDControl},
+        Mem,
+    };
+
+    use super::Ppu;
+
+    // Tiles of color 1, of color 3, and of color 1 on the right half only.
+    const COLOR_1: u8 = 1;
+    const COLOR_3: u8 = 2;
+    const RIGHT_HALF_COLOR_1: u8 = 3;
+
+    /// Line 40 of the screen with two sprites on it, given as the X they are drawn at and their tile, in OAM order,
+    /// over a background of shade 0. OBP0 maps each color to the shade of the same number.
+    fn sprites_drawn(opri: u8, sprites: [(u8, u8); 2]) -> Vec<u8> {
+        // LD A,opri; LDH (0x6c),A; LD A,0x93; LDH (0x40),A; JR -2
+        let mut gameboy = GameBoy::new();
+        gameboy.load_flat([vec![0; 0x100], vec![0x3e, opri, 0xe0, 0x6c, 0x3e, 0x93, 0xe0, 0x40, 0x18, 0xfe]].concat());
+
+        for (tile, [low, high]) in [(COLOR_1, [0xff, 0x00]), (COLOR_3, [0xff, 0xff]), (RIGHT_HALF_COLOR_1, [0x0f, 0x00])] {
+            for row in 0..8 {
+                gameboy.poke(0x8000 + tile as u16 * 16 + row * 2, low);
+                gameboy.poke(0x8001 + tile as u16 * 16 + row * 2, high);
             }
-            _ => {
-                let bit_10: u16 = if registers.lcdc.contains(LCDControl::BG_TILEMAP_AREA) { 1 } else { 0 };
-                let offset_y = registers.ly.wrapping_add(registers.scy) as u16 / 8;
-
-                let tile_map_row_addr = 0b1001_1000_0000_0000 | (bit_10 << 10) | offset_y << 5;
-                // TODO: It's possible for SCX (possibly SCY) to change mid frame under certain conditions. 
-                //  This causes a full-line glitch.
-                //  Need to change the fetcher to allow it to recalculate the tile offsets every 
-                //  iteration to reduce it to only a few pixels in a line.
-                let tile_offset_x = self.screen_x.wrapping_add(registers.scx) / 8;
-                let tile_row_offset = registers.ly.wrapping_add(registers.scy) % 8;
-
-                (tile_map_row_addr, tile_offset_x, tile_row_offset)
+        }
+
+        for (addr, (x, tile)) in (0xfe00..).step_by(4).zip(sprites) {
+            gameboy.poke(addr, 40 + 16);
+            gameboy.poke(addr + 1, x + 8);
+            gameboy.poke(addr + 2, tile);
+            gameboy.poke(addr + 3, 0);
+        }
+
+        gameboy.poke(0xff47, 0xe4);
+        gameboy.poke(0xff48, 0xe4);
+
+        gameboy.run_frame();
+        gameboy.run_frame();
+
+        gameboy.screen()[40 * SCREEN_WIDTH..][..SCREEN_WIDTH].to_vec()
+    }
+
+    /// A line of shade 0 with `shade` over `columns`, and `overlap_shade` over the part of them in `overlap`.
+    fn line_with(columns: Range<usize>, shade: u8, overlap: Range<usize>, overlap_shade: u8) -> Vec<u8> {
+        (0..SCREEN_WIDTH)
+            .map(|x| match x {
+                x if overlap.contains(&x) => overlap_shade,
+                x if columns.contains(&x) => shade,
+                _ => 0,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn sprites_of_lower_x_are_drawn_over_others_with_opri_set() {
+        // The first sprite in OAM is further right.
+        let line = sprites_drawn(0x01, [(24, COLOR_1), (20, COLOR_3)]);
+
+        assert_eq!(line, line_with(20..32, 1, 20..28, 3));
+    }
+
+    #[test]
+    fn sprites_earlier_in_oam_are_drawn_over_others_with_opri_clear() {
+        let line = sprites_drawn(0x00, [(24, COLOR_1), (20, COLOR_3)]);
+
+        assert_eq!(line, line_with(20..32, 3, 24..32, 1));
+    }
+
+    #[test]
+    fn sprites_at_the_same_x_are_drawn_in_oam_order_either_way() {
+        for opri in [0x00, 0x01] {
+            let line = sprites_drawn(opri, [(20, COLOR_1), (20, COLOR_3)]);
+
+            assert_eq!(line, line_with(20..28, 1, 20..20, 0), "OPRI {opri}");
+        }
+    }
+
+    #[test]
+    fn transparent_pixels_of_the_sprite_on_top_show_the_one_under_it() {
+        for opri in [0x00, 0x01] {
+            let line = sprites_drawn(opri, [(20, RIGHT_HALF_COLOR_1), (20, COLOR_3)]);
+
+            assert_eq!(line, line_with(20..28, 3, 24..28, 1), "OPRI {opri}");
+        }
+    }
+
+    /// Clearing OBJ_ENABLE in LCDC while a line is drawn hides sprites from the next pixel on. Sprites reached while it
+    /// is clear aren't drawn, even once it is set again, and the pixels of a sprite already fetched show the background
+    /// instead.
+    #[test]
+    fn clearing_obj_enable_while_a_line_is_drawn_hides_sprites_from_the_next_pixel() {
+        let mut rom = vec![0; 0x8000];
+
+        // LCDC with the LCD, sprites and the background on; the OAM STAT interrupt; EI; HALT; JR back to HALT.
+        rom[0x100..0x110].copy_from_slice(&[0x3e, 0x93, 0xe0, 0x40, 0x3e, 0x20, 0xe0, 0x41, 0x3e, 0x02, 0xe0, 0xff, 0xfb, 0x76, 0x18, 0xfd]);
+
+        // On line 40, clears OBJ_ENABLE with LD (HL), 0x91 after 10 NOPs, and sets it again with LD (HL), B after 4 more.
+        let mut handler = vec![0x21, 0x40, 0xff, 0x06, 0x93, 0xf0, 0x44, 0xfe, 40, 0x20, 17];
+        handler.extend([0; 10]);
+        handler.extend([0x36, 0x91]);
+        handler.extend([0; 4]);
+        handler.extend([0x70, 0xd9]);
+        rom[0x48..0x48 + handler.len()].copy_from_slice(&handler);
+
+        let mut gameboy = GameBoy::new();
+        gameboy.load(rom).unwrap();
+
+        // A background of shade 1, and 10 sprites of shade 3 side by side over the first 80 pixels of lines 40 to 47.
+        for addr in 0x8000..0x8010 {
+            gameboy.poke(addr, if addr % 2 == 0 { 0xff } else { 0x00 });
+        }
+        for addr in 0x8010..0x8020 {
+            gameboy.poke(addr, 0xff);
+        }
+        for sprite in 0..10 {
+            gameboy.poke(0xfe00 + sprite * 4, 40 + 16);
+            gameboy.poke(0xfe01 + sprite * 4, 8 + sprite as u8 * 8);
+            gameboy.poke(0xfe02 + sprite * 4, 1);
+        }
+        gameboy.poke(0xff47, 0xe4);
+        gameboy.poke(0xff48, 0xe4);
+
+        gameboy.run_frame();
+        gameboy.run_frame();
+
+        let line = |ly: usize| &gameboy.screen()[ly * SCREEN_WIDTH..][..SCREEN_WIDTH];
+
+        // Sprites are hidden from pixel 12, part way through the second sprite, until the sixth one at pixel 40.
+        let expected = (0..SCREEN_WIDTH).map(|x| if x < 12 || (40..80).contains(&x) { 3 } else { 1 }).collect::<Vec<_>>();
+
+        assert_eq!(line(40), &expected[..]);
+        assert!(line(41)[..80].iter().all(|&shade| shade == 3));
+    }
+
+    /// With BG_WINDOW_ENABLE in LCDC clear, the background and the window are white whatever BGP is, and sprites show
+    /// over them even if they have priority.
+    #[test]
+    fn clearing_bg_window_enable_shows_white_under_every_sprite() {
+        let mut rom = vec![0; 0x8000];
+
+        // LCDC with the LCD, the window and sprites on, but the background off; JR -2.
+        rom[0x100..0x106].copy_from_slice(&[0x3e, 0xb2, 0xe0, 0x40, 0x18, 0xfe]);
+
+        let mut gameboy = GameBoy::new();
+        gameboy.load(rom).unwrap();
+
+        // A background and window of shade 3, and two sprites with their left half of shade 1 at 20 and 60 on lines 40
+        // to 47, the first behind the background.
+        for addr in 0x8000..0x8010 {
+            gameboy.poke(addr, 0xff);
+        }
+        for addr in 0x8010..0x8020 {
+            gameboy.poke(addr, if addr % 2 == 0 { 0xf0 } else { 0x00 });
+        }
+        for (sprite, (x, attributes)) in [(20, 0x80), (60, 0x00)].into_iter().enumerate() {
+            gameboy.poke(0xfe00 + sprite as u16 * 4, 40 + 16);
+            gameboy.poke(0xfe01 + sprite as u16 * 4, 8 + x);
+            gameboy.poke(0xfe02 + sprite as u16 * 4, 1);
+            gameboy.poke(0xfe03 + sprite as u16 * 4, attributes);
+        }
+        // Color 0 of the background is shade 3 too.
+        gameboy.poke(0xff47, 0xe7);
+        gameboy.poke(0xff48, 0xe4);
+        gameboy.poke(0xff4a, 0);
+        gameboy.poke(0xff4b, 7);
+
+        gameboy.run_frame();
+        gameboy.run_frame();
+
+        let sprite_line = |background| (0..SCREEN_WIDTH).map(move |x| match x {
+            20..=23 if background == 0 => 1,
+            60..=63 => 1,
+            _ => background,
+        });
+
+        for (ly, line) in gameboy.screen().chunks(SCREEN_WIDTH).enumerate() {
+            match ly {
+                40..=47 => assert!(line.iter().copied().eq(sprite_line(0)), "line {ly}"),
+                _ => assert!(line.iter().all(|&shade| shade == 0), "line {ly}"),
             }
-        };
+        }
+
+        // With the background on, the first sprite is behind it.
+        gameboy.poke(0xff40, 0xb3);
+        gameboy.run_frame();
+
+        assert!(gameboy.screen()[40 * SCREEN_WIDTH..][..SCREEN_WIDTH].iter().copied().eq(sprite_line(3)));
+    }
+
+    /// Writes to the palettes take effect from the pixel output as they land.
+    #[test]
+    fn palette_writes_take_effect_from_the_pixel_output_as_they_land() {
+        const NOPS: usize = 20;
+
+        let mut rom = vec![0; 0x8000];
+
+        // The OAM STAT interrupt; EI; HALT; JR back to HALT.
+        rom[0x100..0x10c].copy_from_slice(&[0x3e, 0x20, 0xe0, 0x41, 0x3e, 0x02, 0xe0, 0xff, 0xfb, 0x76, 0x18, 0xfd]);
+
+        // On line 40, makes shade 1 of the background shade 3 with LD (HL), B after the NOPs, and back on other lines.
+        let mut handler = vec![0x21, 0x47, 0xff, 0x06, 0xec, 0xf0, 0x44, 0xfe, 40, 0x20, NOPS as u8 + 2];
+        handler.extend([0; NOPS]);
+        handler.extend([0x70, 0xd9, 0x36, 0xe4, 0xd9]);
+        rom[0x48..0x48 + handler.len()].copy_from_slice(&handler);
+
+        let mut gameboy = GameBoy::new();
+        gameboy.load(rom).unwrap();
+
+        // A background of shade 1.
+        for addr in (0x8000..0x8010).step_by(2) {
+            gameboy.poke(addr, 0xff);
+        }
+        gameboy.poke(0xff47, 0xe4);
+
+        gameboy.run_frame();
+        gameboy.run_frame();
+
+        // On hardware, the write lands at dot 80 + 4 * NOPS of the line: 24 dots to leave HALT and call the handler,
+        // 48 for the code before the NOPs and 8 for LD (HL), B. Pixel x is output at dot 92 + x.
+        let expected = 4 * NOPS - 12;
+        let line = &gameboy.screen()[40 * SCREEN_WIDTH..][..SCREEN_WIDTH];
+        let column = line.iter().position(|&shade| shade == 3).unwrap();
+
+        assert!(column.abs_diff(expected) <= 2, "shade changed at {column}, expected {expected}");
+        assert!(line[column..].iter().all(|&shade| shade == 3));
+    }
+
+    /// Hashes of the first frames `rom` draws over a background of four different tiles, with the window in the bottom
+    /// right quarter and a row of sprites over both, drawn in one pass or dot by dot.
+    fn frame_hashes(rom: &[u8], dot_accurate_ppu: bool) -> Vec<u64> {
+        let mut gameboy = GameBoy::new();
+        gameboy.set_accuracy(Accuracy { dot_accurate_ppu, ..Default::default() });
+        gameboy.load(rom.to_vec()).unwrap();
+
+        for (tile, [low, high]) in [[0x00, 0x00], [0xff, 0x00], [0x3c, 0xc3], [0xaa, 0x55]].into_iter().enumerate() {
+            for row in 0..8 {
+                gameboy.poke(0x8000 + tile as u16 * 16 + row * 2, low);
+                gameboy.poke(0x8001 + tile as u16 * 16 + row * 2, high);
+            }
+        }
+        for index in 0..0x400 {
+            gameboy.poke(0x9800 + index, (index % 3) as u8 + 1);
+            gameboy.poke(0x9c00 + index, 3 - (index % 4) as u8);
+        }
+        for sprite in 0..10 {
+            gameboy.poke(0xfe00 + sprite * 4, 60 + 16 + sprite as u8 * 2);
+            gameboy.poke(0xfe01 + sprite * 4, 8 + sprite as u8 * 14);
+            gameboy.poke(0xfe02 + sprite * 4, 2);
+        }
+        gameboy.poke(0xff47, 0xe4);
+        gameboy.poke(0xff48, 0xd2);
+        gameboy.poke(0xff4a, 72);
+        gameboy.poke(0xff4b, 87);
+
+        (0..3)
+            .map(|_| {
+                gameboy.run_frame();
+
+                let mut hasher = DefaultHasher::new();
+                gameboy.screen().hash(&mut hasher);
+                hasher.finish()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn lines_drawn_in_one_pass_match_lines_drawn_dot_by_dot() {
+        let mut rom = vec![0; 0x8000];
+
+        // LCDC with the LCD, the window, sprites and the background on; HALT, with no interrupts to leave it.
+        rom[0x100..0x105].copy_from_slice(&[0x3e, 0xf3, 0xe0, 0x40, 0x76]);
+
+        assert_eq!(frame_hashes(&rom, false), frame_hashes(&rom, true));
+    }
+
+    /// Lines where the registers are written while they are drawn fall back to being drawn dot by dot from where the
+    /// write landed, which has to give the same pixels as drawing them dot by dot from the start.
+    #[test]
+    fn lines_written_to_while_drawn_match_lines_drawn_dot_by_dot() {
+        const NOPS: usize = 20;
+
+        let mut rom = vec![0; 0x8000];
+
+        // LCDC with the LCD, the window, sprites and the background on; the OAM STAT interrupt; EI; HALT; JR back to HALT.
+        rom[0x100..0x110].copy_from_slice(&[0x3e, 0xf3, 0xe0, 0x40, 0x3e, 0x20, 0xe0, 0x41, 0x3e, 0x02, 0xe0, 0xff, 0xfb, 0x76, 0x18, 0xfd]);
+
+        // On every line, writes LY to SCX part way through pixel transfer, and its complement to BGP just after.
+        let mut handler = vec![0xf0, 0x44];
+        handler.extend([0; NOPS]);
+        handler.extend([0xe0, 0x43, 0x2f, 0xe0, 0x47, 0xd9]);
+        rom[0x48..0x48 + handler.len()].copy_from_slice(&handler);
+
+        assert_eq!(frame_hashes(&rom, false), frame_hashes(&rom, true));
+    }
+
+    /// SCX and SCY written from the HBlank STAT interrupt scroll the next line on its own, as games do for raster
+    /// effects.
+    #[test]
+    fn scroll_written_in_hblank_applies_to_the_next_line() {
+        let mut rom = vec![0; 0x8000];
+
+        // LCDC with the LCD and the background on; the HBlank STAT interrupt; EI; HALT; JR back to HALT.
+        rom[0x100..0x110].copy_from_slice(&[0x3e, 0x91, 0xe0, 0x40, 0x3e, 0x08, 0xe0, 0x41, 0x3e, 0x02, 0xe0, 0xff, 0xfb, 0x76, 0x18, 0xfd]);
+
+        // Scrolls the next line by 3 times its LY horizontally and 5 times vertically.
+        rom[0x48..0x57].copy_from_slice(&[0xf0, 0x44, 0x3c, 0x47, 0x87, 0x80, 0xe0, 0x43, 0x78, 0x87, 0x87, 0x80, 0xe0, 0x42, 0xd9]);
+
+        let mut gameboy = GameBoy::new();
+        gameboy.load(rom).unwrap();
+
+        // Tiles with a diagonal of color 1 for the even rows of the tile map and of color 3 for the odd ones, so every
+        // pixel shows where in 16 lines and 8 columns of the background it comes from.
+        for row in 0..8 {
+            gameboy.poke(0x8010 + row * 2, 0x80 >> row);
+            gameboy.poke(0x8020 + row * 2, 0x80 >> row);
+            gameboy.poke(0x8021 + row * 2, 0x80 >> row);
+        }
+        for index in 0..0x400 {
+            gameboy.poke(0x9800 + index, if index / 32 % 2 == 0 { 1 } else { 2 });
+        }
+        gameboy.poke(0xff47, 0xe4);
+
+        gameboy.run_frame();
+        gameboy.run_frame();
+
+        for (ly, line) in gameboy.screen().chunks(SCREEN_WIDTH).enumerate() {
+            // The first line keeps the scroll written after the last one.
+            let scrolled_by = if ly == 0 { SCREEN_HEIGHT } else { ly } as u8;
+            let scx = scrolled_by.wrapping_mul(3);
+            let y = (ly as u8).wrapping_add(scrolled_by.wrapping_mul(5));
+
+            let expected = (0..SCREEN_WIDTH).map(|x| match (x as u8).wrapping_add(scx) % 8 == y % 8 {
+                true if y & 8 == 0 => 1,
+                true => 3,
+                false => 0,
+            });
+
+            assert!(line.iter().copied().eq(expected), "line {ly}");
+        }
+    }
+
+    /// The dots of the first line the HBlank interrupt is requested at and the mode switches to HBlank at, with the
+    /// sprite given as the X it is drawn at on the line, and the window at `wx`.
+    fn hblank_dots(dot_accurate: bool, scx: u8, sprite_x: Option<u8>, wx: u8) -> (Option<usize>, usize) {
+        let mut ppu = Ppu::new();
+        let mut registers = IoRegisters::new();
+
+        registers.lcdc = LCDControl::LCD_PPU_ENABLE | LCDControl::BG_WINDOW_ENABLE | LCDControl::OBJ_ENABLE
+            | LCDControl::WINDOW_ENABLE;
+        // HBlank is the only source enabled, and the PPU starts in VBlank.
+        registers.stat = (1 << 3) | 0b01;
+        registers.scx = scx;
+        registers.wx = wx;
+
+        if let Some(x) = sprite_x {
+            ppu.vram.mem_write(0xfe00, 16);
+            ppu.vram.mem_write(0xfe01, x + 8);
+        }
+
+        let mut interrupt_dot = None;
+
+        for dot in 0..456 {
+            registers.interrupt_flag = InterruptFlags::empty();
+
+            ppu.tick(&mut registers, dot_accurate);
+
+            if registers.interrupt_flag.contains(InterruptFlags::LCD_STAT) {
+                assert_eq!(interrupt_dot, None, "requested again at dot {dot}");
+
+                interrupt_dot = Some(dot);
+            }
+
+            if registers.stat & 0b11 == 0 {
+                return (interrupt_dot, dot);
+            }
+        }
+
+        panic!("the line never ended");
+    }
+
+    #[test]
+    fn the_hblank_interrupt_is_requested_a_dot_before_the_mode_switches() {
+        let lines = [
+            (0, None, 0xff),
+            (5, None, 0xff),
+            // A sprite fetched for the last pixel, and the window triggered by it, stall it.
+            (0, Some(SCREEN_WIDTH as u8 - 1), 0xff),
+            (0, None, SCREEN_WIDTH as u8 + 6),
+        ];
+
+        for (scx, sprite_x, wx) in lines {
+            let one_pass = hblank_dots(false, scx, sprite_x, wx);
+            let (interrupt_dot, mode_dot) = hblank_dots(true, scx, sprite_x, wx);
+
+            assert_eq!(interrupt_dot, Some(mode_dot - 1), "SCX {scx}, sprite {sprite_x:?}, WX {wx}");
+            assert_eq!(one_pass, (interrupt_dot, mode_dot), "SCX {scx}, sprite {sprite_x:?}, WX {wx}");
+        }
+    }
+
+    #[test]
+    fn the_hblank_interrupt_isnt_requested_while_lyc_holds_the_stat_line() {
+        let mut ppu = Ppu::new();
+        let mut registers = IoRegisters::new();
+
+        registers.stat = (1 << 6) | (1 << 3) | 0b01;
+
+        for dot in 0..456 {
+            ppu.tick(&mut registers, false);
+
+            if dot == 80 {
+                registers.interrupt_flag = InterruptFlags::empty();
+            }
+        }
 
-        self.pixel_fetcher.fetch_bg_tile(tile_map_row_addr, tile_offset_x, tile_row_offset);
+        assert!(!registers.interrupt_flag.contains(InterruptFlags::LCD_STAT));
     }
 }
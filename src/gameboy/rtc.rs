@@ -0,0 +1,275 @@
+use super::state::state_fields;
+
+// T-cycles per second of the CPU clock, which the RTC of an emulated cartridge is driven by.
+const CYCLES_PER_SECOND: u32 = 4_194_304;
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+// The day counter is 9 bits.
+const DAY_COUNT: u64 = 512;
+
+/// Size of the footer most emulators append to the RAM of MBC3 cartridges with a clock, see `Rtc::footer`.
+pub const FOOTER_SIZE: usize = 48;
+/// An older variant of the footer, with a 32-bit timestamp.
+pub const SHORT_FOOTER_SIZE: usize = 44;
+
+// Bits of the DH register.
+const DAY_HIGH: u8 = 1 << 0;
+const HALT: u8 = 1 << 6;
+const DAY_CARRY: u8 = 1 << 7;
+
+/// The RTC registers, in the order they are selected by RAM banks 0x08-0x0c.
+#[derive(Clone, Copy, Default)]
+struct Registers {
+    seconds: u8,
+    minutes: u8,
+    hours: u8,
+    day_low: u8,
+    day_high: u8,
+}
+
+impl Registers {
+    fn to_array(self) -> [u8; 5] {
+        [self.seconds, self.minutes, self.hours, self.day_low, self.day_high]
+    }
+
+    fn from_array([seconds, minutes, hours, day_low, day_high]: [u8; 5]) -> Self {
+        Self {
+            seconds: seconds & 0x3f,
+            minutes: minutes & 0x3f,
+            hours: hours & 0x1f,
+            day_low,
+            day_high: day_high & (DAY_HIGH | HALT | DAY_CARRY),
+        }
+    }
+
+    fn days(&self) -> u64 {
+        (self.day_high & DAY_HIGH) as u64 * 256 + self.day_low as u64
+    }
+
+    /// Moves the clock forward, setting the carry bit when the day counter overflows.
+    ///
+    /// Values out of range, which games can write, are counted from as if they were in range, instead of wrapping
+    /// at the register size like the hardware does.
+    fn advance(&mut self, seconds: u64) {
+        if self.day_high & HALT != 0 || seconds == 0 {
+            return;
+        }
+
+        let total = self.seconds as u64
+            + self.minutes as u64 * 60
+            + self.hours as u64 * 3600
+            + self.days() * SECONDS_PER_DAY
+            + seconds;
+
+        let days = total / SECONDS_PER_DAY;
+
+        self.seconds = (total % 60) as u8;
+        self.minutes = (total / 60 % 60) as u8;
+        self.hours = (total / 3600 % 24) as u8;
+        self.day_low = (days % DAY_COUNT) as u8;
+        self.day_high = (self.day_high & !DAY_HIGH) | ((days % DAY_COUNT) >> 8) as u8;
+
+        if days >= DAY_COUNT {
+            self.day_high |= DAY_CARRY;
+        }
+    }
+}
+
+/// The real-time clock of MBC3 cartridges.
+///
+/// It counts emulated time rather than the host's, so it stays in step with the game when emulation is paused or
+/// sped up. Time that passes while the emulator isn't running is added when a save is loaded.
+pub struct Rtc {
+    registers: Registers,
+    latched: Registers,
+    /// The last value written to the latch register, which latches on a write of 1 after 0.
+    latch: u8,
+    cycles: u32,
+}
+
+impl Rtc {
+    pub fn new() -> Self {
+        Self {
+            registers: Registers::default(),
+            latched: Registers::default(),
+            latch: 0xff,
+            cycles: 0,
+        }
+    }
+
+    pub fn tick(&mut self, t_cycles: u32) {
+        if self.registers.day_high & HALT != 0 {
+            return;
+        }
+
+        self.cycles += t_cycles;
+
+        if self.cycles >= CYCLES_PER_SECOND {
+            self.cycles -= CYCLES_PER_SECOND;
+            self.registers.advance(1);
+        }
+    }
+
+    pub fn write_latch(&mut self, value: u8) {
+        if self.latch == 0x00 && value == 0x01 {
+            self.latched = self.registers;
+        }
+
+        self.latch = value;
+    }
+
+    /// Reads the latched value of a register, selected by RAM banks 0x08-0x0c.
+    pub fn read(&self, bank: u8) -> u8 {
+        self.latched.to_array()[(bank - 0x08) as usize]
+    }
+
+    /// Writes a register, selected by RAM banks 0x08-0x0c.
+    pub fn write(&mut self, bank: u8, value: u8) {
+        let mut registers = self.registers.to_array();
+        registers[(bank - 0x08) as usize] = value;
+
+        self.registers = Registers::from_array(registers);
+
+        // Writing the seconds resets the divider that counts them.
+        if bank == 0x08 {
+            self.cycles = 0;
+        }
+    }
+
+    /// The footer appended to the RAM in save files, as written by BGB, VBA and SameBoy: the current and then the
+    /// latched registers as 32-bit little-endian words, followed by the UNIX time of the save as a 64-bit word.
+    pub fn footer(&self, unix_time: u64) -> [u8; FOOTER_SIZE] {
+        let mut footer = [0; FOOTER_SIZE];
+
+        let registers = self.registers.to_array().into_iter().chain(self.latched.to_array());
+
+        for (word, value) in footer.chunks_exact_mut(4).zip(registers) {
+            word.copy_from_slice(&(value as u32).to_le_bytes());
+        }
+
+        footer[40..].copy_from_slice(&unix_time.to_le_bytes());
+
+        footer
+    }
+
+    /// Restores the clock from a footer of either size, advancing it by the time since it was saved.
+    ///
+    /// Returns `None` if `footer` is neither size.
+    pub fn load_footer(&mut self, footer: &[u8], unix_time: u64) -> Option<()> {
+        let saved_at = match footer.len() {
+            FOOTER_SIZE => u64::from_le_bytes(footer[40..48].try_into().unwrap()),
+            SHORT_FOOTER_SIZE => u32::from_le_bytes(footer[40..44].try_into().unwrap()) as u64,
+            _ => return None,
+        };
+
+        let mut words = footer[..40]
+            .chunks_exact(4)
+            .map(|word| u32::from_le_bytes(word.try_into().unwrap()) as u8);
+
+        let mut registers = [0; 5];
+        registers.fill_with(|| words.next().unwrap());
+        let mut latched = [0; 5];
+        latched.fill_with(|| words.next().unwrap());
+
+        self.registers = Registers::from_array(registers);
+        self.latched = Registers::from_array(latched);
+        self.cycles = 0;
+
+        // A clock set in the future, or saved by a host with a wrong clock, is left as it is.
+        self.registers.advance(unix_time.saturating_sub(saved_at));
+
+        Some(())
+    }
+}
+
+state_fields!(Registers { seconds, minutes, hours, day_low, day_high });
+state_fields!(Rtc { registers, latched, latch, cycles });
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAVED_AT: u64 = 1_700_000_000;
+
+    /// A clock set to `[seconds, minutes, hours, day low, day high]`.
+    fn rtc(registers: [u8; 5]) -> Rtc {
+        let mut rtc = Rtc::new();
+
+        for (bank, value) in (0x08..).zip(registers) {
+            rtc.write(bank, value);
+        }
+
+        rtc
+    }
+
+    /// Latches the registers and reads them.
+    fn latch_and_read(rtc: &mut Rtc) -> [u8; 5] {
+        rtc.write_latch(0x00);
+        rtc.write_latch(0x01);
+
+        [0x08, 0x09, 0x0a, 0x0b, 0x0c].map(|bank| rtc.read(bank))
+    }
+
+    /// Saves `rtc` at `SAVED_AT` and loads the footer `elapsed` seconds later.
+    fn reload(rtc: &Rtc, elapsed: u64) -> Rtc {
+        let mut loaded = Rtc::new();
+        loaded.load_footer(&rtc.footer(SAVED_AT), SAVED_AT + elapsed).unwrap();
+
+        loaded
+    }
+
+    #[test]
+    fn footers_keep_the_registers_and_the_latched_ones() {
+        let mut saved = rtc([12, 34, 5, 0x2a, DAY_HIGH]);
+        latch_and_read(&mut saved);
+        saved.write(0x08, 59);
+
+        let footer = saved.footer(SAVED_AT);
+        assert_eq!(footer[40..], SAVED_AT.to_le_bytes());
+
+        let mut loaded = reload(&saved, 0);
+        assert_eq!([0x08, 0x09, 0x0a, 0x0b, 0x0c].map(|bank| loaded.read(bank)), [12, 34, 5, 0x2a, DAY_HIGH]);
+        assert_eq!(latch_and_read(&mut loaded), [59, 34, 5, 0x2a, DAY_HIGH]);
+    }
+
+    #[test]
+    fn loading_a_footer_adds_the_time_since_it_was_saved() {
+        let mut loaded = reload(&rtc([50, 59, 23, 2, 0]), SECONDS_PER_DAY + 3600 + 15);
+
+        assert_eq!(latch_and_read(&mut loaded), [5, 0, 1, 4, 0]);
+    }
+
+    #[test]
+    fn the_day_counter_overflows_into_the_carry_bit() {
+        let mut loaded = reload(&rtc([0, 0, 12, 0xff, DAY_HIGH]), SECONDS_PER_DAY);
+
+        assert_eq!(latch_and_read(&mut loaded), [0, 0, 12, 0, DAY_CARRY]);
+    }
+
+    #[test]
+    fn a_halted_clock_doesnt_advance() {
+        let mut loaded = reload(&rtc([1, 2, 3, 4, HALT]), 10 * SECONDS_PER_DAY);
+
+        assert_eq!(latch_and_read(&mut loaded), [1, 2, 3, 4, HALT]);
+    }
+
+    #[test]
+    fn short_footers_have_a_32_bit_timestamp() {
+        let mut footer = rtc([0, 10, 0, 0, 0]).footer(SAVED_AT)[..SHORT_FOOTER_SIZE].to_vec();
+        footer[40..].copy_from_slice(&(SAVED_AT as u32).to_le_bytes());
+
+        let mut loaded = Rtc::new();
+        loaded.load_footer(&footer, SAVED_AT + 60).unwrap();
+
+        assert_eq!(latch_and_read(&mut loaded), [0, 11, 0, 0, 0]);
+    }
+
+    #[test]
+    fn footers_of_other_sizes_are_refused() {
+        let footer = rtc([0; 5]).footer(SAVED_AT);
+
+        for len in [0, 40, 45, 47] {
+            assert!(Rtc::new().load_footer(&footer[..len], SAVED_AT).is_none(), "{len} bytes");
+        }
+    }
+}
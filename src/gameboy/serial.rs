@@ -0,0 +1,80 @@
+use std::sync::mpsc::{self, Receiver, Sender};
+use super::io_registers::IoRegisters;
+
+// T-cycles to shift out a byte with the internal clock, at 8192 Hz.
+const TRANSFER_T_CYCLES: usize = 8 * 512;
+
+/// One end of a link cable between two emulators in the same process, made with `pair` and plugged in with
+/// `GameBoy::connect_serial`.
+pub struct SerialEndpoint {
+    sender: Sender<u8>,
+    receiver: Receiver<u8>,
+}
+
+impl SerialEndpoint {
+    /// Both ends of a cable.
+    pub fn pair() -> (Self, Self) {
+        let (a_sender, b_receiver) = mpsc::channel();
+        let (b_sender, a_receiver) = mpsc::channel();
+
+        (Self { sender: a_sender, receiver: a_receiver }, Self { sender: b_sender, receiver: b_receiver })
+    }
+}
+
+/// The serial port of a `Bus` with a cable plugged in.
+///
+/// The side with the internal clock drives a transfer: its byte is sent as the transfer starts, and the transfer
+/// completes once 8 bits' worth of clock passed and the byte of the other side came back. The side with the external
+/// clock answers with its own byte once it has a transfer started and a byte waiting, so that a byte sent before the
+/// other game is ready waits for it instead of being lost, whichever emulator happens to run ahead.
+pub(crate) struct SerialLink {
+    endpoint: SerialEndpoint,
+    /// T-cycles left in the transfer driven by the internal clock, while there is one.
+    clocking: Option<usize>,
+    /// The byte of the other side that the next transfer receives.
+    received: Option<u8>,
+}
+
+impl SerialLink {
+    pub fn new(endpoint: SerialEndpoint) -> Self {
+        Self {
+            endpoint,
+            clocking: None,
+            received: None,
+        }
+    }
+
+    pub fn tick(&mut self, io_registers: &mut IoRegisters, t_cycles: usize) {
+        if self.received.is_none() {
+            // Nothing arrives once the other end is gone, which leaves transfers hanging as without a cable.
+            self.received = self.endpoint.receiver.try_recv().ok();
+        }
+
+        if io_registers.sc & 0b1000_0000 == 0 {
+            self.clocking = None;
+            return;
+        }
+
+        if io_registers.sc & 0b0000_0001 != 0 {
+            let remaining = self.clocking.unwrap_or_else(|| {
+                let _ = self.endpoint.sender.send(io_registers.sb);
+
+                TRANSFER_T_CYCLES
+            });
+
+            self.clocking = Some(remaining.saturating_sub(t_cycles));
+
+            if self.clocking == Some(0) {
+                if let Some(byte) = self.received.take() {
+                    self.clocking = None;
+
+                    io_registers.complete_serial_transfer(byte);
+                }
+            }
+        } else if let Some(byte) = self.received.take() {
+            let _ = self.endpoint.sender.send(io_registers.sb);
+
+            io_registers.complete_serial_transfer(byte);
+        }
+    }
+}
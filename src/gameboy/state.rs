@@ -0,0 +1,712 @@
+//! Save states: the machine serialized field by field into a byte buffer, see `GameBoy::save_state`.
+//!
+//! Most of a state is memory that is unchanged or zeroed, so states are compressed with a simple run-length encoding.
+//! Rewind snapshots are also kept as their difference to the next newer snapshot, which is mostly zeroes.
+//!
+//! Subsystems are saved in tagged sections, see `save_section`. A state missing a section still loads, with that
+//! subsystem reset, so adding a subsystem doesn't break older states. Changing the fields of a section does, and
+//! needs `VERSION` raised, with the older layout still read for the versions before it. A state of a newer version
+//! is refused with `CoreError::StateVersion`.
+
+use std::collections::VecDeque;
+use super::{
+    apu::{SoundEnable, SoundPanning},
+    cpu_registers::CpuFlags,
+    error::CoreError,
+    io_registers::{InterruptFlags, LCDControl},
+    GameBoy,
+    SCREEN_HEIGHT,
+    SCREEN_WIDTH,
+};
+
+/// Identifies state files, followed by the format version, the version of the core that made it, the title hash of
+/// the game, the preview, the size of the state and the compressed state. Version 1 had no core version, and versions
+/// 1 and 2 no preview.
+///
+/// The preview is the time the state was saved, then whether a thumbnail follows, and if so its compressed size and
+/// the compressed screen, see `StatePreview`.
+const MAGIC: &[u8; 8] = b"YAGBESTA";
+
+/// Version 1 saved every subsystem one after the other, without sections. Versions before 4 saved an accumulator of
+/// TIMA's own, see `IoRegisters::load_with_accumulator`.
+pub(crate) const VERSION: u32 = 4;
+
+/// The version of the crate the core is built into, which is only used to tell where a newer state came from.
+const CORE_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+const SCREEN_SIZE: usize = SCREEN_WIDTH * SCREEN_HEIGHT;
+
+const HEADER_SIZE: usize = MAGIC.len() + 4 + 1 + CORE_VERSION.len() + 8 + PREVIEW_SIZE + 4;
+
+const PREVIEW_SIZE: usize = 8 + 1 + 4 + SCREEN_SIZE + SCREEN_SIZE / MAX_LITERAL + 1;
+
+// Runs of equal bytes at least this long are encoded as a repeat, shorter ones are kept in literals.
+const MIN_RUN: usize = 3;
+const MAX_RUN: usize = MIN_RUN + 0x7f;
+const MAX_LITERAL: usize = 0x80;
+
+/// Machine state that is saved and restored field by field, in a fixed order.
+pub(crate) trait State {
+    fn save(&self, out: &mut Vec<u8>);
+    fn load(&mut self, input: &mut StateReader) -> Result<(), CoreError>;
+
+    /// Saves the items of an array in order. Bytes copy the whole array at once instead, since most of a state is
+    /// memory.
+    fn save_slice(items: &[Self], out: &mut Vec<u8>) where Self: Sized {
+        for item in items {
+            item.save(out);
+        }
+    }
+
+    fn load_slice(items: &mut [Self], input: &mut StateReader) -> Result<(), CoreError> where Self: Sized {
+        for item in items {
+            item.load(input)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Implements `State` for a struct by saving the given fields in order.
+macro_rules! state_fields {
+    ($type:ty { $($field:ident),* $(,)? }) => {
+        impl crate::gameboy::state::State for $type {
+            fn save(&self, out: &mut Vec<u8>) {
+                $(crate::gameboy::state::State::save(&self.$field, out);)*
+            }
+
+            fn load(&mut self, input: &mut crate::gameboy::state::StateReader) -> Result<(), crate::gameboy::CoreError> {
+                $(crate::gameboy::state::State::load(&mut self.$field, input)?;)*
+
+                Ok(())
+            }
+        }
+    };
+}
+
+pub(crate) use state_fields;
+
+pub(crate) struct StateReader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    pub fn bytes(&mut self, len: usize) -> Result<&'a [u8], CoreError> {
+        if self.data.len() < len {
+            return Err(CoreError::InvalidState("it is truncated"));
+        }
+
+        let (bytes, rest) = self.data.split_at(len);
+        self.data = rest;
+
+        Ok(bytes)
+    }
+
+    /// Reads a length saved before a variable number of items, checking it against what could be left.
+    pub fn read_len(&mut self) -> Result<usize, CoreError> {
+        let len: u32 = read(self)?;
+
+        match len as usize {
+            len if len <= self.data.len() => Ok(len),
+            _ => Err(CoreError::InvalidState("it is truncated")),
+        }
+    }
+}
+
+/// Saves `value` as a section: `tag`, the length of its fields, then its fields. Sections can be found without knowing
+/// what comes before them, so one that is missing leaves no gap.
+pub(crate) fn save_section(tag: &[u8; 4], value: &impl State, out: &mut Vec<u8>) {
+    out.extend_from_slice(tag);
+
+    let len_offset = out.len();
+    0u32.save(out);
+
+    value.save(out);
+
+    let len = (out.len() - len_offset - 4) as u32;
+    out[len_offset..len_offset + 4].copy_from_slice(&len.to_le_bytes());
+}
+
+/// The sections of a state, see `save_section`.
+pub(crate) struct Sections<'a> {
+    sections: Vec<([u8; 4], &'a [u8])>,
+}
+
+impl<'a> Sections<'a> {
+    /// Reads the sections up to the end of `input`.
+    pub fn read(input: &mut StateReader<'a>) -> Result<Self, CoreError> {
+        let mut sections = Vec::new();
+
+        while !input.is_empty() {
+            let tag = input.bytes(4)?.try_into().unwrap();
+            let len = input.read_len()?;
+
+            sections.push((tag, input.bytes(len)?));
+        }
+
+        Ok(Self { sections })
+    }
+
+    /// Restores `value` from the section with `tag`, which must hold exactly its fields. Returns false, leaving `value`
+    /// as it was, if there is no such section.
+    pub fn load(&self, tag: &[u8; 4], value: &mut impl State) -> Result<bool, CoreError> {
+        self.load_with(tag, |input| value.load(input))
+    }
+
+    /// Like `load`, but restores the section with `load`, for the layout of an older version.
+    pub fn load_with(
+        &self,
+        tag: &[u8; 4],
+        load: impl FnOnce(&mut StateReader) -> Result<(), CoreError>,
+    ) -> Result<bool, CoreError> {
+        let Some(&(_, data)) = self.sections.iter().find(|(section_tag, _)| section_tag == tag) else {
+            return Ok(false);
+        };
+
+        let mut input = StateReader::new(data);
+        load(&mut input)?;
+
+        if !input.is_empty() {
+            return Err(CoreError::InvalidState("it is corrupt"));
+        }
+
+        Ok(true)
+    }
+}
+
+/// Reads a value that is created while loading, like an item of a `Vec`.
+pub(crate) fn read<T: State + Default>(input: &mut StateReader) -> Result<T, CoreError> {
+    let mut value = T::default();
+    value.load(input)?;
+
+    Ok(value)
+}
+
+macro_rules! state_int {
+    ($($type:ty),*) => {
+        $(impl State for $type {
+            fn save(&self, out: &mut Vec<u8>) {
+                out.extend_from_slice(&self.to_le_bytes());
+            }
+
+            fn load(&mut self, input: &mut StateReader) -> Result<(), CoreError> {
+                *self = <$type>::from_le_bytes(input.bytes(std::mem::size_of::<$type>())?.try_into().unwrap());
+
+                Ok(())
+            }
+        })*
+    };
+}
+
+state_int!(u16, u32, u64, i8);
+
+impl State for u8 {
+    fn save(&self, out: &mut Vec<u8>) {
+        out.push(*self);
+    }
+
+    fn load(&mut self, input: &mut StateReader) -> Result<(), CoreError> {
+        *self = input.bytes(1)?[0];
+
+        Ok(())
+    }
+
+    fn save_slice(items: &[Self], out: &mut Vec<u8>) {
+        out.extend_from_slice(items);
+    }
+
+    fn load_slice(items: &mut [Self], input: &mut StateReader) -> Result<(), CoreError> {
+        items.copy_from_slice(input.bytes(items.len())?);
+
+        Ok(())
+    }
+}
+
+// Sizes are saved as 64 bits, so that states move between 32 and 64-bit builds.
+impl State for usize {
+    fn save(&self, out: &mut Vec<u8>) {
+        (*self as u64).save(out);
+    }
+
+    fn load(&mut self, input: &mut StateReader) -> Result<(), CoreError> {
+        let value: u64 = read(input)?;
+
+        *self = usize::try_from(value).map_err(|_| CoreError::InvalidState("a value is out of range"))?;
+
+        Ok(())
+    }
+}
+
+impl State for isize {
+    fn save(&self, out: &mut Vec<u8>) {
+        (*self as i64 as u64).save(out);
+    }
+
+    fn load(&mut self, input: &mut StateReader) -> Result<(), CoreError> {
+        let value: u64 = read(input)?;
+
+        *self = isize::try_from(value as i64).map_err(|_| CoreError::InvalidState("a value is out of range"))?;
+
+        Ok(())
+    }
+}
+
+impl State for f32 {
+    fn save(&self, out: &mut Vec<u8>) {
+        self.to_bits().save(out);
+    }
+
+    fn load(&mut self, input: &mut StateReader) -> Result<(), CoreError> {
+        *self = f32::from_bits(read(input)?);
+
+        Ok(())
+    }
+}
+
+impl State for bool {
+    fn save(&self, out: &mut Vec<u8>) {
+        out.push(*self as u8);
+    }
+
+    fn load(&mut self, input: &mut StateReader) -> Result<(), CoreError> {
+        *self = input.bytes(1)?[0] != 0;
+
+        Ok(())
+    }
+}
+
+impl<T: State, const N: usize> State for [T; N] {
+    fn save(&self, out: &mut Vec<u8>) {
+        T::save_slice(self, out);
+    }
+
+    fn load(&mut self, input: &mut StateReader) -> Result<(), CoreError> {
+        T::load_slice(self, input)
+    }
+}
+
+impl<T: State + Default> State for Option<T> {
+    fn save(&self, out: &mut Vec<u8>) {
+        self.is_some().save(out);
+
+        if let Some(value) = self {
+            value.save(out);
+        }
+    }
+
+    fn load(&mut self, input: &mut StateReader) -> Result<(), CoreError> {
+        *self = match read(input)? {
+            true => Some(read(input)?),
+            false => None,
+        };
+
+        Ok(())
+    }
+}
+
+impl<T: State + Default> State for Vec<T> {
+    fn save(&self, out: &mut Vec<u8>) {
+        (self.len() as u32).save(out);
+
+        for item in self {
+            item.save(out);
+        }
+    }
+
+    fn load(&mut self, input: &mut StateReader) -> Result<(), CoreError> {
+        let len = input.read_len()?;
+
+        self.clear();
+
+        for _ in 0..len {
+            self.push(read(input)?);
+        }
+
+        Ok(())
+    }
+}
+
+impl<T: State + Default> State for VecDeque<T> {
+    fn save(&self, out: &mut Vec<u8>) {
+        (self.len() as u32).save(out);
+
+        for item in self {
+            item.save(out);
+        }
+    }
+
+    fn load(&mut self, input: &mut StateReader) -> Result<(), CoreError> {
+        let len = input.read_len()?;
+
+        self.clear();
+
+        for _ in 0..len {
+            self.push_back(read(input)?);
+        }
+
+        Ok(())
+    }
+}
+
+macro_rules! state_flags {
+    ($($type:ty),*) => {
+        $(impl State for $type {
+            fn save(&self, out: &mut Vec<u8>) {
+                out.push(self.bits());
+            }
+
+            fn load(&mut self, input: &mut StateReader) -> Result<(), CoreError> {
+                *self = <$type>::from_bits_retain(input.bytes(1)?[0]);
+
+                Ok(())
+            }
+        })*
+    };
+}
+
+state_flags!(CpuFlags, InterruptFlags, LCDControl, SoundEnable, SoundPanning);
+
+/// Appends `data` to `out`, run-length encoded: a control byte below 0x80 is followed by that many bytes plus one,
+/// copied as they are, and one of 0x80 or above is followed by a byte repeated that many times minus 0x80 plus 3.
+fn compress(data: &[u8], out: &mut Vec<u8>) {
+    let mut literal_start = 0;
+    let mut i = 0;
+
+    while i < data.len() {
+        let byte = data[i];
+        let run = data[i..].iter().take(MAX_RUN).take_while(|&&b| b == byte).count();
+
+        if run >= MIN_RUN {
+            flush_literals(&data[literal_start..i], out);
+
+            out.push(0x80 | (run - MIN_RUN) as u8);
+            out.push(byte);
+
+            i += run;
+            literal_start = i;
+        } else {
+            i += run;
+        }
+    }
+
+    flush_literals(&data[literal_start..], out);
+}
+
+fn flush_literals(literals: &[u8], out: &mut Vec<u8>) {
+    for chunk in literals.chunks(MAX_LITERAL) {
+        out.push((chunk.len() - 1) as u8);
+        out.extend_from_slice(chunk);
+    }
+}
+
+/// Decodes `data`, compressed by `compress`, into `out`, which must end up `len` bytes long.
+fn decompress(data: &[u8], len: usize, out: &mut Vec<u8>) -> Result<(), CoreError> {
+    const CORRUPT: CoreError = CoreError::InvalidState("it is corrupt");
+
+    out.clear();
+    out.reserve(len);
+
+    let mut i = 0;
+
+    while i < data.len() {
+        let control = data[i] as usize;
+
+        if control & 0x80 != 0 {
+            let &byte = data.get(i + 1).ok_or(CORRUPT)?;
+
+            out.resize(out.len() + (control & 0x7f) + MIN_RUN, byte);
+            i += 2;
+        } else {
+            let literals = data.get(i + 1..i + 2 + control).ok_or(CORRUPT)?;
+
+            out.extend_from_slice(literals);
+            i += 2 + control;
+        }
+
+        if out.len() > len {
+            return Err(CORRUPT);
+        }
+    }
+
+    match out.len() == len {
+        true => Ok(()),
+        false => Err(CORRUPT),
+    }
+}
+
+/// Upper bound of the size of a state file holding `len` bytes of state, since a literal is at most a byte longer
+/// than its contents every `MAX_LITERAL` bytes.
+pub(crate) fn file_size_bound(len: usize) -> usize {
+    HEADER_SIZE + len + len / MAX_LITERAL + 1
+}
+
+/// What a state file shows of itself without restoring it, such as for picking one of several to load.
+///
+/// # Examples
+///
+/// ```
+/// use yagbe::gameboy::{GameBoy, StatePreview};
+///
+/// let mut gameboy = GameBoy::new();
+/// gameboy.load(vec![0; 0x8000])?;
+/// gameboy.run_frame();
+///
+/// let state = gameboy.save_state().unwrap();
+/// let preview = StatePreview::read(&state)?;
+///
+/// assert!(preview.saved_at.is_some());
+/// assert_eq!(preview.screen.as_deref(), Some(gameboy.screen()));
+/// # Ok::<(), yagbe::gameboy::CoreError>(())
+/// ```
+#[derive(Clone, Default)]
+pub struct StatePreview {
+    /// When the state was saved, in seconds since the Unix epoch. States of format version 2 or older don't say.
+    pub saved_at: Option<u64>,
+    /// The screen at the time, as `GameBoy::screen` returns it. States of format version 2 or older have none.
+    pub screen: Option<Box<[u8; SCREEN_SIZE]>>,
+}
+
+impl StatePreview {
+    /// Reads the preview of a state file made by `GameBoy::save_state`, of any game. Only the thumbnail is
+    /// decompressed, and the state itself isn't looked at, so this doesn't tell whether the state can be restored.
+    pub fn read(file: &[u8]) -> Result<Self, CoreError> {
+        let mut input = StateReader::new(file);
+
+        let (version, _) = read_header(&mut input)?;
+
+        read_preview(&mut input, version, true)
+    }
+
+    fn save(&self, out: &mut Vec<u8>) {
+        self.saved_at.unwrap_or(0).save(out);
+        self.screen.is_some().save(out);
+
+        if let Some(screen) = &self.screen {
+            let len_offset = out.len();
+            0u32.save(out);
+
+            compress(screen.as_slice(), out);
+
+            let len = (out.len() - len_offset - 4) as u32;
+            out[len_offset..len_offset + 4].copy_from_slice(&len.to_le_bytes());
+        }
+    }
+}
+
+/// Builds a self-contained state file from a serialized state, with `preview` in front of it.
+pub(crate) fn write_file(title_hash: u64, preview: &StatePreview, state: &[u8]) -> Vec<u8> {
+    let mut file = Vec::with_capacity(file_size_bound(state.len()) / 4);
+
+    file.extend_from_slice(MAGIC);
+    VERSION.save(&mut file);
+    (CORE_VERSION.len() as u8).save(&mut file);
+    file.extend_from_slice(CORE_VERSION.as_bytes());
+    title_hash.save(&mut file);
+    preview.save(&mut file);
+    (state.len() as u32).save(&mut file);
+
+    compress(state, &mut file);
+
+    file
+}
+
+/// Reads the header of a state file up to the preview, returning the version of its format and the title hash of
+/// its game.
+fn read_header(input: &mut StateReader) -> Result<(u32, u64), CoreError> {
+    if input.bytes(MAGIC.len()).ok() != Some(MAGIC.as_slice()) {
+        return Err(CoreError::InvalidState("it isn't a save state"));
+    }
+
+    let version: u32 = read(input)?;
+
+    if version == 0 {
+        return Err(CoreError::InvalidState("it is corrupt"));
+    }
+
+    let core = match version {
+        1 => None,
+        _ => {
+            let len: u8 = read(input)?;
+
+            Some(String::from_utf8_lossy(input.bytes(len as usize)?).into_owned())
+        }
+    };
+
+    // Only the magic and the versions are kept where they are by newer formats, so nothing more is read.
+    if version > VERSION {
+        return Err(CoreError::StateVersion { version, core: core.unwrap_or_default() });
+    }
+
+    let hash: u64 = read(input)?;
+
+    Ok((version, hash))
+}
+
+/// Reads the preview that follows the header of a state file of `version`, skipping over the thumbnail unless
+/// `thumbnail` is set.
+fn read_preview(input: &mut StateReader, version: u32, thumbnail: bool) -> Result<StatePreview, CoreError> {
+    if version < 3 {
+        return Ok(StatePreview::default());
+    }
+
+    let saved_at: u64 = read(input)?;
+    let has_screen: bool = read(input)?;
+
+    let screen = match has_screen {
+        true => {
+            let len = input.read_len()?;
+            let data = input.bytes(len)?;
+
+            match thumbnail {
+                true => {
+                    let mut screen = Vec::new();
+                    decompress(data, SCREEN_SIZE, &mut screen)?;
+
+                    Some(screen.into_boxed_slice().try_into().unwrap())
+                }
+                false => None,
+            }
+        }
+        false => None,
+    };
+
+    Ok(StatePreview {
+        saved_at: Some(saved_at),
+        screen,
+    })
+}
+
+/// Extracts the serialized state from a state file, checking that it belongs to the game with `title_hash`. Returns
+/// the version of its format.
+pub(crate) fn read_file(file: &[u8], title_hash: u64, out: &mut Vec<u8>) -> Result<u32, CoreError> {
+    let mut input = StateReader::new(file);
+
+    let (version, hash) = read_header(&mut input)?;
+
+    if hash != title_hash {
+        return Err(CoreError::InvalidState("it was made with a different game"));
+    }
+
+    read_preview(&mut input, version, false)?;
+
+    let len: u32 = read(&mut input)?;
+
+    decompress(input.data, len as usize, out)?;
+
+    Ok(version)
+}
+
+/// A snapshot in a `Rewind` buffer, as the difference to the next newer snapshot, or as it is if their sizes differ.
+enum Snapshot {
+    Delta(Vec<u8>),
+    Full { len: usize, data: Vec<u8> },
+}
+
+/// Snapshots of the emulator to step back through, most recent first.
+///
+/// Only the most recent snapshot is kept as it is. Older ones are kept compressed as their difference to the next newer
+/// one, so a snapshot usually takes a few kilobytes, see `GameBoy::state_size_hint` for the worst case.
+pub struct Rewind {
+    capacity: usize,
+    newest: Option<Vec<u8>>,
+    older: VecDeque<Snapshot>,
+    scratch: Vec<u8>,
+}
+
+impl Rewind {
+    /// Keeps at most `capacity` snapshots, dropping the oldest ones for new ones.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            newest: None,
+            older: VecDeque::with_capacity(capacity),
+            scratch: Vec::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.older.len() + self.newest.is_some() as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.newest.is_none()
+    }
+
+    pub fn clear(&mut self) {
+        self.newest = None;
+        self.older.clear();
+    }
+
+    /// Takes a snapshot of `gameboy`.
+    pub fn push(&mut self, gameboy: &GameBoy) {
+        // The buffer of the previous snapshot is reused, after its difference to this one is compressed.
+        let mut state = std::mem::take(&mut self.scratch);
+        state.clear();
+        gameboy.save_raw(&mut state);
+
+        let Some(mut previous) = self.newest.replace(state) else {
+            return;
+        };
+        let state = self.newest.as_deref().unwrap_or_default();
+        let mut snapshot = Vec::new();
+
+        if previous.len() == state.len() {
+            previous.iter_mut().zip(state).for_each(|(a, b)| *a ^= b);
+
+            compress(&previous, &mut snapshot);
+            self.older.push_back(Snapshot::Delta(snapshot));
+        } else {
+            compress(&previous, &mut snapshot);
+            self.older.push_back(Snapshot::Full { len: previous.len(), data: snapshot });
+        }
+
+        if self.older.len() >= self.capacity {
+            self.older.pop_front();
+        }
+
+        self.scratch = previous;
+    }
+
+    /// Restores the most recent snapshot into `gameboy` and drops it, so the next call goes further back. Returns
+    /// false if there are no snapshots left, or if the snapshot couldn't be restored because another game was loaded
+    /// since, in which case every snapshot is dropped.
+    pub fn pop(&mut self, gameboy: &mut GameBoy) -> bool {
+        let Some(newest) = self.newest.take() else {
+            return false;
+        };
+
+        if gameboy.load_raw(&newest).is_err() {
+            self.clear();
+
+            return false;
+        }
+
+        self.newest = match self.older.pop_back() {
+            Some(Snapshot::Delta(delta)) => {
+                decompress(&delta, newest.len(), &mut self.scratch).ok()
+                    .map(|()| newest.iter().zip(&self.scratch).map(|(a, b)| a ^ b).collect())
+            }
+            Some(Snapshot::Full { len, data }) => {
+                let mut previous = Vec::new();
+
+                decompress(&data, len, &mut previous).ok().map(|()| previous)
+            }
+            None => None,
+        };
+
+        // Older snapshots are differences to the one that couldn't be decoded, so they're lost with it.
+        if self.newest.is_none() {
+            self.older.clear();
+        }
+
+        true
+    }
+}
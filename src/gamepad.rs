@@ -0,0 +1,86 @@
+use sdl2::{
+    controller::{Button, GameController},
+    GameControllerSubsystem,
+};
+use crate::{
+    emulation::Emulator,
+    gameboy::Buttons,
+    input::{Control, InputSources},
+};
+
+const BUTTON_MAP: [(Button, Buttons); 8] = [
+    (Button::DPadUp, Buttons::Up),
+    (Button::DPadDown, Buttons::Down),
+    (Button::DPadLeft, Buttons::Left),
+    (Button::DPadRight, Buttons::Right),
+    (Button::A, Buttons::A),
+    (Button::B, Buttons::B),
+    (Button::Start, Buttons::Start),
+    (Button::Back, Buttons::Select),
+];
+
+/// The first game controller found, which controls the second game of a split view.
+///
+/// SDL events aren't pumped, since the window belongs to tao, so the buttons are polled once per host frame instead.
+/// A controller plugged in later is picked up, and unplugging it releases its buttons.
+pub(crate) struct Gamepad {
+    subsystem: GameControllerSubsystem,
+    controller: Option<GameController>,
+    held: Buttons,
+    sources: InputSources,
+}
+
+impl Gamepad {
+    pub fn new(subsystem: GameControllerSubsystem) -> Self {
+        Self {
+            subsystem,
+            controller: None,
+            held: Buttons::empty(),
+            sources: InputSources::default(),
+        }
+    }
+
+    /// Sends the buttons pressed and released since the last poll to `emulator`.
+    pub fn poll(&mut self, emulator: &Emulator) {
+        self.subsystem.update();
+
+        if self.controller.as_ref().is_some_and(|controller| !controller.attached()) {
+            self.controller = None;
+        }
+
+        if self.controller.is_none() {
+            self.controller = self.open_first();
+        }
+
+        let held = self.controller.as_ref().map_or(Buttons::empty(), |controller| {
+            BUTTON_MAP.iter()
+                .filter(|&&(button, _)| controller.button(button))
+                .fold(Buttons::empty(), |held, &(_, buttons)| held | buttons)
+        });
+
+        for &(button, buttons) in BUTTON_MAP.iter().filter(|&&(_, buttons)| (held - self.held).contains(buttons)) {
+            emulator.press(Control::Button(buttons));
+            self.sources.press(Control::Button(buttons), button.string());
+        }
+
+        for &(button, buttons) in BUTTON_MAP.iter().filter(|&&(_, buttons)| (self.held - held).contains(buttons)) {
+            emulator.release(Control::Button(buttons));
+            self.sources.release(Control::Button(buttons), &button.string());
+        }
+
+        self.held = held;
+    }
+
+    /// The controller buttons held, for the input viewer.
+    pub fn sources(&self) -> &InputSources {
+        &self.sources
+    }
+
+    fn open_first(&self) -> Option<GameController> {
+        let count = self.subsystem.num_joysticks().ok()?;
+
+        (0..count)
+            .filter(|&index| self.subsystem.is_game_controller(index))
+            .find_map(|index| self.subsystem.open(index).ok())
+    }
+}
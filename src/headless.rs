@@ -0,0 +1,277 @@
+use std::{
+    fs,
+    io::Write,
+    sync::mpsc,
+};
+use sdl2::pixels::Color;
+use crate::{
+    cli::{Options, SerialPatterns},
+    gameboy::{Buttons, FrameEnd, GameBoy},
+    palette,
+    screenshot,
+    script::{self, Action, Command},
+};
+
+/// Runs the ROM for the requested number of frames without a window or audio device, then saves a screenshot or
+/// prints the screen hash. Debug events are printed to stderr as they happen. While the LCD is off, a frame's worth of
+/// time counts as a frame.
+///
+/// The commands of a script run at the start of their frame, with the buttons it holds latched for every frame.
+///
+/// With `--exit-on-serial`, the run ends at the end of the frame the game sent the pass or fail pattern in, and
+/// running out of frames before it does is a failure.
+///
+/// The config file is ignored, so that runs are reproducible.
+pub(crate) fn run(options: &Options) -> Result<(), String> {
+    let rom_path = options.rom_path.as_ref().ok_or("No ROM given")?;
+
+    let script = match &options.script_path {
+        Some(path) => script::load(path)?,
+        None => Vec::new(),
+    };
+    let mut commands = script.iter().peekable();
+
+    let frames = options.frames.into_iter().chain(options.timeout_frames).min()
+        .or_else(|| script.last().map(|command| command.frame))
+        .ok_or("No frame count given")?;
+
+    let rom = fs::read(rom_path).map_err(|_| "Could not read ROM file")?;
+
+    let mut gameboy = GameBoy::new();
+    options.configure(&mut gameboy)?;
+    gameboy.set_accuracy(options.accuracy.unwrap_or_default());
+    // Nothing plays the audio, so it isn't made.
+    gameboy.set_audio_enabled(false);
+    gameboy.load(rom).map_err(|e| e.to_string())?;
+
+    // Bytes are sent from within a frame, and only looked at between frames.
+    let mut serial = options.exit_on_serial.as_ref().map(|patterns| {
+        let (sender, receiver) = mpsc::channel();
+
+        gameboy.set_serial_callback(Some(Box::new(move |_, byte| {
+            let _ = sender.send(byte);
+        })));
+
+        (SerialWatch::new(patterns), receiver)
+    });
+    let mut verdict = None;
+
+    let palette = options.palette.unwrap_or(palette::GREY);
+    let mut buttons = Buttons::empty();
+    let mut failed_assertions = Vec::new();
+    let mut completed = 0;
+
+    loop {
+        while let Some(command) = commands.next_if(|command| command.frame <= completed) {
+            if let Some(failure) = run_command(command, &mut gameboy, &mut buttons, &palette, completed)? {
+                failed_assertions.push(failure);
+            }
+        }
+
+        if completed >= frames || verdict.is_some() {
+            break;
+        }
+
+        gameboy.latch_input(buttons);
+
+        match gameboy.run_frame() {
+            Some(FrameEnd::Watchpoint(event) | FrameEnd::Locked(event)) => eprintln!("{event}"),
+            _ => {
+                completed += 1;
+
+                for event in gameboy.drain_events() {
+                    eprintln!("{event}");
+                }
+            }
+        }
+
+        if let Some((watch, bytes)) = &mut serial {
+            let mut stdout = std::io::stdout().lock();
+
+            for byte in bytes.try_iter() {
+                let _ = stdout.write_all(&[byte]);
+
+                verdict = verdict.or(watch.push(byte));
+            }
+
+            let _ = stdout.flush();
+        }
+    }
+
+    // The serial output doesn't necessarily end its last line.
+    if serial.is_some() {
+        println!();
+    }
+
+    match &options.screenshot_path {
+        Some(path) => {
+            let blend = options.frame_blend.map(|weight| (gameboy.previous_screen(), weight));
+
+            screenshot::save_png(path, gameboy.screen(), blend, &palette)?
+        }
+        None if options.script_path.is_none() && options.exit_on_serial.is_none() => println!("{:016x}", screenshot::screen_hash(gameboy.screen())),
+        None => {}
+    }
+
+    if !failed_assertions.is_empty() {
+        return Err(format!("Script assertions failed:\n{}", failed_assertions.join("\n")));
+    }
+
+    match (verdict, &options.exit_on_serial) {
+        (Some(Verdict::Failed), Some(patterns)) => Err(format!("The game sent \"{}\" over the serial port at frame {completed}", patterns.fail)),
+        (None, Some(patterns)) => Err(format!("The game sent neither \"{}\" nor \"{}\" over the serial port in {completed} frames", patterns.pass, patterns.fail)),
+        _ => Ok(()),
+    }
+}
+
+/// Runs a command of a script at the start of frame `frame`, with `buttons` the buttons the script holds. Returns the
+/// message of a failed assertion, which doesn't stop the run.
+fn run_command(command: &Command, gameboy: &mut GameBoy, buttons: &mut Buttons, palette: &[Color; 4], frame: u64) -> Result<Option<String>, String> {
+    match &command.action {
+        Action::Press(button) => *buttons |= *button,
+        Action::Release(button) => *buttons -= *button,
+        Action::Screenshot(path) => screenshot::save_png(path, gameboy.screen(), None, palette)?,
+        Action::AssertHash(hash) => {
+            let actual = screenshot::screen_hash(gameboy.screen());
+
+            if actual != *hash {
+                return Ok(Some(format!("line {}: expected screen hash {hash:016x} at frame {frame}, got {actual:016x}", command.line)));
+            }
+        }
+        Action::Poke { addr, value } => gameboy.poke(*addr, *value),
+    }
+
+    Ok(None)
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Verdict {
+    Passed,
+    Failed,
+}
+
+/// Looks for the pass and fail patterns in the serial output, a byte at a time, so that a pattern sent across two
+/// frames is still found.
+struct SerialWatch {
+    pass: Vec<u8>,
+    fail: Vec<u8>,
+    /// The last bytes sent, as many as the longer pattern has.
+    recent: Vec<u8>,
+}
+
+impl SerialWatch {
+    fn new(patterns: &SerialPatterns) -> Self {
+        Self {
+            pass: patterns.pass.as_bytes().to_vec(),
+            fail: patterns.fail.as_bytes().to_vec(),
+            recent: Vec::new(),
+        }
+    }
+
+    /// Records a byte sent, and returns the verdict if it completes either pattern.
+    fn push(&mut self, byte: u8) -> Option<Verdict> {
+        if self.recent.len() == self.pass.len().max(self.fail.len()) {
+            self.recent.remove(0);
+        }
+
+        self.recent.push(byte);
+
+        if self.recent.ends_with(&self.fail) {
+            Some(Verdict::Failed)
+        } else if self.recent.ends_with(&self.pass) {
+            Some(Verdict::Passed)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use super::*;
+
+    fn command(action: Action) -> Command {
+        Command { frame: 0, action, line: 7 }
+    }
+
+    fn run_action(gameboy: &mut GameBoy, buttons: &mut Buttons, action: Action) -> Option<String> {
+        run_command(&command(action), gameboy, buttons, &palette::GREY, 3).unwrap()
+    }
+
+    fn loaded() -> GameBoy {
+        let mut gameboy = GameBoy::new();
+        gameboy.load_flat(Vec::new());
+
+        gameboy
+    }
+
+    #[test]
+    fn presses_and_releases_the_buttons_the_script_holds() {
+        let mut gameboy = loaded();
+        let mut buttons = Buttons::empty();
+
+        assert_eq!(run_action(&mut gameboy, &mut buttons, Action::Press(Buttons::A)), None);
+        assert_eq!(run_action(&mut gameboy, &mut buttons, Action::Press(Buttons::Start)), None);
+        assert_eq!(run_action(&mut gameboy, &mut buttons, Action::Release(Buttons::A)), None);
+
+        assert_eq!(buttons, Buttons::Start);
+    }
+
+    #[test]
+    fn pokes_memory() {
+        let mut gameboy = loaded();
+
+        assert_eq!(run_action(&mut gameboy, &mut Buttons::empty(), Action::Poke { addr: 0xc123, value: 0x05 }), None);
+        assert_eq!(gameboy.peek(0xc123), 0x05);
+    }
+
+    #[test]
+    fn reports_failed_hash_assertions() {
+        let mut gameboy = loaded();
+        gameboy.run_frame();
+
+        let hash = screenshot::screen_hash(gameboy.screen());
+
+        assert_eq!(run_action(&mut gameboy, &mut Buttons::empty(), Action::AssertHash(hash)), None);
+        assert_eq!(
+            run_action(&mut gameboy, &mut Buttons::empty(), Action::AssertHash(hash ^ 1)),
+            Some(format!("line 7: expected screen hash {:016x} at frame 3, got {hash:016x}", hash ^ 1)),
+        );
+    }
+
+    #[test]
+    fn fails_on_screenshots_that_cant_be_saved() {
+        let mut gameboy = loaded();
+        let path = PathBuf::from("/nonexistent directory/shot.png");
+
+        assert!(run_command(&command(Action::Screenshot(path)), &mut gameboy, &mut Buttons::empty(), &palette::GREY, 0).is_err());
+    }
+
+    /// Sends the bytes of `chunks` one chunk, like one frame's, at a time, and returns the verdict after each chunk.
+    fn watch_chunks(watch: &mut SerialWatch, chunks: &[&str]) -> Vec<Option<Verdict>> {
+        chunks.iter().map(|chunk| chunk.bytes().fold(None, |verdict, byte| verdict.or(watch.push(byte)))).collect()
+    }
+
+    #[test]
+    fn finds_a_pattern_sent_across_chunks() {
+        let mut watch = SerialWatch::new(&SerialPatterns::default());
+
+        assert_eq!(watch_chunks(&mut watch, &["Test 1: Pa", "ss", "ed"]), [None, None, Some(Verdict::Passed)]);
+    }
+
+    #[test]
+    fn finds_the_fail_pattern_after_a_partial_pass_pattern() {
+        let mut watch = SerialWatch::new(&SerialPatterns::default());
+
+        assert_eq!(watch_chunks(&mut watch, &["Pass", "\n", "F", "ailed"]), [None, None, None, Some(Verdict::Failed)]);
+    }
+
+    #[test]
+    fn forgets_bytes_older_than_the_longest_pattern() {
+        let patterns = SerialPatterns { pass: "ok".to_string(), fail: "fail".to_string() };
+        let mut watch = SerialWatch::new(&patterns);
+
+        assert_eq!(watch_chunks(&mut watch, &["o", "xxxx", "k", "o", "k"]), [None, None, None, None, Some(Verdict::Passed)]);
+    }
+}
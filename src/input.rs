@@ -0,0 +1,287 @@
+use crate::gameboy::{Buttons, GameBoy, FRAMES_PER_SECOND};
+
+pub(crate) const DEFAULT_TURBO_RATE: u8 = 15;
+
+const DIRECTIONS: Buttons = Buttons::Right.union(Buttons::Left).union(Buttons::Up).union(Buttons::Down);
+
+/// What a key is bound to.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Control {
+    Button(Buttons),
+    /// Presses and releases the button repeatedly while held.
+    Turbo(Buttons),
+}
+
+/// Turns the keys held in the frontend into the buttons the game sees. Presses and releases only change what is held
+/// here, and the game sees them when `apply` latches them at the start of the next frame.
+pub(crate) struct Input {
+    held: Buttons,
+    turbo: Buttons,
+    /// Held directions hidden from the game, because the opposite direction was pressed after them.
+    masked_directions: Buttons,
+    /// How many times per second turbo buttons are pressed.
+    turbo_rate: u8,
+    /// Frames since a turbo button was first held, so every turbo press starts pressed.
+    turbo_frames: u32,
+    /// The buttons latched for the current frame.
+    latched: Buttons,
+    /// The frame each button was last latched pressed or released on, by bit, for the input viewer.
+    toggled_at: [Option<u64>; 8],
+}
+
+/// What the input viewer shows of `Input`, copied so it can be drawn on another thread.
+#[derive(Clone, Copy)]
+pub(crate) struct InputSnapshot {
+    /// The buttons the game sees this frame.
+    pub latched: Buttons,
+    /// The buttons held in the frontend, including turbo buttons between presses and masked directions, which the
+    /// game doesn't see.
+    pub held: Buttons,
+    pub toggled_at: [Option<u64>; 8],
+}
+
+impl InputSnapshot {
+    /// The frame `button` was last latched pressed or released on, if it ever was.
+    pub fn toggled_at(&self, button: Buttons) -> Option<u64> {
+        self.toggled_at[button.bits().trailing_zeros() as usize]
+    }
+}
+
+impl Input {
+    pub fn new(turbo_rate: u8) -> Self {
+        Self {
+            held: Buttons::empty(),
+            turbo: Buttons::empty(),
+            masked_directions: Buttons::empty(),
+            turbo_rate,
+            turbo_frames: 0,
+            latched: Buttons::empty(),
+            toggled_at: [None; 8],
+        }
+    }
+
+    pub fn set_turbo_rate(&mut self, turbo_rate: u8) {
+        self.turbo_rate = turbo_rate;
+    }
+
+    pub fn press(&mut self, control: Control) {
+        match control {
+            Control::Button(button) => {
+                self.held |= button;
+
+                // The D-pad can't press opposite directions together, and some games misbehave when they are, so
+                // the newer press hides the older one until either is released.
+                self.masked_directions = (self.masked_directions | opposite_directions(button)) - button;
+            }
+            Control::Turbo(button) => {
+                if self.turbo.is_empty() {
+                    self.turbo_frames = 0;
+                }
+
+                self.turbo |= button;
+            }
+        }
+    }
+
+    pub fn release(&mut self, control: Control) {
+        match control {
+            Control::Button(button) => {
+                self.held -= button;
+                self.masked_directions -= button | opposite_directions(button);
+            }
+            Control::Turbo(button) => self.turbo -= button,
+        }
+    }
+
+    /// Releases every button, which the game sees on the next frame.
+    pub fn release_all(&mut self) {
+        self.held = Buttons::empty();
+        self.turbo = Buttons::empty();
+        self.masked_directions = Buttons::empty();
+    }
+
+    /// Latches the buttons of `gameboy` for the frame about to start.
+    pub fn apply(&mut self, gameboy: &mut GameBoy) {
+        let mut buttons = self.held - self.masked_directions;
+
+        if !self.turbo.is_empty() {
+            // Turbo buttons toggle on frame boundaries, so the game sees each state for whole frames.
+            let frames_per_toggle = (FRAMES_PER_SECOND / (self.turbo_rate as f64 * 2.0)).round().max(1.0) as u32;
+
+            if (self.turbo_frames / frames_per_toggle) & 1 == 0 {
+                buttons |= self.turbo;
+            }
+
+            self.turbo_frames += 1;
+        }
+
+        for button in (buttons ^ self.latched).iter() {
+            self.toggled_at[button.bits().trailing_zeros() as usize] = Some(gameboy.frame_count());
+        }
+
+        self.latched = buttons;
+
+        gameboy.latch_input(buttons);
+    }
+
+    pub fn snapshot(&self) -> InputSnapshot {
+        InputSnapshot {
+            latched: self.latched,
+            held: self.held | self.turbo,
+            toggled_at: self.toggled_at,
+        }
+    }
+}
+
+/// The keys or controller buttons holding each button, by name, for the input viewer. The emulation thread only knows
+/// the buttons, so this follows the presses and releases sent to it.
+#[derive(Default)]
+pub(crate) struct InputSources {
+    held: Vec<(Control, String)>,
+}
+
+impl InputSources {
+    pub fn press(&mut self, control: Control, name: String) {
+        if !self.held.iter().any(|(held, held_name)| *held == control && *held_name == name) {
+            self.held.push((control, name));
+        }
+    }
+
+    pub fn release(&mut self, control: Control, name: &str) {
+        self.held.retain(|(held, held_name)| *held != control || held_name != name);
+    }
+
+    pub fn clear(&mut self) {
+        self.held.clear();
+    }
+
+    /// The names of the sources holding `button`, turbo ones marked as such.
+    pub fn names(&self, button: Buttons) -> Vec<String> {
+        self.held.iter()
+            .filter_map(|(control, name)| match *control {
+                Control::Button(buttons) if buttons.contains(button) => Some(name.clone()),
+                Control::Turbo(buttons) if buttons.contains(button) => Some(format!("{name} (turbo)")),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+/// Keeps frontend hotkeys away from the game. A key pressed while a chord modifier is held, like O in Ctrl+O, is a
+/// hotkey, and neither its press nor its release is passed on, even if the modifier is let go of first. A key pressed
+/// before the modifier is played as usual.
+///
+/// It is generic over the keys so that it doesn't depend on the windowing library.
+pub(crate) struct Chords<K> {
+    /// Chord modifiers held.
+    modifiers: Vec<K>,
+    /// Keys pressed as hotkeys and not released yet.
+    hotkeys: Vec<K>,
+}
+
+impl<K: Copy + PartialEq> Chords<K> {
+    pub fn new() -> Self {
+        Self {
+            modifiers: Vec::new(),
+            hotkeys: Vec::new(),
+        }
+    }
+
+    /// Records a press of `key`, which is a chord modifier if `modifier` is set. Returns whether it is a hotkey,
+    /// hidden from the game. Repeats must not be passed, since one of a key pressed before the modifier would make it
+    /// a hotkey, whose release the game never sees.
+    pub fn press(&mut self, key: K, modifier: bool) -> bool {
+        if modifier {
+            if !self.modifiers.contains(&key) {
+                self.modifiers.push(key);
+            }
+
+            return false;
+        }
+
+        if self.modifiers.is_empty() {
+            return false;
+        }
+
+        if !self.hotkeys.contains(&key) {
+            self.hotkeys.push(key);
+        }
+
+        true
+    }
+
+    /// Records a release of `key`. Returns whether it was pressed as a hotkey, so that the game doesn't see it.
+    pub fn release(&mut self, key: K) -> bool {
+        self.modifiers.retain(|&modifier| modifier != key);
+
+        let len = self.hotkeys.len();
+        self.hotkeys.retain(|&hotkey| hotkey != key);
+
+        self.hotkeys.len() != len
+    }
+
+    /// Forgets every held key, for when releases can't arrive, like while the window is inactive.
+    pub fn clear(&mut self) {
+        self.modifiers.clear();
+        self.hotkeys.clear();
+    }
+}
+
+fn opposite_directions(buttons: Buttons) -> Buttons {
+    let directions = (buttons & DIRECTIONS).bits();
+
+    // Right and left, and up and down, are adjacent bits.
+    Buttons::from_bits_retain((directions & 0b0101) << 1 | (directions & 0b1010) >> 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CTRL: char = 'c';
+
+    #[test]
+    fn keys_without_a_modifier_are_not_hotkeys() {
+        let mut chords = Chords::new();
+
+        assert!(!chords.press('a', false));
+        assert!(!chords.release('a'));
+    }
+
+    #[test]
+    fn chords_are_hidden_until_released() {
+        let mut chords = Chords::new();
+
+        assert!(!chords.press(CTRL, true));
+        assert!(chords.press('a', false));
+
+        // The modifier is let go of first, but the release of the hotkey is still hidden.
+        assert!(!chords.release(CTRL));
+        assert!(chords.release('a'));
+
+        assert!(!chords.press('a', false));
+        assert!(!chords.release('a'));
+    }
+
+    #[test]
+    fn keys_held_before_the_modifier_are_played() {
+        let mut chords = Chords::new();
+
+        assert!(!chords.press('a', false));
+        assert!(!chords.press(CTRL, true));
+        assert!(!chords.release('a'));
+        assert!(!chords.release(CTRL));
+    }
+
+    #[test]
+    fn clear_forgets_held_keys() {
+        let mut chords = Chords::new();
+
+        chords.press(CTRL, true);
+        chords.press('a', false);
+        chords.clear();
+
+        assert!(!chords.release('a'));
+        assert!(!chords.press('b', false));
+    }
+}
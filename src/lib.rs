@@ -0,0 +1,9 @@
+//! The emulator core as a library, without any of the frontend, for the examples and the documentation's examples.
+//!
+//! The executable builds the same module on its own, since the frontend also uses the core's crate-private parts.
+
+#[allow(dead_code)]
+pub mod gameboy;
+
+#[macro_use]
+extern crate bitflags;
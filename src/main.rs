@@ -1,12 +1,37 @@
+﻿mod audio_output;
+mod cli;
+mod config;
 mod dialog;
+mod emulation;
+mod filter;
 mod gameboy;
+mod gamepad;
+mod headless;
+mod input;
 mod menu;
+mod overlay;
+mod palette;
+mod paths;
+mod placement;
+mod recorder;
+mod rom_info;
+mod screenshot;
+mod script;
+mod slot_picker;
+mod slowdown;
+mod stats;
+mod stretch;
+mod title;
+mod triple_buffer;
 
 use std::{
+    collections::VecDeque,
     fs,
+    path::{Path, PathBuf},
     ptr::addr_of_mut,
     time::{Duration, Instant},
     sync::{Arc, Mutex},
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
 };
 
 use sdl2::{
@@ -14,71 +39,413 @@ use sdl2::{
     messagebox::MessageBoxFlag,
     pixels::{Color, PixelFormatEnum},
     rect::{Point, Rect},
-    render::{TextureCreator, TextureQuery, WindowCanvas},
-    ttf::Font,
+    rwops::RWops,
     video::Window,
-    video::WindowContext,
     VideoSubsystem,
-    audio::{AudioCallback, AudioDevice, AudioStatus},
 };
 use tao::{
-    dpi::PhysicalSize,
-    event::{DeviceEvent, Event, WindowEvent},
-    event::{ElementState, RawKeyEvent},
+    dpi::{LogicalSize, PhysicalPosition, PhysicalSize},
+    event::{Event, WindowEvent},
+    event::{ElementState, KeyEvent},
     event_loop::{ControlFlow, EventLoop},
-    keyboard::KeyCode,
+    keyboard::{KeyCode, ModifiersState},
     platform::run_return::EventLoopExtRunReturn,
     platform::windows::WindowExtWindows,
     window::WindowBuilder,
     menu::MenuId,
 };
 use crate::{
-    gameboy::{Buttons, GameBoy},
-    menu::MENU_OPEN,
+    audio_output::{AudioOutput, Callback, DeviceChange},
+    cli::Options,
+    config::{AccuracyProfile, Config, ModelSelection},
+    emulation::{AudioQueue, Emulator},
+    filter::FilterOverlay,
+    gameboy::{Accuracy, CartridgeHeader, GameBoy, SerialEndpoint, OAM_ENTRY_COUNT},
+    gamepad::Gamepad,
+    input::{Chords, Input, InputSources},
+    menu::{
+        FrontendState, MenuHandles, Toggle, MENU_ACCURACY_PROFILES, MENU_AUTO_FAST_PROFILE, MENU_FILTERS, MENU_FRAME_BLEND, MENU_MODELS,
+        MENU_MUTE_FAST_SPEEDS, MENU_OPEN, MENU_PALETTES, MENU_PAUSE_WHEN_INACTIVE, MENU_RECORD, MENU_ROM_INFO, MENU_SPEEDS, MENU_STREAMER_MODE,
+        MENU_STRETCH_MODES, MENU_SYNC_MODES, MENU_TURBO_RATES,
+    },
+    overlay::{
+        render_audio_inspector, render_channel_levels, render_idle_screen, render_input_viewer, render_line_stats, render_oam_inspector,
+        render_slot_picker, render_text,
+    },
+    paths::Paths,
+    placement::MonitorArea,
+    recorder::{AudioTap, Recorder},
+    slot_picker::{Slot, SlotPicker, SLOT_COLUMNS, SLOT_COUNT},
+    slowdown::Slowdown,
+    stats::Stats,
+    title::{Title, TitleState, WINDOW_TITLE},
 };
 
 #[macro_use]
 extern crate bitflags;
 
-const FRAME_DURATION: Duration = Duration::from_micros(16_742);
-
-const COLORS: [Color; 4] = [
-    Color::RGB(0xff, 0xff, 0xff),
-    Color::RGB(0xc0, 0xc0, 0xc0),
-    Color::RGB(0x40, 0x40, 0x40),
-    Color::RGB(0, 0, 0),
-];
-// const COLORS: [Color; 4] = [
-//     Color::RGB(0xe2, 0xf3, 0xe4),
-//     Color::RGB(0x94, 0xe3, 0x44),
-//     Color::RGB(0x46, 0x87, 0x8f),
-//     Color::RGB(0x33, 0x2c, 0x50),
-// ];
-
-struct Callback {
-    gameboy: Arc<Mutex<GameBoy>>,
+const FONT: &[u8] = include_bytes!("../JetBrainsMono-Regular.ttf");
+
+// How often the event loop checks for a frame once one is due, and how early before that it starts. SDL sets the
+// timer resolution to 1 ms on Windows, so such short waits are kept.
+const FRAME_POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+/// The ROM that is running, and what its header says about it.
+struct LoadedRom {
+    path: PathBuf,
+    header: CartridgeHeader,
+    save_path: PathBuf,
+}
+
+struct Context {
+    pub audio_output: AudioOutput,
+    /// Whether emulation is stopped, because no ROM is loaded yet, it was paused from the menu, a modal dialog is open
+    /// or the window is inactive. Shared with the audio callback, which outputs silence while it is set.
+    pub paused: Arc<AtomicBool>,
+    /// Shared with the audio callback, which plays silence while it is set.
+    pub muted: Arc<AtomicBool>,
+    pub audio_tap: AudioTap,
+    pub recorder: Option<Recorder>,
+    pub emulator: Emulator,
+    /// The second game of a split view, connected to the first by a link cable, see `--link`. It follows the first
+    /// when paused or resumed, and is controlled by a game controller.
+    pub linked: Option<Emulator>,
+    pub rom: Option<LoadedRom>,
+    pub palette: [Color; 4],
+    /// Percent of the previous frame mixed into every frame, if frame blending is on.
+    pub frame_blend: Option<u8>,
+    pub menu: MenuHandles,
+    /// Whether emulation was paused from the menu, which only the menu resumes from.
+    pub user_paused: bool,
+    /// Number of modal dialogs blocking the event loop, which can nest when one reports an error.
+    pub modal_depth: u32,
+    /// Accuracy features enabled on top of the profile, from the command line or the config.
+    pub accuracy: Accuracy,
+    /// The overlays toggled by F3-F5, F11, F12 or the Options menu, for this session only.
+    pub stats_in_title: bool,
+    pub show_oam: bool,
+    pub show_lines: bool,
+    pub show_audio: bool,
+    pub show_input: bool,
+    /// The keys holding the buttons of the first game, for the input viewer.
+    pub key_sources: InputSources,
+    /// The save state slots, while F6 is held.
+    pub slot_picker: Option<SlotPicker>,
+    /// The slot the picker opens on: the one last loaded or saved, for this session only.
+    pub state_slot: u8,
+    /// Whether the first game keeps up with the speed, reset whenever emulation pauses, resumes or changes speed.
+    pub slowdown: Slowdown,
+    /// Whether the accuracy profile was switched to Fast for the slowdown shown, see `Config::auto_fast_profile`.
+    pub switched_to_fast: bool,
+    /// Whether the overlay font loaded, without which overlays with text can't be drawn.
+    pub overlays_available: bool,
+    /// Whether the user was told that overlays are unavailable, which is only done once.
+    pub overlays_unavailable_shown: bool,
+    /// Whether the menu bar, the window borders and overlays are hidden, see `Config::streamer_mode`.
+    pub streamer_mode: bool,
+    pub paths: Paths,
 }
 
-impl AudioCallback for Callback {
-    type Channel = f32;
+impl Context {
+    fn emulators(&self) -> impl Iterator<Item = &Emulator> {
+        std::iter::once(&self.emulator).chain(&self.linked)
+    }
 
-    fn callback(&mut self, buffer: &mut [Self::Channel]) {
-        let mut gameboy = self.gameboy.lock().unwrap();
+    /// Stops emulation and the audio device, releasing all buttons. Returns whether emulation was running before.
+    fn pause(&mut self) -> bool {
+        let was_running = !self.paused.swap(true, Ordering::Relaxed);
 
-        while gameboy.audio_buffer_size() < gameboy::apu::AUDIO_BUFFER_SIZE {
-            gameboy.tick();
+        // Pausing is usually followed by a dialog or the window losing focus, which keeps releases from arriving.
+        for emulator in self.emulators() {
+            emulator.release_all_buttons();
+            emulator.pause();
         }
 
-        buffer.copy_from_slice(gameboy.extract_audio_buffer().as_slice());
+        self.key_sources.clear();
+        self.slowdown.reset();
+
+        self.audio_output.pause();
+
+        was_running
     }
-}
 
-struct Context {
-    pub audio_device: AudioDevice<Callback>,
+    /// Shows a message box, releasing all buttons first since it blocks the event loop and any releases with it.
+    fn show_message_box(&mut self, config: &Config, flags: MessageBoxFlag, msg: &str) {
+        self.emulator.release_all_buttons();
+        self.key_sources.clear();
+
+        self.begin_modal(config);
+        sdl2::messagebox::show_simple_message_box(flags, "YAGBE", msg, None).unwrap();
+        self.end_modal(config);
+    }
+
+    /// Warns about a ROM that likely won't run correctly, before it starts running.
+    fn warn_about_rom(&mut self, config: &Config, rom: &LoadedRom) {
+        if let Some(warning) = rom_info::load_warning(&rom.header) {
+            self.show_message_box(config, MessageBoxFlag::WARNING, warning);
+        }
+    }
+
+    /// Tells the user that overlays are unavailable, the first time one is toggled without the font. Returns whether it
+    /// did, in which case the overlay is left as it is.
+    fn warn_overlays_unavailable(&mut self, config: &Config) -> bool {
+        if self.overlays_available || self.overlays_unavailable_shown {
+            return false;
+        }
+
+        self.overlays_unavailable_shown = true;
+        self.show_message_box(config, MessageBoxFlag::WARNING, "The overlay font could not be loaded, so overlays are unavailable.");
+
+        true
+    }
+
+    // The toggles below are shared by the hotkeys and the menu, and update the menu themselves, so the check marks
+    // follow whichever was used.
+
+    fn toggle(&mut self, config: &mut Config, toggle: Toggle) {
+        match toggle {
+            Toggle::Pause => self.toggle_pause(config),
+            Toggle::Mute => self.toggle_mute(config),
+            Toggle::ShowFps => self.toggle_show_fps(config),
+            Toggle::StatsInTitle => self.toggle_stats_in_title(config),
+            Toggle::OamInspector => self.toggle_oam_inspector(config),
+            Toggle::LineStats => self.toggle_line_stats(config),
+            Toggle::AudioInspector => self.toggle_audio_inspector(config),
+            Toggle::InputViewer => self.toggle_input_viewer(config),
+        }
+    }
+
+    fn toggle_pause(&mut self, config: &Config) {
+        if self.rom.is_none() {
+            return;
+        }
+
+        if self.user_paused {
+            self.user_paused = false;
+            self.resume();
+        } else {
+            self.user_paused = true;
+            self.pause();
+        }
+
+        self.update_menu(config);
+    }
+
+    fn toggle_mute(&mut self, config: &Config) {
+        self.muted.fetch_xor(true, Ordering::Relaxed);
+
+        self.update_menu(config);
+    }
+
+    fn toggle_show_fps(&mut self, config: &mut Config) {
+        if self.warn_overlays_unavailable(config) {
+            return;
+        }
+
+        config.show_fps = !config.show_fps;
+        save_config(config);
+
+        self.update_menu(config);
+    }
+
+    fn toggle_stats_in_title(&mut self, config: &Config) {
+        self.stats_in_title = !self.stats_in_title;
+
+        self.update_menu(config);
+    }
+
+    fn toggle_oam_inspector(&mut self, config: &Config) {
+        if self.warn_overlays_unavailable(config) {
+            return;
+        }
+
+        self.show_oam = !self.show_oam;
+        self.emulator.inspect_oam(self.show_oam);
+
+        self.update_menu(config);
+    }
+
+    fn toggle_line_stats(&mut self, config: &Config) {
+        self.show_lines = !self.show_lines;
+        self.emulator.inspect_lines(self.show_lines);
+
+        self.update_menu(config);
+    }
+
+    fn toggle_audio_inspector(&mut self, config: &Config) {
+        if self.warn_overlays_unavailable(config) {
+            return;
+        }
+
+        self.show_audio = !self.show_audio;
+        self.emulator.inspect_audio(self.show_audio);
+
+        self.update_menu(config);
+    }
+
+    fn toggle_input_viewer(&mut self, config: &Config) {
+        if self.warn_overlays_unavailable(config) {
+            return;
+        }
+
+        self.show_input = !self.show_input;
+
+        for emulator in self.emulators() {
+            emulator.inspect_input(self.show_input);
+        }
+
+        self.update_menu(config);
+    }
+
+    /// Opens the slot picker on the slot last used, if a game is loaded and overlays are shown.
+    fn open_slot_picker(&mut self, config: &Config) {
+        if self.rom.is_none() || self.streamer_mode || self.warn_overlays_unavailable(config) {
+            return;
+        }
+
+        if let Some(rom) = &self.rom {
+            self.slot_picker = Some(SlotPicker::open(&self.paths, &rom.path, &rom.header, self.state_slot));
+        }
+    }
+
+    /// Loads the state in the slot selected in the picker, or saves one into it if `save` is set, and closes the
+    /// picker. An empty slot can't be loaded, which leaves the picker open.
+    fn use_state_slot(&mut self, config: &Config, save: bool) {
+        let (Some(picker), Some(rom)) = (&self.slot_picker, &self.rom) else {
+            return;
+        };
+
+        let slot = picker.selected;
+
+        if !save && matches!(picker.slots[slot as usize], Slot::Empty) {
+            return;
+        }
+
+        let path = self.paths.state_path(&rom.path, &rom.header, slot);
+        let result = match save {
+            true => save_state_file(&self.emulator, &path),
+            false => load_state_file(&self.emulator, &path),
+        };
+
+        self.slot_picker = None;
+        self.state_slot = slot;
+
+        if let Err(msg) = result {
+            self.show_message_box(config, MessageBoxFlag::ERROR, &msg);
+        }
+    }
+
+    /// Marks a modal dialog as open, so that the menu can't open another one over it.
+    fn begin_modal(&mut self, config: &Config) {
+        self.modal_depth += 1;
+        self.update_menu(config);
+    }
+
+    fn end_modal(&mut self, config: &Config) {
+        self.modal_depth -= 1;
+        self.update_menu(config);
+    }
+
+    fn update_menu(&mut self, config: &Config) {
+        let state = FrontendState {
+            rom_loaded: self.rom.is_some(),
+            modal_open: self.modal_depth > 0,
+            paused: self.user_paused,
+            recording: self.recorder.is_some(),
+            show_fps: config.show_fps,
+            stats_in_title: self.stats_in_title,
+            show_oam: self.show_oam,
+            show_lines: self.show_lines,
+            show_audio: self.show_audio,
+            show_input: self.show_input,
+            pause_when_inactive: config.pause_when_inactive,
+            muted: self.muted.load(Ordering::Relaxed),
+            turbo_rate: config.turbo_rate,
+            palette: self.palette,
+            stretch_mode: config.stretch_mode,
+            filter: config.filter,
+            frame_blend: self.frame_blend.is_some(),
+            streamer_mode: self.streamer_mode,
+            accuracy_profile: config.accuracy_profile,
+            auto_fast_profile: config.auto_fast_profile,
+            speed: config.speed,
+            mute_fast_speeds: config.mute_fast_speeds,
+            sync_mode: config.sync_mode,
+            model: config.model,
+        };
+
+        menu::update_menu_state(&mut self.menu, &state);
+    }
+
+    /// Switches to `profile` between two frames, keeping the game running.
+    fn set_accuracy_profile(&self, config: &mut Config, profile: AccuracyProfile) {
+        config.accuracy_profile = profile;
+
+        for emulator in self.emulators() {
+            emulator.set_accuracy(profile.apply(self.accuracy));
+        }
+
+        save_config(config);
+    }
+
+    fn set_speed(&mut self, config: &mut Config, speed: u16) {
+        config.speed = speed;
+        self.slowdown.reset();
+
+        for emulator in self.emulators() {
+            emulator.set_speed(speed, config.mute_fast_speeds);
+        }
+
+        save_config(config);
+    }
+
+    /// Switches to the next faster or slower speed in the menu, if there is one. A speed from the config file that
+    /// isn't in the menu switches to the nearest one in that direction.
+    fn step_speed(&mut self, config: &mut Config, faster: bool) {
+        let mut speeds = MENU_SPEEDS.iter().map(|&(_, speed)| speed);
+
+        let speed = if faster {
+            speeds.find(|&speed| speed > config.speed)
+        } else {
+            speeds.rev().find(|&speed| speed < config.speed)
+        };
+
+        if let Some(speed) = speed {
+            self.set_speed(config, speed);
+        }
+    }
+
+    fn resume(&mut self) {
+        self.paused.store(false, Ordering::Relaxed);
+        self.slowdown.reset();
+
+        for emulator in self.emulators() {
+            emulator.resume();
+        }
+
+        self.audio_output.resume();
+    }
 }
 
 fn main() -> Result<(), String> {
-    if let Err(msg) = run() {
+    let options = match cli::parse(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(msg) => {
+            eprintln!("{msg}\n\n{}", cli::USAGE);
+            std::process::exit(2);
+        }
+    };
+
+    if options.help {
+        println!("{}", cli::USAGE);
+        return Ok(());
+    }
+
+    if options.headless {
+        return headless::run(&options);
+    }
+
+    if let Err(msg) = run(&options) {
         sdl2::messagebox::show_simple_message_box(MessageBoxFlag::ERROR, "YAGBE", &msg, None)
             .map_err(|err| err.to_string())?;
 
@@ -88,26 +455,94 @@ fn main() -> Result<(), String> {
     Ok(())
 }
 
-fn run() -> Result<(), String> {
-    let gameboy = GameBoy::new();
-    let gameboy = Arc::new(Mutex::new(gameboy));
+fn run(options: &Options) -> Result<(), String> {
+    let (mut config, invalid_keys) = Config::load();
+
+    if !invalid_keys.is_empty() {
+        let msg = format!("Invalid values in the config file were reset to their defaults: {}", invalid_keys.join(", "));
+
+        sdl2::messagebox::show_simple_message_box(MessageBoxFlag::WARNING, "YAGBE", &msg, None)
+            .map_err(|err| err.to_string())?;
+    }
+
+    let scale = options.scale.unwrap_or(config.scale);
+    let palette = options.palette
+        .or_else(|| palette::by_name(&config.palette))
+        .unwrap_or(palette::GREY);
+    let volume = options.volume.unwrap_or(config.volume as f32 / 100.0);
+    let frame_blend = options.frame_blend.or(config.frame_blend.then_some(config.frame_blend_weight));
+
+    let mut gameboy = GameBoy::new();
+    options.configure(&mut gameboy)?;
+    let accuracy = options.accuracy.unwrap_or(config.accuracy);
+    gameboy.set_accuracy(config.accuracy_profile.apply(accuracy));
+
+    let linked_gameboy = options.link_rom_path.is_some().then(|| {
+        let (endpoint, linked_endpoint) = SerialEndpoint::pair();
+
+        let mut linked_gameboy = GameBoy::new();
+        linked_gameboy.set_accuracy(config.accuracy_profile.apply(accuracy));
+        linked_gameboy.connect_serial(Some(linked_endpoint));
+
+        gameboy.connect_serial(Some(endpoint));
+
+        linked_gameboy
+    });
+
+    // A split view shows the linked game to the right.
+    let views = if linked_gameboy.is_some() { 2 } else { 1 };
+    let content_width = gameboy::SCREEN_WIDTH as u32 * views;
+    let content_height = gameboy::SCREEN_HEIGHT as u32;
 
     // Window
     let mut event_loop = EventLoop::new();
-    let window = WindowBuilder::new()
-        .with_title("Yet Another Game Boy Emulator")
-        .with_menu(menu::build_menu())
-        .with_inner_size(PhysicalSize::new(320, 288 + menu_height()))
-        .with_resizable(false)
-        .build(&event_loop)
-        .map_err(|e| e.to_string())?;
+    // The window opens where it was in the last session, kept on the monitors connected now, and as large as it was
+    // unless a scale is given. Otherwise, it opens where the OS puts it, at `scale` in logical pixels. It can be
+    // resized from there.
+    let (screen_width, screen_height, screen_scale) = match config.window_size.filter(|_| options.scale.is_none()) {
+        Some((width, height)) => (width * views, height, 1),
+        None => (content_width, content_height, scale),
+    };
+    let primary_scale_factor = event_loop.primary_monitor().map_or(1.0, |monitor| monitor.scale_factor());
+    let position = config.window_position.and_then(|(x, y)| {
+        let monitors = event_loop.available_monitors().map(|monitor| MonitorArea::of(&monitor)).collect::<Vec<_>>();
+        let size = stretch::window_size(screen_width, screen_height, screen_scale, primary_scale_factor, menu_height(primary_scale_factor));
+
+        placement::clamp_to_monitors(PhysicalPosition::new(x, y), size, &monitors)
+    });
+    let initial_scale_factor = position
+        .and_then(|position| event_loop.available_monitors().find(|monitor| MonitorArea::of(monitor).contains(position)))
+        .map_or(primary_scale_factor, |monitor| monitor.scale_factor());
+    let initial_menu_height = menu_height(initial_scale_factor);
+    let (menu_bar, menu_handles) = menu::build_menu(&config);
+    let window_builder = WindowBuilder::new()
+        .with_title(WINDOW_TITLE)
+        .with_menu(menu_bar)
+        .with_inner_size(stretch::window_size(screen_width, screen_height, screen_scale, initial_scale_factor, initial_menu_height))
+        .with_min_inner_size(min_window_size(initial_scale_factor, initial_menu_height))
+        .with_maximized(config.window_maximized);
+    let window_builder = match position {
+        Some(position) => window_builder.with_position(position),
+        None => window_builder,
+    };
+    let window = window_builder.build(&event_loop).map_err(|e| e.to_string())?;
+
+    let streamer_mode = options.streamer_mode || config.streamer_mode;
+
+    if streamer_mode {
+        set_streamer_mode(&window, true);
+    }
 
     // SDL
     let sdl_context = sdl2::init()?;
 
     // Load a font
     let ttf_context = sdl2::ttf::init().map_err(|e| e.to_string())?;
-    let font = ttf_context.load_font("JetBrainsMono-Regular.ttf", 9)?;
+    // Overlays are disabled if the font can't be loaded, rather than failing to start.
+    let font = RWops::from_bytes(FONT)
+        .and_then(|rwops| ttf_context.load_font_from_rwops(rwops, 9))
+        .map_err(|e| eprintln!("Could not load font, overlays are disabled: {e}"))
+        .ok();
 
     // Video
     let video_subsystem = sdl_context.video()?;
@@ -122,151 +557,764 @@ fn run() -> Result<(), String> {
         .create_texture_streaming(PixelFormatEnum::RGB24, gameboy::SCREEN_WIDTH as u32, gameboy::SCREEN_HEIGHT as u32)
         .map_err(|e| e.to_string())?;
 
+    let mut linked_screen = match &linked_gameboy {
+        Some(_) => Some(texture_creator
+            .create_texture_streaming(PixelFormatEnum::RGB24, gameboy::SCREEN_WIDTH as u32, gameboy::SCREEN_HEIGHT as u32)
+            .map_err(|e| e.to_string())?),
+        None => None,
+    };
+
+    let mut sprite_thumbnails = texture_creator
+        .create_texture_streaming(PixelFormatEnum::RGB24, 8 * OAM_ENTRY_COUNT as u32, 16)
+        .map_err(|e| e.to_string())?;
+
+    let slot_rows = (SLOT_COUNT / SLOT_COLUMNS) as u32;
+    let mut slot_thumbnails = texture_creator
+        .create_texture_streaming(PixelFormatEnum::RGB24, gameboy::SCREEN_WIDTH as u32 * SLOT_COLUMNS as u32, gameboy::SCREEN_HEIGHT as u32 * slot_rows)
+        .map_err(|e| e.to_string())?;
+
+    let mut filter_overlay = FilterOverlay::new();
+
+    let mut stats = Stats::new();
+    let paused = Arc::new(AtomicBool::new(true));
+    let muted = Arc::new(AtomicBool::new(false));
+    let audio_tap: AudioTap = Arc::new(Mutex::new(None));
+    let audio: AudioQueue = Arc::new(Mutex::new(VecDeque::new()));
+
+    // Keep one more device buffer queued than is being played.
+    let audio_target = config.audio_latency as usize * 2 * 2;
+    let (emulator, mut frames) = Emulator::start(gameboy, Input::new(config.turbo_rate), audio.clone(), audio_target, stats.emulated_frames());
+
+    // The linked game's audio is mixed in by the callback, and its frames aren't counted in the statistics.
+    let linked_audio: Option<AudioQueue> = linked_gameboy.as_ref().map(|_| Arc::new(Mutex::new(VecDeque::new())));
+    let (linked, mut linked_frames) = match (linked_gameboy, &linked_audio) {
+        (Some(gameboy), Some(audio)) => {
+            let (emulator, frames) = Emulator::start(gameboy, Input::new(config.turbo_rate), audio.clone(), audio_target, Arc::new(AtomicU64::new(0)));
+
+            (Some(emulator), Some(frames))
+        }
+        _ => (None, None),
+    };
+
+    let mut gamepad = match &linked {
+        Some(_) => Some(Gamepad::new(sdl_context.game_controller()?)),
+        None => None,
+    };
+
     // Audio
     let desired_spec = AudioSpecDesired {
         freq: Some(gameboy::apu::AUDIO_SAMPLE_RATE as i32),
         channels: Some(2),
-        samples: Some(gameboy::apu::AUDIO_BUFFER_SIZE as u16 / 2),
+        samples: Some(config.audio_latency),
     };
 
     let audio_subsystem = sdl_context.audio()?;
     let audio_device = audio_subsystem.audio_playback_device_name(0)?;
-    let device = audio_subsystem.open_playback(audio_device.as_str(), &desired_spec, |_spec| {
-        Callback {
-            gameboy: gameboy.clone()
-        }
+    let audio_output = AudioOutput::open(audio_subsystem, Some(audio_device.as_str()), desired_spec, Callback {
+        audio,
+        linked_audio,
+        volume,
+        muted: muted.clone(),
+        paused: paused.clone(),
+        audio_tap: audio_tap.clone(),
     })?;
 
-    let context = Context {
-        audio_device: device,
+    let mut context = Context {
+        audio_output,
+        paused,
+        muted,
+        audio_tap,
+        recorder: None,
+        emulator,
+        linked,
+        rom: None,
+        palette,
+        frame_blend,
+        menu: menu_handles,
+        user_paused: false,
+        modal_depth: 0,
+        accuracy,
+        stats_in_title: false,
+        show_oam: false,
+        show_lines: false,
+        show_audio: false,
+        show_input: false,
+        slot_picker: None,
+        state_slot: 0,
+        slowdown: Slowdown::new(),
+        switched_to_fast: false,
+        key_sources: InputSources::default(),
+        overlays_available: font.is_some(),
+        overlays_unavailable_shown: false,
+        streamer_mode,
+        paths: Paths::new(config.save_dir.as_deref(), config.state_dir.as_deref(), options.portable),
     };
 
-    if let Some(rom_path) = std::env::args().nth(1) {
-        let rom = fs::read(rom_path).map_err(|_| "Could not read ROM file")?;
+    for emulator in context.emulators() {
+        emulator.set_speed(config.speed, config.mute_fast_speeds);
+        emulator.set_sync_mode(config.sync_mode);
+    }
+
+    if let Some(rom_path) = &options.rom_path {
+        // A ROM that can't be loaded leaves the idle screen up, to open another from the menu.
+        match load_rom(&context.emulator, rom_path, |header| context.paths.save_path(rom_path, header), config.model) {
+            Ok(rom) => {
+                context.warn_about_rom(&config, &rom);
+
+                if let (Some(linked), Some(link_rom_path)) = (&context.linked, &options.link_rom_path) {
+                    match load_rom(linked, link_rom_path, |header| context.paths.linked_save_path(link_rom_path, header), config.model) {
+                        Ok(linked_rom) => context.warn_about_rom(&config, &linked_rom),
+                        Err(msg) => context.show_message_box(&config, MessageBoxFlag::ERROR, &msg),
+                    }
+                }
+
+                context.resume();
 
-        gameboy.lock().unwrap().load(rom);
+                config.add_recent_rom(rom_path);
+                save_config(&config);
 
-        context.audio_device.resume();
+                context.rom = Some(rom);
+            }
+            Err(msg) => context.show_message_box(&config, MessageBoxFlag::ERROR, &msg),
+        }
     }
 
-    let mut show_fps = false;
+    context.update_menu(&config);
 
-    let mut frame_start = Instant::now();
-    let mut frame_delta = FRAME_DURATION;
+    let mut paused_while_inactive = false;
+    let mut chords = Chords::new();
+    let mut last_recorded_frame = 0;
+    let mut title = Title::new();
+    // When the event loop next wakes up to look for a frame, while a game runs.
+    let mut next_frame = Instant::now();
+    // Whether anything other than a new frame changed what is drawn, and whether a message on a timer was shown.
+    let mut redraw_needed = true;
+    let mut timed_message_shown = false;
+    // The scale factor the window's size was last worked out for, which changes as it moves between monitors.
+    let mut scale_factor = window.scale_factor();
+    // Shift+Enter saves into a slot of the picker.
+    let mut modifiers = ModifiersState::empty();
 
     event_loop.run_return(|event, _, control_flow| {
-        *control_flow = ControlFlow::Poll;
+        // Input, resizing, focus and the menu can all change what is drawn, from overlays to the title.
+        if matches!(event, Event::WindowEvent { .. } | Event::MenuEvent { .. }) {
+            redraw_needed = true;
+        }
 
         match event {
             Event::WindowEvent {
                 event: WindowEvent::CloseRequested,
                 ..
             } => {
+                // Save before anything else can go wrong on the way out.
+                for emulator in context.emulators() {
+                    emulator.flush_sram();
+                }
+
+                // The config is saved once the loop has exited.
+                save_window_placement(&window, views, &mut config);
+
                 *control_flow = ControlFlow::Exit;
             }
-            Event::DeviceEvent { event: DeviceEvent::Key(RawKeyEvent { physical_key, state: ElementState::Pressed }), .. } => gameboy.lock().map(|mut gameboy| {
-                match physical_key {
-                    KeyCode::F2 => show_fps = !show_fps,
-
-                    KeyCode::ArrowDown => gameboy.button_pressed(Buttons::Down),
-                    KeyCode::ArrowUp => gameboy.button_pressed(Buttons::Up),
-                    KeyCode::ArrowLeft => gameboy.button_pressed(Buttons::Left),
-                    KeyCode::ArrowRight => gameboy.button_pressed(Buttons::Right),
-
-                    KeyCode::Enter => gameboy.button_pressed(Buttons::Start),
-                    KeyCode::Tab => gameboy.button_pressed(Buttons::Select),
-                    KeyCode::AltLeft => gameboy.button_pressed(Buttons::A),
-                    KeyCode::ControlLeft => gameboy.button_pressed(Buttons::B),
-                    _ => {}
+            Event::WindowEvent {
+                event: WindowEvent::KeyboardInput { event: KeyEvent { physical_key, state, repeat, .. }, .. },
+                ..
+            } => {
+                // While the slot picker is open, the arrows pick a slot, also when held, and Enter uses it. The game
+                // doesn't see presses meanwhile, but sees releases, so nothing held before it opened stays held.
+                let picking = context.slot_picker.is_some() && state == ElementState::Pressed;
+
+                if picking {
+                    match physical_key {
+                        KeyCode::Enter | KeyCode::NumpadEnter if !repeat => context.use_state_slot(&config, modifiers.shift_key()),
+                        _ => {
+                            if let Some(picker) = &mut context.slot_picker {
+                                picker.navigate(physical_key);
+                            }
+                        }
+                    }
                 }
-            }).unwrap(),
-            Event::DeviceEvent { event: DeviceEvent::Key(RawKeyEvent { physical_key, state: ElementState::Released }), .. } => gameboy.lock().map(|mut gameboy| {
-                match physical_key {
-                    KeyCode::ArrowDown => gameboy.button_released(Buttons::Down),
-                    KeyCode::ArrowUp => gameboy.button_released(Buttons::Up),
-                    KeyCode::ArrowLeft => gameboy.button_released(Buttons::Left),
-                    KeyCode::ArrowRight => gameboy.button_released(Buttons::Right),
-
-                    KeyCode::Enter => gameboy.button_released(Buttons::Start),
-                    KeyCode::Tab => gameboy.button_released(Buttons::Select),
-                    KeyCode::AltLeft => gameboy.button_released(Buttons::A),
-                    KeyCode::ControlLeft => gameboy.button_released(Buttons::B),
-                    _ => {}
+
+                if physical_key == KeyCode::F6 && state == ElementState::Released {
+                    context.slot_picker = None;
                 }
-            }).unwrap(),
-            Event::MenuEvent { menu_id, .. } => gameboy.lock()
-                .map(|mut gameboy| handle_menu_event(&mut gameboy, &context, menu_id))
-                .unwrap(),
+
+                if state == ElementState::Pressed && !repeat {
+                    match physical_key {
+                        // Lets go of every button, in case a release was lost and one appears stuck.
+                        KeyCode::Escape => {
+                            context.emulator.release_all_buttons();
+                            context.key_sources.clear();
+                        }
+                        KeyCode::F6 => context.open_slot_picker(&config),
+                        KeyCode::F8 | KeyCode::F9 => {
+                            context.step_speed(&mut config, physical_key == KeyCode::F9);
+                            context.update_menu(&config);
+                        }
+                        KeyCode::F7 => {
+                            let profile = config.accuracy_profile.next();
+
+                            context.set_accuracy_profile(&mut config, profile);
+                            context.update_menu(&config);
+                        }
+                        key => {
+                            if let Some(toggle) = Toggle::for_hotkey(key) {
+                                context.toggle(&mut config, toggle);
+                            }
+                        }
+                    }
+                }
+
+                // Repeats don't change the button state, so only the first press and the release count.
+                if !repeat {
+                    let hotkey = match state {
+                        ElementState::Pressed => chords.press(physical_key, config.key_bindings.is_chord_modifier(physical_key)),
+                        ElementState::Released => chords.release(physical_key),
+                        _ => false,
+                    };
+
+                    if let Some(control) = config.key_bindings.control_for_key(physical_key).filter(|_| !hotkey && !picking) {
+                        match state {
+                            ElementState::Pressed => {
+                                context.emulator.press(control);
+                                context.key_sources.press(control, format!("{physical_key:?}"));
+                            }
+                            ElementState::Released => {
+                                context.emulator.release(control);
+                                context.key_sources.release(control, &format!("{physical_key:?}"));
+                            }
+                            _ => {}
+                        }
+                    }
+                }
+            }
+            Event::WindowEvent {
+                event: WindowEvent::Focused(false),
+                ..
+            } => {
+                // Releases are not delivered to an unfocused window, so nothing would let go of held buttons.
+                context.emulator.release_all_buttons();
+                context.key_sources.clear();
+                chords.clear();
+                // Nor is the release of F6.
+                context.slot_picker = None;
+
+                if config.pause_when_inactive {
+                    paused_while_inactive = context.pause();
+                }
+            }
+            Event::WindowEvent {
+                event: WindowEvent::Focused(true),
+                ..
+            } => {
+                if paused_while_inactive {
+                    paused_while_inactive = false;
+
+                    context.resume();
+                }
+            }
+            Event::WindowEvent {
+                event: WindowEvent::ModifiersChanged(new_modifiers),
+                ..
+            } => modifiers = new_modifiers,
+            Event::WindowEvent {
+                event: WindowEvent::ScaleFactorChanged { scale_factor: new_scale_factor, new_inner_size },
+                ..
+            } => {
+                // Keep the screen the same size in logical pixels, so that it looks the same size on the new monitor.
+                // The menu bar, when shown, is as high as the new DPI makes it. Maximized and fullscreen windows are
+                // sized by the monitor instead. The canvas follows the new size at the next redraw, as for any resize.
+                let menu_bar_height = |factor| if window.is_menu_visible() { menu_height(factor) } else { 0 };
+
+                if !window.is_maximized() && window.fullscreen().is_none() {
+                    *new_inner_size = stretch::rescaled_window_size(
+                        window.inner_size(),
+                        (scale_factor, menu_bar_height(scale_factor)),
+                        (new_scale_factor, menu_bar_height(new_scale_factor)),
+                    );
+                }
+
+                window.set_min_inner_size(Some(min_window_size(new_scale_factor, menu_bar_height(new_scale_factor))));
+                scale_factor = new_scale_factor;
+            }
+            Event::WindowEvent {
+                event: WindowEvent::DroppedFile(path),
+                ..
+            } => {
+                if context.modal_depth == 0 {
+                    open_rom_and_run(&mut context, &mut config, &mut stats, Some(path.as_path()));
+                }
+            }
+            Event::MenuEvent { menu_id, .. } => handle_menu_event(&mut context, &mut config, &mut stats, &window, menu_id),
             Event::MainEventsCleared => {
-                // TODO: Wait until a screen is ready to draw.
-                window.request_redraw();
+                if options.frames.is_some_and(|frames| stats.total_emulated_frames() >= frames) {
+                    *control_flow = ControlFlow::Exit;
+                    return;
+                }
+
+                if let (Some(gamepad), Some(linked)) = (&mut gamepad, &context.linked) {
+                    gamepad.poll(linked);
+                }
+
+                if let Some(change) = context.audio_output.poll() {
+                    for emulator in context.emulators() {
+                        emulator.set_audio_output(change == DeviceChange::Restored);
+                    }
+                }
+
+                let crashes = context.emulators().filter_map(Emulator::take_crash).collect::<Vec<_>>();
+
+                for crash in crashes {
+                    let msg = match crash.report {
+                        Ok(path) => format!("The game locked up. A diagnostic report for a bug report was written to {}\n\n{}", path.display(), crash.event),
+                        Err(e) => format!("The game locked up, and no diagnostic report could be written. {e}\n\n{}", crash.event),
+                    };
+
+                    let was_running = context.pause();
+
+                    context.show_message_box(&config, MessageBoxFlag::ERROR, &msg);
+
+                    if was_running {
+                        context.resume();
+                    }
+                }
+
+                let new_frame = frames.has_new() || linked_frames.as_ref().is_some_and(|frames| frames.has_new());
+                // The audio device's messages come and go on their own, and the frame after one went away clears it.
+                let timed_message = !context.streamer_mode && context.audio_output.status_line().is_some();
+
+                if new_frame || redraw_needed || timed_message || timed_message_shown {
+                    window.request_redraw();
+                    redraw_needed = false;
+                }
+
+                timed_message_shown = timed_message;
+
+                let now = Instant::now();
+                let running = context.rom.is_some() && !context.paused.load(Ordering::Relaxed);
+
+                // Sleep until just before the next frame is due, then look for it in short steps, since the emulation
+                // thread makes frames on its own clock, or in bursts when syncing to audio.
+                if new_frame {
+                    next_frame = now + emulation::frame_duration(config.speed).saturating_sub(FRAME_POLL_INTERVAL * 2);
+                } else if next_frame <= now {
+                    next_frame = now + FRAME_POLL_INTERVAL;
+                }
+
+                *control_flow = match (running, timed_message) {
+                    (true, _) => ControlFlow::WaitUntil(next_frame),
+                    // The audio device is tried again while lost, so keep polling it.
+                    (false, true) => ControlFlow::WaitUntil(now + emulation::frame_duration(100)),
+                    (false, false) => ControlFlow::Wait,
+                };
             }
-            Event::RedrawRequested(_) => gameboy.lock().map(|gameboy| {
-                frame_start = Instant::now();
+            Event::RedrawRequested(_) => {
+                let frame_start = Instant::now();
+
+                let frame = frames.read();
+                let blend = context.frame_blend.map(|weight| (&frame.previous_screen, weight));
 
                 // Draw screen
                 {
-                    screen.with_lock(None, |buffer: &mut [u8], pitch: usize| {
-                        for (index, &color) in gameboy.screen().iter().enumerate() {
-                            let x = index % gameboy::SCREEN_WIDTH;
-                            let y = index / gameboy::SCREEN_WIDTH;
+                    screen.with_lock(None, |pixels, pitch| screenshot::write_rgb(&frame.screen, blend, &context.palette, pixels, pitch)).unwrap();
 
-                            let color = COLORS[color as usize];
+                    // Redraws outpace emulation, so only record each emulated frame once.
+                    if let Some(recorder) = context.recorder.as_mut().filter(|_| frame.loaded) {
+                        if frame.number != last_recorded_frame {
+                            last_recorded_frame = frame.number;
 
-                            let offset = y * pitch + x * 3;
-                            buffer[offset] = color.r;
-                            buffer[offset + 1] = color.g;
-                            buffer[offset + 2] = color.b;
+                            recorder.push_frame(screenshot::screen_to_rgb(&frame.screen, blend, &context.palette));
                         }
-                    }).unwrap();
+                    }
+
+                    // The window can be resized, so where the screen goes is worked out again every frame.
+                    let (output_width, output_height) = canvas.output_size().unwrap();
+                    let content = config.stretch_mode.destination(content_width, content_height, output_width, output_height);
+                    let screen_width = content.width() / views;
+                    let screen_height = content.height();
+
+                    canvas.set_draw_color(Color::BLACK);
+                    canvas.clear();
 
                     // Draw screen
-                    canvas.copy(&screen, None, Some(Rect::new(0, 0, (gameboy::SCREEN_WIDTH * 2) as u32, (gameboy::SCREEN_HEIGHT * 2) as u32))).unwrap();
+                    let screen_rect = Rect::new(content.x(), content.y(), screen_width, screen_height);
+
+                    if frame.loaded {
+                        config.stretch_mode.set_scale_mode(&mut screen);
+                        canvas.copy(&screen, None, Some(screen_rect)).unwrap();
+                        filter_overlay.render(&mut canvas, &texture_creator, config.filter, config.filter_intensity, screen_rect).unwrap();
+                    } else {
+                        render_idle_screen(font.as_ref(), &mut canvas, &texture_creator, screen_rect).unwrap();
+                    }
+
+                    if let (Some(linked_frames), Some(linked_screen)) = (&mut linked_frames, &mut linked_screen) {
+                        let linked_frame = linked_frames.read();
+                        let linked_blend = context.frame_blend.map(|weight| (&linked_frame.previous_screen, weight));
+                        let linked_rect = Rect::new(content.x() + screen_width as i32, content.y(), screen_width, screen_height);
+
+                        linked_screen.with_lock(None, |pixels, pitch| screenshot::write_rgb(&linked_frame.screen, linked_blend, &context.palette, pixels, pitch)).unwrap();
+                        config.stretch_mode.set_scale_mode(linked_screen);
+                        canvas.copy(linked_screen, None, Some(linked_rect)).unwrap();
+                        filter_overlay.render(&mut canvas, &texture_creator, config.filter, config.filter_intensity, linked_rect).unwrap();
+
+                        // The linked game is played with the controller, so its own input viewer shows the controller's buttons.
+                        let linked_input = linked_frame.input.as_ref().filter(|_| context.show_input && !context.streamer_mode);
+
+                        if let (Some(input), Some(font), Some(gamepad)) = (linked_input, &font, &gamepad) {
+                            canvas.set_viewport(linked_rect);
+                            render_input_viewer(input, gamepad.sources(), font, &mut canvas, &texture_creator, Point::new(4, 4)).unwrap();
+                            canvas.set_viewport(None);
+                        }
+                    }
+
+                    // Streamer mode shows nothing but the game.
+                    if !context.streamer_mode {
+                        // Overlays are drawn over the first screen, with positions relative to it.
+                        canvas.set_viewport(screen_rect);
+
+                        if let Some(line_stats) = frame.line_stats.as_ref().filter(|_| context.show_lines) {
+                            render_line_stats(line_stats, &mut canvas, Rect::new(0, 0, screen_width, screen_height)).unwrap();
+                        }
+
+                        if let Some(font) = &font {
+                            if let Some(oam) = frame.oam.as_ref().filter(|_| context.show_oam) {
+                                render_oam_inspector(oam, &context.palette, font, &mut canvas, &texture_creator, &mut sprite_thumbnails).unwrap();
+                            }
+
+                            if let Some(audio) = frame.audio.as_ref().filter(|_| context.show_audio) {
+                                render_audio_inspector(audio, font, &mut canvas, &texture_creator).unwrap();
+                            }
+
+                            if let Some(input) = frame.input.as_ref().filter(|_| context.show_input) {
+                                render_input_viewer(input, &context.key_sources, font, &mut canvas, &texture_creator, Point::new(4, 4)).unwrap();
+                            }
+
+                            if let Some(picker) = &context.slot_picker {
+                                render_slot_picker(picker, &context.palette, font, &mut canvas, &texture_creator, &mut slot_thumbnails, Rect::new(0, 0, screen_width, screen_height)).unwrap();
+                            }
+
+                            // Messages stack up from the bottom left corner.
+                            let slowdown = context.slowdown.slow_speed().map(|speed| {
+                                let line = format!("Running at {:.0} %", speed * config.speed as f32);
+
+                                match config.accuracy_profile {
+                                    _ if context.switched_to_fast => format!("{line}, switched to the Fast profile"),
+                                    AccuracyProfile::Fast => line,
+                                    _ => format!("{line} - try the Fast profile"),
+                                }
+                            });
+                            let messages = [paused_while_inactive.then_some("Paused while inactive"), context.audio_output.status_line(), slowdown.as_deref()];
+
+                            for (line, message) in messages.into_iter().flatten().enumerate() {
+                                render_text(font, &mut canvas, &texture_creator, message, Point::new(4, screen_height as i32 - font.height() - 4 - (font.height() + 1) * line as i32)).unwrap();
+                            }
+
+                            if config.show_fps {
+                                render_text(font, &mut canvas, &texture_creator, stats.speed_line().as_str(), Point::new(4, 4)).unwrap();
+                                render_text(font, &mut canvas, &texture_creator, stats.frame_time_line().as_str(), Point::new(4, 4 + font.height() + 1)).unwrap();
+                                render_text(font, &mut canvas, &texture_creator, &format!("Accuracy: {} | Speed: {} % | Sync: {}", config.accuracy_profile.label(), config.speed, config.sync_mode.name()), Point::new(4, 4 + (font.height() + 1) * 2)).unwrap();
+                                render_channel_levels(font, &mut canvas, &texture_creator, frame.audio_channel_levels, Point::new(4, 4 + (font.height() + 1) * 3)).unwrap();
+                            }
+
+                            if let Some(recorder) = &context.recorder {
+                                let status = recorder.status_line();
+                                let (width, _) = font.size_of(&status).unwrap();
+
+                                render_text(font, &mut canvas, &texture_creator, &status, Point::new(screen_width as i32 - width as i32 - 4, screen_height as i32 - font.height() - 4)).unwrap();
+                            }
+                        }
 
-                    if show_fps {
-                        render_text(&font, &mut canvas, &texture_creator, format!("{:.2}", 1.0 / frame_delta.as_secs_f32()).as_str(), Point::new(4, 4)).unwrap();
+                        canvas.set_viewport(None);
                     }
 
                     canvas.present();
                 }
 
-                frame_delta = frame_start.elapsed();
+                let audio_fill = context.emulator.queued_audio() as f32 / (config.audio_latency as f32 * 2.0);
+
+                let running = context.rom.is_some() && !context.paused.load(Ordering::Relaxed);
+
+                // Slowdowns are judged against the speed asked for, on intervals emulation ran all through.
+                if stats.record_host_frame(frame_start.elapsed(), audio_fill, context.emulator.audio_overruns()) && running {
+                    let became_slow = context.slowdown.record(stats.interval, stats.speed_percent / config.speed as f32);
 
-                frame_start = Instant::now();
-            }).unwrap(),
+                    if became_slow && config.auto_fast_profile && config.accuracy_profile != AccuracyProfile::Fast {
+                        context.set_accuracy_profile(&mut config, AccuracyProfile::Fast);
+                        context.update_menu(&config);
+                        context.switched_to_fast = true;
+                    }
+
+                    if context.slowdown.slow_speed().is_none() {
+                        context.switched_to_fast = false;
+                    }
+                }
+
+                // The title stays as it is in streamer mode, where it may be captured along with the window.
+                let title_state = match context.streamer_mode {
+                    true => TitleState {
+                        rom_name: None,
+                        stats: None,
+                        recording: false,
+                        paused: false,
+                        speed: 100,
+                    },
+                    false => TitleState {
+                        rom_name: context.rom.as_ref().and_then(|rom| rom.path.file_stem()).map(|name| name.to_string_lossy().into_owned()),
+                        stats: context.stats_in_title.then(|| format!("{} | {}", stats.speed_line(), stats.frame_time_line())),
+                        recording: context.recorder.is_some(),
+                        paused: paused_while_inactive || context.user_paused,
+                        speed: config.speed,
+                    },
+                };
+
+                title.update(&window, &title_state);
+            }
             _ => {}
         };
     });
 
+    save_config(&config);
+
+    if let Some(recorder) = context.recorder.take() {
+        // Let the encoder finish the file before exiting.
+        let _ = recorder.stop().join();
+    }
+
+    context.emulator.stop();
+
+    if let Some(linked) = context.linked.take() {
+        linked.stop();
+    }
+
+    if let Some(path) = &options.screenshot_path {
+        let frame = frames.read();
+
+        screenshot::save_png(path, &frame.screen, context.frame_blend.map(|weight| (&frame.previous_screen, weight)), &context.palette)?;
+    }
+
     Ok(())
 }
 
-fn handle_menu_event(mut gameboy: &mut GameBoy, context: &Context, menu_id: MenuId) {
+fn handle_menu_event(context: &mut Context, config: &mut Config, stats: &mut Stats, window: &tao::window::Window, menu_id: MenuId) {
+    // Disabled items send nothing, but an accelerator might still get through while a dialog is open.
+    if context.modal_depth > 0 {
+        return;
+    }
+
     match menu_id {
-        MENU_OPEN => {
-            open_rom(&mut gameboy).unwrap();
+        MENU_OPEN => open_rom_and_run(context, config, stats, None),
+        MENU_ROM_INFO => {
+            let Some(rom) = &context.rom else {
+                return;
+            };
+
+            let state_path = context.paths.state_path(&rom.path, &rom.header, 0);
+            let info = rom_info::describe(&rom.header, &rom.save_path, state_path.parent().unwrap_or(&state_path));
+
+            let was_running = context.pause();
+
+            context.show_message_box(config, MessageBoxFlag::INFORMATION, &info);
+
+            if was_running {
+                context.resume();
+            }
+        }
+        MENU_PAUSE_WHEN_INACTIVE => {
+            config.pause_when_inactive = !config.pause_when_inactive;
+
+            save_config(config);
+        }
+        MENU_AUTO_FAST_PROFILE => {
+            config.auto_fast_profile = !config.auto_fast_profile;
+
+            save_config(config);
+        }
+        MENU_MUTE_FAST_SPEEDS => {
+            config.mute_fast_speeds = !config.mute_fast_speeds;
+
+            let speed = config.speed;
+            context.set_speed(config, speed);
+        }
+        MENU_STREAMER_MODE => {
+            context.streamer_mode = !context.streamer_mode;
+            config.streamer_mode = context.streamer_mode;
 
-            if context.audio_device.status() != AudioStatus::Playing {
-                context.audio_device.resume();
+            set_streamer_mode(window, context.streamer_mode);
+            save_config(config);
+        }
+        MENU_FRAME_BLEND => {
+            context.frame_blend = match context.frame_blend {
+                Some(_) => None,
+                None => Some(config.frame_blend_weight),
+            };
+            config.frame_blend = context.frame_blend.is_some();
+
+            save_config(config);
+        }
+        MENU_RECORD => {
+            match context.recorder.take() {
+                Some(recorder) => {
+                    recorder.stop();
+                }
+                None => match Recorder::start(&context.audio_tap, &context.paths) {
+                    Ok(recorder) => context.recorder = Some(recorder),
+                    Err(msg) => context.show_message_box(config, MessageBoxFlag::ERROR, &msg),
+                },
+            }
+        }
+        _ => {
+            if let Some(toggle) = Toggle::for_menu(menu_id) {
+                context.toggle(config, toggle);
+            } else if let Some(&(_, turbo_rate)) = MENU_TURBO_RATES.iter().find(|(id, _)| *id == menu_id) {
+                config.turbo_rate = turbo_rate;
+                context.emulator.set_turbo_rate(turbo_rate);
+
+                save_config(config);
+            } else if let Some(&(_, name, _)) = MENU_PALETTES.iter().find(|(id, _, _)| *id == menu_id) {
+                context.palette = palette::by_name(name).unwrap();
+                config.palette = name.to_string();
+
+                save_config(config);
+            } else if let Some(&(_, profile)) = MENU_ACCURACY_PROFILES.iter().find(|(id, _)| *id == menu_id) {
+                context.set_accuracy_profile(config, profile);
+            } else if let Some(&(_, speed)) = MENU_SPEEDS.iter().find(|(id, _)| *id == menu_id) {
+                context.set_speed(config, speed);
+            } else if let Some(&(_, mode)) = MENU_STRETCH_MODES.iter().find(|(id, _)| *id == menu_id) {
+                config.stretch_mode = mode;
+
+                save_config(config);
+            } else if let Some(&(_, mode)) = MENU_SYNC_MODES.iter().find(|(id, _)| *id == menu_id) {
+                config.sync_mode = mode;
+
+                for emulator in context.emulators() {
+                    emulator.set_sync_mode(mode);
+                }
+
+                save_config(config);
+            } else if let Some(&(_, filter)) = MENU_FILTERS.iter().find(|(id, _)| *id == menu_id) {
+                config.filter = filter;
+
+                save_config(config);
+            } else if let Some(&(_, model)) = MENU_MODELS.iter().find(|(id, _)| *id == menu_id) {
+                if config.model != model {
+                    config.model = model;
+                    save_config(config);
+
+                    // The model only takes effect at load, so start the running game over on it. The linked game
+                    // keeps its model until it is loaded again.
+                    if let Some(rom_path) = context.rom.as_ref().map(|rom| rom.path.clone()) {
+                        open_rom_and_run(context, config, stats, Some(&rom_path));
+                    }
+                }
             }
         }
-        _ => {}
     }
+
+    context.update_menu(config);
+}
+
+/// Opens `rom_path`, as dropped on the window, or the ROM picked in the file dialog if `None`, and runs it.
+fn open_rom_and_run(context: &mut Context, config: &mut Config, stats: &mut Stats, rom_path: Option<&Path>) {
+    // The dialog blocks the event loop, so keep the game from running (and playing audio) behind it.
+    let was_running = context.pause();
+
+    context.begin_modal(config);
+    let loaded = open_rom(&context.emulator, &context.paths, rom_path, config.model);
+    context.end_modal(config);
+
+    let loaded = match loaded {
+        Ok(loaded) => loaded,
+        Err(msg) => {
+            context.show_message_box(config, MessageBoxFlag::ERROR, &msg);
+            None
+        }
+    };
+
+    if let Some(rom) = &loaded {
+        context.warn_about_rom(config, rom);
+
+        context.user_paused = false;
+    }
+
+    if loaded.is_some() || was_running {
+        context.resume();
+    }
+
+    if let Some(rom) = loaded {
+        stats.reset();
+        config.add_recent_rom(&rom.path);
+        save_config(config);
+
+        context.rom = Some(rom);
+    }
+
+    context.update_menu(config);
+}
+
+/// Hides or shows the menu bar and the window borders, keeping the size of the screen.
+fn set_streamer_mode(window: &tao::window::Window, streamer_mode: bool) {
+    let size = window.inner_size();
+
+    let scale_factor = window.scale_factor();
+
+    // The inner size includes the menu bar while it is shown.
+    let screen_height = match window.is_menu_visible() {
+        true => size.height.saturating_sub(menu_height(scale_factor)),
+        false => size.height,
+    };
+    let menu_bar_height = if streamer_mode { 0 } else { menu_height(scale_factor) };
+
+    if streamer_mode {
+        window.hide_menu();
+    } else {
+        window.show_menu();
+    }
+
+    window.set_decorations(!streamer_mode);
+    window.set_min_inner_size(Some(min_window_size(scale_factor, menu_bar_height)));
+    window.set_inner_size(PhysicalSize::new(size.width, screen_height + menu_bar_height));
+}
+
+/// Records where the window is and how large its screens are, for the next session to open it the same way.
+fn save_window_placement(window: &tao::window::Window, views: u32, config: &mut Config) {
+    config.window_maximized = window.is_maximized();
+
+    // Only the place of the maximized or minimized window could be read, so the one from before is kept for when it
+    // is restored.
+    if config.window_maximized || window.is_minimized() {
+        return;
+    }
+
+    config.window_position = window.outer_position().ok().map(|position| (position.x, position.y));
+
+    let size = window.inner_size();
+    let menu_bar_height = if window.is_menu_visible() { menu_height(window.scale_factor()) } else { 0 };
+    let screens: LogicalSize<u32> = PhysicalSize::new(size.width, size.height.saturating_sub(menu_bar_height)).to_logical(window.scale_factor());
+
+    config.window_size = Some((screens.width / views, screens.height)).filter(|&(width, height)| width > 0 && height > 0);
 }
 
-fn menu_height() -> i32 {
+/// The smallest window, showing the screen at 1x in logical pixels.
+fn min_window_size(scale_factor: f64, menu_bar_height: u32) -> PhysicalSize<u32> {
+    stretch::window_size(gameboy::SCREEN_WIDTH as u32, gameboy::SCREEN_HEIGHT as u32, 1, scale_factor, menu_bar_height)
+}
+
+/// The height of the menu bar in physical pixels, on a monitor with `scale_factor`.
+fn menu_height(scale_factor: f64) -> u32 {
     use windows::{
         Win32::Foundation::{RECT},
-        Win32::UI::WindowsAndMessaging::{AdjustWindowRectEx, WINDOW_EX_STYLE, WINDOW_STYLE},
+        Win32::UI::HiDpi::AdjustWindowRectExForDpi,
+        Win32::UI::WindowsAndMessaging::{WINDOW_EX_STYLE, WINDOW_STYLE},
     };
 
     let mut rect = RECT::default();
+    let dpi = (96.0 * scale_factor).round() as u32;
 
     unsafe {
-        AdjustWindowRectEx(addr_of_mut!(rect), WINDOW_STYLE::default(), true, WINDOW_EX_STYLE::default());
+        AdjustWindowRectExForDpi(addr_of_mut!(rect), WINDOW_STYLE::default(), true, WINDOW_EX_STYLE::default(), dpi);
     }
 
-    rect.top.abs()
+    rect.top.unsigned_abs()
 }
 
 fn init_sdl_window(window: &tao::window::Window, video_subsystem: VideoSubsystem) -> Window {
@@ -277,30 +1325,62 @@ fn init_sdl_window(window: &tao::window::Window, video_subsystem: VideoSubsystem
     }
 }
 
-fn open_rom(gameboy: &mut GameBoy) -> Result<(), String> {
+/// Loads `rom_path`, or asks for a ROM with the file dialog if `None`. Returns the loaded ROM, or `None` if the
+/// dialog was cancelled. The running game is kept if the ROM can't be loaded.
+fn open_rom(emulator: &Emulator, paths: &Paths, rom_path: Option<&Path>, model: ModelSelection) -> Result<Option<LoadedRom>, String> {
+    if let Some(rom_path) = rom_path {
+        return load_rom(emulator, rom_path, |header| paths.save_path(rom_path, header), model).map(Some);
+    }
+
     if let Ok(rom_path) = dialog::open_file() {
-        let rom = fs::read(rom_path).map_err(|_| "Could not read ROM file")?;
+        return load_rom(emulator, &rom_path, |header| paths.save_path(&rom_path, header), model).map(Some);
+    }
+
+    Ok(None)
+}
+
+/// Loads `rom_path` on the model `model` picks for it, saving to the path `save_path` gives for its header.
+fn load_rom(emulator: &Emulator, rom_path: &Path, save_path: impl FnOnce(&CartridgeHeader) -> PathBuf, model: ModelSelection) -> Result<LoadedRom, String> {
+    let rom = fs::read(rom_path).map_err(|_| "Could not read ROM file")?;
+    let header = CartridgeHeader::parse(&rom).map_err(|e| format!("Could not load ROM: {e}"))?;
+    let model = model.resolve(&header);
+    let save_path = save_path(&header);
 
-        gameboy.load(rom);
+    // The folder of a game in a save folder is made on its first load. Should that fail, so does saving, which is
+    // reported then.
+    if let Some(dir) = save_path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        let _ = fs::create_dir_all(dir);
     }
 
-    Ok(())
+    emulator.load(rom, save_path.clone(), model).map_err(|e| format!("Could not load ROM: {e}"))?;
+
+    Ok(LoadedRom {
+        path: rom_path.to_owned(),
+        header,
+        save_path,
+    })
 }
 
-fn render_text(font: &Font, canvas: &mut WindowCanvas, texture_creator: &TextureCreator<WindowContext>, text: &str, pos: Point) -> Result<(), String> {
-    // render a surface, and convert it to a texture bound to the canvas
-    let surface = font
-        .render(text)
-        .shaded(Color::RGBA(255, 255, 0, 255), Color::RGBA(0, 0, 0, 192))
-        .map_err(|e| e.to_string())?;
-    let texture = texture_creator
-        .create_texture_from_surface(&surface)
-        .map_err(|e| e.to_string())?;
+/// Saves the state of the running game to `path`, making its folder on the way.
+fn save_state_file(emulator: &Emulator, path: &Path) -> Result<(), String> {
+    let state = emulator.save_state().ok_or("No game is running")?;
 
-    let TextureQuery { width, height, .. } = texture.query();
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        fs::create_dir_all(dir).map_err(|e| format!("Could not create the save state folder: {e}"))?;
+    }
 
-    // Draw FPS
-    canvas.copy(&texture, None, Some(Rect::new(pos.x, pos.y, width, height)))?;
+    fs::write(path, state).map_err(|e| format!("Could not write the save state: {e}"))
+}
 
-    Ok(())
+/// Restores the running game from the state at `path`.
+fn load_state_file(emulator: &Emulator, path: &Path) -> Result<(), String> {
+    let state = fs::read(path).map_err(|e| format!("Could not read the save state: {e}"))?;
+
+    emulator.load_state(state).map_err(|e| e.to_string())
+}
+
+fn save_config(config: &Config) {
+    if let Err(msg) = config.save() {
+        eprintln!("{msg}");
+    }
 }
@@ -1,21 +1,418 @@
-﻿use tao::{
+﻿use sdl2::pixels::Color;
+use tao::{
     keyboard::{KeyCode, ModifiersState},
     accelerator::Accelerator,
-    menu::{MenuBar, MenuId, MenuItem, MenuItemAttributes}
+    menu::{CustomMenuItem, MenuBar, MenuId, MenuItem, MenuItemAttributes}
 };
+use crate::{config::{AccuracyProfile, Config, ModelSelection}, emulation::SyncMode, filter::Filter, palette, stretch::StretchMode};
 
 pub(crate) const MENU_OPEN: MenuId = MenuId(1);
+pub(crate) const MENU_PAUSE_WHEN_INACTIVE: MenuId = MenuId(2);
+pub(crate) const MENU_RECORD: MenuId = MenuId(3);
+/// The turbo rates offered in the Input menu, in presses per second.
+pub(crate) const MENU_TURBO_RATES: [(MenuId, u8); 4] = [(MenuId(4), 10), (MenuId(5), 15), (MenuId(6), 20), (MenuId(7), 30)];
+pub(crate) const MENU_ROM_INFO: MenuId = MenuId(8);
+pub(crate) const MENU_PAUSE: MenuId = MenuId(9);
+pub(crate) const MENU_SHOW_FPS: MenuId = MenuId(10);
+/// The palettes offered in the Video menu, by name as accepted by `palette::by_name`, and their labels.
+pub(crate) const MENU_PALETTES: [(MenuId, &str, &str); 2] = [(MenuId(11), "grey", "&Grey"), (MenuId(12), "dmg-green", "DMG &green")];
+/// The accuracy profiles offered in the Emulation menu.
+pub(crate) const MENU_ACCURACY_PROFILES: [(MenuId, AccuracyProfile); 3] =
+    [(MenuId(13), AccuracyProfile::Fast), (MenuId(14), AccuracyProfile::Balanced), (MenuId(15), AccuracyProfile::Accurate)];
 
-pub(crate) fn build_menu() -> MenuBar {
+/// The speeds offered in the Emulation menu, in percent, from slowest to fastest.
+pub(crate) const MENU_SPEEDS: [(MenuId, u16); 4] = [(MenuId(16), 50), (MenuId(17), 100), (MenuId(18), 200), (MenuId(19), 300)];
+pub(crate) const MENU_MUTE_FAST_SPEEDS: MenuId = MenuId(20);
+/// The stretch modes offered in the Video menu.
+pub(crate) const MENU_STRETCH_MODES: [(MenuId, StretchMode); 3] =
+    [(MenuId(21), StretchMode::Integer), (MenuId(22), StretchMode::Fit), (MenuId(23), StretchMode::Stretch)];
+/// The filters offered in the Video menu.
+pub(crate) const MENU_FILTERS: [(MenuId, Filter); 3] = [(MenuId(24), Filter::None), (MenuId(25), Filter::Scanlines), (MenuId(26), Filter::Grid)];
+pub(crate) const MENU_FRAME_BLEND: MenuId = MenuId(27);
+/// The sync modes offered in the Emulation menu.
+pub(crate) const MENU_SYNC_MODES: [(MenuId, SyncMode); 2] = [(MenuId(28), SyncMode::Audio), (MenuId(29), SyncMode::Video)];
+pub(crate) const MENU_MUTE: MenuId = MenuId(30);
+pub(crate) const MENU_STATS_IN_TITLE: MenuId = MenuId(31);
+pub(crate) const MENU_OAM_INSPECTOR: MenuId = MenuId(32);
+pub(crate) const MENU_LINE_STATS: MenuId = MenuId(33);
+pub(crate) const MENU_AUDIO_INSPECTOR: MenuId = MenuId(34);
+pub(crate) const MENU_STREAMER_MODE: MenuId = MenuId(35);
+/// The model selections offered in the Emulation menu.
+pub(crate) const MENU_MODELS: [(MenuId, ModelSelection); 3] =
+    [(MenuId(36), ModelSelection::Auto), (MenuId(37), ModelSelection::Dmg), (MenuId(38), ModelSelection::Cgb)];
+pub(crate) const MENU_INPUT_VIEWER: MenuId = MenuId(39);
+pub(crate) const MENU_AUTO_FAST_PROFILE: MenuId = MenuId(40);
+
+/// The menu items that switch a `Toggle`. Pause and mute have accelerators, so their hotkeys arrive as menu events.
+pub(crate) const MENU_TOGGLES: [(MenuId, Toggle); 8] = [
+    (MENU_PAUSE, Toggle::Pause),
+    (MENU_MUTE, Toggle::Mute),
+    (MENU_SHOW_FPS, Toggle::ShowFps),
+    (MENU_STATS_IN_TITLE, Toggle::StatsInTitle),
+    (MENU_OAM_INSPECTOR, Toggle::OamInspector),
+    (MENU_LINE_STATS, Toggle::LineStats),
+    (MENU_AUDIO_INSPECTOR, Toggle::AudioInspector),
+    (MENU_INPUT_VIEWER, Toggle::InputViewer),
+];
+
+/// The hotkeys of the overlay toggles, which aren't accelerators of their menu items, see `build_menu`.
+pub(crate) const HOTKEY_TOGGLES: [(KeyCode, Toggle); 6] = [
+    (KeyCode::F2, Toggle::ShowFps),
+    (KeyCode::F3, Toggle::StatsInTitle),
+    (KeyCode::F4, Toggle::OamInspector),
+    (KeyCode::F5, Toggle::LineStats),
+    (KeyCode::F11, Toggle::InputViewer),
+    (KeyCode::F12, Toggle::AudioInspector),
+];
+
+/// A setting switched on and off both from the menu and with a hotkey, which go through the same toggle so that the
+/// check marks follow whichever was used.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum Toggle {
+    Pause,
+    Mute,
+    ShowFps,
+    StatsInTitle,
+    OamInspector,
+    LineStats,
+    AudioInspector,
+    InputViewer,
+}
+
+impl Toggle {
+    pub fn for_menu(menu_id: MenuId) -> Option<Self> {
+        MENU_TOGGLES.iter().find(|(id, _)| *id == menu_id).map(|&(_, toggle)| toggle)
+    }
+
+    pub fn for_hotkey(key: KeyCode) -> Option<Self> {
+        HOTKEY_TOGGLES.iter().find(|(hotkey, _)| *hotkey == key).map(|&(_, toggle)| toggle)
+    }
+}
+
+/// Handles to the menu items whose state changes after the menu is built, see `update_menu_state`.
+pub(crate) struct MenuHandles {
+    pub open: CustomMenuItem,
+    pub rom_info: CustomMenuItem,
+    pub pause: CustomMenuItem,
+    pub accuracy_profiles: Vec<CustomMenuItem>,
+    pub auto_fast_profile: CustomMenuItem,
+    pub speeds: Vec<CustomMenuItem>,
+    pub mute_fast_speeds: CustomMenuItem,
+    pub sync_modes: Vec<CustomMenuItem>,
+    pub models: Vec<CustomMenuItem>,
+    pub pause_when_inactive: CustomMenuItem,
+    pub mute: CustomMenuItem,
+    pub show_fps: CustomMenuItem,
+    pub stats_in_title: CustomMenuItem,
+    pub oam_inspector: CustomMenuItem,
+    pub line_stats: CustomMenuItem,
+    pub audio_inspector: CustomMenuItem,
+    pub input_viewer: CustomMenuItem,
+    pub turbo_rates: Vec<CustomMenuItem>,
+    pub record: CustomMenuItem,
+    pub palettes: Vec<CustomMenuItem>,
+    pub stretch_modes: Vec<CustomMenuItem>,
+    pub filters: Vec<CustomMenuItem>,
+    pub frame_blend: CustomMenuItem,
+    pub streamer_mode: CustomMenuItem,
+}
+
+/// The frontend state that menu items reflect.
+pub(crate) struct FrontendState {
+    pub rom_loaded: bool,
+    /// Whether a modal dialog blocks the event loop, which menu items must not open another one over.
+    pub modal_open: bool,
+    /// Whether emulation was paused from the menu.
+    pub paused: bool,
+    pub recording: bool,
+    pub show_fps: bool,
+    pub stats_in_title: bool,
+    pub show_oam: bool,
+    pub show_lines: bool,
+    pub show_audio: bool,
+    pub show_input: bool,
+    pub pause_when_inactive: bool,
+    pub muted: bool,
+    pub turbo_rate: u8,
+    pub palette: [Color; 4],
+    pub stretch_mode: StretchMode,
+    pub filter: Filter,
+    pub frame_blend: bool,
+    pub streamer_mode: bool,
+    pub accuracy_profile: AccuracyProfile,
+    pub auto_fast_profile: bool,
+    pub speed: u16,
+    pub mute_fast_speeds: bool,
+    pub sync_mode: SyncMode,
+    pub model: ModelSelection,
+}
+
+pub(crate) fn build_menu(config: &Config) -> (MenuBar, MenuHandles) {
     let mut root = MenuBar::new();
     let mut file_menu = MenuBar::new();
 
-    file_menu.add_item(MenuItemAttributes::new("&Open ROM file...")
+    let open = file_menu.add_item(MenuItemAttributes::new("&Open ROM file...")
         .with_id(MENU_OPEN)
         .with_accelerators(&Accelerator::new(Some(ModifiersState::CONTROL), KeyCode::KeyO)));
+    let rom_info = file_menu.add_item(MenuItemAttributes::new("ROM &Info...")
+        .with_id(MENU_ROM_INFO)
+        .with_enabled(false));
     file_menu.add_native_item(MenuItem::Separator);
     file_menu.add_native_item(MenuItem::Quit);
     root.add_submenu("&File", true, file_menu);
 
-    return root;
+    let mut emulation_menu = MenuBar::new();
+
+    let pause = emulation_menu.add_item(MenuItemAttributes::new("&Pause")
+        .with_id(MENU_PAUSE)
+        .with_enabled(false)
+        .with_accelerators(&Accelerator::new(Some(ModifiersState::CONTROL), KeyCode::KeyP)));
+
+    let mut accuracy_menu = MenuBar::new();
+
+    let accuracy_profiles = MENU_ACCURACY_PROFILES.iter()
+        .map(|&(id, profile)| accuracy_menu.add_item(MenuItemAttributes::new(&format!("&{}", profile.label()))
+            .with_id(id)
+            .with_selected(config.accuracy_profile == profile)))
+        .collect();
+    accuracy_menu.add_native_item(MenuItem::Separator);
+    let auto_fast_profile = accuracy_menu.add_item(MenuItemAttributes::new("Switch to Fast when &slow")
+        .with_id(MENU_AUTO_FAST_PROFILE)
+        .with_selected(config.auto_fast_profile));
+    emulation_menu.add_submenu("&Accuracy", true, accuracy_menu);
+
+    let mut speed_menu = MenuBar::new();
+
+    let speeds = MENU_SPEEDS.iter()
+        .map(|&(id, speed)| speed_menu.add_item(MenuItemAttributes::new(&format!("{speed} %"))
+            .with_id(id)
+            .with_selected(config.speed == speed)))
+        .collect();
+    speed_menu.add_native_item(MenuItem::Separator);
+    let mute_fast_speeds = speed_menu.add_item(MenuItemAttributes::new("&Mute above 150 %")
+        .with_id(MENU_MUTE_FAST_SPEEDS)
+        .with_selected(config.mute_fast_speeds));
+    emulation_menu.add_submenu("&Speed", true, speed_menu);
+
+    let mut sync_menu = MenuBar::new();
+
+    let sync_modes = MENU_SYNC_MODES.iter()
+        .map(|&(id, mode)| sync_menu.add_item(MenuItemAttributes::new(mode.label())
+            .with_id(id)
+            .with_selected(config.sync_mode == mode)))
+        .collect();
+    emulation_menu.add_submenu("S&ync", true, sync_menu);
+
+    let mut model_menu = MenuBar::new();
+
+    let models = MENU_MODELS.iter()
+        .map(|&(id, model)| model_menu.add_item(MenuItemAttributes::new(model.label())
+            .with_id(id)
+            .with_selected(config.model == model)))
+        .collect();
+    emulation_menu.add_submenu("&Model", true, model_menu);
+    root.add_submenu("&Emulation", true, emulation_menu);
+
+    let mut options_menu = MenuBar::new();
+
+    let pause_when_inactive = options_menu.add_item(MenuItemAttributes::new("&Pause when inactive")
+        .with_id(MENU_PAUSE_WHEN_INACTIVE)
+        .with_selected(config.pause_when_inactive));
+    let mute = options_menu.add_item(MenuItemAttributes::new("&Mute")
+        .with_id(MENU_MUTE)
+        .with_accelerators(&Accelerator::new(Some(ModifiersState::CONTROL), KeyCode::KeyM)));
+    options_menu.add_native_item(MenuItem::Separator);
+    // The overlays below also toggle with F2-F5, F11 and F12, which the labels can't show without making them accelerators.
+    let show_fps = options_menu.add_item(MenuItemAttributes::new("Show &FPS")
+        .with_id(MENU_SHOW_FPS)
+        .with_selected(config.show_fps));
+    let stats_in_title = options_menu.add_item(MenuItemAttributes::new("Show stats in &title")
+        .with_id(MENU_STATS_IN_TITLE));
+
+    let mut inspectors_menu = MenuBar::new();
+
+    let oam_inspector = inspectors_menu.add_item(MenuItemAttributes::new("&OAM")
+        .with_id(MENU_OAM_INSPECTOR));
+    let line_stats = inspectors_menu.add_item(MenuItemAttributes::new("&Line statistics")
+        .with_id(MENU_LINE_STATS));
+    let audio_inspector = inspectors_menu.add_item(MenuItemAttributes::new("&Audio channels")
+        .with_id(MENU_AUDIO_INSPECTOR));
+    let input_viewer = inspectors_menu.add_item(MenuItemAttributes::new("&Input")
+        .with_id(MENU_INPUT_VIEWER));
+    options_menu.add_submenu("&Inspectors", true, inspectors_menu);
+    root.add_submenu("&Options", true, options_menu);
+
+    let mut input_menu = MenuBar::new();
+    let mut turbo_rate_menu = MenuBar::new();
+
+    let turbo_rates = MENU_TURBO_RATES.iter()
+        .map(|&(id, rate)| turbo_rate_menu.add_item(MenuItemAttributes::new(&format!("{rate} Hz"))
+            .with_id(id)
+            .with_selected(config.turbo_rate == rate)))
+        .collect();
+    input_menu.add_submenu("&Turbo rate", true, turbo_rate_menu);
+    root.add_submenu("&Input", true, input_menu);
+
+    let mut video_menu = MenuBar::new();
+    let mut palette_menu = MenuBar::new();
+
+    let palettes = MENU_PALETTES.iter()
+        .map(|&(id, name, label)| palette_menu.add_item(MenuItemAttributes::new(label)
+            .with_id(id)
+            .with_selected(config.palette == name)))
+        .collect();
+    video_menu.add_submenu("&Palette", true, palette_menu);
+
+    let mut stretch_menu = MenuBar::new();
+
+    let stretch_modes = MENU_STRETCH_MODES.iter()
+        .map(|&(id, mode)| stretch_menu.add_item(MenuItemAttributes::new(mode.label())
+            .with_id(id)
+            .with_selected(config.stretch_mode == mode)))
+        .collect();
+    video_menu.add_submenu("&Stretch", true, stretch_menu);
+
+    let mut filter_menu = MenuBar::new();
+
+    let filters = MENU_FILTERS.iter()
+        .map(|&(id, filter)| filter_menu.add_item(MenuItemAttributes::new(filter.label())
+            .with_id(id)
+            .with_selected(config.filter == filter)))
+        .collect();
+    video_menu.add_submenu("&Filter", true, filter_menu);
+
+    let frame_blend = video_menu.add_item(MenuItemAttributes::new("Frame &blending")
+        .with_id(MENU_FRAME_BLEND)
+        .with_selected(config.frame_blend));
+
+    let record = video_menu.add_item(MenuItemAttributes::new("&Record")
+        .with_id(MENU_RECORD)
+        .with_enabled(false));
+    video_menu.add_native_item(MenuItem::Separator);
+    // Accelerators still work while the menu is hidden, which is the only way out of streamer mode.
+    let streamer_mode = video_menu.add_item(MenuItemAttributes::new("S&treamer mode")
+        .with_id(MENU_STREAMER_MODE)
+        .with_accelerators(&Accelerator::new(Some(ModifiersState::CONTROL | ModifiersState::SHIFT), KeyCode::KeyS)));
+    root.add_submenu("&Video", true, video_menu);
+
+    return (root, MenuHandles {
+        open,
+        rom_info,
+        pause,
+        accuracy_profiles,
+        auto_fast_profile,
+        speeds,
+        mute_fast_speeds,
+        sync_modes,
+        models,
+        pause_when_inactive,
+        mute,
+        show_fps,
+        stats_in_title,
+        oam_inspector,
+        line_stats,
+        audio_inspector,
+        input_viewer,
+        turbo_rates,
+        record,
+        palettes,
+        stretch_modes,
+        filters,
+        frame_blend,
+        streamer_mode,
+    });
+}
+
+/// Enables and checks every item in `handles` according to `state`. Called whenever any of it changes.
+pub(crate) fn update_menu_state(handles: &mut MenuHandles, state: &FrontendState) {
+    handles.open.set_enabled(!state.modal_open);
+    handles.rom_info.set_enabled(state.rom_loaded && !state.modal_open);
+
+    handles.pause.set_enabled(state.rom_loaded && !state.modal_open);
+    handles.pause.set_selected(state.paused);
+
+    for (item, &(_, profile)) in handles.accuracy_profiles.iter_mut().zip(MENU_ACCURACY_PROFILES.iter()) {
+        item.set_selected(profile == state.accuracy_profile);
+    }
+
+    handles.auto_fast_profile.set_selected(state.auto_fast_profile);
+
+    for (item, &(_, speed)) in handles.speeds.iter_mut().zip(MENU_SPEEDS.iter()) {
+        item.set_selected(speed == state.speed);
+    }
+
+    handles.mute_fast_speeds.set_selected(state.mute_fast_speeds);
+
+    for (item, &(_, mode)) in handles.sync_modes.iter_mut().zip(MENU_SYNC_MODES.iter()) {
+        item.set_selected(mode == state.sync_mode);
+    }
+
+    for (item, &(_, model)) in handles.models.iter_mut().zip(MENU_MODELS.iter()) {
+        item.set_selected(model == state.model);
+    }
+
+    handles.pause_when_inactive.set_selected(state.pause_when_inactive);
+    handles.mute.set_selected(state.muted);
+    handles.show_fps.set_selected(state.show_fps);
+    handles.stats_in_title.set_selected(state.stats_in_title);
+    handles.oam_inspector.set_selected(state.show_oam);
+    handles.line_stats.set_selected(state.show_lines);
+    handles.audio_inspector.set_selected(state.show_audio);
+    handles.input_viewer.set_selected(state.show_input);
+
+    for (item, &(_, rate)) in handles.turbo_rates.iter_mut().zip(MENU_TURBO_RATES.iter()) {
+        item.set_selected(rate == state.turbo_rate);
+    }
+
+    // A recording can always be stopped, but there is nothing to record without a ROM.
+    handles.record.set_enabled(state.recording || state.rom_loaded);
+    handles.record.set_selected(state.recording);
+
+    for (item, &(_, name, _)) in handles.palettes.iter_mut().zip(MENU_PALETTES.iter()) {
+        item.set_selected(palette::by_name(name) == Some(state.palette));
+    }
+
+    for (item, &(_, mode)) in handles.stretch_modes.iter_mut().zip(MENU_STRETCH_MODES.iter()) {
+        item.set_selected(mode == state.stretch_mode);
+    }
+
+    for (item, &(_, filter)) in handles.filters.iter_mut().zip(MENU_FILTERS.iter()) {
+        item.set_selected(filter == state.filter);
+    }
+
+    handles.frame_blend.set_selected(state.frame_blend);
+    handles.streamer_mode.set_selected(state.streamer_mode);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_toggle_has_a_menu_item_of_its_own() {
+        for (index, &(id, toggle)) in MENU_TOGGLES.iter().enumerate() {
+            assert_eq!(Toggle::for_menu(id), Some(toggle));
+            assert!(MENU_TOGGLES[index + 1..].iter().all(|&(other_id, other)| other_id != id && other != toggle), "{toggle:?}");
+        }
+    }
+
+    #[test]
+    fn hotkeys_switch_the_same_toggles_as_the_menu() {
+        for (key, toggle) in HOTKEY_TOGGLES {
+            assert_eq!(Toggle::for_hotkey(key), Some(toggle));
+            assert!(MENU_TOGGLES.iter().any(|&(id, _)| Toggle::for_menu(id) == Some(toggle)), "{key:?}");
+        }
+    }
+
+    #[test]
+    fn other_keys_and_items_arent_toggles() {
+        // The slot picker, the accuracy profile, the speed and releasing every button.
+        for key in [KeyCode::F6, KeyCode::F7, KeyCode::F8, KeyCode::F9, KeyCode::Escape, KeyCode::KeyP] {
+            assert_eq!(Toggle::for_hotkey(key), None, "{key:?}");
+        }
+
+        for id in [MENU_OPEN, MENU_PAUSE_WHEN_INACTIVE, MENU_MUTE_FAST_SPEEDS, MENU_FRAME_BLEND] {
+            assert_eq!(Toggle::for_menu(id), None);
+        }
+    }
 }
@@ -0,0 +1,499 @@
+﻿use sdl2::{
+    pixels::Color,
+    rect::{Point, Rect},
+    render::{Texture, TextureCreator, TextureQuery, WindowCanvas},
+    ttf::Font,
+    video::WindowContext,
+};
+use crate::{
+    gameboy::{Buttons, ChannelShape, ChannelStatus, GameBoy, LineStats, OamAttributes, OamEntry, CHANNEL_TAP_LEN, OAM_ENTRY_COUNT, SCREEN_HEIGHT, SCREEN_WIDTH},
+    input::{InputSnapshot, InputSources},
+    screenshot,
+    slot_picker::{self, Slot, SlotPicker, SLOT_COLUMNS, SLOT_COUNT},
+};
+
+const TEXT_COLOR: Color = Color::RGBA(255, 255, 0, 255);
+const TEXT_BACKGROUND: Color = Color::RGBA(0, 0, 0, 192);
+const SELECTED_BACKGROUND: Color = Color::RGBA(0, 96, 0, 192);
+
+const IDLE_BACKGROUND: Color = Color::RGB(0x18, 0x18, 0x20);
+// Ctrl+O is also how ROMs are opened in streamer mode, which has no menu.
+const IDLE_LINES: [&str; 2] = ["No ROM loaded", "Drop a ROM file here or press Ctrl+O"];
+
+// Color used for sprite pixels of color index 0, which are transparent on screen.
+const TRANSPARENT_COLOR: Color = Color::RGB(0x30, 0x30, 0x40);
+
+const OAM_ROWS_PER_COLUMN: usize = OAM_ENTRY_COUNT / 2;
+const OAM_COLUMN_WIDTH: i32 = 160;
+
+const WAVEFORM_COLOR: Color = Color::RGB(0, 255, 128);
+const SILENT_WAVEFORM_COLOR: Color = Color::RGB(0x60, 0x60, 0x60);
+const WAVEFORM_HEIGHT: i32 = 24;
+const VOLUME_BAR_WIDTH: i32 = 6;
+
+const HEAT_STRIP_WIDTH: i32 = 8;
+const LINE_MARK_WIDTH: i32 = 3;
+const SPRITE_LIMIT_COLOR: Color = Color::RGB(255, 255, 255);
+const WINDOW_LINE_COLOR: Color = Color::RGB(0, 160, 255);
+
+// The input viewer's pad, in cells: the D-pad on the left, Select and Start in the middle and B and A on the right.
+const PAD_CELL: i32 = 8;
+const PAD_LAYOUT: [(Buttons, &str, i32, i32, u32); 8] = [
+    (Buttons::Up, "Up", 1, 0, 1),
+    (Buttons::Down, "Down", 1, 2, 1),
+    (Buttons::Left, "Left", 0, 1, 1),
+    (Buttons::Right, "Right", 2, 1, 1),
+    (Buttons::Select, "Select", 4, 2, 2),
+    (Buttons::Start, "Start", 7, 2, 2),
+    (Buttons::B, "B", 10, 1, 1),
+    (Buttons::A, "A", 12, 1, 1),
+];
+const PAD_WIDTH: i32 = 13;
+const PAD_HEIGHT: i32 = 3;
+// Held in the frontend, but not seen by the game this frame, like a turbo button between presses.
+const UNSEEN_BUTTON_COLOR: Color = Color::RGB(0x80, 0x80, 0x00);
+const RELEASED_BUTTON_COLOR: Color = Color::RGB(0x60, 0x60, 0x60);
+
+// Gaps around the cells of the slot picker, and the border of the selected one.
+const SLOT_GAP: i32 = 4;
+const SLOT_BORDER: i32 = 2;
+const SLOT_ROWS: u8 = SLOT_COUNT / SLOT_COLUMNS;
+const EMPTY_SLOT_COLOR: Color = Color::RGB(0x30, 0x30, 0x40);
+const SLOT_PICKER_HINT: &str = "Enter: load  Shift+Enter: save";
+
+// Pixel transfer takes 172 dots at the least, and can stretch to 289 before it runs into the end of the line.
+const MIN_TRANSFER_DOTS: u16 = 172;
+const MAX_TRANSFER_DOTS: u16 = 289;
+
+pub(crate) fn render_text(font: &Font, canvas: &mut WindowCanvas, texture_creator: &TextureCreator<WindowContext>, text: &str, pos: Point) -> Result<(), String> {
+    render_text_with_background(font, canvas, texture_creator, text, pos, TEXT_BACKGROUND)
+}
+
+pub(crate) fn render_text_with_background(font: &Font, canvas: &mut WindowCanvas, texture_creator: &TextureCreator<WindowContext>, text: &str, pos: Point, background: Color) -> Result<(), String> {
+    // render a surface, and convert it to a texture bound to the canvas
+    let surface = font
+        .render(text)
+        .shaded(TEXT_COLOR, background)
+        .map_err(|e| e.to_string())?;
+    let texture = texture_creator
+        .create_texture_from_surface(&surface)
+        .map_err(|e| e.to_string())?;
+
+    let TextureQuery { width, height, .. } = texture.query();
+
+    canvas.copy(&texture, None, Some(Rect::new(pos.x, pos.y, width, height)))?;
+
+    Ok(())
+}
+
+/// Renders the left/right level of each audio channel in percent, e.g. "CH1 100/0".
+pub(crate) fn render_channel_levels(font: &Font, canvas: &mut WindowCanvas, texture_creator: &TextureCreator<WindowContext>, levels: [[f32; 2]; 4], pos: Point) -> Result<(), String> {
+    let text = levels.iter()
+        .enumerate()
+        .map(|(index, [left, right])| format!("CH{} {:.0}/{:.0}", index + 1, left * 100.0, right * 100.0))
+        .collect::<Vec<_>>()
+        .join("  ");
+
+    render_text(font, canvas, texture_creator, text.as_str(), pos)
+}
+
+/// What the OAM inspector shows, copied from the emulator so it can be drawn on another thread.
+#[derive(Clone)]
+pub(crate) struct OamSnapshot {
+    entries: [OamEntry; OAM_ENTRY_COUNT],
+    palettes: [u8; 2],
+    sprite_height: u8,
+    ly: u8,
+    line_sprites: Vec<usize>,
+    /// The unflipped rows of every entry's sprite, `sprite_height` of them.
+    rows: [[[u8; 8]; 16]; OAM_ENTRY_COUNT],
+}
+
+impl OamSnapshot {
+    pub fn capture(gameboy: &GameBoy) -> Self {
+        let entries = gameboy.oam_entries();
+
+        Self {
+            entries,
+            palettes: gameboy.obj_palettes(),
+            sprite_height: gameboy.sprite_height(),
+            ly: gameboy.ly(),
+            line_sprites: gameboy.line_sprites(),
+            rows: entries.map(|entry| gameboy.sprite_rows(entry.tile)),
+        }
+    }
+}
+
+/// Lists all OAM entries in two columns, each with a thumbnail of its sprite drawn with the object's palette.
+/// Entries selected for the current scanline are highlighted.
+///
+/// `thumbnails` is a streaming RGB24 texture of `8 * OAM_ENTRY_COUNT` by 16 pixels, one 8 pixel wide slot per entry.
+pub(crate) fn render_oam_inspector(
+    snapshot: &OamSnapshot,
+    colors: &[Color; 4],
+    font: &Font,
+    canvas: &mut WindowCanvas,
+    texture_creator: &TextureCreator<WindowContext>,
+    thumbnails: &mut Texture,
+) -> Result<(), String> {
+    let entries = &snapshot.entries;
+    let palettes = snapshot.palettes;
+    let sprite_height = snapshot.sprite_height;
+    let line_sprites = &snapshot.line_sprites;
+
+    thumbnails.with_lock(None, |buffer: &mut [u8], pitch: usize| {
+        for (index, entry) in entries.iter().enumerate() {
+            let attributes = entry.attributes;
+
+            let flip_h = attributes.contains(OamAttributes::X_FLIP);
+            let flip_v = attributes.contains(OamAttributes::Y_FLIP);
+            let palette = palettes[attributes.contains(OamAttributes::DMG_PALETTE) as usize];
+
+            for y in 0..sprite_height {
+                let row = if flip_v { sprite_height - 1 - y } else { y };
+
+                let pixels = snapshot.rows[index][row as usize];
+
+                for x in 0..8 {
+                    let pixel = pixels[if flip_h { 7 - x } else { x }];
+
+                    let color = if pixel == 0 {
+                        TRANSPARENT_COLOR
+                    } else {
+                        colors[((palette >> (pixel * 2)) & 0b0000_0011) as usize]
+                    };
+
+                    let offset = y as usize * pitch + (index * 8 + x) * 3;
+                    buffer[offset] = color.r;
+                    buffer[offset + 1] = color.g;
+                    buffer[offset + 2] = color.b;
+                }
+            }
+        }
+    })?;
+
+    let line_height = font.height() + 1;
+
+    render_text(
+        font,
+        canvas,
+        texture_creator,
+        format!("OAM  LY {:3}  {}/10 on line  # Y X T PXY+pal", snapshot.ly, line_sprites.len()).as_str(),
+        Point::new(4, 4),
+    )?;
+
+    let thumbnail_height = (line_height - 1).min(sprite_height as i32);
+    let thumbnail_width = thumbnail_height * 8 / sprite_height as i32;
+
+    for (index, entry) in entries.iter().enumerate() {
+        let x = 4 + (index / OAM_ROWS_PER_COLUMN) as i32 * OAM_COLUMN_WIDTH;
+        let y = 4 + (index % OAM_ROWS_PER_COLUMN + 1) as i32 * line_height;
+
+        let attributes = entry.attributes;
+
+        canvas.copy(
+            thumbnails,
+            Some(Rect::new(index as i32 * 8, 0, 8, sprite_height as u32)),
+            Some(Rect::new(x, y, thumbnail_width as u32, thumbnail_height as u32)),
+        )?;
+
+        let flags = [
+            if attributes.contains(OamAttributes::BG_OVER_OBJ) { 'P' } else { '-' },
+            if attributes.contains(OamAttributes::X_FLIP) { 'X' } else { '-' },
+            if attributes.contains(OamAttributes::Y_FLIP) { 'Y' } else { '-' },
+            if attributes.contains(OamAttributes::DMG_PALETTE) { '1' } else { '0' },
+        ].iter().collect::<String>();
+
+        let background = if line_sprites.contains(&index) {
+            SELECTED_BACKGROUND
+        } else {
+            TEXT_BACKGROUND
+        };
+
+        render_text_with_background(
+            font,
+            canvas,
+            texture_creator,
+            format!("{index:02} {:02X} {:02X} {:02X} {flags}", entry.y, entry.x, entry.tile).as_str(),
+            Point::new(x + 10, y),
+            background,
+        )?;
+    }
+
+    Ok(())
+}
+
+/// What the audio channel inspector shows, copied from the emulator so it can be drawn on another thread.
+#[derive(Clone)]
+pub(crate) struct AudioSnapshot {
+    channels: [ChannelStatus; 4],
+    /// Each channel's own output, oldest first. Empty until the emulator has recorded some.
+    waveforms: [Vec<f32>; 4],
+}
+
+impl AudioSnapshot {
+    pub fn capture(gameboy: &GameBoy) -> Self {
+        let waveforms = match gameboy.audio_channel_taps() {
+            Some(taps) => taps.each_ref().map(|tap| tap.iter().copied().collect()),
+            None => Default::default(),
+        };
+
+        Self {
+            channels: gameboy.audio_channel_status(),
+            waveforms,
+        }
+    }
+}
+
+/// Lists the four audio channels, each with what it is set to play, its volume as a bar and its recent output as a
+/// waveform. Channels that can't be heard have their waveform greyed out.
+pub(crate) fn render_audio_inspector(
+    snapshot: &AudioSnapshot,
+    font: &Font,
+    canvas: &mut WindowCanvas,
+    texture_creator: &TextureCreator<WindowContext>,
+) -> Result<(), String> {
+    let line_height = font.height() + 1;
+    let row_height = line_height + WAVEFORM_HEIGHT + 4;
+
+    for (index, (status, waveform)) in snapshot.channels.iter().zip(&snapshot.waveforms).enumerate() {
+        let y = 4 + index as i32 * row_height;
+
+        let shape = match status.shape {
+            ChannelShape::Duty(eighths) => format!("duty {:.1}%", eighths as f32 * 12.5),
+            ChannelShape::Wave => "wave".to_string(),
+            ChannelShape::Lfsr(width) => format!("LFSR {width} bit"),
+        };
+
+        let text = format!(
+            "CH{} {} DAC {}  vol {:2}  {:8.1} Hz  {shape}",
+            index + 1,
+            if status.enabled { "on " } else { "off" },
+            if status.dac_enabled { "on " } else { "off" },
+            status.volume,
+            status.frequency,
+        );
+
+        render_text(font, canvas, texture_creator, text.as_str(), Point::new(4, y))?;
+
+        let graph_y = y + line_height;
+        let audible = status.enabled && status.dac_enabled;
+
+        canvas.set_draw_color(TEXT_BACKGROUND);
+        canvas.fill_rect(Rect::new(4, graph_y, (VOLUME_BAR_WIDTH + 2 + CHANNEL_TAP_LEN as i32) as u32, WAVEFORM_HEIGHT as u32))?;
+
+        // The volume bar fills up from the bottom.
+        let volume_height = WAVEFORM_HEIGHT * status.volume as i32 / 0xf;
+
+        if volume_height > 0 {
+            canvas.set_draw_color(TEXT_COLOR);
+            canvas.fill_rect(Rect::new(4, graph_y + WAVEFORM_HEIGHT - volume_height, VOLUME_BAR_WIDTH as u32, volume_height as u32))?;
+        }
+
+        // Samples go from -1 at the bottom to 1 at the top.
+        let points = waveform.iter()
+            .enumerate()
+            .map(|(x, sample)| Point::new(
+                4 + VOLUME_BAR_WIDTH + 2 + x as i32,
+                graph_y + ((1.0 - sample.clamp(-1.0, 1.0)) * 0.5 * (WAVEFORM_HEIGHT - 1) as f32) as i32,
+            ))
+            .collect::<Vec<_>>();
+
+        canvas.set_draw_color(if audible { WAVEFORM_COLOR } else { SILENT_WAVEFORM_COLOR });
+        canvas.draw_lines(points.as_slice())?;
+    }
+
+    Ok(())
+}
+
+/// Draws a pad with the buttons the game sees highlighted, and lists every button with what holds it and the frame it
+/// was last pressed or released on. Buttons held but hidden from the game, like turbo buttons between presses or a
+/// direction overridden by its opposite, are drawn dimmed.
+pub(crate) fn render_input_viewer(
+    snapshot: &InputSnapshot,
+    sources: &InputSources,
+    font: &Font,
+    canvas: &mut WindowCanvas,
+    texture_creator: &TextureCreator<WindowContext>,
+    pos: Point,
+) -> Result<(), String> {
+    canvas.set_draw_color(TEXT_BACKGROUND);
+    canvas.fill_rect(Rect::new(pos.x, pos.y, ((PAD_WIDTH + 2) * PAD_CELL) as u32, ((PAD_HEIGHT + 2) * PAD_CELL) as u32))?;
+
+    for &(button, _, x, y, width) in PAD_LAYOUT.iter() {
+        let color = if snapshot.latched.contains(button) {
+            TEXT_COLOR
+        } else if snapshot.held.contains(button) {
+            UNSEEN_BUTTON_COLOR
+        } else {
+            RELEASED_BUTTON_COLOR
+        };
+
+        canvas.set_draw_color(color);
+        canvas.fill_rect(Rect::new(pos.x + (x + 1) * PAD_CELL, pos.y + (y + 1) * PAD_CELL, width * PAD_CELL as u32 - 1, PAD_CELL as u32 - 1))?;
+    }
+
+    let line_height = font.height() + 1;
+    let top = pos.y + (PAD_HEIGHT + 2) * PAD_CELL + 2;
+
+    for (index, &(button, name, ..)) in PAD_LAYOUT.iter().enumerate() {
+        let names = sources.names(button);
+        let held = if names.is_empty() { "-".to_string() } else { names.join(", ") };
+
+        let toggled = match snapshot.toggled_at(button) {
+            Some(frame) => format!("frame {frame}"),
+            None => "never".to_string(),
+        };
+
+        render_text(font, canvas, texture_creator, &format!("{name:6} {toggled:12} {held}"), Point::new(pos.x, top + index as i32 * line_height))?;
+    }
+
+    Ok(())
+}
+
+/// Draws a strip down the right edge of the screen with a line for every scanline, going from green to red the longer
+/// its pixel transfer took. Lines that hit the 10-sprite limit are marked in white beside it, and lines showing the
+/// window in blue.
+pub(crate) fn render_line_stats(line_stats: &[LineStats; SCREEN_HEIGHT], canvas: &mut WindowCanvas, screen: Rect) -> Result<(), String> {
+    let strip_x = screen.right() - HEAT_STRIP_WIDTH;
+
+    for (line, stats) in line_stats.iter().enumerate() {
+        let top = screen.y() + (line as u32 * screen.height() / SCREEN_HEIGHT as u32) as i32;
+        let bottom = screen.y() + ((line as u32 + 1) * screen.height() / SCREEN_HEIGHT as u32) as i32;
+        let height = (bottom - top).max(1) as u32;
+
+        let heat = stats.transfer_dots.clamp(MIN_TRANSFER_DOTS, MAX_TRANSFER_DOTS) - MIN_TRANSFER_DOTS;
+        let red = (heat as u32 * 255 / (MAX_TRANSFER_DOTS - MIN_TRANSFER_DOTS) as u32) as u8;
+
+        canvas.set_draw_color(Color::RGB(red, 255 - red, 0));
+        canvas.fill_rect(Rect::new(strip_x, top, HEAT_STRIP_WIDTH as u32, height))?;
+
+        if stats.window {
+            canvas.set_draw_color(WINDOW_LINE_COLOR);
+            canvas.fill_rect(Rect::new(strip_x - LINE_MARK_WIDTH, top, LINE_MARK_WIDTH as u32, height))?;
+        }
+
+        if stats.sprite_limit_hit {
+            canvas.set_draw_color(SPRITE_LIMIT_COLOR);
+            canvas.fill_rect(Rect::new(strip_x - LINE_MARK_WIDTH * 2, top, LINE_MARK_WIDTH as u32, height))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Draws the screen shown while no ROM is loaded, over the whole of `screen`: how to load one, centered, and the
+/// version in the corner. Without a font, only the background is drawn.
+pub(crate) fn render_idle_screen(font: Option<&Font>, canvas: &mut WindowCanvas, texture_creator: &TextureCreator<WindowContext>, screen: Rect) -> Result<(), String> {
+    canvas.set_draw_color(IDLE_BACKGROUND);
+    canvas.fill_rect(screen)?;
+
+    let Some(font) = font else {
+        return Ok(());
+    };
+
+    let line_height = font.height() + 1;
+    let top = screen.center().y() - line_height * IDLE_LINES.len() as i32 / 2;
+
+    for (index, line) in IDLE_LINES.iter().enumerate() {
+        let (width, _) = font.size_of(line).map_err(|e| e.to_string())?;
+        let pos = Point::new(screen.center().x() - width as i32 / 2, top + line_height * index as i32);
+
+        render_text_with_background(font, canvas, texture_creator, line, pos, IDLE_BACKGROUND)?;
+    }
+
+    let version = concat!("v", env!("CARGO_PKG_VERSION"));
+    let (width, _) = font.size_of(version).map_err(|e| e.to_string())?;
+    let pos = Point::new(screen.right() - width as i32 - 4, screen.bottom() - font.height() - 4);
+
+    render_text_with_background(font, canvas, texture_creator, version, pos, IDLE_BACKGROUND)
+}
+
+/// Draws the save state slots over the whole of `screen`, in a grid with the thumbnail of every state in `colors`,
+/// and below it the slot number and how long ago it was saved, or that the slot is empty. The selected slot is
+/// framed.
+///
+/// `thumbnails` is a streaming RGB24 texture of `SLOT_COLUMNS` by `SLOT_COUNT / SLOT_COLUMNS` screens, one per slot.
+pub(crate) fn render_slot_picker(
+    picker: &SlotPicker,
+    colors: &[Color; 4],
+    font: &Font,
+    canvas: &mut WindowCanvas,
+    texture_creator: &TextureCreator<WindowContext>,
+    thumbnails: &mut Texture,
+    screen: Rect,
+) -> Result<(), String> {
+    thumbnails.with_lock(None, |buffer: &mut [u8], pitch: usize| {
+        for (index, slot) in picker.slots.iter().enumerate() {
+            let (column, row) = (index % SLOT_COLUMNS as usize, index / SLOT_COLUMNS as usize);
+
+            if let Slot::State(preview) = slot {
+                if let Some(thumbnail) = &preview.screen {
+                    let offset = row * SCREEN_HEIGHT * pitch + column * SCREEN_WIDTH * 3;
+
+                    screenshot::write_rgb(thumbnail, None, colors, &mut buffer[offset..], pitch);
+                }
+            }
+        }
+    })?;
+
+    canvas.set_draw_color(IDLE_BACKGROUND);
+    canvas.fill_rect(screen)?;
+
+    let line_height = font.height() + 1;
+    let top = screen.y() + SLOT_GAP + line_height;
+
+    render_text_with_background(font, canvas, texture_creator, SLOT_PICKER_HINT, Point::new(screen.x() + SLOT_GAP, screen.y() + SLOT_GAP), IDLE_BACKGROUND)?;
+
+    // The cells are as large as fits, with the thumbnails in the aspect ratio of the screen.
+    let cell_width = (screen.width() as i32 - SLOT_GAP * (SLOT_COLUMNS as i32 + 1)) / SLOT_COLUMNS as i32;
+    let cell_height = (screen.bottom() - top - SLOT_GAP * SLOT_ROWS as i32) / SLOT_ROWS as i32 - line_height;
+    let cell_width = cell_width.min(cell_height * SCREEN_WIDTH as i32 / SCREEN_HEIGHT as i32).max(1);
+    let cell_height = cell_width * SCREEN_HEIGHT as i32 / SCREEN_WIDTH as i32;
+
+    for (index, slot) in picker.slots.iter().enumerate() {
+        let (column, row) = ((index % SLOT_COLUMNS as usize) as i32, (index / SLOT_COLUMNS as usize) as i32);
+
+        let cell = Rect::new(
+            screen.x() + SLOT_GAP + column * (cell_width + SLOT_GAP),
+            top + row * (cell_height + line_height + SLOT_GAP),
+            cell_width as u32,
+            cell_height as u32,
+        );
+
+        if index == picker.selected as usize {
+            canvas.set_draw_color(TEXT_COLOR);
+            canvas.fill_rect(Rect::new(
+                cell.x() - SLOT_BORDER,
+                cell.y() - SLOT_BORDER,
+                cell.width() + SLOT_BORDER as u32 * 2,
+                cell.height() + SLOT_BORDER as u32 * 2,
+            ))?;
+        }
+
+        let (thumbnail, label) = match slot {
+            Slot::Empty => (None, String::from("empty")),
+            Slot::Unreadable => (None, String::from("unreadable")),
+            Slot::State(preview) => (
+                preview.screen.as_ref(),
+                preview.saved_at.map_or_else(|| String::from("saved"), |saved_at| slot_picker::age(saved_at, picker.opened_at)),
+            ),
+        };
+
+        match thumbnail {
+            Some(_) => canvas.copy(
+                thumbnails,
+                Some(Rect::new(column * SCREEN_WIDTH as i32, row * SCREEN_HEIGHT as i32, SCREEN_WIDTH as u32, SCREEN_HEIGHT as u32)),
+                Some(cell),
+            )?,
+            None => {
+                canvas.set_draw_color(EMPTY_SLOT_COLOR);
+                canvas.fill_rect(cell)?;
+            }
+        }
+
+        render_text_with_background(font, canvas, texture_creator, &format!("{index} {label}"), Point::new(cell.x(), cell.bottom() + SLOT_BORDER), IDLE_BACKGROUND)?;
+    }
+
+    Ok(())
+}
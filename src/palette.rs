@@ -0,0 +1,31 @@
+use sdl2::pixels::Color;
+
+pub(crate) const GREY: [Color; 4] = [
+    Color::RGB(0xff, 0xff, 0xff),
+    Color::RGB(0xc0, 0xc0, 0xc0),
+    Color::RGB(0x40, 0x40, 0x40),
+    Color::RGB(0, 0, 0),
+];
+
+// The greenish LCD of the original DMG.
+pub(crate) const DMG_GREEN: [Color; 4] = [
+    Color::RGB(0x9b, 0xbc, 0x0f),
+    Color::RGB(0x8b, 0xac, 0x0f),
+    Color::RGB(0x30, 0x62, 0x30),
+    Color::RGB(0x0f, 0x38, 0x0f),
+];
+
+// pub(crate) const COLORS: [Color; 4] = [
+//     Color::RGB(0xe2, 0xf3, 0xe4),
+//     Color::RGB(0x94, 0xe3, 0x44),
+//     Color::RGB(0x46, 0x87, 0x8f),
+//     Color::RGB(0x33, 0x2c, 0x50),
+// ];
+
+pub(crate) fn by_name(name: &str) -> Option<[Color; 4]> {
+    match name {
+        "grey" | "gray" => Some(GREY),
+        "dmg-green" => Some(DMG_GREEN),
+        _ => None,
+    }
+}
@@ -0,0 +1,172 @@
+use std::path::{Path, PathBuf};
+use crate::gameboy::CartridgeHeader;
+
+const CONFIG_FILE_NAME: &str = "yagbe.cfg";
+
+// The folders of portable mode, next to the executable.
+const PORTABLE_SAVE_DIR: &str = "saves";
+const PORTABLE_STATE_DIR: &str = "states";
+
+// The name of the files in the folder of a game, when they don't go next to the ROM.
+const SAVE_FILE_STEM: &str = "cartridge";
+
+/// The folder of the executable, where the config file is.
+fn exe_dir() -> Option<PathBuf> {
+    std::env::current_exe().ok()?.parent().map(Path::to_path_buf)
+}
+
+/// The config file, next to the executable.
+pub(crate) fn config_file() -> Option<PathBuf> {
+    exe_dir().map(|dir| dir.join(CONFIG_FILE_NAME))
+}
+
+/// Where the files the frontend writes go: saves, save states and recordings.
+///
+/// By default, saves and states go next to the ROM and recordings in the working folder. A save or state folder puts
+/// each game's files in a folder of its own within it, see `game_folder`, so ROMs on read-only drives can be saved.
+/// Portable mode puts everything in folders next to the executable, whatever the config says.
+pub(crate) struct Paths {
+    save_dir: Option<PathBuf>,
+    state_dir: Option<PathBuf>,
+    recording_dir: Option<PathBuf>,
+}
+
+impl Paths {
+    /// Relative folders are relative to the executable, like the config file they come from.
+    pub fn new(save_dir: Option<&Path>, state_dir: Option<&Path>, portable: bool) -> Self {
+        let exe_dir = exe_dir().unwrap_or_default();
+
+        if portable {
+            return Self {
+                save_dir: Some(exe_dir.join(PORTABLE_SAVE_DIR)),
+                state_dir: Some(exe_dir.join(PORTABLE_STATE_DIR)),
+                recording_dir: Some(exe_dir),
+            };
+        }
+
+        Self {
+            save_dir: save_dir.map(|dir| exe_dir.join(dir)),
+            state_dir: state_dir.map(|dir| exe_dir.join(dir)),
+            recording_dir: None,
+        }
+    }
+
+    /// The cartridge RAM of `rom_path`, which has `header`. Its folder may not exist yet.
+    pub fn save_path(&self, rom_path: &Path, header: &CartridgeHeader) -> PathBuf {
+        game_file(self.save_dir.as_deref(), rom_path, header, "sav")
+    }
+
+    /// The save of the linked game of a split view, apart from the first in case both run the same ROM.
+    pub fn linked_save_path(&self, rom_path: &Path, header: &CartridgeHeader) -> PathBuf {
+        game_file(self.save_dir.as_deref(), rom_path, header, "link.sav")
+    }
+
+    /// The save state in `slot` of `rom_path`. Its folder may not exist yet.
+    pub fn state_path(&self, rom_path: &Path, header: &CartridgeHeader, slot: u8) -> PathBuf {
+        game_file(self.state_dir.as_deref(), rom_path, header, &format!("state{slot}"))
+    }
+
+    /// The path of a recording, without an extension, since it may be split into several files.
+    pub fn recording_base(&self, timestamp: u64) -> PathBuf {
+        let name = format!("recording-{timestamp}");
+
+        match &self.recording_dir {
+            Some(dir) => dir.join(name),
+            None => PathBuf::from(name),
+        }
+    }
+}
+
+/// A file of a game with `extension`, next to the ROM and named after it, or in its folder within `dir`.
+fn game_file(dir: Option<&Path>, rom_path: &Path, header: &CartridgeHeader, extension: &str) -> PathBuf {
+    match dir {
+        Some(dir) => dir.join(game_folder(header)).join(SAVE_FILE_STEM).with_extension(extension),
+        None => rom_path.with_extension(extension),
+    }
+}
+
+/// The folder of a game within a save or state folder: its title and global checksum, e.g. "TETRIS-0BF6". Different
+/// games with the same title, or ROMs with the same file name, get folders of their own, and copies of the same ROM
+/// share theirs.
+///
+/// Characters that aren't allowed in file names on Windows are replaced. The checksum at the end also keeps the name
+/// from being a reserved device name like "CON", or ending with the dot or space that Windows would drop.
+fn game_folder(header: &CartridgeHeader) -> String {
+    let title = header.title.trim();
+
+    let title = match title.is_empty() {
+        true => String::from("UNTITLED"),
+        false => title.chars()
+            .map(|c| if c.is_control() || matches!(c, '<' | '>' | ':' | '"' | '/' | '\\' | '|' | '?' | '*') { '_' } else { c })
+            .collect(),
+    };
+
+    format!("{title}-{:04X}", header.global_checksum)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(title: &str) -> CartridgeHeader {
+        let mut rom = vec![0; 0x8000];
+        rom[0x14e..0x150].copy_from_slice(&[0x0b, 0xf6]);
+
+        let mut header = CartridgeHeader::parse(&rom).unwrap();
+        header.title = title.to_string();
+
+        header
+    }
+
+    #[test]
+    fn files_go_next_to_the_rom_by_default() {
+        let paths = Paths::new(None, None, false);
+        let rom_path = Path::new("/games/tetris.gb");
+
+        assert_eq!(paths.save_path(rom_path, &header("TETRIS")), Path::new("/games/tetris.sav"));
+        assert_eq!(paths.linked_save_path(rom_path, &header("TETRIS")), Path::new("/games/tetris.link.sav"));
+        assert_eq!(paths.state_path(rom_path, &header("TETRIS"), 3), Path::new("/games/tetris.state3"));
+        assert_eq!(paths.recording_base(1234), Path::new("recording-1234"));
+    }
+
+    #[test]
+    fn save_and_state_folders_have_a_folder_for_every_game() {
+        let paths = Paths::new(Some(Path::new("/saves")), Some(Path::new("/states")), false);
+        let rom_path = Path::new("/games/tetris.gb");
+
+        assert_eq!(paths.save_path(rom_path, &header("TETRIS")), Path::new("/saves/TETRIS-0BF6/cartridge.sav"));
+        assert_eq!(paths.state_path(rom_path, &header("TETRIS"), 0), Path::new("/states/TETRIS-0BF6/cartridge.state0"));
+        assert_eq!(paths.recording_base(1234), Path::new("recording-1234"));
+    }
+
+    #[test]
+    fn relative_folders_are_relative_to_the_executable() {
+        let exe_dir = exe_dir().unwrap();
+        let paths = Paths::new(Some(Path::new("saves")), None, false);
+
+        assert_eq!(
+            paths.save_path(Path::new("tetris.gb"), &header("TETRIS")),
+            exe_dir.join("saves").join("TETRIS-0BF6").join("cartridge.sav"),
+        );
+    }
+
+    #[test]
+    fn portable_mode_puts_everything_next_to_the_executable() {
+        let exe_dir = exe_dir().unwrap();
+        let paths = Paths::new(Some(Path::new("/saves")), Some(Path::new("/states")), true);
+        let rom_path = Path::new("/games/tetris.gb");
+
+        assert_eq!(paths.save_path(rom_path, &header("TETRIS")), exe_dir.join("saves/TETRIS-0BF6/cartridge.sav"));
+        assert_eq!(paths.state_path(rom_path, &header("TETRIS"), 1), exe_dir.join("states/TETRIS-0BF6/cartridge.state1"));
+        assert_eq!(paths.recording_base(1234), exe_dir.join("recording-1234"));
+    }
+
+    #[test]
+    fn game_folders_are_valid_file_names() {
+        assert_eq!(game_folder(&header("POKEMON RED")), "POKEMON RED-0BF6");
+        assert_eq!(game_folder(&header("A/B\\C:D*?\"<>|")), "A_B_C_D______-0BF6");
+        assert_eq!(game_folder(&header("TAB\tEND ")), "TAB_END-0BF6");
+        assert_eq!(game_folder(&header("  ")), "UNTITLED-0BF6");
+        assert_eq!(game_folder(&header("CON")), "CON-0BF6");
+    }
+}
@@ -0,0 +1,138 @@
+use tao::dpi::{PhysicalPosition, PhysicalSize};
+
+/// How much of a window's top edge, in physical pixels, has to be on a monitor for its title bar to be dragged.
+const MIN_VISIBLE: u32 = 64;
+
+/// The bounds of a monitor in physical pixels of the desktop, as tao reports them.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) struct MonitorArea {
+    pub position: PhysicalPosition<i32>,
+    pub size: PhysicalSize<u32>,
+}
+
+impl MonitorArea {
+    pub fn of(monitor: &tao::monitor::MonitorHandle) -> Self {
+        Self {
+            position: monitor.position(),
+            size: monitor.size(),
+        }
+    }
+
+    pub fn contains(&self, position: PhysicalPosition<i32>) -> bool {
+        self.distance_squared((position.x as i64, position.y as i64)) == 0
+    }
+
+    /// Whether enough of the top edge of a window at `position` that is `width` wide is on the monitor to drag it.
+    fn holds_title_bar(&self, position: PhysicalPosition<i32>, width: u32) -> bool {
+        let (left, top) = (self.position.x as i64, self.position.y as i64);
+        let (right, bottom) = (left + self.size.width as i64, top + self.size.height as i64);
+
+        let overlap = (position.x as i64 + width as i64).min(right) - (position.x as i64).max(left);
+
+        overlap >= MIN_VISIBLE.min(width) as i64 && (top..bottom - MIN_VISIBLE as i64).contains(&(position.y as i64))
+    }
+
+    /// The squared distance from `point` to the nearest point of the monitor, 0 if it is on it.
+    fn distance_squared(&self, point: (i64, i64)) -> i64 {
+        let (left, top) = (self.position.x as i64, self.position.y as i64);
+
+        let dx = point.0 - point.0.clamp(left, left + self.size.width as i64);
+        let dy = point.1 - point.1.clamp(top, top + self.size.height as i64);
+
+        dx * dx + dy * dy
+    }
+}
+
+/// Where to open a window of outer size `size` that was at `position` in an earlier session. The window stays where
+/// it was while its title bar is on one of `monitors`, so that windows spanning monitors are kept as they were.
+/// Otherwise, as when the monitor it was on is disconnected, it is moved onto the monitor nearest to its center, as
+/// little as it takes to be wholly on it, or to its top left corner if it is larger than the monitor.
+///
+/// Returns `None` without monitors, to leave the window where the OS puts it.
+pub(crate) fn clamp_to_monitors(position: PhysicalPosition<i32>, size: PhysicalSize<u32>, monitors: &[MonitorArea]) -> Option<PhysicalPosition<i32>> {
+    if monitors.iter().any(|monitor| monitor.holds_title_bar(position, size.width)) {
+        return Some(position);
+    }
+
+    let center = (position.x as i64 + size.width as i64 / 2, position.y as i64 + size.height as i64 / 2);
+    let monitor = monitors.iter().min_by_key(|monitor| monitor.distance_squared(center))?;
+
+    let clamp = |value: i32, length: u32, start: i32, monitor_length: u32| {
+        let end = start as i64 + monitor_length as i64 - length as i64;
+
+        (value as i64).min(end).max(start as i64) as i32
+    };
+
+    Some(PhysicalPosition::new(
+        clamp(position.x, size.width, monitor.position.x, monitor.size.width),
+        clamp(position.y, size.height, monitor.position.y, monitor.size.height),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn monitor(x: i32, y: i32, width: u32, height: u32) -> MonitorArea {
+        MonitorArea {
+            position: PhysicalPosition::new(x, y),
+            size: PhysicalSize::new(width, height),
+        }
+    }
+
+    const SIZE: PhysicalSize<u32> = PhysicalSize::new(480, 432);
+
+    fn clamp(x: i32, y: i32, monitors: &[MonitorArea]) -> Option<(i32, i32)> {
+        clamp_to_monitors(PhysicalPosition::new(x, y), SIZE, monitors).map(|position| (position.x, position.y))
+    }
+
+    #[test]
+    fn windows_with_their_title_bar_on_a_monitor_stay() {
+        let monitors = [monitor(0, 0, 1920, 1080), monitor(1920, 0, 1280, 1024)];
+
+        assert_eq!(clamp(100, 100, &monitors), Some((100, 100)));
+        // Spanning both monitors.
+        assert_eq!(clamp(1700, 200, &monitors), Some((1700, 200)));
+        // Mostly off the right of the second, but with 64 pixels of the title bar on it.
+        assert_eq!(clamp(3136, 0, &monitors), Some((3136, 0)));
+        // Down to 64 pixels above the bottom.
+        assert_eq!(clamp(100, 1015, &monitors), Some((100, 1015)));
+    }
+
+    #[test]
+    fn windows_off_the_monitors_move_onto_the_nearest_one() {
+        let monitors = [monitor(0, 0, 1920, 1080), monitor(1920, 0, 1280, 1024)];
+
+        // Too little of the title bar left on the second monitor.
+        assert_eq!(clamp(3140, 0, &monitors), Some((3200 - 480, 0)));
+        // Too close to the bottom, or above the top.
+        assert_eq!(clamp(100, 1017, &monitors), Some((100, 1080 - 432)));
+        assert_eq!(clamp(100, -10, &monitors), Some((100, 0)));
+        // On a monitor that was disconnected, left of the first.
+        assert_eq!(clamp(-1500, 300, &monitors), Some((0, 300)));
+        // Below the second, nearer to it than to the first.
+        assert_eq!(clamp(2500, 1500, &monitors), Some((2500, 1024 - 432)));
+    }
+
+    #[test]
+    fn windows_larger_than_the_monitor_go_to_its_top_left_corner() {
+        let monitors = [monitor(-800, 100, 400, 300)];
+
+        assert_eq!(clamp(0, 0, &monitors), Some((-800, 100)));
+    }
+
+    #[test]
+    fn windows_are_left_to_the_os_without_monitors() {
+        assert_eq!(clamp(100, 100, &[]), None);
+    }
+
+    #[test]
+    fn monitors_contain_their_edges() {
+        let monitor = monitor(-1280, 0, 1280, 1024);
+
+        assert!(monitor.contains(PhysicalPosition::new(-1280, 0)));
+        assert!(monitor.contains(PhysicalPosition::new(-1, 1023)));
+        assert!(!monitor.contains(PhysicalPosition::new(-1281, 0)));
+        assert!(!monitor.contains(PhysicalPosition::new(0, 1025)));
+    }
+}
@@ -0,0 +1,247 @@
+use std::{
+    fs::{self, File},
+    io::{BufWriter, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    sync::{
+        mpsc::{self, Receiver, Sender, SyncSender, TrySendError},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+use crate::{
+    gameboy::{self, FRAMES_PER_SECOND, SCREEN_HEIGHT, SCREEN_WIDTH},
+    paths::Paths,
+    screenshot,
+};
+
+// Frames waiting for the encoder. Once full, new frames are dropped instead of blocking the emulator.
+const FRAME_QUEUE_SIZE: usize = 8;
+
+/// Where the audio callback sends its output while recording.
+pub(crate) type AudioTap = Arc<Mutex<Option<Sender<Vec<f32>>>>>;
+
+/// Records presented frames and audio output.
+///
+/// Frames are piped to ffmpeg as raw RGB24 and audio is written to a WAV file next to it. When stopped, both are
+/// muxed into an mp4. If ffmpeg can't be started, frames are written as numbered PNGs into a folder instead.
+pub(crate) struct Recorder {
+    frames: SyncSender<Vec<u8>>,
+    audio_tap: AudioTap,
+    encoder: JoinHandle<()>,
+    started: Instant,
+    dropped_frames: u64,
+}
+
+enum FrameOutput {
+    Ffmpeg {
+        ffmpeg: std::process::Child,
+        base: PathBuf,
+    },
+    Images(PathBuf),
+}
+
+impl Recorder {
+    pub fn start(audio_tap: &AudioTap, paths: &Paths) -> Result<Self, String> {
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        let base = paths.recording_base(timestamp);
+
+        let output = match spawn_video_encoder(&base.with_extension("video.mp4")) {
+            Ok(ffmpeg) => FrameOutput::Ffmpeg { ffmpeg, base: base.clone() },
+            Err(e) => {
+                eprintln!("Could not start ffmpeg, recording frames as images instead: {e}");
+
+                fs::create_dir_all(&base).map_err(|e| format!("Could not create recording folder: {e}"))?;
+
+                FrameOutput::Images(base.clone())
+            }
+        };
+
+        let (audio_sender, audio_receiver) = mpsc::channel();
+        let wav = WavWriter::create(&base.with_extension("wav"))?;
+        let audio_thread = thread::spawn(move || write_audio(wav, audio_receiver));
+
+        let (frames, frame_receiver) = mpsc::sync_channel(FRAME_QUEUE_SIZE);
+        let encoder = thread::spawn(move || {
+            write_frames(output, frame_receiver);
+
+            // The audio is complete once the tap is removed, which happens before the frame queue is closed.
+            let _ = audio_thread.join();
+        });
+
+        *audio_tap.lock().unwrap() = Some(audio_sender);
+
+        Ok(Self {
+            frames,
+            audio_tap: audio_tap.clone(),
+            encoder,
+            started: Instant::now(),
+            dropped_frames: 0,
+        })
+    }
+
+    /// Queues a frame of RGB24 pixels, dropping it if the encoder is behind.
+    pub fn push_frame(&mut self, rgb: Vec<u8>) {
+        if let Err(TrySendError::Full(_)) = self.frames.try_send(rgb) {
+            self.dropped_frames += 1;
+        }
+    }
+
+    pub fn status_line(&self) -> String {
+        let seconds = self.started.elapsed().as_secs();
+
+        let mut status = format!("REC {:02}:{:02}", seconds / 60, seconds % 60);
+        if self.dropped_frames > 0 {
+            status += &format!(" ({} dropped)", self.dropped_frames);
+        }
+
+        status
+    }
+
+    /// Stops recording. Encoding finishes in the background, join the returned handle to wait for it.
+    pub fn stop(self) -> JoinHandle<()> {
+        // Dropping the senders ends the audio thread, and then the frame thread.
+        self.audio_tap.lock().unwrap().take();
+
+        self.encoder
+    }
+}
+
+fn spawn_video_encoder(path: &Path) -> std::io::Result<std::process::Child> {
+    Command::new("ffmpeg")
+        .args(["-y", "-loglevel", "error", "-f", "rawvideo", "-pixel_format", "rgb24"])
+        .args(["-video_size", &format!("{SCREEN_WIDTH}x{SCREEN_HEIGHT}")])
+        .args(["-framerate", &format!("{FRAMES_PER_SECOND:.4}")])
+        .args(["-i", "-"])
+        // Scale up without smoothing, since most players blur a 160x144 video when shown any larger.
+        .args(["-vf", "scale=iw*4:ih*4:flags=neighbor", "-c:v", "libx264", "-pix_fmt", "yuv420p"])
+        .arg(path)
+        .stdin(Stdio::piped())
+        .spawn()
+}
+
+fn write_frames(output: FrameOutput, frames: Receiver<Vec<u8>>) {
+    match output {
+        FrameOutput::Ffmpeg { mut ffmpeg, base } => {
+            if let Some(mut stdin) = ffmpeg.stdin.take() {
+                for frame in frames {
+                    if stdin.write_all(&frame).is_err() {
+                        break;
+                    }
+                }
+            }
+
+            // Closing stdin lets ffmpeg finish the file.
+            if !matches!(ffmpeg.wait(), Ok(status) if status.success()) {
+                eprintln!("ffmpeg failed to encode the recording");
+                return;
+            }
+
+            mux(&base);
+        }
+        FrameOutput::Images(folder) => {
+            for (index, frame) in frames.iter().enumerate() {
+                if let Err(msg) = screenshot::save_rgb_png(&folder.join(format!("{index:06}.png")), &frame) {
+                    eprintln!("{msg}");
+                    break;
+                }
+            }
+        }
+    }
+}
+
+/// Combines the encoded video and the WAV into one mp4, removing both on success.
+fn mux(base: &Path) {
+    let video = base.with_extension("video.mp4");
+    let audio = base.with_extension("wav");
+
+    let status = Command::new("ffmpeg")
+        .args(["-y", "-loglevel", "error", "-i"])
+        .arg(&video)
+        .arg("-i")
+        .arg(&audio)
+        .args(["-c:v", "copy", "-c:a", "aac", "-shortest"])
+        .arg(base.with_extension("mp4"))
+        .status();
+
+    if matches!(status, Ok(status) if status.success()) {
+        let _ = fs::remove_file(video);
+        let _ = fs::remove_file(audio);
+    } else {
+        eprintln!("Could not add audio to the recording, keeping {} and {}", video.display(), audio.display());
+    }
+}
+
+fn write_audio(mut wav: WavWriter, samples: Receiver<Vec<f32>>) {
+    for buffer in samples {
+        if let Err(msg) = wav.write(&buffer) {
+            eprintln!("{msg}");
+            break;
+        }
+    }
+
+    if let Err(msg) = wav.finish() {
+        eprintln!("{msg}");
+    }
+}
+
+/// Writes interleaved stereo 32-bit float samples at the APU's sample rate.
+struct WavWriter {
+    file: BufWriter<File>,
+    data_len: u32,
+}
+
+impl WavWriter {
+    fn create(path: &Path) -> Result<Self, String> {
+        let file = File::create(path).map_err(|e| format!("Could not create audio file: {e}"))?;
+
+        let mut wav = Self {
+            file: BufWriter::new(file),
+            data_len: 0,
+        };
+
+        // The sizes are filled in by `finish`.
+        wav.write_header().map_err(|e| format!("Could not write audio file: {e}"))?;
+
+        Ok(wav)
+    }
+
+    fn write_header(&mut self) -> std::io::Result<()> {
+        const CHANNELS: u16 = 2;
+        const BYTES_PER_SAMPLE: u16 = 4;
+        const FORMAT_IEEE_FLOAT: u16 = 3;
+
+        let sample_rate = gameboy::apu::AUDIO_SAMPLE_RATE as u32;
+
+        self.file.write_all(b"RIFF")?;
+        self.file.write_all(&(36 + self.data_len).to_le_bytes())?;
+        self.file.write_all(b"WAVEfmt ")?;
+        self.file.write_all(&16u32.to_le_bytes())?;
+        self.file.write_all(&FORMAT_IEEE_FLOAT.to_le_bytes())?;
+        self.file.write_all(&CHANNELS.to_le_bytes())?;
+        self.file.write_all(&sample_rate.to_le_bytes())?;
+        self.file.write_all(&(sample_rate * (CHANNELS * BYTES_PER_SAMPLE) as u32).to_le_bytes())?;
+        self.file.write_all(&(CHANNELS * BYTES_PER_SAMPLE).to_le_bytes())?;
+        self.file.write_all(&(BYTES_PER_SAMPLE * 8).to_le_bytes())?;
+        self.file.write_all(b"data")?;
+        self.file.write_all(&self.data_len.to_le_bytes())
+    }
+
+    fn write(&mut self, samples: &[f32]) -> Result<(), String> {
+        for sample in samples {
+            self.file.write_all(&sample.to_le_bytes()).map_err(|e| format!("Could not write audio file: {e}"))?;
+        }
+
+        self.data_len += samples.len() as u32 * 4;
+
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<(), String> {
+        self.file.seek(SeekFrom::Start(0))
+            .and_then(|_| self.write_header())
+            .and_then(|_| self.file.flush())
+            .map_err(|e| format!("Could not write audio file: {e}"))
+    }
+}
@@ -0,0 +1,65 @@
+use std::path::Path;
+use crate::gameboy::{CartridgeHeader, CgbSupport, LicenseeCode};
+
+/// A warning to show before running a ROM that likely won't run correctly, if it is one. It still runs after.
+pub(crate) fn load_warning(header: &CartridgeHeader) -> Option<&'static str> {
+    // TODO: Drop this once CGB hardware is emulated. Until then, the CGB model only sets the registers of its boot ROM.
+    (!header.cgb.runs_on_dmg()).then_some("This is a CGB-only ROM. Only DMG hardware is emulated, so running it will likely fail, usually with a screen saying that it requires a Game Boy Color, or a black screen.")
+}
+
+/// Formats a cartridge header for the ROM Info dialog, one field per line.
+pub(crate) fn describe(header: &CartridgeHeader, save_path: &Path, state_dir: &Path) -> String {
+    let mut lines = vec![format!("Title: {}", header.title)];
+
+    if let Some(code) = &header.manufacturer_code {
+        lines.push(format!("Manufacturer code: {code}"));
+    }
+
+    lines.push(format!("Cartridge type: {} ({:#04x})", header.cartridge_type_name(), header.cartridge_type));
+    lines.push(format!("Mapper: {}", header.mapper()));
+    lines.push(format!("ROM size: {}", format_size(header.rom_size)));
+    lines.push(if header.ram_size_valid {
+        format!("RAM size: {}", format_size(header.ram_size))
+    } else {
+        String::from("RAM size: None, the size in the header is invalid")
+    });
+
+    lines.push(format!("CGB: {}", match header.cgb {
+        CgbSupport::None => "No",
+        CgbSupport::Enhanced => "Supported",
+        CgbSupport::Only => "Required",
+    }));
+    lines.push(format!("SGB: {}", if header.sgb { "Supported" } else { "No" }));
+
+    let code = match &header.licensee_code {
+        LicenseeCode::Old(code) => format!("{code:02X}"),
+        LicenseeCode::New(code) => code.clone(),
+    };
+    lines.push(format!("Licensee: {} ({code})", header.licensee().unwrap_or("Unknown")));
+
+    lines.push(format!("Version: {}", header.version));
+    lines.push(format!("Header checksum: {:#04x} ({})", header.header_checksum, checksum_status(header.header_checksum_valid)));
+    lines.push(format!("Global checksum: {:#06x} ({})", header.global_checksum, checksum_status(header.global_checksum_valid)));
+
+    lines.push(if header.has_battery() {
+        format!("Save file: {}", save_path.display())
+    } else {
+        String::from("Save file: None, the cartridge has no battery")
+    });
+    lines.push(format!("Save state folder: {}", state_dir.display()));
+
+    lines.join("\n")
+}
+
+fn format_size(bytes: usize) -> String {
+    match bytes {
+        0 => String::from("None"),
+        // The multicart sizes of 72, 80 and 96 banks aren't a whole number of MiB.
+        bytes if bytes >= 1024 * 1024 && bytes % (1024 * 1024) == 0 => format!("{} MiB", bytes / (1024 * 1024)),
+        bytes => format!("{} KiB", bytes / 1024),
+    }
+}
+
+fn checksum_status(valid: bool) -> &'static str {
+    if valid { "valid" } else { "invalid" }
+}
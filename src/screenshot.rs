@@ -0,0 +1,142 @@
+use std::{fs, path::Path};
+use sdl2::pixels::Color;
+use crate::gameboy::{SCREEN_HEIGHT, SCREEN_WIDTH};
+
+// Largest payload of an uncompressed deflate block.
+const STORED_BLOCK_SIZE: usize = 0xffff;
+
+/// FNV-1a hash of the screen's color indices, stable across builds so it can be compared in scripts.
+pub(crate) fn screen_hash(screen: &[u8; SCREEN_WIDTH * SCREEN_HEIGHT]) -> u64 {
+    screen.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &pixel| (hash ^ pixel as u64).wrapping_mul(0x0100_0000_01b3))
+}
+
+/// The previous screen, and how much of it in percent is mixed into every pixel of the current one, imitating the
+/// ghosting of the slow DMG LCD that games flickering sprites for transparency rely on.
+pub(crate) type Blend<'a> = Option<(&'a [u8; SCREEN_WIDTH * SCREEN_HEIGHT], u8)>;
+
+/// Converts the screen's color indices to RGB24 pixels, the format used for display, screenshots and recording.
+pub(crate) fn screen_to_rgb(screen: &[u8; SCREEN_WIDTH * SCREEN_HEIGHT], blend: Blend, palette: &[Color; 4]) -> Vec<u8> {
+    let mut rgb = vec![0; SCREEN_WIDTH * SCREEN_HEIGHT * 3];
+
+    write_rgb(screen, blend, palette, &mut rgb, SCREEN_WIDTH * 3);
+
+    rgb
+}
+
+/// Like `screen_to_rgb`, but into an existing buffer with `pitch` bytes per row, such as a locked texture.
+pub(crate) fn write_rgb(screen: &[u8; SCREEN_WIDTH * SCREEN_HEIGHT], blend: Blend, palette: &[Color; 4], rgb: &mut [u8], pitch: usize) {
+    let colors = palette.map(|color| [color.r, color.g, color.b]);
+
+    let Some((previous, weight)) = blend else {
+        for (row, out) in screen.chunks_exact(SCREEN_WIDTH).zip(rgb.chunks_mut(pitch)) {
+            for (&color, out) in row.iter().zip(out.chunks_exact_mut(3)) {
+                out.copy_from_slice(&colors[color as usize]);
+            }
+        }
+
+        return;
+    };
+
+    // There are only 4 colors, so every mix of a current and a previous one is worked out up front.
+    let mixed = colors.map(|current| colors.map(|previous| mix(current, previous, weight)));
+
+    for ((row, previous_row), out) in screen.chunks_exact(SCREEN_WIDTH).zip(previous.chunks_exact(SCREEN_WIDTH)).zip(rgb.chunks_mut(pitch)) {
+        for ((&color, &previous_color), out) in row.iter().zip(previous_row).zip(out.chunks_exact_mut(3)) {
+            out.copy_from_slice(&mixed[color as usize][previous_color as usize]);
+        }
+    }
+}
+
+/// Mixes `weight` percent of `previous` into `current`. Squaring the channels first approximates mixing the light
+/// they stand for, which keeps a mix of black and white from looking too dark.
+fn mix(current: [u8; 3], previous: [u8; 3], weight: u8) -> [u8; 3] {
+    let weight = weight.min(100) as f32 / 100.0;
+
+    std::array::from_fn(|channel| {
+        let current = current[channel] as f32;
+        let previous = previous[channel] as f32;
+
+        (current * current * (1.0 - weight) + previous * previous * weight).sqrt().round() as u8
+    })
+}
+
+/// Writes the screen as an RGB PNG.
+pub(crate) fn save_png(path: &Path, screen: &[u8; SCREEN_WIDTH * SCREEN_HEIGHT], blend: Blend, palette: &[Color; 4]) -> Result<(), String> {
+    save_rgb_png(path, &screen_to_rgb(screen, blend, palette))
+}
+
+/// Writes a screen of RGB24 pixels as a PNG. The image data is stored uncompressed, which keeps this free of
+/// dependencies.
+pub(crate) fn save_rgb_png(path: &Path, rgb: &[u8]) -> Result<(), String> {
+    // Every row starts with its filter type, 0 being no filter.
+    let mut pixels = Vec::with_capacity(SCREEN_HEIGHT * (1 + SCREEN_WIDTH * 3));
+
+    for row in rgb.chunks(SCREEN_WIDTH * 3) {
+        pixels.push(0);
+        pixels.extend_from_slice(row);
+    }
+
+    // zlib stream: header, stored deflate blocks, then the Adler-32 of the data.
+    let mut data = vec![0x78, 0x01];
+
+    let block_count = pixels.chunks(STORED_BLOCK_SIZE).count();
+
+    for (index, block) in pixels.chunks(STORED_BLOCK_SIZE).enumerate() {
+        let len = block.len() as u16;
+
+        data.push((index == block_count - 1) as u8);
+        data.extend_from_slice(&len.to_le_bytes());
+        data.extend_from_slice(&(!len).to_le_bytes());
+        data.extend_from_slice(block);
+    }
+
+    data.extend_from_slice(&adler32(&pixels).to_be_bytes());
+
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&(SCREEN_WIDTH as u32).to_be_bytes());
+    header.extend_from_slice(&(SCREEN_HEIGHT as u32).to_be_bytes());
+    // 8 bits per channel, RGB, default compression, filtering and no interlacing.
+    header.extend_from_slice(&[8, 2, 0, 0, 0]);
+
+    let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+    write_chunk(&mut png, b"IHDR", &header);
+    write_chunk(&mut png, b"IDAT", &data);
+    write_chunk(&mut png, b"IEND", &[]);
+
+    fs::write(path, png).map_err(|e| format!("Could not write screenshot: {e}"))
+}
+
+fn write_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+
+    let start = png.len();
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+
+    let crc = crc32(&png[start..]);
+    png.extend_from_slice(&crc.to_be_bytes());
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xffff_ffffu32;
+
+    for &byte in data {
+        crc ^= byte as u32;
+
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xedb8_8320 } else { crc >> 1 };
+        }
+    }
+
+    !crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (a, b) = data.iter().fold((1u32, 0u32), |(a, b), &byte| {
+        let a = (a + byte as u32) % 65521;
+
+        (a, (b + a) % 65521)
+    });
+
+    (b << 16) | a
+}
@@ -0,0 +1,153 @@
+use std::{fs, path::{Path, PathBuf}};
+use crate::gameboy::Buttons;
+
+/// What a script does at the start of a frame.
+pub(crate) enum Action {
+    Press(Buttons),
+    Release(Buttons),
+    Screenshot(PathBuf),
+    /// Fails the run unless the screen hash, as printed by headless runs, is this.
+    AssertHash(u64),
+    Poke { addr: u16, value: u8 },
+}
+
+/// An action and the number of frames to run before it.
+pub(crate) struct Command {
+    pub frame: u64,
+    pub action: Action,
+    /// The line of the script it is on, for reporting failed assertions.
+    pub line: usize,
+}
+
+/// Reads a script of one command per line, like `frame 120: press A`. Blank lines and lines starting with `#` are
+/// skipped. The commands are returned in the order they run, which for the same frame is the order of their lines.
+///
+/// The commands are `press BUTTON`, `release BUTTON`, `screenshot FILE`, `assert-hash HASH` and `poke ADDR=VALUE`,
+/// with the hash, address and value in hexadecimal, and buttons named as in `Buttons`, e.g. `Start`.
+pub(crate) fn load(path: &Path) -> Result<Vec<Command>, String> {
+    let contents = fs::read_to_string(path).map_err(|e| format!("Could not read script: {e}"))?;
+
+    parse(&contents)
+}
+
+fn parse(contents: &str) -> Result<Vec<Command>, String> {
+    let mut commands = Vec::new();
+
+    for (index, line) in contents.lines().map(str::trim).enumerate() {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let command = parse_line(line, index + 1).ok_or_else(|| format!("Invalid command on line {} of the script: {line}", index + 1))?;
+
+        commands.push(command);
+    }
+
+    // A stable sort keeps the order of the lines within a frame.
+    commands.sort_by_key(|command| command.frame);
+
+    Ok(commands)
+}
+
+fn parse_line(line: &str, line_number: usize) -> Option<Command> {
+    let (frame, command) = line.strip_prefix("frame")?.split_once(':')?;
+    let frame = frame.trim().parse().ok()?;
+
+    let command = command.trim();
+    let (name, argument) = command.split_once(char::is_whitespace).unwrap_or((command, ""));
+    let argument = argument.trim();
+
+    let action = match name {
+        "press" => Action::Press(Buttons::from_name(argument)?),
+        "release" => Action::Release(Buttons::from_name(argument)?),
+        "screenshot" if !argument.is_empty() => Action::Screenshot(argument.into()),
+        "assert-hash" => Action::AssertHash(parse_hex(argument)?),
+        "poke" => {
+            let (addr, value) = argument.split_once('=')?;
+
+            Action::Poke {
+                addr: parse_hex(addr.trim())?.try_into().ok()?,
+                value: parse_hex(value.trim())?.try_into().ok()?,
+            }
+        }
+        _ => return None,
+    };
+
+    Some(Command {
+        frame,
+        action,
+        line: line_number,
+    })
+}
+
+fn parse_hex(value: &str) -> Option<u64> {
+    let digits = value.strip_prefix("0x").or_else(|| value.strip_prefix("0X")).unwrap_or(value);
+
+    u64::from_str_radix(digits, 16).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_every_command() {
+        let commands = parse("\
+frame 1: press A
+frame 2: release Start
+frame 3: screenshot shots/title.png
+frame 4: assert-hash 0xDEADBEEF
+frame 5: poke C123=05
+").unwrap();
+
+        assert_eq!(commands.len(), 5);
+        assert!(matches!(commands[0].action, Action::Press(Buttons::A)));
+        assert!(matches!(commands[1].action, Action::Release(Buttons::Start)));
+        assert!(matches!(&commands[2].action, Action::Screenshot(path) if path == Path::new("shots/title.png")));
+        assert!(matches!(commands[3].action, Action::AssertHash(0xdead_beef)));
+        assert!(matches!(commands[4].action, Action::Poke { addr: 0xc123, value: 0x05 }));
+
+        assert_eq!(commands.iter().map(|command| command.frame).collect::<Vec<_>>(), [1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn skips_blank_lines_and_comments() {
+        let commands = parse("\n# Title screen\n  frame 60 : press Start  \n\n").unwrap();
+
+        assert_eq!(commands.len(), 1);
+        assert_eq!(commands[0].frame, 60);
+        assert_eq!(commands[0].line, 3);
+    }
+
+    #[test]
+    fn orders_commands_by_frame_and_then_by_line() {
+        let commands = parse("\
+frame 10: press B
+frame 5: press A
+frame 10: release A
+").unwrap();
+
+        assert_eq!(commands.iter().map(|command| command.line).collect::<Vec<_>>(), [2, 1, 3]);
+    }
+
+    #[test]
+    fn rejects_invalid_lines() {
+        for line in [
+            "press A",
+            "frame: press A",
+            "frame x: press A",
+            "frame 1 press A",
+            "frame 1: press Turbo",
+            "frame 1: jump",
+            "frame 1: screenshot",
+            "frame 1: assert-hash nothex",
+            "frame 1: poke C123",
+            "frame 1: poke 10000=00",
+            "frame 1: poke C123=100",
+        ] {
+            let error = parse(&format!("frame 0: press A\n{line}")).err();
+
+            assert_eq!(error, Some(format!("Invalid command on line 2 of the script: {line}")), "{line}");
+        }
+    }
+}
@@ -0,0 +1,84 @@
+use std::{
+    fs,
+    io::ErrorKind,
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tao::keyboard::KeyCode;
+use crate::{
+    gameboy::{CartridgeHeader, StatePreview},
+    paths::Paths,
+};
+
+/// Number of save state slots of a game.
+pub(crate) const SLOT_COUNT: u8 = 10;
+
+/// Slots per row of the picker, which shows them in two rows.
+pub(crate) const SLOT_COLUMNS: u8 = 5;
+
+/// What a save state slot holds, as far as the picker reads it.
+pub(crate) enum Slot {
+    Empty,
+    /// A file that isn't a save state, or of a format newer than this version reads.
+    Unreadable,
+    State(StatePreview),
+}
+
+impl Slot {
+    /// Reads the preview of the state file at `path`, without restoring it.
+    pub fn read(path: &Path) -> Self {
+        match fs::read(path) {
+            Ok(file) => StatePreview::read(&file).map_or(Slot::Unreadable, Slot::State),
+            Err(e) if e.kind() == ErrorKind::NotFound => Slot::Empty,
+            Err(_) => Slot::Unreadable,
+        }
+    }
+}
+
+/// The grid of save state slots shown while F6 is held, where the arrows pick a slot to load or save.
+pub(crate) struct SlotPicker {
+    pub selected: u8,
+    pub slots: Vec<Slot>,
+    /// When the picker opened, in seconds since the Unix epoch, which the ages of the states are shown from.
+    pub opened_at: u64,
+}
+
+impl SlotPicker {
+    /// Reads every slot of the game at `rom_path`, with `selected` picked.
+    pub fn open(paths: &Paths, rom_path: &Path, header: &CartridgeHeader, selected: u8) -> Self {
+        Self {
+            selected,
+            slots: (0..SLOT_COUNT).map(|slot| Slot::read(&paths.state_path(rom_path, header, slot))).collect(),
+            opened_at: SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |time| time.as_secs()),
+        }
+    }
+
+    /// Moves the selection for an arrow key, wrapping around within its row or column. Other keys are ignored.
+    pub fn navigate(&mut self, key: KeyCode) {
+        let (column, row) = (self.selected % SLOT_COLUMNS, self.selected / SLOT_COLUMNS);
+        let rows = SLOT_COUNT / SLOT_COLUMNS;
+
+        let (column, row) = match key {
+            KeyCode::ArrowLeft => ((column + SLOT_COLUMNS - 1) % SLOT_COLUMNS, row),
+            KeyCode::ArrowRight => ((column + 1) % SLOT_COLUMNS, row),
+            KeyCode::ArrowUp => (column, (row + rows - 1) % rows),
+            KeyCode::ArrowDown => (column, (row + 1) % rows),
+            _ => return,
+        };
+
+        self.selected = row * SLOT_COLUMNS + column;
+    }
+}
+
+/// How long before `now` a state saved at `saved_at` was, both in seconds since the Unix epoch, e.g. "5 min ago".
+pub(crate) fn age(saved_at: u64, now: u64) -> String {
+    let seconds = now.saturating_sub(saved_at);
+
+    match seconds {
+        0..=59 => String::from("just now"),
+        60..=3599 => format!("{} min ago", seconds / 60),
+        3600..=86_399 => format!("{} h ago", seconds / 3600),
+        86_400..=172_799 => String::from("yesterday"),
+        _ => format!("{} days ago", seconds / 86_400),
+    }
+}
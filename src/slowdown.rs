@@ -0,0 +1,142 @@
+use std::{collections::VecDeque, time::Duration};
+
+/// How long emulation has to run too slow before it is reported, and how long the speed is averaged over.
+const WINDOW: Duration = Duration::from_secs(2);
+
+/// Speeds below this fraction of the speed asked for are too slow.
+const SLOW_BELOW: f32 = 0.95;
+
+/// The fraction of the speed asked for that ends a slowdown, above `SLOW_BELOW` so that a speed hovering around it
+/// isn't reported on and off.
+const RECOVERED_AT: f32 = 0.98;
+
+/// Tells whether emulation keeps up with the speed asked for, from the speeds of the stats' intervals.
+pub(crate) struct Slowdown {
+    /// The last intervals and the speed measured over each, as a fraction of the speed asked for, covering `WINDOW`.
+    samples: VecDeque<(Duration, f32)>,
+    /// The average speed of the window, while it is too slow.
+    slow: Option<f32>,
+    /// Whether the next interval is ignored, having started before the last reset.
+    skip_next: bool,
+}
+
+impl Slowdown {
+    pub fn new() -> Self {
+        Self {
+            samples: VecDeque::new(),
+            slow: None,
+            skip_next: false,
+        }
+    }
+
+    /// Forgets the speeds so far, for when emulation was paused or its speed or game changed, which the interval in
+    /// progress was partly measured before.
+    pub fn reset(&mut self) {
+        self.samples.clear();
+        self.slow = None;
+        self.skip_next = true;
+    }
+
+    /// Records that emulation ran at `speed`, a fraction of the speed asked for, over an interval of `length`. Returns
+    /// true when it has just become too slow.
+    pub fn record(&mut self, length: Duration, speed: f32) -> bool {
+        if std::mem::take(&mut self.skip_next) {
+            return false;
+        }
+
+        self.samples.push_back((length, speed));
+
+        let total = |samples: &VecDeque<(Duration, f32)>| samples.iter().map(|&(length, _)| length).sum::<Duration>();
+
+        while self.samples.len() > 1 && total(&self.samples) - self.samples[0].0 >= WINDOW {
+            self.samples.pop_front();
+        }
+
+        let window = total(&self.samples);
+
+        if window < WINDOW {
+            return false;
+        }
+
+        let average = self.samples.iter().map(|&(length, speed)| length.as_secs_f32() * speed).sum::<f32>() / window.as_secs_f32();
+        let was_slow = self.slow.is_some();
+
+        self.slow = match was_slow {
+            true => (average < RECOVERED_AT).then_some(average),
+            false => (average < SLOW_BELOW).then_some(average),
+        };
+
+        self.slow.is_some() && !was_slow
+    }
+
+    /// The average speed over the last `WINDOW`, as a fraction of the speed asked for, while it is too slow.
+    pub fn slow_speed(&self) -> Option<f32> {
+        self.slow
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECOND: Duration = Duration::from_secs(1);
+
+    /// Records a second at each of `speeds`, returning whether each made emulation too slow.
+    fn record(slowdown: &mut Slowdown, speeds: &[f32]) -> Vec<bool> {
+        speeds.iter().map(|&speed| slowdown.record(SECOND, speed)).collect()
+    }
+
+    #[test]
+    fn slowdowns_are_reported_once_the_window_is_too_slow() {
+        let mut slowdown = Slowdown::new();
+
+        assert_eq!(record(&mut slowdown, &[0.5, 0.9]), [false, true]);
+        assert_eq!(slowdown.slow_speed(), Some(0.7));
+
+        // Staying slow isn't reported again.
+        assert_eq!(record(&mut slowdown, &[0.9]), [false]);
+        assert_eq!(slowdown.slow_speed(), Some(0.9));
+    }
+
+    #[test]
+    fn nothing_is_reported_before_a_whole_window() {
+        let mut slowdown = Slowdown::new();
+
+        assert!(!slowdown.record(Duration::from_millis(1500), 0.1));
+        assert_eq!(slowdown.slow_speed(), None);
+    }
+
+    #[test]
+    fn slowdowns_end_only_above_the_recovery_speed() {
+        let mut slowdown = Slowdown::new();
+        record(&mut slowdown, &[0.5, 0.5]);
+
+        // Between `SLOW_BELOW` and `RECOVERED_AT`.
+        record(&mut slowdown, &[0.97, 0.97]);
+        assert_eq!(slowdown.slow_speed(), Some(0.97));
+
+        record(&mut slowdown, &[1.0, 1.0]);
+        assert_eq!(slowdown.slow_speed(), None);
+    }
+
+    #[test]
+    fn speeds_between_the_thresholds_dont_start_a_slowdown() {
+        let mut slowdown = Slowdown::new();
+
+        assert_eq!(record(&mut slowdown, &[0.96, 0.96, 0.96]), [false, false, false]);
+        assert_eq!(slowdown.slow_speed(), None);
+    }
+
+    #[test]
+    fn resets_forget_the_speeds_and_skip_the_interval_in_progress() {
+        let mut slowdown = Slowdown::new();
+        record(&mut slowdown, &[0.5, 0.5]);
+
+        slowdown.reset();
+        assert_eq!(slowdown.slow_speed(), None);
+
+        // The first interval after the reset is skipped, so it takes two more to fill the window.
+        assert_eq!(record(&mut slowdown, &[0.1, 0.5, 0.5]), [false, false, true]);
+        assert_eq!(slowdown.slow_speed(), Some(0.5));
+    }
+}
@@ -0,0 +1,124 @@
+﻿use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+use crate::gameboy::{AUDIO_SAMPLE_RATE, FRAMES_PER_SECOND};
+
+const UPDATE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Speed and frame time statistics, recomputed once per second so they stay readable.
+///
+/// Emulated frames are counted by whoever runs the emulator, through the shared counter, so that no lock on the
+/// `GameBoy` is needed to read them.
+pub(crate) struct Stats {
+    emulated_frames: Arc<AtomicU64>,
+    interval_start: Instant,
+    interval_start_frames: u64,
+    host_frames: u32,
+    host_frame_time: Duration,
+    audio_fill_total: f32,
+    /// Total stereo samples dropped by the emulator when the interval started, and when last reset.
+    audio_overruns: u64,
+    reset_audio_overruns: u64,
+    pub emulated_fps: f32,
+    pub speed_percent: f32,
+    pub audio_fill_percent: f32,
+    pub frame_time_ms: f32,
+    /// How long the displayed values were measured over.
+    pub interval: Duration,
+    /// Audio dropped since the last reset, because the audio device didn't take it in time.
+    pub audio_dropped_ms: f32,
+}
+
+impl Stats {
+    pub fn new() -> Self {
+        Self {
+            emulated_frames: Arc::new(AtomicU64::new(0)),
+            interval_start: Instant::now(),
+            interval_start_frames: 0,
+            host_frames: 0,
+            host_frame_time: Duration::ZERO,
+            audio_fill_total: 0.0,
+            audio_overruns: 0,
+            reset_audio_overruns: 0,
+            emulated_fps: 0.0,
+            speed_percent: 0.0,
+            audio_fill_percent: 0.0,
+            frame_time_ms: 0.0,
+            interval: Duration::ZERO,
+            audio_dropped_ms: 0.0,
+        }
+    }
+
+    pub fn emulated_frames(&self) -> Arc<AtomicU64> {
+        self.emulated_frames.clone()
+    }
+
+    pub fn total_emulated_frames(&self) -> u64 {
+        self.emulated_frames.load(Ordering::Relaxed)
+    }
+
+    /// Clears the displayed values and starts a new interval, so a newly loaded game isn't measured with the previous
+    /// one, or with the time it took to load.
+    pub fn reset(&mut self) {
+        self.interval_start = Instant::now();
+        self.interval_start_frames = self.total_emulated_frames();
+        self.host_frames = 0;
+        self.host_frame_time = Duration::ZERO;
+        self.audio_fill_total = 0.0;
+        self.reset_audio_overruns = self.audio_overruns;
+        self.emulated_fps = 0.0;
+        self.speed_percent = 0.0;
+        self.audio_fill_percent = 0.0;
+        self.frame_time_ms = 0.0;
+        self.interval = Duration::ZERO;
+        self.audio_dropped_ms = 0.0;
+    }
+
+    /// Records a presented host frame, with the total of stereo samples the emulator dropped so far. Returns true when
+    /// the displayed values were updated.
+    pub fn record_host_frame(&mut self, frame_time: Duration, audio_fill: f32, audio_overruns: u64) -> bool {
+        self.host_frames += 1;
+        self.host_frame_time += frame_time;
+        self.audio_fill_total += audio_fill;
+        self.audio_overruns = audio_overruns;
+
+        let elapsed = self.interval_start.elapsed();
+        if elapsed < UPDATE_INTERVAL {
+            return false;
+        }
+
+        let frames = self.emulated_frames.load(Ordering::Relaxed);
+
+        self.emulated_fps = (frames - self.interval_start_frames) as f32 / elapsed.as_secs_f32();
+        self.speed_percent = self.emulated_fps / FRAMES_PER_SECOND as f32 * 100.0;
+        self.audio_fill_percent = self.audio_fill_total / self.host_frames as f32 * 100.0;
+        self.frame_time_ms = self.host_frame_time.as_secs_f32() * 1000.0 / self.host_frames as f32;
+        self.interval = elapsed;
+        self.audio_dropped_ms = (audio_overruns - self.reset_audio_overruns) as f32 * 1000.0 / AUDIO_SAMPLE_RATE as f32;
+
+        self.interval_start = Instant::now();
+        self.interval_start_frames = frames;
+        self.host_frames = 0;
+        self.host_frame_time = Duration::ZERO;
+        self.audio_fill_total = 0.0;
+
+        true
+    }
+
+    pub fn speed_line(&self) -> String {
+        let line = format!("{:.1} fps ({:.1} %) | audio {:.0} %", self.emulated_fps, self.speed_percent, self.audio_fill_percent);
+
+        match self.audio_dropped_ms {
+            dropped if dropped > 0.0 => format!("{line}, {dropped:.0} ms dropped"),
+            _ => line,
+        }
+    }
+
+    pub fn frame_time_line(&self) -> String {
+        format!("{:.1} ms/frame", self.frame_time_ms)
+    }
+}
@@ -0,0 +1,164 @@
+use sdl2::{
+    rect::Rect,
+    render::Texture,
+    sys::{SDL_ScaleMode, SDL_SetTextureScaleMode},
+};
+use tao::dpi::{LogicalSize, PhysicalSize};
+
+/// How the screen is scaled to the window, with black bars filling what it leaves uncovered.
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) enum StretchMode {
+    /// The largest whole multiple of the screen size that fits, so every pixel is drawn the same size.
+    #[default]
+    Integer,
+    /// As large as fits while keeping the 10:9 aspect ratio, at any scale.
+    Fit,
+    /// Fills the whole window, whatever its aspect ratio.
+    Stretch,
+}
+
+impl StretchMode {
+    pub const ALL: [Self; 3] = [Self::Integer, Self::Fit, Self::Stretch];
+
+    pub fn by_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|mode| mode.name() == name)
+    }
+
+    /// The name used in the config file.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Integer => "integer",
+            Self::Fit => "fit",
+            Self::Stretch => "stretch",
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::Integer => "&Integer scale",
+            Self::Fit => "&Fit",
+            Self::Stretch => "&Stretch",
+        }
+    }
+
+    /// Where content of `width` by `height` pixels is drawn in an output of `output_width` by `output_height`,
+    /// centered. Integer scaling never goes below 1x, cropping the content in a window too small for it.
+    pub fn destination(self, width: u32, height: u32, output_width: u32, output_height: u32) -> Rect {
+        let (dest_width, dest_height) = match self {
+            Self::Integer => {
+                let scale = (output_width / width).min(output_height / height).max(1);
+
+                (width * scale, height * scale)
+            }
+            Self::Fit => {
+                // Compare the aspect ratios without dividing, to tell which side limits the scale.
+                if output_width as u64 * height as u64 <= output_height as u64 * width as u64 {
+                    (output_width, (output_width as u64 * height as u64 / width as u64) as u32)
+                } else {
+                    ((output_height as u64 * width as u64 / height as u64) as u32, output_height)
+                }
+            }
+            Self::Stretch => (output_width, output_height),
+        };
+
+        Rect::new(
+            (output_width as i32 - dest_width as i32) / 2,
+            (output_height as i32 - dest_height as i32) / 2,
+            dest_width.max(1),
+            dest_height.max(1),
+        )
+    }
+
+    /// Samples `texture` with the nearest pixel, keeping pixels crisp, unless stretching, which can't keep every
+    /// pixel the same size anyway and is filtered instead.
+    pub fn set_scale_mode(self, texture: &mut Texture) {
+        let scale_mode = match self {
+            Self::Integer | Self::Fit => SDL_ScaleMode::SDL_ScaleModeNearest,
+            Self::Stretch => SDL_ScaleMode::SDL_ScaleModeLinear,
+        };
+
+        unsafe {
+            SDL_SetTextureScaleMode(texture.raw(), scale_mode);
+        }
+    }
+}
+
+/// The physical inner size of a window showing `width` by `height` pixels of content at `scale` times their size, in
+/// logical pixels, below a menu bar of `menu_height` physical pixels. Scaling logical pixels keeps a scale the same
+/// size on monitors of any DPI, where the scale factor is the monitor's DPI over 96.
+pub(crate) fn window_size(width: u32, height: u32, scale: u32, scale_factor: f64, menu_height: u32) -> PhysicalSize<u32> {
+    let screen: PhysicalSize<u32> = LogicalSize::new(width * scale, height * scale).to_physical(scale_factor);
+
+    PhysicalSize::new(screen.width, screen.height + menu_height)
+}
+
+/// The physical inner size that keeps the area below the menu bar of a window of inner size `size` the same size in
+/// logical pixels, when the scale factor and the menu bar height change along with the monitor the window is on.
+pub(crate) fn rescaled_window_size(size: PhysicalSize<u32>, old: (f64, u32), new: (f64, u32)) -> PhysicalSize<u32> {
+    let (old_scale_factor, old_menu_height) = old;
+    let (new_scale_factor, new_menu_height) = new;
+
+    let screen: LogicalSize<f64> = PhysicalSize::new(size.width, size.height.saturating_sub(old_menu_height)).to_logical(old_scale_factor);
+    let screen: PhysicalSize<u32> = screen.to_physical(new_scale_factor);
+
+    PhysicalSize::new(screen.width, screen.height + new_menu_height)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn destination(mode: StretchMode, output_width: u32, output_height: u32) -> (i32, i32, u32, u32) {
+        let rect = mode.destination(160, 144, output_width, output_height);
+
+        (rect.x(), rect.y(), rect.width(), rect.height())
+    }
+
+    #[test]
+    fn integer_scaling_uses_the_largest_multiple_that_fits() {
+        assert_eq!(destination(StretchMode::Integer, 160, 144), (0, 0, 160, 144));
+        assert_eq!(destination(StretchMode::Integer, 500, 500), (10, 34, 480, 432));
+        assert_eq!(destination(StretchMode::Integer, 1920, 1080), (400, 36, 1120, 1008));
+    }
+
+    #[test]
+    fn integer_scaling_crops_in_outputs_smaller_than_the_content() {
+        assert_eq!(destination(StretchMode::Integer, 100, 100), (-30, -22, 160, 144));
+    }
+
+    #[test]
+    fn fitting_keeps_the_aspect_ratio() {
+        // Limited by the height, then by the width.
+        assert_eq!(destination(StretchMode::Fit, 1920, 1080), (360, 0, 1200, 1080));
+        assert_eq!(destination(StretchMode::Fit, 500, 1000), (0, 275, 500, 450));
+        assert_eq!(destination(StretchMode::Fit, 320, 288), (0, 0, 320, 288));
+    }
+
+    #[test]
+    fn stretching_fills_the_output() {
+        assert_eq!(destination(StretchMode::Stretch, 1920, 1080), (0, 0, 1920, 1080));
+    }
+
+    #[test]
+    fn destinations_are_never_empty() {
+        for mode in StretchMode::ALL {
+            let (_, _, width, height) = destination(mode, 0, 0);
+
+            assert!(width > 0 && height > 0, "{}", mode.name());
+        }
+    }
+
+    #[test]
+    fn window_sizes_scale_with_the_scale_factor() {
+        assert_eq!(window_size(160, 144, 3, 1.0, 20), PhysicalSize::new(480, 452));
+        assert_eq!(window_size(160, 144, 3, 1.5, 30), PhysicalSize::new(720, 678));
+    }
+
+    #[test]
+    fn rescaled_windows_keep_their_logical_size_below_the_menu_bar() {
+        let size = window_size(160, 144, 3, 1.0, 20);
+
+        assert_eq!(rescaled_window_size(size, (1.0, 20), (2.0, 40)), window_size(160, 144, 3, 2.0, 40));
+        assert_eq!(rescaled_window_size(PhysicalSize::new(720, 678), (1.5, 30), (1.0, 20)), size);
+    }
+}
@@ -0,0 +1,172 @@
+use std::time::{Duration, Instant};
+use tao::window::Window;
+
+pub(crate) const WINDOW_TITLE: &str = "Yet Another Game Boy Emulator";
+
+// Titles change at most this often, so that they stay readable and don't flicker in the taskbar.
+const UPDATE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Everything the window title reflects, gathered from the features that change it.
+pub(crate) struct TitleState {
+    pub rom_name: Option<String>,
+    /// Speed and frame time statistics, while they are shown in the title.
+    pub stats: Option<String>,
+    pub recording: bool,
+    pub paused: bool,
+    /// Emulation speed in percent, shown unless it is 100 %.
+    pub speed: u16,
+}
+
+/// Composes the window title, e.g. "Yet Another Game Boy Emulator - tetris [REC] [PAUSED] [200 %] | 59.7 fps (99.5 %)".
+pub(crate) fn compose(state: &TitleState) -> String {
+    let mut title = String::from(WINDOW_TITLE);
+
+    if let Some(rom_name) = &state.rom_name {
+        title.push_str(" - ");
+        title.push_str(rom_name);
+    }
+
+    if state.recording {
+        title.push_str(" [REC]");
+    }
+
+    if state.paused {
+        title.push_str(" [PAUSED]");
+    }
+
+    if state.speed != 100 {
+        title.push_str(&format!(" [{} %]", state.speed));
+    }
+
+    if let Some(stats) = &state.stats {
+        title.push_str(" | ");
+        title.push_str(stats);
+    }
+
+    title
+}
+
+/// Keeps the window title, and the taskbar button on Windows, in line with a `TitleState`.
+pub(crate) struct Title {
+    shown: String,
+    last_update: Instant,
+    taskbar: Option<Taskbar>,
+    recording: bool,
+}
+
+impl Title {
+    pub fn new() -> Self {
+        Self {
+            shown: String::from(WINDOW_TITLE),
+            last_update: Instant::now(),
+            taskbar: Taskbar::new(),
+            recording: false,
+        }
+    }
+
+    pub fn update(&mut self, window: &Window, state: &TitleState) {
+        if self.last_update.elapsed() < UPDATE_INTERVAL {
+            return;
+        }
+
+        self.last_update = Instant::now();
+
+        let title = compose(state);
+
+        if title != self.shown {
+            window.set_title(&title);
+            self.shown = title;
+        }
+
+        if state.recording != self.recording {
+            self.recording = state.recording;
+
+            if let Some(taskbar) = &self.taskbar {
+                taskbar.set_busy(window, state.recording);
+            }
+        }
+    }
+}
+
+/// The taskbar button of the window, which shows a progress indicator during long operations.
+struct Taskbar(windows::Win32::UI::Shell::ITaskbarList3);
+
+impl Taskbar {
+    /// Returns `None` if the taskbar can't be reached, which only loses the indicator.
+    fn new() -> Option<Self> {
+        use windows::Win32::{
+            System::Com::{CoCreateInstance, CoInitializeEx, CLSCTX_INPROC_SERVER, COINIT_APARTMENTTHREADED},
+            UI::Shell::{ITaskbarList3, TaskbarList},
+        };
+
+        unsafe {
+            // Fails harmlessly if COM was already initialized on this thread, e.g. by the window.
+            let _ = CoInitializeEx(None, COINIT_APARTMENTTHREADED);
+
+            let taskbar: ITaskbarList3 = CoCreateInstance(&TaskbarList, None, CLSCTX_INPROC_SERVER).ok()?;
+            taskbar.HrInit().ok()?;
+
+            Some(Self(taskbar))
+        }
+    }
+
+    /// Shows an indeterminate progress indicator while `busy`.
+    fn set_busy(&self, window: &Window, busy: bool) {
+        use tao::platform::windows::WindowExtWindows;
+        use windows::Win32::{
+            Foundation::HWND,
+            UI::Shell::{TBPF_INDETERMINATE, TBPF_NOPROGRESS},
+        };
+
+        let state = if busy { TBPF_INDETERMINATE } else { TBPF_NOPROGRESS };
+
+        unsafe {
+            let _ = self.0.SetProgressState(HWND(window.hwnd() as isize), state);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state() -> TitleState {
+        TitleState {
+            rom_name: None,
+            stats: None,
+            recording: false,
+            paused: false,
+            speed: 100,
+        }
+    }
+
+    #[test]
+    fn titles_without_a_game_are_the_emulator_name() {
+        assert_eq!(compose(&state()), WINDOW_TITLE);
+    }
+
+    #[test]
+    fn titles_name_the_game_and_what_it_is_doing() {
+        let state = TitleState {
+            rom_name: Some(String::from("tetris")),
+            stats: Some(String::from("59.7 fps (99.5 %)")),
+            recording: true,
+            paused: true,
+            speed: 200,
+        };
+
+        assert_eq!(compose(&state), "Yet Another Game Boy Emulator - tetris [REC] [PAUSED] [200 %] | 59.7 fps (99.5 %)");
+    }
+
+    #[test]
+    fn titles_show_only_the_parts_that_apply() {
+        let paused = TitleState { rom_name: Some(String::from("tetris")), paused: true, ..state() };
+        assert_eq!(compose(&paused), "Yet Another Game Boy Emulator - tetris [PAUSED]");
+
+        let slowed = TitleState { speed: 50, ..state() };
+        assert_eq!(compose(&slowed), "Yet Another Game Boy Emulator [50 %]");
+
+        let stats = TitleState { stats: Some(String::from("60.0 fps")), ..state() };
+        assert_eq!(compose(&stats), "Yet Another Game Boy Emulator | 60.0 fps");
+    }
+}
@@ -0,0 +1,71 @@
+use std::{
+    mem,
+    sync::{Arc, Mutex},
+};
+
+/// The slot between the two sides, and whether it holds a value the reader hasn't seen yet.
+type Middle<T> = Arc<Mutex<(Box<T>, bool)>>;
+
+/// Hands values from one thread to another without either side waiting for the other to finish with its value.
+///
+/// The writer fills its back buffer in place and publishes it, and the reader always gets the latest published value.
+/// Values that are published faster than they are read are overwritten. Both sides only ever swap pointers with
+/// the middle slot, and the reader keeps its current value instead of waiting when the slot is busy.
+pub(crate) fn triple_buffer<T: Clone>(initial: T) -> (Writer<T>, Reader<T>) {
+    let middle = Arc::new(Mutex::new((Box::new(initial.clone()), false)));
+
+    let writer = Writer {
+        back: Box::new(initial.clone()),
+        middle: middle.clone(),
+    };
+
+    let reader = Reader {
+        front: Box::new(initial),
+        middle,
+    };
+
+    (writer, reader)
+}
+
+pub(crate) struct Writer<T> {
+    back: Box<T>,
+    middle: Middle<T>,
+}
+
+impl<T> Writer<T> {
+    /// The value being written, which still holds whatever was published two values ago.
+    pub fn back(&mut self) -> &mut T {
+        &mut self.back
+    }
+
+    pub fn publish(&mut self) {
+        let mut middle = self.middle.lock().unwrap();
+
+        mem::swap(&mut self.back, &mut middle.0);
+        middle.1 = true;
+    }
+}
+
+pub(crate) struct Reader<T> {
+    front: Box<T>,
+    middle: Middle<T>,
+}
+
+impl<T> Reader<T> {
+    /// Whether a value was published since the last `read`. It may miss one while the writer is publishing.
+    pub fn has_new(&self) -> bool {
+        self.middle.try_lock().is_ok_and(|middle| middle.1)
+    }
+
+    /// Returns the latest published value, or the one returned before if nothing new was published since.
+    pub fn read(&mut self) -> &T {
+        if let Ok(mut middle) = self.middle.try_lock() {
+            if middle.1 {
+                mem::swap(&mut self.front, &mut middle.0);
+                middle.1 = false;
+            }
+        }
+
+        &self.front
+    }
+}